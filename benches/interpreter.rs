@@ -0,0 +1,173 @@
+use toys_in_rust::{execute_program_with, parser, Options};
+
+use std::env;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const SAMPLES: usize = 20;
+const WARM_UP: Duration = Duration::from_millis(300);
+
+// 同じプログラムをインタプリタの実行方式ごとに計測する。C などに変換したコードは計測しない
+struct Mode {
+    name: &'static str,
+    run: fn(&str) -> i32,
+}
+const MODES: [Mode; 2] = [
+    Mode {
+        name: "interpreter",
+        run: |x| execute_program_with(x, &Options { optimize: false, ..Options::default() }),
+    },
+    Mode {
+        name: "interpreter+optimizer",
+        run: |x| execute_program_with(x, &Options { optimize: true, ..Options::default() }),
+    },
+];
+
+fn measure<F: FnMut()>(mut f: F) -> (Duration, Duration) {
+    let warm_up = Instant::now();
+    while warm_up.elapsed() < WARM_UP {
+        f();
+    }
+    let mut samples: Vec<Duration> = (0..SAMPLES).map(|_| {
+        let start = Instant::now();
+        f();
+        start.elapsed()
+    }).collect();
+    samples.sort();
+    (samples[SAMPLES / 2], samples[0])
+}
+
+fn report(name: &str, mode: &str, (median, min): (Duration, Duration)) {
+    println!("{:<20} {:<24} median {:>12.3?}   min {:>12.3?}", name, mode, median, min);
+}
+
+fn skipped(filter: &Option<String>, name: &str) -> bool {
+    filter.as_ref().is_some_and(|x| !name.contains(x.as_str()))
+}
+
+fn bench_program(filter: &Option<String>, name: &str, program: &str) {
+    if skipped(filter, name) {
+        return;
+    }
+    for mode in MODES.iter() {
+        report(name, mode.name, measure(|| { black_box((mode.run)(program)); }));
+    }
+}
+
+fn bench_parse(filter: &Option<String>, name: &str, functions: usize) {
+    if skipped(filter, name) {
+        return;
+    }
+    let program = generate_program(functions);
+    report(name, "parser", measure(|| { black_box(parser::parse(&program).unwrap()); }));
+}
+
+// f0 から f{n-1} までの関数がそれぞれ一つ前の関数を呼ぶプログラムを作る
+fn generate_program(functions: usize) -> String {
+    let mut program = String::new();
+    for i in 0..functions {
+        let call = if i == 0 { "0".to_string() } else { format!("f{}(n - 1)", i - 1) };
+        program.push_str(&format!(r#"
+            /// generated function {i}
+            define f{i}(n) {{
+                x = n * {i} + 1;
+                for (k in 1 to 3) {{
+                    x = x + k;
+                }}
+                if (n > 0) {{
+                    x = x + {call};
+                }} else {{
+                    x = x - 1;
+                }}
+                x;
+            }}
+        "#, i = i, call = call));
+    }
+    program.push_str(&format!("define main() {{ f{}(10); }}\n", functions - 1));
+    program
+}
+
+fn main() {
+    // `cargo bench -- fact` のように名前で絞り込める
+    let filter = env::args().skip(1).find(|x| !x.starts_with('-'));
+
+    bench_program(&filter, "fact", r#"
+        define fact(n) {
+            if (n < 2) 1; else n * fact(n - 1);
+        }
+        define main() {
+            i = 0;
+            while (i < 2000) {
+                r = fact(12);
+                i = i + 1;
+            }
+            r;
+        }
+    "#);
+    bench_program(&filter, "fib", r#"
+        define fib(n) {
+            if (n < 2) n; else fib(n - 1) + fib(n - 2);
+        }
+        define main() {
+            fib(20);
+        }
+    "#);
+    bench_program(&filter, "nested_while_global", r#"
+        define main() {
+            i = 0;
+            s = 0;
+            while (i < 300) {
+                j = 0;
+                while (j < 300) {
+                    s = s + j;
+                    j = j + 1;
+                }
+                i = i + 1;
+            }
+            s;
+        }
+    "#);
+    bench_program(&filter, "nested_while_local", r#"
+        define sum(n) {
+            i = 0;
+            s = 0;
+            while (i < n) {
+                j = 0;
+                while (j < n) {
+                    s = s + j;
+                    j = j + 1;
+                }
+                i = i + 1;
+            }
+            s;
+        }
+        define main() {
+            sum(300);
+        }
+    "#);
+    bench_program(&filter, "for_in", r#"
+        define main() {
+            s = 0;
+            for (i in 1 to 300) {
+                for (j in 1 to 300) {
+                    s = s + (2 * 3 + 1) * j / 7;
+                }
+            }
+            s;
+        }
+    "#);
+    bench_program(&filter, "labelled_call", r#"
+        define area(width, height) {
+            width * height;
+        }
+        define main() {
+            s = 0;
+            for (i in 1 to 20000) {
+                s = s + area[height = i, width = 2] - area(i, 2);
+            }
+            s;
+        }
+    "#);
+    bench_parse(&filter, "parse_100", 100);
+    bench_parse(&filter, "parse_1000", 1000);
+}
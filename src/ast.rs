@@ -1,10 +1,17 @@
+mod error;
+mod hook;
 mod operator;
-use operator::*;
+mod value;
+pub use error::*;
+pub use hook::*;
+pub use operator::*;
+pub use value::*;
 
 use std::collections::HashMap;
 use std::collections::LinkedList;
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::fmt;
 
 pub type Expression = Box<dyn ExpressionTrait>;
 pub type TopLevel = Box<dyn TopLevelTrait>;
@@ -12,6 +19,13 @@ pub type TopLevel = Box<dyn TopLevelTrait>;
 pub struct Ast {}
 
 impl Ast {
+    pub fn binary(operator: Operator, lhs: Expression, rhs: Expression) -> Box<BinaryExpression> {
+        Box::new(BinaryExpression::new(
+            operator,
+            lhs,
+            rhs
+        ))
+    }
     pub fn add(lhs: Expression, rhs:Expression) -> Box<BinaryExpression> {
         Box::new(BinaryExpression::new(
             Operator::ADD,
@@ -115,166 +129,384 @@ impl Ast {
     pub fn println(body: Expression) -> Box<PrintlnExpression> {
         Box::new(PrintlnExpression::new(body))
     }
+    pub fn environment_variable(name: String, default: Option<Expression>) -> Box<EnvironmentVariable> {
+        Box::new(EnvironmentVariable::new(name, default))
+    }
+    pub fn assert(left: Expression, right: Option<Expression>) -> Box<AssertExpression> {
+        Box::new(AssertExpression::new(left, right))
+    }
+    pub fn match_expr(value: Expression, arms: Vec<MatchArm>) -> Box<MatchExpression> {
+        Box::new(MatchExpression::new(value, arms))
+    }
+    pub fn field(value: Expression, field: String) -> Box<FieldAccess> {
+        Box::new(FieldAccess::new(value, field))
+    }
+    pub fn field_assignment(name: String, path: Vec<String>, expression: Expression) -> Box<FieldAssignment> {
+        Box::new(FieldAssignment::new(name, path, expression))
+    }
+    pub fn construct(name: String, enumeration: Option<String>) -> Box<Construction> {
+        Box::new(Construction::new(name, enumeration))
+    }
+    pub fn throw(value: Expression) -> Box<ThrowExpression> {
+        Box::new(ThrowExpression::new(value))
+    }
+    pub fn try_expr(body: Expression, catch_clause: Option<(String, Expression)>, finally_clause: Option<Expression>) -> Box<TryExpression> {
+        Box::new(TryExpression::new(body, catch_clause, finally_clause))
+    }
+    pub fn define_record(name: String, fields: LinkedList<String>) -> Box<RecordDefinition> {
+        Box::new(RecordDefinition::new(name, fields))
+    }
+    pub fn define_enum(name: String, variants: Vec<(String, LinkedList<String>)>) -> Box<EnumDefinition> {
+        Box::new(EnumDefinition::new(name, variants))
+    }
+    pub fn define_test(name: String, body: Expression) -> Box<TestDefinition> {
+        Box::new(TestDefinition::new(name, body))
+    }
+}
+
+// 構文木を調べるパス（最適化など）のための、各ノードへの参照
+pub enum Node<'a> {
+    Binary(&'a BinaryExpression),
+    Integer(&'a IntegerLiteral),
+    Assignment(&'a Assignment),
+    Identifier(&'a Identifier),
+    Block(&'a BlockExpression),
+    While(&'a WhileExpression),
+    If(&'a IfExpression),
+    Println(&'a PrintlnExpression),
+    Call(&'a FunctionCall),
+    LabelledCall(&'a LabelledCall),
+    EnvironmentVariable(&'a EnvironmentVariable),
+    Assert(&'a AssertExpression),
+    Match(&'a MatchExpression),
+    Field(&'a FieldAccess),
+    FieldAssignment(&'a FieldAssignment),
+    Construct(&'a Construction),
+    Throw(&'a ThrowExpression),
+    Try(&'a TryExpression),
+}
+
+impl<'a> Node<'a> {
+    pub fn children(&self) -> Vec<&'a Expression> {
+        match *self {
+            Node::Binary(x) => vec![&x.lhs, &x.rhs],
+            Node::Integer(_) | Node::Identifier(_) | Node::Construct(_) => vec![],
+            Node::Assignment(x) => vec![&x.expression],
+            Node::Block(x) => x.elements.iter().collect(),
+            Node::While(x) => vec![&x.condition, &x.body],
+            Node::If(x) => {
+                let mut children = vec![&x.condition, &x.then_clause];
+                children.extend(x.else_clause.iter());
+                children
+            },
+            Node::Println(x) => vec![&x.body],
+            Node::Call(x) => x.args.iter().collect(),
+            Node::LabelledCall(x) => x.args.iter().map(|y| &y.parameter).collect(),
+            Node::EnvironmentVariable(x) => x.default.iter().collect(),
+            Node::Assert(x) => {
+                let mut children = vec![&x.left];
+                children.extend(x.right.iter());
+                children
+            },
+            Node::Match(x) => {
+                let mut children = vec![&x.value];
+                for arm in x.arms.iter() {
+                    children.extend(arm.guard.iter());
+                    children.push(&arm.body);
+                }
+                children
+            },
+            Node::Field(x) => vec![&x.value],
+            Node::FieldAssignment(x) => vec![&x.expression],
+            Node::Throw(x) => vec![&x.value],
+            Node::Try(x) => {
+                let mut children = vec![&x.body];
+                children.extend(x.catch_clause.iter().map(|y| &y.body));
+                children.extend(x.finally_clause.iter());
+                children
+            },
+        }
+    }
+}
+
+pub enum Definition<'a> {
+    Function(&'a FunctionDefinition),
+    Global(&'a GlobalVariableDefinition),
+    Test(&'a TestDefinition),
+    Record(&'a RecordDefinition),
+    Enum(&'a EnumDefinition),
 }
 
 pub trait ExpressionTrait {
+    fn node(&self) -> Node<'_>;
+    fn span_cell(&self) -> &Cell<Option<Span>>;
+    fn span(&self) -> Option<Span> {
+        self.span_cell().get()
+    }
+    fn set_span(&self, span: Span) {
+        self.span_cell().set(Some(span));
+    }
     fn eval(
         &self,
         _variable_environment: &Rc<Environment>,
         _function_environment: &HashMap<String, &FunctionDefinition>
-    ) -> i32 {
-        0
+    ) -> Result<Value, RuntimeError> {
+        Ok(Value::Integer(0))
     }
 }
 
 pub struct BinaryExpression {
-    operator: Operator,
-    lhs: Expression,
-    rhs: Expression,
+    pub operator: Operator,
+    pub lhs: Expression,
+    pub rhs: Expression,
+    pub span: Cell<Option<Span>>,
 }
 impl ExpressionTrait for BinaryExpression {
-    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> i32 {
-        let lhs: i32 = self.lhs.eval(v, f);
-        let rhs: i32 = self.rhs.eval(v, f);
-        match self.operator {
-            Operator::ADD => lhs + rhs,
-            Operator::SUBTRACT => lhs - rhs,
-            Operator::MULTIPLY => lhs * rhs,
-            Operator::DIVIDE => lhs / rhs,
-            Operator::LessThan => if lhs < rhs { 1 } else { 0 },
-            Operator::LessOrEqual => if lhs <= rhs { 1 } else { 0 },
-            Operator::GreaterThan => if lhs > rhs { 1 } else { 0 },
-            Operator::GreaterOrEqual => if lhs >= rhs { 1 } else { 0 },
-            Operator::EqualEqual => if lhs == rhs { 1 } else { 0 },
-            Operator::NotEqual => if lhs != rhs { 1 } else { 0 },
-        }
+    fn node(&self) -> Node<'_> {
+        Node::Binary(self)
+    }
+    fn span_cell(&self) -> &Cell<Option<Span>> {
+        &self.span
+    }
+    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
+        let lhs = self.lhs.eval(v, f)?;
+        let rhs = self.rhs.eval(v, f)?;
+        // 整数どうしの演算がほとんどなので、値の型を確かめずにそのまま計算する
+        let value = match (&lhs, &rhs) {
+            (Value::Integer(x), Value::Integer(y)) => self.integers(*x, *y),
+            _ => self.apply(&lhs, &rhs),
+        };
+        value.map_err(|e| e.with_span(self.span()))
     }
 }
 impl BinaryExpression {
+    fn apply(&self, lhs: &Value, rhs: &Value) -> Result<Value, RuntimeError> {
+        // == と != はどの値どうしでも比べられる。型が違えば等しくない
+        match self.operator {
+            Operator::EqualEqual => return Ok(Value::Integer(if lhs == rhs { 1 } else { 0 })),
+            Operator::NotEqual => return Ok(Value::Integer(if lhs != rhs { 1 } else { 0 })),
+            _ => {},
+        }
+        let operand = || format!("operand of {}", self.operator);
+        self.integers(lhs.integer(operand)?, rhs.integer(operand)?)
+    }
+    fn integers(&self, lhs: i32, rhs: i32) -> Result<Value, RuntimeError> {
+        let overflow = || RuntimeError::new(ErrorKind::Overflow, format!("integer overflow: {} {} {}", lhs, self.operator, rhs));
+        let value = match self.operator {
+            Operator::ADD => lhs.checked_add(rhs).ok_or_else(overflow),
+            Operator::SUBTRACT => lhs.checked_sub(rhs).ok_or_else(overflow),
+            Operator::MULTIPLY => lhs.checked_mul(rhs).ok_or_else(overflow),
+            Operator::DIVIDE => {
+                if rhs == 0 {
+                    return Err(RuntimeError::new(ErrorKind::DivisionByZero, format!("division by zero: {} / 0", lhs)));
+                }
+                lhs.checked_div(rhs).ok_or_else(overflow)
+            },
+            Operator::LessThan => Ok(if lhs < rhs { 1 } else { 0 }),
+            Operator::LessOrEqual => Ok(if lhs <= rhs { 1 } else { 0 }),
+            Operator::GreaterThan => Ok(if lhs > rhs { 1 } else { 0 }),
+            Operator::GreaterOrEqual => Ok(if lhs >= rhs { 1 } else { 0 }),
+            Operator::EqualEqual => Ok(if lhs == rhs { 1 } else { 0 }),
+            Operator::NotEqual => Ok(if lhs != rhs { 1 } else { 0 }),
+        };
+        value.map(Value::Integer)
+    }
     fn new(operator: Operator, lhs: Expression, rhs: Expression) -> Self {
         Self {
-            operator: operator,
-            lhs: lhs,
-            rhs: rhs,
+            operator,
+            lhs,
+            rhs,
+            span: Cell::new(None),
         }
     }
 }
 
 pub struct IntegerLiteral {
-    value: i32,
+    pub value: i32,
+    pub span: Cell<Option<Span>>,
 }
 impl ExpressionTrait for IntegerLiteral {
-    fn eval(&self, _v: &Rc<Environment>, _f: &HashMap<String, &FunctionDefinition>) -> i32 {
-        self.value
+    fn node(&self) -> Node<'_> {
+        Node::Integer(self)
+    }
+    fn span_cell(&self) -> &Cell<Option<Span>> {
+        &self.span
+    }
+    fn eval(&self, _v: &Rc<Environment>, _f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
+        Ok(Value::Integer(self.value))
     }
 }
 impl IntegerLiteral {
     fn new(value: i32) -> Self {
         Self {
-            value: value,
+            value,
+            span: Cell::new(None),
         }
     }
 }
 
 pub struct Assignment {
-    name: String,
-    expression: Expression,
+    pub name: String,
+    pub expression: Expression,
+    pub slot: Cell<Option<Slot>>,
+    pub span: Cell<Option<Span>>,
 }
 impl ExpressionTrait for Assignment {
-    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> i32 {
-        let value = self.expression.eval(v, f);
-        v.bindings.borrow_mut().insert(self.name.clone(), value);
-        value
+    fn node(&self) -> Node<'_> {
+        Node::Assignment(self)
+    }
+    fn span_cell(&self) -> &Cell<Option<Span>> {
+        &self.span
+    }
+    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
+        let value = self.expression.eval(v, f)?;
+        if let Some(hook) = current_hook() {
+            hook.assign(self, v.get(self.resolved_slot()), &value);
+        }
+        v.set(self.resolved_slot(), value.clone());
+        Ok(value)
     }
 }
 impl Assignment {
     fn new(name: String, expression: Expression) -> Self {
         Self {
-            name: name,
-            expression: expression,
+            name,
+            expression,
+            slot: Cell::new(None),
+            span: Cell::new(None),
         }
     }
+    fn resolved_slot(&self) -> Slot {
+        self.slot.get().unwrap_or_else(|| panic!("unresolved variable: {}", self.name))
+    }
 }
 
 pub struct Identifier {
-    name: String,
+    pub name: String,
+    pub slot: Cell<Option<Slot>>,
+    pub dynamic: Cell<bool>, // 代入される前に読むかもしれない変数。slot はグローバル変数で、先に関数と呼び出し元の環境を名前で探す
+    pub span: Cell<Option<Span>>,
 }
 impl ExpressionTrait for Identifier {
-    fn eval(&self, v: &Rc<Environment>, _f: &HashMap<String, &FunctionDefinition>) -> i32 {
-        let bindings_opt = v.find_binding(&self.name);
-        bindings_opt
-            .expect(&format!("undefinend variable: {}", &self.name))
-            .borrow().get(&self.name).unwrap().clone()
+    fn node(&self) -> Node<'_> {
+        Node::Identifier(self)
+    }
+    fn span_cell(&self) -> &Cell<Option<Span>> {
+        &self.span
+    }
+    fn eval(&self, v: &Rc<Environment>, _f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
+        let slot = self.slot.get().unwrap_or_else(|| panic!("unresolved variable: {}", self.name));
+        let value = if self.dynamic.get() { v.find(&self.name, slot) } else { v.get(slot) };
+        value.ok_or_else(|| RuntimeError::new(ErrorKind::UndefinedVariable, format!("undefined variable: {}", &self.name)).with_span(self.span()))
     }
 }
 impl Identifier {
     fn new(name: String) -> Self {
         Self {
-            name: name,
+            name,
+            slot: Cell::new(None),
+            dynamic: Cell::new(false),
+            span: Cell::new(None),
         }
     }
 }
 
 pub struct BlockExpression {
-    elements: LinkedList<Expression>,
+    pub elements: LinkedList<Expression>,
+    pub span: Cell<Option<Span>>,
 }
 impl ExpressionTrait for BlockExpression {
-    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> i32 {
-        let mut value = 0;
+    fn node(&self) -> Node<'_> {
+        Node::Block(self)
+    }
+    fn span_cell(&self) -> &Cell<Option<Span>> {
+        &self.span
+    }
+    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
+        let hook = current_hook();
+        let mut value = Value::Integer(0);
         for e in self.elements.iter() {
-            value = e.eval(v, f);
+            if let Some(hook) = &hook {
+                hook.statement(e.as_ref(), v, f)?;
+            }
+            value = e.eval(v, f)?;
         }
-        value
+        Ok(value)
     }
 }
 impl BlockExpression {
     fn new(elements: LinkedList<Expression>) -> Self {
         Self {
-            elements: elements,
+            elements,
+            span: Cell::new(None),
         }
     }
 }
 
 pub struct WhileExpression {
-    condition: Expression,
-    body: Expression,
+    pub condition: Expression,
+    pub body: Expression,
+    pub span: Cell<Option<Span>>,
 }
 impl ExpressionTrait for WhileExpression {
-    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> i32 {
+    fn node(&self) -> Node<'_> {
+        Node::While(self)
+    }
+    fn span_cell(&self) -> &Cell<Option<Span>> {
+        &self.span
+    }
+    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
+        let hook = current_hook();
+        let mut count = 0;
         loop {
-            let condition = self.condition.eval(v, f);
+            let condition = self.condition.eval(v, f)?.integer(|| "condition of while".to_string())?;
             if condition != 0 {
-                self.body.eval(v, f);
+                count += 1;
+                if let Some(hook) = &hook {
+                    hook.iteration(self, count);
+                }
+                self.body.eval(v, f)?;
             } else {
                 break;
             }
         }
-        1
+        Ok(Value::Integer(1))
     }
 }
 impl WhileExpression {
     fn new(condition: Expression, body: Expression) -> Self {
         Self {
-            condition: condition,
-            body: body,
+            condition,
+            body,
+            span: Cell::new(None),
         }
     }
 }
 
 pub struct IfExpression {
-    condition: Expression,
-    then_clause: Expression,
-    else_clause: Option<Expression>,
+    pub condition: Expression,
+    pub then_clause: Expression,
+    pub else_clause: Option<Expression>,
+    pub span: Cell<Option<Span>>,
 }
 impl ExpressionTrait for IfExpression {
-    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> i32 {
-        let condition: i32 = self.condition.eval(v, f);
+    fn node(&self) -> Node<'_> {
+        Node::If(self)
+    }
+    fn span_cell(&self) -> &Cell<Option<Span>> {
+        &self.span
+    }
+    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
+        let condition: i32 = self.condition.eval(v, f)?.integer(|| "condition of if".to_string())?;
+        if let Some(hook) = current_hook() {
+            hook.branch(self, condition, condition != 0);
+        }
         if condition != 0 {
             self.then_clause.eval(v, f)
         } else {
             match &self.else_clause {
                 Some(u) => u.eval(v, f),
-                None => 1,
+                None => Ok(Value::Integer(1)),
             }
         }
     }
@@ -282,171 +514,389 @@ impl ExpressionTrait for IfExpression {
 impl IfExpression {
     fn new(condition: Expression, then_clause: Expression, else_clause: Option<Expression>) -> Self {
         Self {
-            condition: condition,
-            then_clause: then_clause,
-            else_clause: else_clause,
+            condition,
+            then_clause,
+            else_clause,
+            span: Cell::new(None),
         }
     }
 }
 
 pub struct PrintlnExpression {
-    body: Expression
+    pub body: Expression,
+    pub span: Cell<Option<Span>>,
 }
 impl ExpressionTrait for PrintlnExpression {
-    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> i32 {
-        println!("{}", self.body.eval(v, f));
-        0
+    fn node(&self) -> Node<'_> {
+        Node::Println(self)
+    }
+    fn span_cell(&self) -> &Cell<Option<Span>> {
+        &self.span
+    }
+    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
+        println!("{}", self.body.eval(v, f)?);
+        Ok(Value::Integer(0))
     }
 }
 impl PrintlnExpression {
     fn new(body: Expression) -> Self {
         Self {
-            body: body,
+            body,
+            span: Cell::new(None),
         }
     }
 }
 
-pub struct Environment {
-    bindings: Rc<RefCell<HashMap<String, i32>>>,
-    next: Option<Rc<Environment>>, //一つ上の呼び出し元の環境
+// ソース上の位置。start と end はバイト単位のオフセットで、line と column は start の位置を表す
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
 }
-impl ExpressionTrait for Environment {
-    fn eval(&self, _v: &Rc<Environment>, _f: &HashMap<String, &FunctionDefinition>) -> i32 {
-        0
-    }
+
+// 変数の格納場所。depth は環境をいくつたどるか（関数内では 0 がローカル、1 がグローバル）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+pub struct Environment {
+    bindings: RefCell<Vec<Option<Value>>>,
+    next: Option<Rc<Environment>>, //グローバル環境
+    caller: Option<Rc<Environment>>, // 呼び出し元の環境
+    names: Option<Rc<RefCell<Vec<String>>>>, // 関数の環境では各スロットの変数の名前
 }
 impl Environment {
     pub fn new() -> Rc<Self> {
         Rc::new(Self {
-            bindings: Rc::new(RefCell::new(HashMap::new())),
+            bindings: RefCell::new(Vec::new()),
             next: None,
+            caller: None,
+            names: None,
         })
     }
-    pub fn find_binding(&self, name: &String) -> Option<Rc<RefCell<HashMap<String, i32>>>> {
-        match self.bindings.borrow().get(name) {
-            Some(_) => Some(Rc::clone(&self.bindings)),
-            _ => {
-                match &self.next {
-                    Some(y) => {
-                        y.find_binding(name)
-                    },
-                    None => None,
-                }
+    // 整数はコピーで読み、レコードなどの値だけ Rc を複製する
+    pub fn get(&self, slot: Slot) -> Option<Value> {
+        match self.frame(slot.depth).bindings.borrow().get(slot.index) {
+            Some(Some(Value::Integer(x))) => Some(Value::Integer(*x)),
+            Some(Some(x)) => Some(x.clone()),
+            _ => None,
+        }
+    }
+    pub fn set(&self, slot: Slot, value: Value) {
+        let mut bindings = self.frame(slot.depth).bindings.borrow_mut();
+        if bindings.len() <= slot.index {
+            bindings.resize(slot.index + 1, None);
+        }
+        // 整数を整数で上書きするときは、古い値を捨てる処理を省く
+        match (&mut bindings[slot.index], value) {
+            (Some(Value::Integer(x)), Value::Integer(y)) => *x = y,
+            (binding, value) => *binding = Some(value),
+        }
+    }
+    // slot の値を入れ替えて前の値を返す。None なら代入前に戻す
+    pub fn replace(&self, slot: Slot, value: Option<Value>) -> Option<Value> {
+        let mut bindings = self.frame(slot.depth).bindings.borrow_mut();
+        if bindings.len() <= slot.index {
+            bindings.resize(slot.index + 1, None);
+        }
+        std::mem::replace(&mut bindings[slot.index], value)
+    }
+    // この環境と呼び出し元の関数の環境を内側から順に名前で探し、どこにもなければ slot のグローバル変数の値
+    pub fn find(&self, name: &str, slot: Slot) -> Option<Value> {
+        let mut environment = Some(self);
+        while let Some(x) = environment {
+            let index = x.names.as_ref().and_then(|y| y.borrow().iter().position(|z| z == name));
+            if let Some(value) = index.and_then(|y| x.bindings.borrow()[y].clone()) {
+                return Some(value);
             }
+            environment = x.caller.as_deref();
+        }
+        self.get(slot)
+    }
+    // 各スロットの値（代入前の変数は None）
+    pub fn bindings(&self) -> Vec<Option<Value>> {
+        self.bindings.borrow().clone()
+    }
+    pub fn next(&self) -> Option<&Rc<Environment>> {
+        self.next.as_ref()
+    }
+    fn frame(&self, depth: usize) -> &Environment {
+        let mut environment = self;
+        for _ in 0..depth {
+            environment = environment.next.as_ref().expect("environment is too shallow");
+        }
+        environment
+    }
+    fn global(self: &Rc<Self>) -> Rc<Environment> {
+        match &self.next {
+            Some(x) => x.global(),
+            None => Rc::clone(self),
         }
     }
-
 }
 
 pub trait TopLevelTrait {
+    fn definition(&self) -> Definition<'_>;
     fn eval<'a>(
         &'a self,
         _variable_environment: &Rc<Environment>,
         _function_environment: &mut HashMap<String, &'a FunctionDefinition>
-    ) -> i32 {
-        0
+    ) -> Result<i32, RuntimeError> {
+        Ok(0)
+    }
+    fn doc(&self) -> Option<&str> {
+        None
     }
 }
 
 pub struct FunctionDefinition {
     pub name: String,
-    args: LinkedList<String>,
+    pub args: LinkedList<String>,
     pub body: Expression,
+    pub doc: Option<String>,
+    pub parameter_types: Vec<Option<String>>, // 仮引数の型注釈（args と同じ順）。注釈がなければ None
+    pub return_type: Option<String>,
+    pub locals: Rc<RefCell<Vec<String>>>, // 仮引数を先頭に並べたローカル変数
+    pub global_parameters: RefCell<Vec<Slot>>, // main の仮引数を割り当てたグローバル変数
+    pub span: Option<Span>,
 }
 impl TopLevelTrait for FunctionDefinition {
-    fn eval<'a>(&'a self, _v: &Rc<Environment>, f: &mut HashMap<String,  &'a FunctionDefinition>) -> i32 {
+    fn definition(&self) -> Definition<'_> {
+        Definition::Function(self)
+    }
+    fn eval<'a>(&'a self, _v: &Rc<Environment>, f: &mut HashMap<String,  &'a FunctionDefinition>) -> Result<i32, RuntimeError> {
         f.insert(
             self.name.clone(),
             self,
         );
-        0
+        Ok(0)
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
     }
 }
 impl FunctionDefinition {
     pub fn new(name: String, args: LinkedList<String>, body: Expression) -> Self {
         Self {
-            name: name,
-            args: args,
-            body: body,
+            name,
+            args,
+            body,
+            doc: None,
+            parameter_types: Vec::new(),
+            return_type: None,
+            locals: Rc::new(RefCell::new(Vec::new())),
+            global_parameters: RefCell::new(Vec::new()),
+            span: None,
+        }
+    }
+    // 型注釈を含めた `f(n: Int, s): Int` の形
+    pub fn signature(&self) -> String {
+        let parameters: Vec<String> = self.args.iter().enumerate()
+            .map(|(i, x)| annotated(x, self.parameter_types.get(i).and_then(|y| y.as_deref())))
+            .collect();
+        annotated(&format!("{}({})", self.name, parameters.join(", ")), self.return_type.as_deref())
+    }
+    // main の本体はグローバル環境で実行される
+    pub fn is_main(&self) -> bool {
+        self.name == "main"
+    }
+    // 本体を評価する環境から仮引数の値を読み出す
+    pub fn arguments(&self, environment: &Environment) -> Vec<(String, Option<Value>)> {
+        if self.is_main() {
+            let slots = self.global_parameters.borrow();
+            self.args.iter().zip(slots.iter()).map(|(name, slot)| (name.clone(), environment.get(*slot))).collect()
+        } else {
+            self.args.iter().zip(environment.bindings()).map(|(name, value)| (name.clone(), value)).collect()
         }
     }
+    pub fn call(&self, values: Vec<Value>, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
+        if values.len() != self.args.len() {
+            return Err(RuntimeError::new(
+                ErrorKind::ArityMismatch,
+                format!("function {} expects {} arguments but {} given.", self.name, self.args.len(), values.len())
+            ));
+        }
+        for ((name, expected), value) in self.args.iter().zip(self.parameter_types.iter()).zip(values.iter()) {
+            check_type(expected.as_deref(), value, || format!("argument {} of function {}", name, self.name))?;
+        }
+        let global = v.global();
+        let environment = if self.is_main() {
+            for (slot, value) in self.global_parameters.borrow().iter().zip(values) {
+                global.set(*slot, value);
+            }
+            global
+        } else {
+            let mut bindings: Vec<Option<Value>> = values.into_iter().map(Some).collect();
+            bindings.resize(self.locals.borrow().len(), None);
+            Rc::new(Environment {
+                bindings: RefCell::new(bindings),
+                next: Some(global),
+                caller: Some(Rc::clone(v)),
+                names: Some(Rc::clone(&self.locals)),
+            })
+        };
+        let result = match current_hook() {
+            Some(hook) => {
+                hook.enter(self, &environment);
+                let result = self.body.eval(&environment, f);
+                hook.leave(self, &result, f);
+                result
+            },
+            None => self.body.eval(&environment, f),
+        };
+        let result = result.map_err(|e| self.unwound(e))?;
+        check_type(self.return_type.as_deref(), &result, || format!("return value of function {}", self.name))?;
+        Ok(result)
+    }
+    // 本体で起きたエラーにこの関数を通り抜けたことを記録する。呼び出し履歴はエラーが戻るときにだけ作る
+    #[cold]
+    fn unwound(&self, mut e: RuntimeError) -> RuntimeError {
+        e.trace.push(Frame { function: self.name.clone(), call: None });
+        e
+    }
+}
+
+// 呼び出した関数から返ってきたエラーに、呼び出した位置を記録する。
+// 本体に入る前のエラー（引数の数や型の誤り）は呼び出しの位置で起きたことにする
+#[cold]
+fn called(mut e: RuntimeError, span: Option<Span>) -> RuntimeError {
+    match e.trace.last_mut() {
+        Some(frame) if frame.call.is_none() => frame.call = span,
+        _ => e.span = e.span.or(span),
+    }
+    e
+}
+
+// 型注釈の付いた名前
+pub fn annotated(name: &str, annotation: Option<&str>) -> String {
+    match annotation {
+        Some(x) => format!("{}: {}", name, x),
+        None => name.to_string(),
+    }
+}
+
+// 型注釈を実行時に確かめる（漸進的型付け）。注釈は値の型の名前（Int かレコードの名前）と比べる
+fn check_type(expected: Option<&str>, value: &Value, target: impl Fn() -> String) -> Result<(), RuntimeError> {
+    match expected {
+        Some(x) if x != value.type_name() => Err(RuntimeError::new(
+            ErrorKind::TypeMismatch,
+            format!("type mismatch: {} must be {} but {} given.", target(), x, value.type_name())
+        )),
+        _ => Ok(()),
+    }
+}
+
+// 実引数は呼び出し元の環境にも仮引数の名前で代入される。呼び出された関数からも、
+// 呼び出し元で後から読んでも見える
+fn bind_parameters(parameters: &RefCell<Vec<Slot>>, values: &[Value], v: &Environment) {
+    for (slot, value) in parameters.borrow().iter().zip(values.iter()) {
+        v.set(*slot, value.clone());
+    }
 }
 
 pub struct GlobalVariableDefinition {
-    name: String,
-    body: Expression,
+    pub name: String,
+    pub body: Expression,
+    pub doc: Option<String>,
+    pub type_annotation: Option<String>,
+    pub slot: Cell<Option<Slot>>,
+    pub span: Option<Span>,
 }
 impl TopLevelTrait for GlobalVariableDefinition {
-    fn eval(&self, v: &Rc<Environment>, f: &mut HashMap<String, &FunctionDefinition>) -> i32 {
-        v.bindings.borrow_mut().insert(
-            self.name.clone(),
-            self.body.eval(v, f)
-        );
-        0
+    fn definition(&self) -> Definition<'_> {
+        Definition::Global(self)
+    }
+    fn eval(&self, v: &Rc<Environment>, f: &mut HashMap<String, &FunctionDefinition>) -> Result<i32, RuntimeError> {
+        let slot = self.slot.get().unwrap_or_else(|| panic!("unresolved variable: {}", self.name));
+        let value = self.body.eval(v, f)?;
+        check_type(self.type_annotation.as_deref(), &value, || format!("global {}", self.name)).map_err(|e| e.with_span(self.span))?;
+        v.set(slot, value);
+        Ok(0)
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
     }
 }
 impl GlobalVariableDefinition {
     pub fn new(name: String, body: Expression) -> Self {
         Self {
-            name: name,
-            body: body,
+            name,
+            body,
+            doc: None,
+            type_annotation: None,
+            slot: Cell::new(None),
+            span: None,
         }
     }
 }
 
 pub struct FunctionCall {
-    name: String,
-    args: LinkedList<Expression>,
+    pub name: String,
+    pub args: LinkedList<Expression>,
+    pub parameters: RefCell<Vec<Slot>>, // 実引数を束縛する呼び出し元の変数（仮引数の順）
+    pub span: Cell<Option<Span>>,
 }
 impl ExpressionTrait for FunctionCall {
-    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> i32 {
+    fn node(&self) -> Node<'_> {
+        Node::Call(self)
+    }
+    fn span_cell(&self) -> &Cell<Option<Span>> {
+        &self.span
+    }
+    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
         let definition = f.get(&self.name);
         match definition {
             Some(x) => {
-                let formal_params = &x.args;
-                let actual_params = &self.args;
-                let body = &x.body;
-                let values: LinkedList<i32> = actual_params.iter().map(|x| x.eval(v, f)).collect();
-                let mut values_iter = values.into_iter();
-                for formal_param_name in formal_params {
-                    v.bindings.borrow_mut().insert(formal_param_name.clone(), values_iter.next().unwrap());
-                }
-                let result = body.eval(&new_environment(v), f);
-                result
+                let values: Vec<Value> = self.args.iter().map(|x| x.eval(v, f)).collect::<Result<_, _>>()?;
+                bind_parameters(&self.parameters, &values, v);
+                x.call(values, v, f).map_err(|e| called(e, self.span()))
             },
-            None => panic!("function {} not found.", self.name),
+            None => Err(RuntimeError::new(ErrorKind::UndefinedFunction, format!("function {} not found.", self.name)).with_span(self.span())),
         }
     }
 }
 impl FunctionCall {
     fn new(name: String, args: LinkedList<Expression>) -> Self {
         Self {
-            name: name,
-            args: args,
+            name,
+            args,
+            parameters: RefCell::new(Vec::new()),
+            span: Cell::new(None),
         }
     }
 }
 
 pub struct LabelledParameter {
-    name: String,
-    parameter: Expression,
+    pub name: String,
+    pub parameter: Expression,
 }
-impl ExpressionTrait for LabelledParameter {}
 impl LabelledParameter {
     fn new(name: String, parameter: Expression) -> Self {
         Self {
-            name: name,
-            parameter: parameter,
+            name,
+            parameter,
         }
     }    
 }
 
 pub struct LabelledCall {
-    name: String,
-    args: LinkedList<LabelledParameter>,
+    pub name: String,
+    pub args: LinkedList<LabelledParameter>,
+    pub parameters: RefCell<Vec<Slot>>, // 実引数を束縛する呼び出し元の変数（仮引数の順）
+    pub span: Cell<Option<Span>>,
 }
 impl ExpressionTrait for LabelledCall {
-    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> i32 {
+    fn node(&self) -> Node<'_> {
+        Node::LabelledCall(self)
+    }
+    fn span_cell(&self) -> &Cell<Option<Span>> {
+        &self.span
+    }
+    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
         let definition = f.get(&self.name);
         match definition {
             Some(x) => {
@@ -454,38 +904,550 @@ impl ExpressionTrait for LabelledCall {
                 let mut mapping: HashMap<&String, &Expression> = HashMap::new();
                 labels.iter().for_each(|x| { mapping.insert(&x.name, &x.parameter); });
 
-                let formal_params = &x.args;
-                let mut actual_params = LinkedList::new();
-                formal_params.iter().for_each(|x| actual_params.push_back(mapping.get(x).unwrap()));
-                let body = &x.body;
-                let values: LinkedList<i32> = actual_params.iter().map(|x| x.eval(v, f)).collect();
-                let mut values_iter = values.into_iter();
-                for formal_param_name in formal_params {
-                    v.bindings.borrow_mut().insert(formal_param_name.clone(), values_iter.next().unwrap());
+                let mut values = Vec::new();
+                for formal_param in x.args.iter() {
+                    let parameter = mapping.get(formal_param).ok_or_else(|| RuntimeError::new(
+                        ErrorKind::MissingArgument,
+                        format!("argument {} of function {} is not given.", formal_param, self.name)
+                    ).with_span(self.span()))?;
+                    values.push(parameter.eval(v, f)?);
                 }
-                let result = body.eval(&new_environment(v), f);
-                result
+                bind_parameters(&self.parameters, &values, v);
+                x.call(values, v, f).map_err(|e| called(e, self.span()))
             },
-            None => panic!("function {} not found.", self.name),
+            None => Err(RuntimeError::new(ErrorKind::UndefinedFunction, format!("function {} not found.", self.name)).with_span(self.span())),
         }
     }
 }
 impl LabelledCall {
     fn new(name: String, args: LinkedList<LabelledParameter>) -> Self {
         Self {
-            name: name,
-            args: args,
+            name,
+            args,
+            parameters: RefCell::new(Vec::new()),
+            span: Cell::new(None),
+        }
+    }
+}
+
+// env("NAME") は環境変数を整数として読む。省略値がなければ未設定のときはエラーになる
+pub struct EnvironmentVariable {
+    pub name: String,
+    pub default: Option<Expression>,
+    pub span: Cell<Option<Span>>,
+}
+impl ExpressionTrait for EnvironmentVariable {
+    fn node(&self) -> Node<'_> {
+        Node::EnvironmentVariable(self)
+    }
+    fn span_cell(&self) -> &Cell<Option<Span>> {
+        &self.span
+    }
+    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
+        match (std::env::var(&self.name), &self.default) {
+            (Ok(value), _) => value.trim().parse().map(Value::Integer).map_err(|_| RuntimeError::new(
+                ErrorKind::InvalidEnvironmentVariable,
+                format!("environment variable {} is not an integer: {}", self.name, value)
+            )),
+            (Err(_), Some(default)) => default.eval(v, f),
+            (Err(_), None) => Err(RuntimeError::new(
+                ErrorKind::InvalidEnvironmentVariable,
+                format!("environment variable {} is not set.", self.name)
+            )),
+        }
+    }
+}
+impl EnvironmentVariable {
+    fn new(name: String, default: Option<Expression>) -> Self {
+        Self {
+            name,
+            default,
+            span: Cell::new(None),
+        }
+    }
+}
+
+// assert(x) は x が 0 のとき、assert_eq(a, b) は a と b が異なるときに失敗する
+pub struct AssertExpression {
+    pub left: Expression,
+    pub right: Option<Expression>, // assert_eq のときだけある
+    pub span: Cell<Option<Span>>,
+}
+impl ExpressionTrait for AssertExpression {
+    fn node(&self) -> Node<'_> {
+        Node::Assert(self)
+    }
+    fn span_cell(&self) -> &Cell<Option<Span>> {
+        &self.span
+    }
+    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
+        let left = self.left.eval(v, f)?;
+        let message = match &self.right {
+            Some(right) => {
+                let right = right.eval(v, f)?;
+                if left == right {
+                    return Ok(Value::Integer(1));
+                }
+                format!("assertion failed: left == right\n  left: {}\n right: {}", left, right)
+            },
+            None if left.integer(|| "argument of assert".to_string())? != 0 => return Ok(Value::Integer(1)),
+            None => "assertion failed".to_string(),
+        };
+        Err(RuntimeError::new(ErrorKind::AssertionFailed, message).with_span(self.span()))
+    }
+}
+impl AssertExpression {
+    fn new(left: Expression, right: Option<Expression>) -> Self {
+        Self {
+            left,
+            right,
+            span: Cell::new(None),
+        }
+    }
+}
+
+// match の腕のパターン。| で並べられるのは整数だけで、束縛は値を変数に代入してから腕を選ぶ。
+// Constructor は列挙型のバリアントで、引数の値をそれぞれのパターンと比べる
+pub enum Pattern {
+    Integers(Vec<i32>),
+    Binding { name: String, slot: Cell<Option<Slot>> },
+    Wildcard,
+    Constructor { name: String, arguments: Vec<Pattern> },
+}
+impl Pattern {
+    pub fn binding(name: String) -> Self {
+        Pattern::Binding { name, slot: Cell::new(None) }
+    }
+    // ガードがなければどんな値にも合う
+    pub fn is_irrefutable(&self) -> bool {
+        matches!(self, Pattern::Binding { .. } | Pattern::Wildcard)
+    }
+    // パターンの中で代入される変数（左から順に）
+    pub fn bindings<'a>(&'a self, names: &mut Vec<&'a String>) {
+        match self {
+            Pattern::Binding { name, .. } => names.push(name),
+            Pattern::Constructor { arguments, .. } => arguments.iter().for_each(|x| x.bindings(names)),
+            Pattern::Integers(_) | Pattern::Wildcard => {},
+        }
+    }
+    // value が合えば true。束縛の変数とその値を bindings に集め、変数にはまだ代入しない
+    fn bind(&self, value: &Value, bindings: &mut Vec<(Slot, Value)>) -> bool {
+        match self {
+            Pattern::Integers(x) => matches!(value, Value::Integer(n) if x.contains(n)),
+            Pattern::Binding { name, slot } => {
+                bindings.push((slot.get().unwrap_or_else(|| panic!("unresolved variable: {}", name)), value.clone()));
+                true
+            },
+            Pattern::Wildcard => true,
+            Pattern::Constructor { name, arguments } => match value {
+                Value::Variant(x) if &x.name == name && x.values.len() == arguments.len() => {
+                    arguments.iter().zip(x.values.iter()).all(|(pattern, value)| pattern.bind(value, bindings))
+                },
+                _ => false,
+            },
+        }
+    }
+}
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Integers(x) => {
+                let values: Vec<String> = x.iter().map(|y| y.to_string()).collect();
+                write!(f, "{}", values.join(" | "))
+            },
+            Pattern::Binding { name, .. } => write!(f, "{}", name),
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Constructor { name, arguments } if arguments.is_empty() => write!(f, "{}", name),
+            Pattern::Constructor { name, arguments } => {
+                let arguments: Vec<String> = arguments.iter().map(|x| x.to_string()).collect();
+                write!(f, "{}({})", name, arguments.join(", "))
+            },
+        }
+    }
+}
+
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expression>,
+    pub body: Expression,
+}
+
+// match (値) { パターン if ガード => 本体, ... }。上の腕から順に試し、どの腕にも合わなければエラーになる
+pub struct MatchExpression {
+    pub value: Expression,
+    pub arms: Vec<MatchArm>,
+    pub span: Cell<Option<Span>>,
+}
+impl ExpressionTrait for MatchExpression {
+    fn node(&self) -> Node<'_> {
+        Node::Match(self)
+    }
+    fn span_cell(&self) -> &Cell<Option<Span>> {
+        &self.span
+    }
+    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
+        let value = self.value.eval(v, f)?;
+        for arm in self.arms.iter() {
+            let mut bindings = Vec::new();
+            if !arm.pattern.bind(&value, &mut bindings) {
+                continue;
+            }
+            // ガードは束縛した値で評価し、偽かエラーなら変数を match の前の値に戻す
+            let saved: Vec<(Slot, Option<Value>)> = bindings.into_iter().map(|(slot, x)| (slot, v.replace(slot, Some(x)))).collect();
+            if let Some(guard) = &arm.guard {
+                let passed = guard.eval(v, f).and_then(|x| x.integer(|| "guard of match".to_string()));
+                if passed.as_ref().map_or(true, |x| *x == 0) {
+                    for (slot, x) in saved.into_iter().rev() {
+                        v.replace(slot, x);
+                    }
+                    passed?;
+                    continue;
+                }
+            }
+            return arm.body.eval(v, f);
+        }
+        Err(RuntimeError::new(ErrorKind::NoMatch, format!("no match arm for value {}", value)).with_span(self.span()))
+    }
+}
+impl MatchExpression {
+    // 腕のパターンで代入される変数の名前
+    pub fn bindings(&self) -> Vec<&String> {
+        let mut names = Vec::new();
+        for arm in self.arms.iter() {
+            arm.pattern.bindings(&mut names);
+        }
+        names
+    }
+    fn new(value: Expression, arms: Vec<MatchArm>) -> Self {
+        Self {
+            value,
+            arms,
+            span: Cell::new(None),
+        }
+    }
+}
+
+// throw 式; は値を投げ、try の catch まで戻る
+pub struct ThrowExpression {
+    pub value: Expression,
+    pub span: Cell<Option<Span>>,
+}
+impl ExpressionTrait for ThrowExpression {
+    fn node(&self) -> Node<'_> {
+        Node::Throw(self)
+    }
+    fn span_cell(&self) -> &Cell<Option<Span>> {
+        &self.span
+    }
+    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
+        let value = self.value.eval(v, f)?;
+        Err(RuntimeError::thrown(value).with_span(self.span()))
+    }
+}
+impl ThrowExpression {
+    fn new(value: Expression) -> Self {
+        Self {
+            value,
+            span: Cell::new(None),
+        }
+    }
+}
+
+// catch (e) { } の e には throw された値か、インタプリタのエラーを表す Error レコードが代入される
+pub struct CatchClause {
+    pub name: String,
+    pub slot: Cell<Option<Slot>>,
+    pub body: Expression,
+}
+
+// try { } catch (e) { } finally { } の値は、本体か catch の本体の値。finally の本体は必ず最後に評価する
+pub struct TryExpression {
+    pub body: Expression,
+    pub catch_clause: Option<CatchClause>,
+    pub finally_clause: Option<Expression>,
+    pub span: Cell<Option<Span>>,
+}
+impl ExpressionTrait for TryExpression {
+    fn node(&self) -> Node<'_> {
+        Node::Try(self)
+    }
+    fn span_cell(&self) -> &Cell<Option<Span>> {
+        &self.span
+    }
+    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
+        let result = match (self.body.eval(v, f), &self.catch_clause) {
+            (Err(e), Some(clause)) if e.is_catchable() => {
+                v.set(clause.slot.get().unwrap_or_else(|| panic!("unresolved variable: {}", clause.name)), e.to_value());
+                clause.body.eval(v, f)
+            },
+            (result, _) => result,
+        };
+        if let Some(finally_clause) = &self.finally_clause {
+            finally_clause.eval(v, f)?;
+        }
+        result
+    }
+}
+impl TryExpression {
+    fn new(body: Expression, catch_clause: Option<(String, Expression)>, finally_clause: Option<Expression>) -> Self {
+        Self {
+            body,
+            catch_clause: catch_clause.map(|(name, body)| CatchClause { name, slot: Cell::new(None), body }),
+            finally_clause,
+            span: Cell::new(None),
+        }
+    }
+}
+
+// p.x はレコードのフィールドの値
+pub struct FieldAccess {
+    pub value: Expression,
+    pub field: String,
+    pub span: Cell<Option<Span>>,
+}
+impl ExpressionTrait for FieldAccess {
+    fn node(&self) -> Node<'_> {
+        Node::Field(self)
+    }
+    fn span_cell(&self) -> &Cell<Option<Span>> {
+        &self.span
+    }
+    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
+        let value = self.value.eval(v, f)?;
+        field(&value, &self.field).map_err(|e| e.with_span(self.span()))
+    }
+}
+impl FieldAccess {
+    fn new(value: Expression, field: String) -> Self {
+        Self {
+            value,
+            field,
+            span: Cell::new(None),
+        }
+    }
+}
+
+fn field(value: &Value, name: &str) -> Result<Value, RuntimeError> {
+    let record = value.record(|| format!("value of .{}", name))?;
+    record.get(name).cloned().ok_or_else(|| undefined_field(record, name))
+}
+
+fn undefined_field(record: &Record, name: &str) -> RuntimeError {
+    RuntimeError::new(ErrorKind::UndefinedField, format!("record {} has no field {}.", record.name, name))
+}
+
+// p.x = 式; はレコードを書き換えず、x だけを置き換えたレコードを p に代入する。値は代入した値
+pub struct FieldAssignment {
+    pub name: String,
+    pub path: Vec<String>, // p.a.b なら [a, b]
+    pub expression: Expression,
+    pub slot: Cell<Option<Slot>>,
+    pub span: Cell<Option<Span>>,
+}
+impl ExpressionTrait for FieldAssignment {
+    fn node(&self) -> Node<'_> {
+        Node::FieldAssignment(self)
+    }
+    fn span_cell(&self) -> &Cell<Option<Span>> {
+        &self.span
+    }
+    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
+        let value = self.expression.eval(v, f)?;
+        let slot = self.slot.get().unwrap_or_else(|| panic!("unresolved variable: {}", self.name));
+        let current = v.get(slot).ok_or_else(|| RuntimeError::new(ErrorKind::UndefinedVariable, format!("undefined variable: {}", &self.name)))?;
+        let updated = replace_field(&current, &self.path, value.clone()).map_err(|e| e.with_span(self.span()))?;
+        v.set(slot, updated);
+        Ok(value)
+    }
+}
+impl FieldAssignment {
+    fn new(name: String, path: Vec<String>, expression: Expression) -> Self {
+        Self {
+            name,
+            path,
+            expression,
+            slot: Cell::new(None),
+            span: Cell::new(None),
+        }
+    }
+}
+
+fn replace_field(target: &Value, path: &[String], value: Value) -> Result<Value, RuntimeError> {
+    let (name, rest) = path.split_first().expect("field path is empty");
+    let record = target.record(|| format!("value of .{}", name))?;
+    let value = match rest {
+        [] => value,
+        _ => replace_field(&field(target, name)?, rest, value)?,
+    };
+    record.with(name, value).map(|x| Value::Record(Rc::new(x))).ok_or_else(|| undefined_field(record, name))
+}
+
+// record Point(x, y) は同じ名前のコンストラクタ関数を定義する。Point[x = 1, y = 2] や Point(1, 2) で作る
+pub struct RecordDefinition {
+    pub constructor: FunctionDefinition, // 仮引数がフィールド、仮引数の型注釈がフィールドの型注釈になる
+}
+impl TopLevelTrait for RecordDefinition {
+    fn definition(&self) -> Definition<'_> {
+        Definition::Record(self)
+    }
+    fn eval<'a>(&'a self, v: &Rc<Environment>, f: &mut HashMap<String, &'a FunctionDefinition>) -> Result<i32, RuntimeError> {
+        self.constructor.eval(v, f)
+    }
+    fn doc(&self) -> Option<&str> {
+        self.constructor.doc.as_deref()
+    }
+}
+impl RecordDefinition {
+    pub fn new(name: String, fields: LinkedList<String>) -> Self {
+        let body = Ast::construct(name.clone(), None);
+        Self {
+            constructor: FunctionDefinition::new(name, fields, body),
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.constructor.name
+    }
+    pub fn fields(&self) -> &LinkedList<String> {
+        &self.constructor.args
+    }
+}
+
+// enum Shape { Circle(r), Rect(w, h) } はバリアントごとに同じ名前のコンストラクタ関数を定義する。
+// 引数のないバリアントは None() のほか、同じファイルの中なら None とも書ける
+pub struct EnumDefinition {
+    pub name: String,
+    pub variants: Vec<FunctionDefinition>, // 仮引数がバリアントの持つ値になる
+    pub doc: Option<String>,
+    pub span: Option<Span>,
+}
+impl TopLevelTrait for EnumDefinition {
+    fn definition(&self) -> Definition<'_> {
+        Definition::Enum(self)
+    }
+    fn eval<'a>(&'a self, v: &Rc<Environment>, f: &mut HashMap<String, &'a FunctionDefinition>) -> Result<i32, RuntimeError> {
+        for variant in self.variants.iter() {
+            variant.eval(v, f)?;
+        }
+        Ok(0)
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+}
+impl EnumDefinition {
+    pub fn new(name: String, variants: Vec<(String, LinkedList<String>)>) -> Self {
+        let variants = variants.into_iter().map(|(variant, fields)| {
+            let body = Ast::construct(variant.clone(), Some(name.clone()));
+            FunctionDefinition::new(variant, fields, body)
+        }).collect();
+        Self {
+            name,
+            variants,
+            doc: None,
+            span: None,
+        }
+    }
+    // Shape { Circle(r), Rect(w, h), Empty }
+    pub fn signature(&self) -> String {
+        let variants: Vec<String> = self.variants.iter()
+            .map(|x| if x.args.is_empty() { x.name.clone() } else { x.signature() })
+            .collect();
+        format!("{} {{ {} }}", self.name, variants.join(", "))
+    }
+}
+
+// コンストラクタの本体。仮引数の値を順に並べて、enumeration がなければレコード、あればその列挙型の値にする
+pub struct Construction {
+    pub name: String,
+    pub enumeration: Option<String>,
+    pub span: Cell<Option<Span>>,
+}
+impl ExpressionTrait for Construction {
+    fn node(&self) -> Node<'_> {
+        Node::Construct(self)
+    }
+    fn span_cell(&self) -> &Cell<Option<Span>> {
+        &self.span
+    }
+    fn eval(&self, v: &Rc<Environment>, f: &HashMap<String, &FunctionDefinition>) -> Result<Value, RuntimeError> {
+        let definition = f.get(&self.name).unwrap_or_else(|| panic!("undefined constructor: {}", self.name));
+        let fields: Vec<(String, Value)> = definition.arguments(v).into_iter()
+            .map(|(name, value)| (name, value.expect("fields are bound to arguments")))
+            .collect();
+        Ok(match &self.enumeration {
+            Some(enumeration) => Value::Variant(Rc::new(Variant {
+                enumeration: enumeration.clone(),
+                name: self.name.clone(),
+                values: fields.into_iter().map(|x| x.1).collect(),
+            })),
+            None => Value::Record(Rc::new(Record { name: self.name.clone(), fields })),
+        })
+    }
+}
+impl Construction {
+    fn new(name: String, enumeration: Option<String>) -> Self {
+        Self {
+            name,
+            enumeration,
+            span: Cell::new(None),
+        }
+    }
+}
+
+// test "名前" { ... }。toys test で実行され、main からは呼ばれない
+pub struct TestDefinition {
+    pub name: String,
+    pub body: Expression,
+    pub span: Option<Span>,
+}
+impl TopLevelTrait for TestDefinition {
+    fn definition(&self) -> Definition<'_> {
+        Definition::Test(self)
+    }
+}
+impl TestDefinition {
+    pub fn new(name: String, body: Expression) -> Self {
+        Self {
+            name,
+            body,
+            span: None,
         }
     }
 }
 
 pub struct Program {
+    pub imports: LinkedList<Import>,
     pub definitions: LinkedList<TopLevel>
 }
 
-fn new_environment(e: &Rc<Environment>) -> Rc<Environment> {
-    Rc::new(Environment {
-        bindings: Rc::new(RefCell::new(HashMap::new())),
-        next: Some(Rc::clone(e)),
-    })
+#[derive(Debug, Clone, PartialEq)]
+pub enum Import {
+    Path(String),   // import "path/lib.toys";
+    Module(String), // import lib;
+}
+impl Import {
+    // 取り込んだ定義に付く `モジュール名.` の名前（ファイル名から拡張子を除いたもの）
+    pub fn module_name(&self) -> String {
+        match self {
+            Import::Path(x) => std::path::Path::new(x).file_stem().map_or(x.clone(), |y| y.to_string_lossy().to_string()),
+            Import::Module(x) => x.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommentKind {
+    Line,
+    Block,
+    Doc,
+}
+
+// ソース中のコメント。構文木からは捨てられるので、整形ツールなどのために別途保持する
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub kind: CommentKind,
+    pub text: String,
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
 }
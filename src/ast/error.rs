@@ -0,0 +1,126 @@
+use super::{Record, Span, Value, Variant};
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    UndefinedVariable,
+    UndefinedFunction,
+    ArityMismatch,
+    MissingArgument,
+    DivisionByZero,
+    Overflow,
+    MissingMain,
+    InvalidEnvironmentVariable,
+    Interrupted,
+    AssertionFailed,
+    TypeMismatch,
+    NoMatch,
+    UndefinedField,
+    Thrown, // throw で投げられ、catch されなかった値
+}
+impl ErrorKind {
+    // catch で Error レコードとして受け取れるエラーの種類。e.kind はこの名前のバリアントになる
+    pub const CATCHABLE: [ErrorKind; 11] = [
+        ErrorKind::UndefinedVariable,
+        ErrorKind::UndefinedFunction,
+        ErrorKind::ArityMismatch,
+        ErrorKind::MissingArgument,
+        ErrorKind::DivisionByZero,
+        ErrorKind::Overflow,
+        ErrorKind::InvalidEnvironmentVariable,
+        ErrorKind::AssertionFailed,
+        ErrorKind::TypeMismatch,
+        ErrorKind::NoMatch,
+        ErrorKind::UndefinedField,
+    ];
+    pub fn name(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+// エラーが通り抜けた関数の呼び出し。call はその関数を呼んだ式の位置
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub function: String,
+    pub call: Option<Span>,
+}
+
+// toys プログラムの実行時エラー。評価の結果の Result を小さく保つため、中身は Box に入れる
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError(Box<ErrorDetail>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorDetail {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub span: Option<Span>, // エラーが起きた式の位置（分かる場合）
+    pub value: Option<Value>, // throw で投げられた値
+    pub trace: Vec<Frame>,    // 内側の関数から順に
+}
+impl Deref for RuntimeError {
+    type Target = ErrorDetail;
+    fn deref(&self) -> &ErrorDetail {
+        &self.0
+    }
+}
+impl DerefMut for RuntimeError {
+    fn deref_mut(&mut self) -> &mut ErrorDetail {
+        &mut self.0
+    }
+}
+impl RuntimeError {
+    pub fn new(kind: ErrorKind, message: String) -> Self {
+        Self(Box::new(ErrorDetail {
+            kind,
+            message,
+            span: None,
+            value: None,
+            trace: Vec::new(),
+        }))
+    }
+    pub fn thrown(value: Value) -> Self {
+        let mut error = Self::new(ErrorKind::Thrown, format!("uncaught exception: {}", value));
+        error.value = Some(value);
+        error
+    }
+    pub fn with_span(mut self, span: Option<Span>) -> Self {
+        self.span = span;
+        self
+    }
+    // 位置がまだ分からなければ span にする
+    pub fn at(mut self, span: Option<Span>) -> Self {
+        self.span = self.span.or(span);
+        self
+    }
+    // デバッガによる中断などは catch しない
+    pub fn is_catchable(&self) -> bool {
+        self.kind == ErrorKind::Thrown || ErrorKind::CATCHABLE.contains(&self.kind)
+    }
+    // catch で受け取る値。throw された値はそのまま、それ以外は Error[kind = DivisionByZero, message = ...] にする
+    pub fn to_value(&self) -> Value {
+        if let Some(value) = &self.value {
+            return value.clone();
+        }
+        let kind = Variant { enumeration: "ErrorKind".to_string(), name: self.kind.name(), values: Vec::new() };
+        Value::Record(Rc::new(Record {
+            name: "Error".to_string(),
+            fields: vec![
+                ("kind".to_string(), Value::Variant(Rc::new(kind))),
+                ("message".to_string(), Value::String(Rc::new(self.message.clone()))),
+            ],
+        }))
+    }
+    // 内側から順に、関数の名前とその中でエラーが起きた（または次の関数を呼んだ）位置
+    pub fn stack_trace(&self) -> Vec<(&str, Option<Span>)> {
+        let locations = std::iter::once(self.span).chain(self.trace.iter().map(|x| x.call));
+        self.trace.iter().map(|x| x.function.as_str()).zip(locations).collect()
+    }
+}
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
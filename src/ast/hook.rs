@@ -0,0 +1,48 @@
+use super::{Assignment, Environment, ExpressionTrait, FunctionDefinition, IfExpression, RuntimeError, Value, WhileExpression};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// 実行中の各文や関数の出入りを外から観察するためのフック（デバッガなどが使う）
+pub trait Hook {
+    // ブロック中の各文を評価する直前に呼ばれる。エラーを返すと実行を打ち切る
+    fn statement(
+        &self,
+        _expression: &dyn ExpressionTrait,
+        _variable_environment: &Rc<Environment>,
+        _function_environment: &HashMap<String, &FunctionDefinition>
+    ) -> Result<(), RuntimeError> {
+        Ok(())
+    }
+    // 実引数を束縛した環境で関数の本体を評価する直前に呼ばれる（main ではグローバル環境）
+    fn enter(&self, _function: &FunctionDefinition, _variable_environment: &Rc<Environment>) {}
+    fn leave(
+        &self,
+        _function: &FunctionDefinition,
+        _result: &Result<Value, RuntimeError>,
+        _function_environment: &HashMap<String, &FunctionDefinition>
+    ) {}
+    // old は代入前の値（初めての代入なら None）
+    fn assign(&self, _expression: &Assignment, _old: Option<Value>, _new: &Value) {}
+    // taken は then 節を評価するかどうか
+    fn branch(&self, _expression: &IfExpression, _condition: i32, _taken: bool) {}
+    // 本体を評価する直前に、1 から数えた繰り返しの回数とともに呼ばれる
+    fn iteration(&self, _expression: &WhileExpression, _count: usize) {}
+}
+
+thread_local! {
+    static HOOK: RefCell<Option<Rc<dyn Hook>>> = RefCell::new(None);
+}
+
+// f を実行している間だけフックを有効にする
+pub fn with_hook<T>(hook: Rc<dyn Hook>, f: impl FnOnce() -> T) -> T {
+    let previous = HOOK.with(|x| x.borrow_mut().replace(hook));
+    let result = f();
+    HOOK.with(|x| *x.borrow_mut() = previous);
+    result
+}
+
+pub(crate) fn current_hook() -> Option<Rc<dyn Hook>> {
+    HOOK.with(|x| x.borrow().clone())
+}
@@ -1,3 +1,6 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Operator {
     ADD,
     SUBTRACT,
@@ -10,3 +13,37 @@ pub enum Operator {
     EqualEqual,
     NotEqual,
 }
+impl Operator {
+    pub fn from_symbol(symbol: &str) -> Option<Operator> {
+        match symbol {
+            "+" => Some(Operator::ADD),
+            "-" => Some(Operator::SUBTRACT),
+            "*" => Some(Operator::MULTIPLY),
+            "/" => Some(Operator::DIVIDE),
+            "<" => Some(Operator::LessThan),
+            "<=" => Some(Operator::LessOrEqual),
+            ">" => Some(Operator::GreaterThan),
+            ">=" => Some(Operator::GreaterOrEqual),
+            "==" => Some(Operator::EqualEqual),
+            "!=" => Some(Operator::NotEqual),
+            _ => None,
+        }
+    }
+}
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Operator::ADD => "+",
+            Operator::SUBTRACT => "-",
+            Operator::MULTIPLY => "*",
+            Operator::DIVIDE => "/",
+            Operator::LessThan => "<",
+            Operator::LessOrEqual => "<=",
+            Operator::GreaterThan => ">",
+            Operator::GreaterOrEqual => ">=",
+            Operator::EqualEqual => "==",
+            Operator::NotEqual => "!=",
+        };
+        write!(f, "{}", symbol)
+    }
+}
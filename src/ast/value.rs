@@ -0,0 +1,93 @@
+use super::{ErrorKind, RuntimeError};
+
+use std::fmt;
+use std::rc::Rc;
+
+// 実行時の値。レコードは書き換えず、フィールドを更新するときは新しいレコードを作る
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i32),
+    Record(Rc<Record>),
+    Variant(Rc<Variant>),
+    String(Rc<String>), // catch したエラーのメッセージ。Value を 2 ワードに収めるため Rc<str> にはしない
+}
+impl Value {
+    // 型注釈や型エラーのメッセージに使う型の名前
+    pub fn type_name(&self) -> &str {
+        match self {
+            Value::Integer(_) => "Int",
+            Value::Record(x) => &x.name,
+            Value::Variant(x) => &x.enumeration,
+            Value::String(_) => "String",
+        }
+    }
+    // 整数でなければ `type mismatch: <target> must be Int but Point given.` のエラーにする
+    pub fn integer(&self, target: impl Fn() -> String) -> Result<i32, RuntimeError> {
+        match self {
+            Value::Integer(x) => Ok(*x),
+            _ => Err(RuntimeError::new(
+                ErrorKind::TypeMismatch,
+                format!("type mismatch: {} must be Int but {} given.", target(), self.type_name())
+            )),
+        }
+    }
+    pub fn record(&self, target: impl Fn() -> String) -> Result<&Rc<Record>, RuntimeError> {
+        match self {
+            Value::Record(x) => Ok(x),
+            _ => Err(RuntimeError::new(
+                ErrorKind::TypeMismatch,
+                format!("type mismatch: {} must be a record but {} given.", target(), self.type_name())
+            )),
+        }
+    }
+}
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Integer(value)
+    }
+}
+// println では `Point[x = 1, y = 2]` や `Rect(1, 2)` のように、レコードを作る式と同じ形で書く
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Integer(x) => write!(f, "{}", x),
+            Value::Record(x) => {
+                let fields: Vec<String> = x.fields.iter().map(|(name, value)| format!("{} = {}", name, value)).collect();
+                write!(f, "{}[{}]", x.name, fields.join(", "))
+            },
+            Value::Variant(x) if x.values.is_empty() => write!(f, "{}", x.name),
+            Value::Variant(x) => {
+                let values: Vec<String> = x.values.iter().map(|y| y.to_string()).collect();
+                write!(f, "{}({})", x.name, values.join(", "))
+            },
+            Value::String(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+// フィールドは定義の順に並ぶので、同じ値のレコードは == で等しくなる
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub name: String,
+    pub fields: Vec<(String, Value)>,
+}
+impl Record {
+    pub fn get(&self, field: &str) -> Option<&Value> {
+        self.fields.iter().find(|x| x.0 == field).map(|x| &x.1)
+    }
+    // field を value に置き換えたレコード
+    pub fn with(&self, field: &str, value: Value) -> Option<Record> {
+        let index = self.fields.iter().position(|x| x.0 == field)?;
+        let mut record = self.clone();
+        record.fields[index].1 = value;
+        Some(record)
+    }
+}
+
+// 列挙型の値。name はバリアント（コンストラクタ）の名前で、values はその引数の値
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub enumeration: String,
+    pub name: String,
+    pub values: Vec<Value>,
+}
@@ -0,0 +1,255 @@
+use crate::ast;
+use crate::ast::{Definition, Node, Span};
+
+use std::collections::{HashMap, HashSet, LinkedList};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    pub definition: Option<String>, // 誤りを含む関数やグローバル変数の名前
+}
+impl Diagnostic {
+    pub(crate) fn error(message: String, span: Option<Span>, definition: &str) -> Self {
+        Self { severity: Severity::Error, message, span, definition: Some(definition.to_string()) }
+    }
+    fn warning(message: String) -> Self {
+        Self { severity: Severity::Warning, message, span: None, definition: None }
+    }
+    fn warning_at(message: String, span: Option<Span>, definition: &str) -> Self {
+        Self { severity: Severity::Warning, message, span, definition: Some(definition.to_string()) }
+    }
+    // import したモジュールの定義は `モジュール名.名前` になっているので、span は別のファイルの位置を指す
+    pub fn is_imported(&self) -> bool {
+        self.definition.as_deref().is_some_and(|x| x.contains('.'))
+    }
+}
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error: {}", self.message),
+            Severity::Warning => write!(f, "warning: {}", self.message),
+        }
+    }
+}
+
+// 実行せずに分かる誤り（未定義の関数・変数の参照や引数の数の不一致）を集める
+pub fn check_program(program: &ast::Program) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut functions: HashMap<&str, &ast::FunctionDefinition> = HashMap::new();
+    let mut globals: HashSet<String> = HashSet::new();
+    // catch で受け取る Error レコードと、その kind の ErrorKind のバリアントは定義しなくても使える
+    let error_kinds = ast::EnumDefinition::new(
+        "ErrorKind".to_string(),
+        ast::ErrorKind::CATCHABLE.iter().map(|x| (x.name(), LinkedList::new())).collect()
+    );
+    let mut fields: HashSet<&str> = HashSet::from(["kind", "message"]);
+    let mut variants: HashMap<&str, &ast::EnumDefinition> = error_kinds.variants.iter().map(|x| (x.name.as_str(), &error_kinds)).collect();
+    for toplevel in program.definitions.iter() {
+        match toplevel.definition() {
+            // レコードのコンストラクタは関数と同じ名前空間にある
+            Definition::Record(x) => {
+                if functions.insert(x.name(), &x.constructor).is_some() {
+                    diagnostics.push(Diagnostic::error(format!("function {} is defined more than once", x.name()), x.constructor.span, x.name()));
+                }
+                fields.extend(x.fields().iter().map(|y| y.as_str()));
+            },
+            // バリアントのコンストラクタも同じ
+            Definition::Enum(x) => {
+                for variant in x.variants.iter() {
+                    if functions.insert(&variant.name, variant).is_some() {
+                        diagnostics.push(Diagnostic::error(format!("function {} is defined more than once", variant.name), variant.span, &variant.name));
+                    }
+                    variants.insert(&variant.name, x);
+                }
+            },
+            Definition::Function(x) => {
+                if functions.insert(&x.name, x).is_some() {
+                    diagnostics.push(Diagnostic::error(format!("function {} is defined more than once", x.name), x.span, &x.name));
+                }
+                if x.is_main() {
+                    globals.extend(x.args.iter().cloned());
+                    collect_assigned(&x.body, &mut globals);
+                }
+            },
+            Definition::Global(x) => { globals.insert(x.name.clone()); },
+            Definition::Test(_) => {},
+        }
+    }
+    if !functions.contains_key("main") {
+        diagnostics.push(Diagnostic::warning("program has no main function".to_string()));
+    }
+
+    let checker = Checker { functions: &functions, globals: &globals, fields: &fields, variants: &variants };
+    for toplevel in program.definitions.iter() {
+        match toplevel.definition() {
+            Definition::Function(x) => {
+                let mut locals: HashSet<String> = HashSet::new();
+                if !x.is_main() {
+                    locals.extend(x.args.iter().cloned());
+                    collect_assigned(&x.body, &mut locals);
+                }
+                checker.check(&x.body, &x.name, &locals, &mut diagnostics);
+            },
+            Definition::Global(x) => checker.check(&x.body, &x.name, &HashSet::new(), &mut diagnostics),
+            // テストは main と同じくグローバル環境で動き、代入した変数はそのテストの中だけで使う
+            Definition::Test(x) => {
+                let mut locals: HashSet<String> = HashSet::new();
+                collect_assigned(&x.body, &mut locals);
+                checker.check(&x.body, &format!("test \"{}\"", x.name), &locals, &mut diagnostics);
+            },
+            Definition::Record(_) | Definition::Enum(_) => {},
+        }
+    }
+    diagnostics
+}
+
+struct Checker<'a> {
+    functions: &'a HashMap<&'a str, &'a ast::FunctionDefinition>,
+    globals: &'a HashSet<String>,
+    fields: &'a HashSet<&'a str>, // どれかのレコードにあるフィールドの名前
+    variants: &'a HashMap<&'a str, &'a ast::EnumDefinition>, // バリアントの名前からその列挙型
+}
+impl<'a> Checker<'a> {
+    fn check(&self, expression: &ast::Expression, context: &str, locals: &HashSet<String>, diagnostics: &mut Vec<Diagnostic>) {
+        let node = expression.node();
+        let error = |message: String| Diagnostic::error(message, expression.span(), context);
+        match node {
+            Node::Identifier(x) if !locals.contains(&x.name) && !self.globals.contains(&x.name) => {
+                diagnostics.push(error(format!("undefined variable {} in {}", x.name, context)));
+            },
+            Node::Call(x) => {
+                match self.functions.get(x.name.as_str()) {
+                    Some(f) if f.args.len() != x.args.len() => {
+                        diagnostics.push(error(format!(
+                            "function {} expects {} arguments but {} given in {}",
+                            x.name, f.args.len(), x.args.len(), context
+                        )));
+                    },
+                    Some(_) => {},
+                    None => diagnostics.push(error(format!("undefined function {} in {}", x.name, context))),
+                }
+            },
+            Node::LabelledCall(x) => {
+                match self.functions.get(x.name.as_str()) {
+                    Some(f) => {
+                        let labels: Vec<&String> = x.args.iter().map(|y| &y.name).collect();
+                        for arg in f.args.iter().filter(|y| !labels.contains(y)) {
+                            diagnostics.push(error(format!("argument {} of function {} is not given in {}", arg, x.name, context)));
+                        }
+                        for label in labels.iter().filter(|y| !f.args.contains(y)) {
+                            diagnostics.push(error(format!("function {} has no parameter {} in {}", x.name, label, context)));
+                        }
+                    },
+                    None => diagnostics.push(error(format!("undefined function {} in {}", x.name, context))),
+                }
+            },
+            Node::Match(x) => {
+                for arm in x.arms.iter() {
+                    self.check_pattern(&arm.pattern, arm.body.span(), context, diagnostics);
+                }
+                self.check_arms(x, expression.span(), context, diagnostics);
+            },
+            Node::Field(x) if !self.fields.contains(x.field.as_str()) => {
+                diagnostics.push(error(format!("no record has field {} in {}", x.field, context)));
+            },
+            Node::FieldAssignment(x) => {
+                if !locals.contains(&x.name) && !self.globals.contains(&x.name) {
+                    diagnostics.push(error(format!("undefined variable {} in {}", x.name, context)));
+                }
+                for field in x.path.iter().filter(|y| !self.fields.contains(y.as_str())) {
+                    diagnostics.push(error(format!("no record has field {} in {}", field, context)));
+                }
+            },
+            _ => {},
+        }
+        for child in node.children() {
+            self.check(child, context, locals, diagnostics);
+        }
+    }
+
+    fn check_pattern(&self, pattern: &ast::Pattern, span: Option<Span>, context: &str, diagnostics: &mut Vec<Diagnostic>) {
+        if let ast::Pattern::Constructor { name, arguments } = pattern {
+            let variant = self.variants.get(name.as_str()).and_then(|x| x.variants.iter().find(|y| &y.name == name));
+            match variant {
+                Some(f) if f.args.len() != arguments.len() => {
+                    diagnostics.push(Diagnostic::error(format!(
+                        "constructor {} expects {} fields but {} given in {}",
+                        name, f.args.len(), arguments.len(), context
+                    ), span, context));
+                },
+                Some(_) => {},
+                None => diagnostics.push(Diagnostic::error(format!("undefined constructor {} in {}", name, context), span, context)),
+            }
+            for argument in arguments.iter() {
+                self.check_pattern(argument, span, context, diagnostics);
+            }
+        }
+    }
+
+    // 整数の値はどれでもありうるので、ガードのない _ か束縛の腕がなければ網羅していない。
+    // 列挙型の値は、ガードも入れ子の条件もないバリアントの腕で全てのバリアントが出尽くせば網羅している。
+    // その後ろの腕や、前の腕で出尽くした整数・バリアントだけの腕には到達しない
+    fn check_arms(&self, expression: &ast::MatchExpression, span: Option<Span>, context: &str, diagnostics: &mut Vec<Diagnostic>) {
+        let mut covered: HashSet<i32> = HashSet::new();
+        let mut covered_variants: HashSet<&str> = HashSet::new();
+        let mut enumeration: Option<&ast::EnumDefinition> = None;
+        let mut exhaustive = false;
+        for arm in expression.arms.iter() {
+            let unreachable = match &arm.pattern {
+                ast::Pattern::Integers(x) => exhaustive || x.iter().all(|y| covered.contains(y)),
+                ast::Pattern::Constructor { name, .. } => exhaustive || covered_variants.contains(name.as_str()),
+                _ => exhaustive,
+            };
+            if unreachable {
+                diagnostics.push(Diagnostic::warning_at(format!("unreachable match arm {} in {}", arm.pattern, context), arm.body.span(), context));
+            }
+            if let ast::Pattern::Constructor { name, .. } = &arm.pattern {
+                enumeration = enumeration.or_else(|| self.variants.get(name.as_str()).copied());
+            }
+            if arm.guard.is_none() {
+                match &arm.pattern {
+                    ast::Pattern::Integers(x) => covered.extend(x.iter()),
+                    ast::Pattern::Constructor { name, arguments } => {
+                        if arguments.iter().all(|x| x.is_irrefutable()) {
+                            covered_variants.insert(name);
+                        }
+                        exhaustive = enumeration.is_some_and(|x| x.variants.iter().all(|y| covered_variants.contains(y.name.as_str())));
+                    },
+                    _ => exhaustive = true,
+                }
+            }
+        }
+        if !exhaustive {
+            let message = match enumeration {
+                Some(x) => {
+                    let missing: Vec<&str> = x.variants.iter().map(|y| y.name.as_str()).filter(|y| !covered_variants.contains(y)).collect();
+                    format!("match in {} is not exhaustive; missing {}", context, missing.join(", "))
+                },
+                None => format!("match in {} is not exhaustive; add a _ arm", context),
+            };
+            diagnostics.push(Diagnostic::warning_at(message, span, context));
+        }
+    }
+}
+
+fn collect_assigned(expression: &ast::Expression, names: &mut HashSet<String>) {
+    let node = expression.node();
+    match &node {
+        Node::Assignment(x) => { names.insert(x.name.clone()); },
+        Node::Match(x) => names.extend(x.bindings().into_iter().cloned()),
+        Node::Try(x) => names.extend(x.catch_clause.iter().map(|y| y.name.clone())),
+        _ => {},
+    }
+    for child in node.children() {
+        collect_assigned(child, names);
+    }
+}
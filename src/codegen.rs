@@ -0,0 +1,178 @@
+pub mod c;
+pub mod js;
+pub mod wat;
+pub mod x86_64;
+
+use crate::ast;
+use crate::ast::{Definition, FunctionDefinition, GlobalVariableDefinition, Node, Slot, Span};
+use crate::resolver::Resolver;
+
+use std::collections::HashMap;
+use std::fmt;
+
+// 変換できないプログラム（未定義の関数の呼び出しなど）。インタプリタなら実行時に起きる誤りを変換時に報告する
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodegenError {
+    pub message: String,
+    pub span: Option<Span>,
+}
+impl CodegenError {
+    pub fn new(message: String, span: Option<Span>) -> Self {
+        Self { message, span }
+    }
+}
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+// 変数の置き場所。Local は仮引数を先頭に並べた関数のローカル変数の番号、Global はグローバル変数の番号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Variable {
+    Local(usize),
+    Global(usize),
+}
+
+// 各バックエンドが共通に使う、変数を解決したプログラムの配置
+pub struct Layout<'a> {
+    pub functions: Vec<&'a FunctionDefinition>,
+    pub globals: Vec<String>,                         // グローバル変数の名前（番号の順）
+    pub initializers: Vec<&'a GlobalVariableDefinition>, // main の前に順に評価するグローバル変数の定義
+    by_name: HashMap<&'a str, &'a FunctionDefinition>,
+}
+
+impl<'a> Layout<'a> {
+    // テストは変換しない。main がなければ誤りにする
+    pub fn new(program: &'a ast::Program) -> Result<Self, CodegenError> {
+        let mut resolver = Resolver::new();
+        resolver.resolve_program(program);
+        let mut functions = Vec::new();
+        let mut initializers = Vec::new();
+        let mut by_name = HashMap::new();
+        for toplevel in program.definitions.iter() {
+            match toplevel.definition() {
+                Definition::Function(x) => {
+                    for annotation in x.parameter_types.iter().chain(std::iter::once(&x.return_type)) {
+                        supported(annotation.as_deref(), x.span)?;
+                    }
+                    static_variables(&x.body)?;
+                    // 同じ名前の関数は後の定義が使われる
+                    if by_name.insert(x.name.as_str(), x).is_none() {
+                        functions.push(x);
+                    } else if let Some(y) = functions.iter_mut().find(|y| y.name == x.name) {
+                        *y = x;
+                    }
+                },
+                Definition::Global(x) => {
+                    supported(x.type_annotation.as_deref(), x.span)?;
+                    initializers.push(x);
+                },
+                Definition::Record(x) => return Err(unsupported_record(x.constructor.span)),
+                Definition::Enum(x) => return Err(unsupported_enum(x.span)),
+                Definition::Test(_) => {},
+            }
+        }
+        if !by_name.contains_key("main") {
+            return Err(CodegenError::new("This program doesn't have main function.".to_string(), None));
+        }
+        let globals = resolver.global_names().into_iter().map(|x| x.to_string()).collect();
+        Ok(Self { functions, globals, initializers, by_name })
+    }
+
+    pub fn function(&self, name: &str) -> Option<&'a FunctionDefinition> {
+        self.by_name.get(name).copied()
+    }
+
+    // function は変数を参照している関数。グローバル変数の初期化式と main の中では None と同じくグローバル環境になる
+    pub fn variable(&self, slot: Option<Slot>, function: Option<&FunctionDefinition>) -> Variable {
+        let slot = slot.expect("unresolved variable");
+        match function {
+            Some(x) if !x.is_main() && slot.depth == 0 => Variable::Local(slot.index),
+            _ => Variable::Global(slot.index),
+        }
+    }
+
+    // 関数の呼び出しを、呼び出す関数と仮引数の順に並べた実引数にする
+    pub fn call<'b>(&self, node: &Node<'b>, span: Option<Span>) -> Result<(&'a FunctionDefinition, Vec<&'b ast::Expression>), CodegenError> {
+        let (name, args): (&str, Vec<&'b ast::Expression>) = match node {
+            Node::Call(x) => (&x.name, x.args.iter().collect()),
+            Node::LabelledCall(x) => {
+                let function = self.lookup(&x.name, span)?;
+                let mut args = Vec::new();
+                for formal in function.args.iter() {
+                    let parameter = x.args.iter().find(|y| &y.name == formal).ok_or_else(|| CodegenError::new(
+                        format!("argument {} of function {} is not given.", formal, x.name),
+                        span
+                    ))?;
+                    args.push(&parameter.parameter);
+                }
+                (&x.name, args)
+            },
+            _ => panic!("not a call"),
+        };
+        let function = self.lookup(name, span)?;
+        if function.args.len() != args.len() {
+            return Err(CodegenError::new(
+                format!("function {} expects {} arguments but {} given.", name, function.args.len(), args.len()),
+                span
+            ));
+        }
+        Ok((function, args))
+    }
+
+    // 呼び出しで実引数を代入する、呼び出し元の変数（仮引数の順）
+    pub fn bindings(&self, node: &Node, function: Option<&FunctionDefinition>) -> Vec<Variable> {
+        let slots = match node {
+            Node::Call(x) => x.parameters.borrow().clone(),
+            Node::LabelledCall(x) => x.parameters.borrow().clone(),
+            _ => panic!("not a call"),
+        };
+        slots.into_iter().map(|x| self.variable(Some(x), function)).collect()
+    }
+
+    fn lookup(&self, name: &str, span: Option<Span>) -> Result<&'a FunctionDefinition, CodegenError> {
+        self.function(name).ok_or_else(|| CodegenError::new(format!("function {} not found.", name), span))
+    }
+}
+
+// 変換したプログラムの値はどれも整数なので、Int 以外の型注釈は実行時にかならず失敗する。変換時に誤りにする
+fn supported(annotation: Option<&str>, span: Option<Span>) -> Result<(), CodegenError> {
+    match annotation {
+        Some(x) if x != "Int" => Err(CodegenError::new(format!("type {} is not supported; only Int values can be compiled.", x), span)),
+        _ => Ok(()),
+    }
+}
+
+// 代入される前に読むかもしれない変数は、インタプリタでは呼び出し元の環境から名前で探す。
+// 変換先には呼び出し元の変数を名前で探す仕組みがないので変換できない
+fn static_variables(expression: &ast::Expression) -> Result<(), CodegenError> {
+    let node = expression.node();
+    match &node {
+        Node::Identifier(x) if x.dynamic.get() => Err(CodegenError::new(
+            format!("variable {} may be read from the caller's environment, which cannot be compiled; assign it first.", x.name),
+            expression.span()
+        )),
+        _ => node.children().into_iter().try_for_each(static_variables),
+    }
+}
+
+// レコードの値は整数でないので変換できない
+pub fn unsupported_record(span: Option<Span>) -> CodegenError {
+    CodegenError::new("records are not supported; only Int values can be compiled.".to_string(), span)
+}
+
+// 列挙型の値も整数でないので変換できない
+pub fn unsupported_enum(span: Option<Span>) -> CodegenError {
+    CodegenError::new("enums are not supported; only Int values can be compiled.".to_string(), span)
+}
+
+// 例外を投げて戻る仕組みは変換先にないので変換できない
+pub fn unsupported_exception(span: Option<Span>) -> CodegenError {
+    CodegenError::new("exceptions are not supported; try and throw cannot be compiled.".to_string(), span)
+}
+
+// 取り込んだモジュールの定義は `モジュール名.名前` なので、. を含まない名前にする
+pub fn mangle(name: &str) -> String {
+    name.replace('.', "__")
+}
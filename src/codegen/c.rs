@@ -0,0 +1,446 @@
+use super::{mangle, unsupported_enum, unsupported_exception, unsupported_record, CodegenError, Layout, Variable};
+use crate::ast;
+use crate::ast::{FunctionDefinition, Node, Operator};
+
+// 実行時のエラーの扱いはインタプリタに合わせ、メッセージを標準エラー出力に書いて 1 で終了する。
+// 使わない関数で警告が出ないよう static にはしない。変数は代入前を表すため int64_t に TOYS_UNSET を入れておく
+const RUNTIME: &str = r#"#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <ctype.h>
+#include <errno.h>
+
+#define TOYS_UNSET INT64_MIN
+
+void toys_fail(const char *message) {
+    fflush(stdout);
+    fprintf(stderr, "error: %s\n", message);
+    exit(1);
+}
+
+int32_t toys_overflow(int32_t lhs, const char *op, int32_t rhs) {
+    char message[96];
+    sprintf(message, "integer overflow: %ld %s %ld", (long)lhs, op, (long)rhs);
+    toys_fail(message);
+    return 0;
+}
+
+int32_t toys_check(int64_t value, int32_t lhs, const char *op, int32_t rhs) {
+    if (value < INT32_MIN || value > INT32_MAX) {
+        return toys_overflow(lhs, op, rhs);
+    }
+    return (int32_t)value;
+}
+
+int32_t toys_add(int32_t lhs, int32_t rhs) {
+    return toys_check((int64_t)lhs + rhs, lhs, "+", rhs);
+}
+
+int32_t toys_sub(int32_t lhs, int32_t rhs) {
+    return toys_check((int64_t)lhs - rhs, lhs, "-", rhs);
+}
+
+int32_t toys_mul(int32_t lhs, int32_t rhs) {
+    return toys_check((int64_t)lhs * rhs, lhs, "*", rhs);
+}
+
+int32_t toys_div(int32_t lhs, int32_t rhs) {
+    char message[64];
+    if (rhs == 0) {
+        sprintf(message, "division by zero: %ld / 0", (long)lhs);
+        toys_fail(message);
+    }
+    return toys_check((int64_t)lhs / rhs, lhs, "/", rhs);
+}
+
+void toys_no_match(int32_t value) {
+    char message[64];
+    sprintf(message, "no match arm for value %ld", (long)value);
+    toys_fail(message);
+}
+
+int32_t toys_read(int64_t value, const char *name) {
+    char message[256];
+    if (value == TOYS_UNSET) {
+        sprintf(message, "undefined variable: %.200s", name);
+        toys_fail(message);
+    }
+    return (int32_t)value;
+}
+
+/* 前後の空白を除いて整数として読む。読めなければ 0 を返す */
+int toys_parse(const char *text, int32_t *value) {
+    char buffer[64];
+    size_t start = 0, end = strlen(text);
+    char *rest;
+    long parsed;
+    while (start < end && isspace((unsigned char)text[start])) start++;
+    while (end > start && isspace((unsigned char)text[end - 1])) end--;
+    if (end == start || end - start >= sizeof(buffer)) return 0;
+    memcpy(buffer, text + start, end - start);
+    buffer[end - start] = '\0';
+    if (!isdigit((unsigned char)buffer[buffer[0] == '+' || buffer[0] == '-' ? 1 : 0])) return 0;
+    errno = 0;
+    parsed = strtol(buffer, &rest, 10);
+    if (errno != 0 || *rest != '\0' || parsed < INT32_MIN || parsed > INT32_MAX) return 0;
+    *value = (int32_t)parsed;
+    return 1;
+}
+
+/* 環境変数が設定されていれば value に読んで 1 を返す */
+int toys_env(const char *name, int32_t *value) {
+    char message[512];
+    const char *text = getenv(name);
+    if (text == NULL) return 0;
+    if (!toys_parse(text, value)) {
+        sprintf(message, "environment variable %.200s is not an integer: %.200s", name, text);
+        toys_fail(message);
+    }
+    return 1;
+}
+
+void toys_env_missing(const char *name) {
+    char message[256];
+    sprintf(message, "environment variable %.200s is not set.", name);
+    toys_fail(message);
+}
+
+void toys_assert_eq(int32_t left, int32_t right) {
+    char message[128];
+    if (left != right) {
+        sprintf(message, "assertion failed: left == right\n  left: %ld\n right: %ld", (long)left, (long)right);
+        toys_fail(message);
+    }
+}
+"#;
+
+// プログラムを一つの C のソースにする。C の main はコマンドライン引数を toys の main に渡し、
+// その返り値を終了コードにする。インタプリタと違い、グローバル変数の初期化式から後で定義する関数も呼べる
+pub fn generate(program: &ast::Program) -> Result<String, CodegenError> {
+    let layout = Layout::new(program)?;
+    let mut out = String::from(RUNTIME);
+    out.push('\n');
+    for (index, name) in layout.globals.iter().enumerate() {
+        out.push_str(&format!("static int64_t {} = TOYS_UNSET; /* {} */\n", global(index, &layout), name));
+    }
+    out.push('\n');
+    for function in layout.functions.iter() {
+        out.push_str(&format!("{};\n", signature(function)));
+    }
+    out.push('\n');
+    for function in layout.functions.iter() {
+        out.push_str(&Generator::new(&layout, Some(function)).function(function)?);
+        out.push('\n');
+    }
+    out.push_str(&Generator::new(&layout, None).initializer()?);
+    out.push('\n');
+    out.push_str(&entry(&layout));
+    Ok(out)
+}
+
+struct Generator<'a> {
+    layout: &'a Layout<'a>,
+    function: Option<&'a FunctionDefinition>,
+    lines: Vec<String>,
+    depth: usize,
+    temporaries: usize,
+}
+
+impl<'a> Generator<'a> {
+    fn new(layout: &'a Layout<'a>, function: Option<&'a FunctionDefinition>) -> Self {
+        Self { layout, function, lines: Vec::new(), depth: 1, temporaries: 0 }
+    }
+
+    // main の仮引数はグローバル変数なので、受け取った値をそこに入れる
+    fn function(mut self, function: &FunctionDefinition) -> Result<String, CodegenError> {
+        if function.is_main() {
+            for (i, slot) in function.global_parameters.borrow().iter().enumerate() {
+                let name = global(slot.index, self.layout);
+                self.emit(format!("{} = a{};", name, i));
+            }
+        } else {
+            for (i, name) in function.locals.borrow().iter().enumerate().skip(function.args.len()) {
+                self.emit(format!("int64_t l{} = TOYS_UNSET; /* {} */", i, name));
+            }
+        }
+        let value = self.expression(&function.body)?;
+        self.emit(format!("return {};", value));
+        Ok(format!("{} {{\n{}}}\n", signature(function), self.finish()))
+    }
+
+    // グローバル変数の初期化式を定義の順に評価する
+    fn initializer(mut self) -> Result<String, CodegenError> {
+        for definition in self.layout.initializers.iter() {
+            let value = self.expression(&definition.body)?;
+            let variable = self.variable(definition.slot.get());
+            self.emit(format!("{} = {};", variable, value));
+        }
+        Ok(format!("void toys_initialize(void) {{\n{}}}\n", self.finish()))
+    }
+
+    fn finish(&self) -> String {
+        self.lines.iter().map(|x| format!("{}\n", x)).collect()
+    }
+
+    fn emit(&mut self, line: String) {
+        self.lines.push(format!("{}{}", "    ".repeat(self.depth), line));
+    }
+
+    // 値を一時変数に入れる。後の式の副作用で値が変わらないよう、途中の値はすべて一時変数に置く
+    fn temporary(&mut self, value: String) -> String {
+        let name = self.fresh();
+        self.emit(format!("int32_t {} = {};", name, value));
+        name
+    }
+
+    // 値を後から入れる一時変数
+    fn declare(&mut self) -> String {
+        let name = self.fresh();
+        self.emit(format!("int32_t {};", name));
+        name
+    }
+
+    fn fresh(&mut self) -> String {
+        self.temporaries += 1;
+        format!("t{}", self.temporaries)
+    }
+
+    fn variable(&self, slot: Option<ast::Slot>) -> String {
+        self.name(self.layout.variable(slot, self.function))
+    }
+
+    fn name(&self, variable: Variable) -> String {
+        match variable {
+            Variable::Local(i) => format!("l{}", i),
+            Variable::Global(i) => global(i, self.layout),
+        }
+    }
+
+    // 式を評価する文を出力し、その値を表す C の式（整数か一時変数）を返す
+    fn expression(&mut self, expression: &ast::Expression) -> Result<String, CodegenError> {
+        let node = expression.node();
+        match &node {
+            Node::Integer(x) => Ok(literal(x.value)),
+            Node::Identifier(x) => {
+                let variable = self.variable(x.slot.get());
+                Ok(self.temporary(format!("toys_read({}, {})", variable, string(&x.name))))
+            },
+            Node::Assignment(x) => {
+                let value = self.expression(&x.expression)?;
+                let variable = self.variable(x.slot.get());
+                self.emit(format!("{} = {};", variable, value));
+                Ok(value)
+            },
+            Node::Binary(x) => {
+                let lhs = self.expression(&x.lhs)?;
+                let rhs = self.expression(&x.rhs)?;
+                let value = match x.operator {
+                    Operator::ADD => format!("toys_add({}, {})", lhs, rhs),
+                    Operator::SUBTRACT => format!("toys_sub({}, {})", lhs, rhs),
+                    Operator::MULTIPLY => format!("toys_mul({}, {})", lhs, rhs),
+                    Operator::DIVIDE => format!("toys_div({}, {})", lhs, rhs),
+                    comparison => format!("{} {} {}", lhs, comparison, rhs),
+                };
+                Ok(self.temporary(value))
+            },
+            Node::Block(x) => {
+                let mut value = "0".to_string();
+                for element in x.elements.iter() {
+                    value = self.expression(element)?;
+                }
+                Ok(value)
+            },
+            Node::While(x) => {
+                self.emit("for (;;) {".to_string());
+                self.depth += 1;
+                let condition = self.expression(&x.condition)?;
+                self.emit(format!("if (!{}) break;", condition));
+                self.expression(&x.body)?;
+                self.depth -= 1;
+                self.emit("}".to_string());
+                Ok("1".to_string())
+            },
+            Node::If(x) => {
+                let condition = self.expression(&x.condition)?;
+                let result = self.declare();
+                self.emit(format!("if ({}) {{", condition));
+                self.branch(&result, Some(&x.then_clause))?;
+                self.emit("} else {".to_string());
+                self.branch(&result, x.else_clause.as_ref())?;
+                self.emit("}".to_string());
+                Ok(result)
+            },
+            Node::Println(x) => {
+                let value = self.expression(&x.body)?;
+                self.emit(format!("printf(\"%ld\\n\", (long){});", value));
+                Ok("0".to_string())
+            },
+            Node::Call(_) | Node::LabelledCall(_) => {
+                let (function, args) = self.layout.call(&node, expression.span())?;
+                let mut values = Vec::new();
+                for arg in args {
+                    values.push(self.expression(arg)?);
+                }
+                // インタプリタと同じく、実引数を呼び出し元の同じ名前の変数にも代入する
+                for (variable, value) in self.layout.bindings(&node, self.function).into_iter().zip(values.iter()) {
+                    let variable = self.name(variable);
+                    self.emit(format!("{} = {};", variable, value));
+                }
+                Ok(self.temporary(format!("{}({})", function_name(&function.name), values.join(", "))))
+            },
+            Node::EnvironmentVariable(x) => {
+                let result = self.declare();
+                self.emit(format!("if (!toys_env({}, &{})) {{", string(&x.name), result));
+                self.depth += 1;
+                match &x.default {
+                    Some(default) => {
+                        let value = self.expression(default)?;
+                        self.emit(format!("{} = {};", result, value));
+                    },
+                    None => self.emit(format!("toys_env_missing({});", string(&x.name))),
+                }
+                self.depth -= 1;
+                self.emit("}".to_string());
+                Ok(result)
+            },
+            Node::Assert(x) => {
+                let left = self.expression(&x.left)?;
+                match &x.right {
+                    Some(right) => {
+                        let right = self.expression(right)?;
+                        self.emit(format!("toys_assert_eq({}, {});", left, right));
+                    },
+                    None => self.emit(format!("if (!{}) toys_fail(\"assertion failed\");", left)),
+                }
+                Ok("1".to_string())
+            },
+            // 合った腕の値を result に入れて do { } while (0) を抜ける
+            Node::Match(x) => {
+                let value = self.expression(&x.value)?;
+                let result = self.declare();
+                self.emit("do {".to_string());
+                self.depth += 1;
+                for arm in x.arms.iter() {
+                    let mut binding = None;
+                    match &arm.pattern {
+                        ast::Pattern::Integers(values) => {
+                            let tests: Vec<String> = values.iter().map(|y| format!("{} == {}", value, literal(*y))).collect();
+                            self.emit(format!("if ({}) {{", tests.join(" || ")));
+                        },
+                        ast::Pattern::Binding { slot, .. } => {
+                            binding = Some(self.variable(slot.get()));
+                            self.emit("{".to_string());
+                        },
+                        ast::Pattern::Wildcard => self.emit("{".to_string()),
+                        ast::Pattern::Constructor { .. } => return Err(unsupported_enum(expression.span())),
+                    }
+                    self.depth += 1;
+                    // ガードが偽なら、束縛した変数を match の前の値に戻す
+                    let saved = match (&binding, &arm.guard) {
+                        (Some(variable), Some(_)) => {
+                            let saved = self.fresh();
+                            self.emit(format!("int64_t {} = {};", saved, variable));
+                            Some(saved)
+                        },
+                        _ => None,
+                    };
+                    if let Some(variable) = &binding {
+                        self.emit(format!("{} = {};", variable, value));
+                    }
+                    if let Some(guard) = &arm.guard {
+                        let condition = self.expression(guard)?;
+                        self.emit(format!("if ({}) {{", condition));
+                        self.depth += 1;
+                    }
+                    let body = self.expression(&arm.body)?;
+                    self.emit(format!("{} = {};", result, body));
+                    self.emit("break;".to_string());
+                    if arm.guard.is_some() {
+                        self.depth -= 1;
+                        self.emit("}".to_string());
+                    }
+                    if let (Some(variable), Some(saved)) = (binding, saved) {
+                        self.emit(format!("{} = {};", variable, saved));
+                    }
+                    self.depth -= 1;
+                    self.emit("}".to_string());
+                }
+                self.emit(format!("toys_no_match({});", value));
+                self.depth -= 1;
+                self.emit("} while (0);".to_string());
+                Ok(result)
+            },
+            Node::Field(_) | Node::FieldAssignment(_) | Node::Construct(_) => Err(unsupported_record(expression.span())),
+            Node::Throw(_) | Node::Try(_) => Err(unsupported_exception(expression.span())),
+        }
+    }
+
+    // if の一方の節を評価して result に入れる。else 節がなければ 1 になる
+    fn branch(&mut self, result: &str, clause: Option<&ast::Expression>) -> Result<(), CodegenError> {
+        self.depth += 1;
+        let value = match clause {
+            Some(x) => self.expression(x)?,
+            None => "1".to_string(),
+        };
+        self.emit(format!("{} = {};", result, value));
+        self.depth -= 1;
+        Ok(())
+    }
+}
+
+fn signature(function: &FunctionDefinition) -> String {
+    let parameters: Vec<String> = if function.is_main() {
+        (0..function.args.len()).map(|i| format!("int32_t a{}", i)).collect()
+    } else {
+        (0..function.args.len()).map(|i| format!("int64_t l{}", i)).collect()
+    };
+    let parameters = if parameters.is_empty() { "void".to_string() } else { parameters.join(", ") };
+    format!("int32_t {}({})", function_name(&function.name), parameters)
+}
+
+// コマンドライン引数の誤りは toys run と同じく 2 で終了する。
+// toys run と同じく、仮引数より多い引数は捨て、足りない仮引数は 0 にする。
+// 終了コードも toys run と同じく、255 より大きい値は 255、負の値は 1 にする
+fn entry(layout: &Layout) -> String {
+    let main = layout.function("main").unwrap();
+    let arity = main.args.len();
+    let mut out = String::from("int main(int argc, char **argv) {\n");
+    if arity > 0 {
+        out.push_str(&format!("    int32_t args[{}] = {{0}};\n", arity));
+    }
+    out.push_str("    int32_t value;\n");
+    out.push_str("    int i;\n");
+    out.push_str("    for (i = 1; i < argc; i++) {\n");
+    out.push_str("        if (!toys_parse(argv[i], &value)) {\n");
+    out.push_str("            fprintf(stderr, \"error: argument %s is not an integer\\n\", argv[i]);\n");
+    out.push_str("            return 2;\n");
+    out.push_str("        }\n");
+    if arity > 0 {
+        out.push_str(&format!("        if (i <= {}) args[i - 1] = value;\n", arity));
+    }
+    out.push_str("    }\n");
+    out.push_str("    toys_initialize();\n");
+    let args: Vec<String> = (0..arity).map(|i| format!("args[{}]", i)).collect();
+    out.push_str(&format!("    value = {}({});\n", function_name(&main.name), args.join(", ")));
+    out.push_str("    return value < 0 ? 1 : value > 255 ? 255 : value;\n");
+    out.push_str("}\n");
+    out
+}
+
+fn literal(value: i32) -> String {
+    if value == i32::MIN { "INT32_MIN".to_string() } else { value.to_string() }
+}
+
+fn function_name(name: &str) -> String {
+    format!("f_{}", mangle(name))
+}
+
+fn global(index: usize, layout: &Layout) -> String {
+    format!("g_{}", mangle(&layout.globals[index]))
+}
+
+// toys の文字列には " が含まれないが、\ はそのままでは C の文字列にならない
+fn string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\"))
+}
@@ -0,0 +1,600 @@
+use super::{mangle, unsupported_enum, unsupported_exception, unsupported_record, CodegenError, Layout, Variable};
+use crate::ast;
+use crate::ast::{FunctionDefinition, Node, Operator, Span};
+use crate::json::Json;
+
+use std::collections::{BTreeSet, HashSet};
+
+// 実行時の処理。使ったものだけを出力する。整数は 32 ビットの範囲を外れたら例外を投げ、
+// | 0 で -0 を 0 にする。未定義の変数の参照、環境変数、assert もインタプリタと同じメッセージの例外にする
+const HELPERS: [(&str, &str); 10] = [
+    ("$check", r#"function $check(value, lhs, operator, rhs) {
+    if (value < -2147483648 || value > 2147483647) {
+        throw new RangeError(`integer overflow: ${lhs} ${operator} ${rhs}`);
+    }
+    return value | 0;
+}
+"#),
+    ("$add", "const $add = (lhs, rhs) => $check(lhs + rhs, lhs, \"+\", rhs);\n"),
+    ("$sub", "const $sub = (lhs, rhs) => $check(lhs - rhs, lhs, \"-\", rhs);\n"),
+    ("$mul", "const $mul = (lhs, rhs) => $check(lhs * rhs, lhs, \"*\", rhs);\n"),
+    ("$div", r#"function $div(lhs, rhs) {
+    if (rhs === 0) {
+        throw new RangeError(`division by zero: ${lhs} / 0`);
+    }
+    return $check(Math.trunc(lhs / rhs), lhs, "/", rhs);
+}
+"#),
+    ("$read", r#"function $read(value, name) {
+    if (value === undefined) {
+        throw new ReferenceError(`undefined variable: ${name}`);
+    }
+    return value;
+}
+"#),
+    ("$println", r#"function $println(value) {
+    console.log(value);
+    return 0;
+}
+"#),
+    ("$env", r#"// ブラウザなど process のない環境では、環境変数はいつも設定されていない
+function $env(name, fallback) {
+    const text = globalThis.process?.env?.[name];
+    if (text === undefined) {
+        if (fallback === undefined) {
+            throw new Error(`environment variable ${name} is not set.`);
+        }
+        return fallback();
+    }
+    const value = Number(text.trim());
+    if (!/^[+-]?\d+$/.test(text.trim()) || value < -2147483648 || value > 2147483647) {
+        throw new Error(`environment variable ${name} is not an integer: ${text}`);
+    }
+    return value;
+}
+"#),
+    ("$assert", r#"function $assert(value) {
+    if (value === 0) {
+        throw new Error("assertion failed");
+    }
+    return 1;
+}
+
+function $assertEq(left, right) {
+    if (left !== right) {
+        throw new Error(`assertion failed: left == right\n  left: ${left}\n right: ${right}`);
+    }
+    return 1;
+}
+"#),
+    ("$noMatch", r#"function $noMatch(value) {
+    throw new Error(`no match arm for value ${value}`);
+}
+"#),
+];
+
+// JavaScript で変数や関数の名前に使えない語と、実行時の処理が使う組み込みの名前
+const RESERVED: [&str; 52] = [
+    "arguments", "await", "break", "case", "catch", "class", "const", "continue", "debugger", "default",
+    "delete", "do", "else", "enum", "eval", "export", "extends", "false", "finally", "for",
+    "function", "if", "implements", "import", "in", "instanceof", "interface", "let", "new", "null",
+    "package", "private", "protected", "public", "return", "static", "super", "switch", "this", "throw",
+    "true", "try", "typeof", "var", "void", "while", "with", "yield",
+    "console", "globalThis", "Math", "Number",
+];
+
+// 変換した ES モジュール。lines は出力した各行に対応する .toys の位置
+pub struct Module {
+    pub code: String,
+    source: String,
+    lines: Vec<Option<Span>>,
+}
+
+impl Module {
+    // Source Map v3。各行の先頭の位置を、その行を生成した toys の式の位置に対応づける
+    pub fn source_map(&self, file: &str, contents: &str) -> Json {
+        let mut mappings = String::new();
+        let mut previous = (0, 0);
+        for (i, span) in self.lines.iter().enumerate() {
+            if i > 0 {
+                mappings.push(';');
+            }
+            if let Some(span) = span {
+                let position = (span.line as i64 - 1, span.column as i64 - 1);
+                vlq(0, &mut mappings);
+                vlq(0, &mut mappings);
+                vlq(position.0 - previous.0, &mut mappings);
+                vlq(position.1 - previous.1, &mut mappings);
+                previous = position;
+            }
+        }
+        Json::object(vec![
+            ("version", Json::from(3)),
+            ("file", Json::from(file)),
+            ("sources", Json::from(vec![Json::from(self.source.as_str())])),
+            ("sourcesContent", Json::from(vec![Json::from(contents)])),
+            ("names", Json::from(Vec::new())),
+            ("mappings", Json::from(mappings)),
+        ])
+    }
+}
+
+// Base64 VLQ。最下位ビットが符号で、5 ビットずつ下の桁から並べる
+fn vlq(value: i64, out: &mut String) {
+    const DIGITS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut rest = if value < 0 { (-value << 1) | 1 } else { value << 1 };
+    loop {
+        let mut digit = rest & 31;
+        rest >>= 5;
+        if rest > 0 {
+            digit |= 32;
+        }
+        out.push(DIGITS[digit as usize] as char);
+        if rest == 0 {
+            break;
+        }
+    }
+}
+
+// プログラムを読みやすい ES モジュールにする。source は Source Map に書く .toys のファイル名。
+// 関数は同じ名前の関数として、global の定義は同じ名前の変数として export する（取り込んだモジュールの定義は除く）。
+// グローバル変数の初期化式はモジュールを読み込んだときに評価され、後で定義する関数も呼べる
+pub fn generate(program: &ast::Program, source: &str) -> Result<Module, CodegenError> {
+    let layout = Layout::new(program)?;
+    let functions: HashSet<String> = layout.functions.iter().map(|x| identifier(&x.name)).collect();
+    // global で定義した変数と main の仮引数は、関数の中では設定済みとして読む
+    let mut initialized: HashSet<Variable> = layout.initializers.iter()
+        .map(|x| Variable::Global(x.slot.get().expect("unresolved variable").index))
+        .collect();
+    if let Some(main) = layout.function("main") {
+        initialized.extend(main.global_parameters.borrow().iter().map(|x| Variable::Global(x.index)));
+    }
+    let mut generator = Generator {
+        layout: &layout,
+        functions: &functions,
+        function: None,
+        lines: Vec::new(),
+        depth: 0,
+        span: None,
+        mapped: true,
+        assigned: initialized.clone(),
+        helpers: BTreeSet::new(),
+        matches: 0,
+    };
+
+    let defined: HashSet<usize> = layout.initializers.iter().filter_map(|x| x.slot.get().map(|y| y.index)).collect();
+    let implicit: Vec<String> = (0..layout.globals.len())
+        .filter(|x| !defined.contains(x))
+        .map(|x| generator.name(Variable::Global(x)))
+        .collect();
+    if !implicit.is_empty() {
+        generator.emit(format!("let {};", implicit.join(", ")));
+        generator.emit(String::new());
+    }
+    let mut declared = HashSet::new();
+    for definition in layout.initializers.iter() {
+        let variable = Variable::Global(definition.slot.get().expect("unresolved variable").index);
+        generator.span = definition.span;
+        generator.mapped = !definition.name.contains('.');
+        let value = generator.expression(&definition.body)?;
+        let name = generator.name(variable);
+        if !declared.insert(variable) {
+            generator.emit(format!("{} = {};", name, value));
+        } else if generator.mapped {
+            generator.emit(format!("export let {} = {};", name, value));
+        } else {
+            generator.emit(format!("let {} = {};", name, value));
+        }
+    }
+    if !layout.initializers.is_empty() {
+        generator.emit(String::new());
+    }
+    for (i, function) in layout.functions.iter().enumerate() {
+        if i > 0 {
+            generator.emit(String::new());
+        }
+        generator.assigned = initialized.clone();
+        generator.function(function)?;
+    }
+
+    let mut header = vec!["// toys から生成した ES モジュール。main の返り値がプログラムの終了コードになる".to_string(), String::new()];
+    for (name, code) in HELPERS.iter() {
+        if generator.helpers.contains(name) {
+            header.extend(code.lines().map(|x| x.to_string()));
+            header.push(String::new());
+        }
+    }
+    let mut lines: Vec<Option<Span>> = header.iter().map(|_| None).collect();
+    lines.extend(generator.lines.iter().map(|x| x.1));
+    let code = header.into_iter().chain(generator.lines.into_iter().map(|x| x.0)).map(|x| x + "\n").collect();
+    Ok(Module { code, source: source.to_string(), lines })
+}
+
+// 式の値の行き先
+enum Target {
+    Return,
+    Discard,
+    Assign(String),
+}
+
+struct Generator<'a> {
+    layout: &'a Layout<'a>,
+    functions: &'a HashSet<String>, // 関数の名前。変数の名前と重ならないようにする
+    function: Option<&'a FunctionDefinition>,
+    lines: Vec<(String, Option<Span>)>,
+    depth: usize,
+    span: Option<Span>, // 出力する行を生成している式の位置
+    mapped: bool,       // 取り込んだモジュールの定義は Source Map に含めない
+    assigned: HashSet<Variable>, // 必ず代入済みの変数。それ以外の変数は $read で読む
+    helpers: BTreeSet<&'static str>,
+    matches: usize, // match の値を入れる定数 $m1, $m2, ... の数
+}
+
+impl<'a> Generator<'a> {
+    // main の仮引数はグローバル変数なので、$ を付けた仮引数で受け取ってそこに入れる
+    fn function(&mut self, function: &'a FunctionDefinition) -> Result<(), CodegenError> {
+        self.function = Some(function);
+        self.mapped = !function.name.contains('.');
+        self.span = function.span;
+        let export = if self.mapped { "export " } else { "" };
+        for line in function.doc.iter().flat_map(|x| x.lines()) {
+            self.emit(format!("// {}", line).trim_end().to_string());
+        }
+        let name = identifier(&function.name);
+        if function.is_main() {
+            let parameters: Vec<String> = function.global_parameters.borrow().iter()
+                .map(|x| format!("${}", self.name(Variable::Global(x.index))))
+                .collect();
+            // toys run と同じく、渡されなかった仮引数は 0 にする
+            let defaults: Vec<String> = parameters.iter().map(|x| format!("{} = 0", x)).collect();
+            self.emit(format!("{}function {}({}) {{", export, name, defaults.join(", ")));
+            self.depth += 1;
+            for parameter in parameters.iter() {
+                self.emit(format!("{} = {};", &parameter[1..], parameter));
+            }
+        } else {
+            let mut locals: Vec<String> = (0..function.locals.borrow().len()).map(|x| self.name(Variable::Local(x))).collect();
+            let variables = locals.split_off(function.args.len());
+            self.assigned.extend((0..function.args.len()).map(Variable::Local));
+            self.emit(format!("{}function {}({}) {{", export, name, locals.join(", ")));
+            self.depth += 1;
+            if !variables.is_empty() {
+                self.emit(format!("let {};", variables.join(", ")));
+            }
+        }
+        self.statement(&function.body, &Target::Return)?;
+        self.depth -= 1;
+        self.span = function.span;
+        self.emit("}".to_string());
+        Ok(())
+    }
+
+    fn emit(&mut self, text: String) {
+        let span = if self.mapped { self.span } else { None };
+        for line in text.split('\n') {
+            if line.is_empty() {
+                self.lines.push((String::new(), None));
+            } else {
+                self.lines.push((format!("{}{}", "    ".repeat(self.depth), line), span));
+            }
+        }
+    }
+
+    fn helper(&mut self, name: &'static str) -> &'static str {
+        if ["$add", "$sub", "$mul", "$div"].contains(&name) {
+            self.helpers.insert("$check");
+        }
+        self.helpers.insert(name);
+        name
+    }
+
+    fn name(&self, variable: Variable) -> String {
+        let name = match variable {
+            Variable::Local(i) => self.function.expect("local variable outside a function").locals.borrow()[i].clone(),
+            Variable::Global(i) => self.layout.globals[i].clone(),
+        };
+        let name = identifier(&name);
+        if self.functions.contains(&name) { format!("{}_", name) } else { name }
+    }
+
+    // 式を文として出力し、その値を target に渡す。if や while は JavaScript の文にする
+    fn statement(&mut self, expression: &ast::Expression, target: &Target) -> Result<(), CodegenError> {
+        let saved = self.span;
+        if expression.span().is_some() {
+            self.span = expression.span();
+        }
+        match expression.node() {
+            Node::Block(x) => {
+                if x.elements.is_empty() {
+                    self.finish(target, "0".to_string());
+                }
+                for (i, element) in x.elements.iter().enumerate() {
+                    let last = i + 1 == x.elements.len();
+                    self.statement(element, if last { target } else { &Target::Discard })?;
+                }
+            },
+            Node::If(x) => {
+                let condition = self.condition(&x.condition)?;
+                self.emit(format!("if ({}) {{", condition));
+                let before = self.assigned.clone();
+                self.depth += 1;
+                self.statement(&x.then_clause, target)?;
+                self.depth -= 1;
+                let then_assigned = std::mem::replace(&mut self.assigned, before);
+                match (&x.else_clause, target) {
+                    (Some(else_clause), _) => {
+                        self.emit("} else {".to_string());
+                        self.depth += 1;
+                        self.statement(else_clause, target)?;
+                        self.depth -= 1;
+                    },
+                    (None, Target::Discard) => {},
+                    (None, _) => {
+                        self.emit("} else {".to_string());
+                        self.depth += 1;
+                        self.finish(target, "1".to_string());
+                        self.depth -= 1;
+                    },
+                }
+                self.emit("}".to_string());
+                self.assigned.retain(|x| then_assigned.contains(x));
+            },
+            Node::While(x) => {
+                let condition = self.condition(&x.condition)?;
+                self.emit(format!("while ({}) {{", condition));
+                let before = self.assigned.clone();
+                self.depth += 1;
+                self.statement(&x.body, &Target::Discard)?;
+                self.depth -= 1;
+                self.assigned = before;
+                self.emit("}".to_string());
+                self.finish(target, "1".to_string());
+            },
+            // 腕は if と else if を並べ、ガードや束縛のない腕があればそこで else にする。
+            // 束縛はガードより先に評価するので、ガードのある腕ではカンマ演算子で代入する。
+            // ガードが偽なら、取っておいた前の値に戻す
+            Node::Match(x) => {
+                let value = self.expression(&x.value)?;
+                self.matches += 1;
+                let constant = format!("$m{}", self.matches);
+                let saved = format!("$s{}", self.matches);
+                self.emit(format!("const {} = {};", constant, value));
+                if x.arms.iter().any(|y| matches!(y.pattern, ast::Pattern::Binding { .. }) && y.guard.is_some()) {
+                    self.emit(format!("let {};", saved));
+                }
+                let before = self.assigned.clone();
+                let mut after: Option<HashSet<Variable>> = None;
+                let mut exhaustive = false;
+                for (i, arm) in x.arms.iter().enumerate() {
+                    self.assigned = before.clone();
+                    if let ast::Pattern::Constructor { .. } = &arm.pattern {
+                        return Err(unsupported_enum(expression.span()));
+                    }
+                    let binding = match &arm.pattern {
+                        ast::Pattern::Binding { slot, .. } => {
+                            let variable = self.layout.variable(slot.get(), self.function);
+                            self.assigned.insert(variable);
+                            Some(self.name(variable))
+                        },
+                        _ => None,
+                    };
+                    let mut tests = Vec::new();
+                    if let ast::Pattern::Integers(values) = &arm.pattern {
+                        let equals: Vec<String> = values.iter().map(|y| format!("{} === {}", constant, y)).collect();
+                        tests.push(if equals.len() > 1 && arm.guard.is_some() { format!("({})", equals.join(" || ")) } else { equals.join(" || ") });
+                    }
+                    if let Some(guard) = &arm.guard {
+                        let condition = self.condition(guard)?;
+                        tests.push(match &binding {
+                            Some(name) => format!("({} = {}, {} = {}, {} || ({} = {}, false))", saved, name, name, constant, condition, name, saved),
+                            None => condition,
+                        });
+                    }
+                    exhaustive = tests.is_empty();
+                    match (i, exhaustive) {
+                        (0, true) => {},
+                        (_, true) => self.emit("} else {".to_string()),
+                        (0, false) => self.emit(format!("if ({}) {{", tests.join(" && "))),
+                        (_, false) => self.emit(format!("}} else if ({}) {{", tests.join(" && "))),
+                    }
+                    let nested = i > 0 || !exhaustive;
+                    self.depth += nested as usize;
+                    if let (Some(name), None) = (&binding, &arm.guard) {
+                        self.emit(format!("{} = {};", name, constant));
+                    }
+                    self.statement(&arm.body, target)?;
+                    self.depth -= nested as usize;
+                    let assigned = std::mem::take(&mut self.assigned);
+                    after = Some(match after {
+                        Some(y) => y.intersection(&assigned).copied().collect(),
+                        None => assigned,
+                    });
+                    if exhaustive {
+                        if nested {
+                            self.emit("}".to_string());
+                        }
+                        break;
+                    }
+                }
+                if !exhaustive {
+                    if !x.arms.is_empty() {
+                        self.emit("} else {".to_string());
+                        self.depth += 1;
+                    }
+                    let no_match = self.helper("$noMatch");
+                    self.emit(format!("{}({});", no_match, constant));
+                    if !x.arms.is_empty() {
+                        self.depth -= 1;
+                        self.emit("}".to_string());
+                    }
+                }
+                self.assigned = after.unwrap_or(before);
+            },
+            Node::Println(x) if matches!(target, Target::Discard) => {
+                let value = self.expression(&x.body)?;
+                self.emit(format!("console.log({});", value));
+            },
+            Node::Assignment(x) => {
+                let variable = self.layout.variable(x.slot.get(), self.function);
+                let name = self.name(variable);
+                match x.expression.node() {
+                    Node::If(_) | Node::While(_) | Node::Block(_) | Node::Match(_) => self.statement(&x.expression, &Target::Assign(name.clone()))?,
+                    _ => {
+                        let value = self.expression(&x.expression)?;
+                        self.emit(format!("{} = {};", name, value));
+                    },
+                }
+                self.assigned.insert(variable);
+                if !matches!(target, Target::Discard) {
+                    self.finish(target, name);
+                }
+            },
+            node => {
+                let value = self.expression(expression)?;
+                // 副作用のない値は捨てるなら出力しない
+                let pure = matches!(node, Node::Integer(_)) || matches!(node, Node::Identifier(_)) && !value.starts_with('$');
+                if !(pure && matches!(target, Target::Discard)) {
+                    self.finish(target, value);
+                }
+            },
+        }
+        self.span = saved;
+        Ok(())
+    }
+
+    fn finish(&mut self, target: &Target, value: String) {
+        match target {
+            Target::Return => self.emit(format!("return {};", value)),
+            Target::Discard if value.parse::<i32>().is_ok() => {},
+            Target::Discard => self.emit(format!("{};", value)),
+            Target::Assign(name) => self.emit(format!("{} = {};", name, value)),
+        }
+    }
+
+    // 条件。比較はそのまま JavaScript の真偽値にする
+    fn condition(&mut self, expression: &ast::Expression) -> Result<String, CodegenError> {
+        match expression.node() {
+            Node::Binary(x) if comparison(&x.operator).is_some() => {
+                let lhs = self.expression(&x.lhs)?;
+                let rhs = self.expression(&x.rhs)?;
+                Ok(format!("{} {} {}", lhs, comparison(&x.operator).unwrap(), rhs))
+            },
+            Node::Integer(x) => Ok((x.value != 0).to_string()),
+            _ => Ok(format!("{} !== 0", self.expression(expression)?)),
+        }
+    }
+
+    // 式を JavaScript の式にする。結果はどれも演算子の優先順位を気にせず引数や被演算子に置ける形になる
+    fn expression(&mut self, expression: &ast::Expression) -> Result<String, CodegenError> {
+        let node = expression.node();
+        match &node {
+            Node::Integer(x) => Ok(x.value.to_string()),
+            Node::Identifier(x) => {
+                let variable = self.layout.variable(x.slot.get(), self.function);
+                let name = self.name(variable);
+                if self.assigned.contains(&variable) {
+                    Ok(name)
+                } else {
+                    let read = self.helper("$read");
+                    Ok(format!("{}({}, {})", read, name, Json::from(x.name.as_str())))
+                }
+            },
+            Node::Assignment(x) => {
+                let value = self.expression(&x.expression)?;
+                let variable = self.layout.variable(x.slot.get(), self.function);
+                self.assigned.insert(variable);
+                Ok(format!("({} = {})", self.name(variable), value))
+            },
+            Node::Binary(x) => {
+                let lhs = self.expression(&x.lhs)?;
+                let rhs = self.expression(&x.rhs)?;
+                if let Some(operator) = comparison(&x.operator) {
+                    return Ok(format!("Number({} {} {})", lhs, operator, rhs));
+                }
+                let helper = self.helper(match x.operator {
+                    Operator::ADD => "$add",
+                    Operator::SUBTRACT => "$sub",
+                    Operator::MULTIPLY => "$mul",
+                    _ => "$div",
+                });
+                Ok(format!("{}({}, {})", helper, lhs, rhs))
+            },
+            Node::Block(_) | Node::While(_) | Node::If(_) | Node::Match(_) => {
+                // 値として使う文は即時実行する関数にする
+                let depth = std::mem::replace(&mut self.depth, 1);
+                let start = self.lines.len();
+                self.statement(expression, &Target::Return)?;
+                self.depth = depth;
+                let body: Vec<String> = self.lines.drain(start..).map(|x| x.0).collect();
+                Ok(format!("(() => {{\n{}\n}})()", body.join("\n")))
+            },
+            Node::Println(x) => {
+                let value = self.expression(&x.body)?;
+                Ok(format!("{}({})", self.helper("$println"), value))
+            },
+            Node::Call(_) | Node::LabelledCall(_) => {
+                let (function, args) = self.layout.call(&node, expression.span())?;
+                let bindings = self.layout.bindings(&node, self.function);
+                let mut values = Vec::new();
+                // 名前付きの引数は仮引数の順に並んでいる
+                for arg in args {
+                    values.push(self.expression(arg)?);
+                }
+                // インタプリタと同じく、実引数を呼び出し元の同じ名前の変数にも代入する。
+                // 分割代入ならすべての実引数を評価してから代入される
+                self.assigned.extend(bindings.iter().copied());
+                let names: Vec<String> = bindings.into_iter().map(|x| self.name(x)).collect();
+                let arguments = match names.len() {
+                    0 => values.join(", "),
+                    1 => format!("{} = {}", names[0], values[0]),
+                    _ => format!("...[{}] = [{}]", names.join(", "), values.join(", ")),
+                };
+                Ok(format!("{}({})", identifier(&function.name), arguments))
+            },
+            Node::EnvironmentVariable(x) => {
+                let env = self.helper("$env");
+                match &x.default {
+                    Some(default) => {
+                        // 既定値は環境変数がないときだけ評価されるので、その中の代入は代入済みにしない
+                        let before = self.assigned.clone();
+                        let value = self.expression(default)?;
+                        self.assigned = before;
+                        Ok(format!("{}({}, () => {})", env, Json::from(x.name.as_str()), value))
+                    },
+                    None => Ok(format!("{}({})", env, Json::from(x.name.as_str()))),
+                }
+            },
+            Node::Assert(x) => {
+                let left = self.expression(&x.left)?;
+                match &x.right {
+                    Some(right) => {
+                        let right = self.expression(right)?;
+                        self.helper("$assert");
+                        Ok(format!("$assertEq({}, {})", left, right))
+                    },
+                    None => Ok(format!("{}({})", self.helper("$assert"), left)),
+                }
+            },
+            Node::Field(_) | Node::FieldAssignment(_) | Node::Construct(_) => Err(unsupported_record(expression.span())),
+            Node::Throw(_) | Node::Try(_) => Err(unsupported_exception(expression.span())),
+        }
+    }
+}
+
+fn comparison(operator: &Operator) -> Option<&'static str> {
+    match operator {
+        Operator::LessThan => Some("<"),
+        Operator::LessOrEqual => Some("<="),
+        Operator::GreaterThan => Some(">"),
+        Operator::GreaterOrEqual => Some(">="),
+        Operator::EqualEqual => Some("==="),
+        Operator::NotEqual => Some("!=="),
+        _ => None,
+    }
+}
+
+// JavaScript の予約語と重なる名前には _ を付ける
+fn identifier(name: &str) -> String {
+    let name = mangle(name);
+    if RESERVED.contains(&name.as_str()) { format!("{}_", name) } else { name }
+}
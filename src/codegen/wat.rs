@@ -0,0 +1,474 @@
+use super::{unsupported_enum, unsupported_exception, unsupported_record, CodegenError, Layout, Variable};
+use crate::ast;
+use crate::ast::{FunctionDefinition, Node, Operator};
+
+use std::collections::HashMap;
+
+// 代入前の変数を表す値。変数は i64 に置き、この値なら読んだときにエラーにする
+const UNSET: &str = "-9223372036854775808";
+
+// ホストが用意する関数。
+// println(値) は値を一行に書き、fail(位置, 長さ) はメモリ上の UTF-8 のメッセージを報告して実行を止める。
+// env(位置, 長さ) は名前の環境変数を整数として返し、未設定なら UNSET を返す（整数でなければホストがエラーにする）
+const IMPORTS: &str = r#"  (import "toys" "println" (func $println (param i32)))
+  (import "toys" "fail" (func $fail (param i32 i32)))
+  (import "toys" "env" (func $env (param i32 i32) (result i64)))
+"#;
+
+// プログラムを WebAssembly のテキスト形式のモジュールにする。
+// 関数は $f.名前、グローバル変数は $g.名前 になり、main は toys の main と同じ引数で export する
+pub fn generate(program: &ast::Program) -> Result<String, CodegenError> {
+    let layout = Layout::new(program)?;
+    let mut data = Data::default();
+    let mut functions = String::new();
+    for function in layout.functions.iter() {
+        functions.push_str(&Generator::new(&layout, Some(function), &mut data).function(function)?);
+    }
+    functions.push_str(&Generator::new(&layout, None, &mut data).initializer()?);
+    let helpers = helpers(&mut data);
+
+    let mut out = String::from("(module\n");
+    out.push_str(IMPORTS);
+    out.push_str(&format!("  (memory (export \"memory\") {})\n", data.bytes.len() / 65536 + 1));
+    out.push_str(&format!("  (data (i32.const 0) \"{}\")\n", escape(&data.bytes)));
+    for name in layout.globals.iter() {
+        out.push_str(&format!("  (global {} (mut i64) (i64.const {}))\n", global(name), UNSET));
+    }
+    out.push_str(&helpers);
+    out.push_str(&functions);
+    out.push_str(&entry(&layout));
+    out.push_str(")\n");
+    Ok(out)
+}
+
+// メッセージや環境変数の名前を置くメモリの中身
+#[derive(Default)]
+struct Data {
+    bytes: Vec<u8>,
+    offsets: HashMap<String, usize>,
+    no_match: bool, // match を使っていれば $no_match を出力する
+}
+
+impl Data {
+    // 文字列を置き、その位置と長さを積む命令を返す
+    fn string(&mut self, text: &str) -> String {
+        let bytes = &mut self.bytes;
+        let offset = *self.offsets.entry(text.to_string()).or_insert_with(|| {
+            let offset = bytes.len();
+            bytes.extend_from_slice(text.as_bytes());
+            offset
+        });
+        format!("i32.const {}\ni32.const {}", offset, text.len())
+    }
+
+    // 実行時に書き換える領域を確保し、その位置を返す
+    fn reserve(&mut self, text: &str, extra: usize) -> usize {
+        let offset = self.bytes.len();
+        self.bytes.extend_from_slice(text.as_bytes());
+        self.bytes.resize(self.bytes.len() + extra, b' ');
+        offset
+    }
+}
+
+struct Generator<'a, 'b> {
+    layout: &'a Layout<'a>,
+    function: Option<&'a FunctionDefinition>,
+    data: &'b mut Data,
+    lines: Vec<String>,
+    depth: usize,
+    matches: usize, // match の値を置く i32 の変数 $m.0, $m.1, ... の数
+    calls: usize,   // 呼び出し元に代入する実引数を置く i32 の変数 $c.0, $c.1, ... の数
+    saves: usize,   // ガードの前に変数の値を取っておく i64 の変数 $s.0, $s.1, ... の数
+}
+
+impl<'a, 'b> Generator<'a, 'b> {
+    fn new(layout: &'a Layout<'a>, function: Option<&'a FunctionDefinition>, data: &'b mut Data) -> Self {
+        Self { layout, function, data, lines: Vec::new(), depth: 2, matches: 0, calls: 0, saves: 0 }
+    }
+
+    fn function(mut self, function: &FunctionDefinition) -> Result<String, CodegenError> {
+        let parameters: Vec<String> = function.args.iter().map(|x| format!(" (param $a.{} i32)", x)).collect();
+        let mut header = format!("  (func {}{} (result i32)\n", function_name(&function.name), parameters.concat());
+        // 仮引数は i32 で受け取り、他の変数と同じく i64 の変数に移す。main の仮引数はグローバル変数になる
+        if function.is_main() {
+            for (name, slot) in function.args.iter().zip(function.global_parameters.borrow().iter()) {
+                self.emit(format!("local.get $a.{}", name));
+                self.emit("i64.extend_i32_s".to_string());
+                self.emit(format!("global.set {}", global(&self.layout.globals[slot.index])));
+            }
+        } else {
+            for (i, name) in function.locals.borrow().iter().enumerate() {
+                header.push_str(&format!("    (local $v.{} i64)\n", name));
+                if i < function.args.len() {
+                    self.emit(format!("local.get $a.{}", name));
+                    self.emit("i64.extend_i32_s".to_string());
+                } else {
+                    self.emit(format!("i64.const {}", UNSET));
+                }
+                self.emit(format!("local.set $v.{}", name));
+            }
+        }
+        header.push_str("    (local $tmp i32)\n    (local $env i64)\n");
+        self.expression(&function.body)?;
+        Ok(format!("{}{}{}  )\n", header, self.temporaries(), self.finish()))
+    }
+
+    // グローバル変数の初期化式を定義の順に評価する
+    fn initializer(mut self) -> Result<String, CodegenError> {
+        for definition in self.layout.initializers.iter() {
+            self.expression(&definition.body)?;
+            self.emit("i64.extend_i32_s".to_string());
+            let variable = self.variable(definition.slot.get());
+            self.emit(format!("global.set {}", variable));
+        }
+        Ok(format!("  (func $initialize\n    (local $tmp i32)\n    (local $env i64)\n{}{}  )\n", self.temporaries(), self.finish()))
+    }
+
+    // match と呼び出しで値を置く変数の宣言
+    fn temporaries(&self) -> String {
+        let matches = (0..self.matches).map(|i| format!("    (local $m.{} i32)\n", i));
+        let calls = (0..self.calls).map(|i| format!("    (local $c.{} i32)\n", i));
+        let saves = (0..self.saves).map(|i| format!("    (local $s.{} i64)\n", i));
+        matches.chain(calls).chain(saves).collect()
+    }
+
+    fn finish(&self) -> String {
+        self.lines.iter().map(|x| format!("{}\n", x)).collect()
+    }
+
+    // 複数行の命令も同じ深さで字下げする
+    fn emit(&mut self, instructions: String) {
+        for line in instructions.lines() {
+            self.lines.push(format!("{}{}", "  ".repeat(self.depth), line));
+        }
+    }
+
+    fn open(&mut self, instruction: &str) {
+        self.emit(instruction.to_string());
+        self.depth += 1;
+    }
+
+    fn close(&mut self, instruction: &str) {
+        self.depth -= 1;
+        self.emit(instruction.to_string());
+    }
+
+    fn variable(&self, slot: Option<ast::Slot>) -> String {
+        self.name(self.layout.variable(slot, self.function))
+    }
+
+    fn name(&self, variable: Variable) -> String {
+        match variable {
+            Variable::Local(i) => format!("$v.{}", self.function.unwrap().locals.borrow()[i]),
+            Variable::Global(i) => global(&self.layout.globals[i]),
+        }
+    }
+
+    fn get(&self, variable: &str) -> String {
+        if variable.starts_with("$g.") { format!("global.get {}", variable) } else { format!("local.get {}", variable) }
+    }
+
+    fn set(&self, variable: &str) -> String {
+        if variable.starts_with("$g.") { format!("global.set {}", variable) } else { format!("local.set {}", variable) }
+    }
+
+    fn fail(&mut self, message: &str) {
+        let message = self.data.string(message);
+        self.emit(message);
+        self.emit("call $fail".to_string());
+        self.emit("unreachable".to_string());
+    }
+
+    // 式の値を一つスタックに積む命令を出力する
+    fn expression(&mut self, expression: &ast::Expression) -> Result<(), CodegenError> {
+        let node = expression.node();
+        match &node {
+            Node::Integer(x) => self.emit(format!("i32.const {}", x.value)),
+            Node::Identifier(x) => {
+                let variable = self.variable(x.slot.get());
+                self.emit(self.get(&variable));
+                let message = self.data.string(&format!("undefined variable: {}", x.name));
+                self.emit(message);
+                self.emit("call $read".to_string());
+            },
+            Node::Assignment(x) => {
+                self.expression(&x.expression)?;
+                let variable = self.variable(x.slot.get());
+                self.emit("local.tee $tmp".to_string());
+                self.emit("i64.extend_i32_s".to_string());
+                self.emit(self.set(&variable));
+                self.emit("local.get $tmp".to_string());
+            },
+            Node::Binary(x) => {
+                self.expression(&x.lhs)?;
+                self.expression(&x.rhs)?;
+                let instruction = match x.operator {
+                    Operator::ADD => "call $add",
+                    Operator::SUBTRACT => "call $sub",
+                    Operator::MULTIPLY => "call $mul",
+                    Operator::DIVIDE => "call $div",
+                    Operator::LessThan => "i32.lt_s",
+                    Operator::LessOrEqual => "i32.le_s",
+                    Operator::GreaterThan => "i32.gt_s",
+                    Operator::GreaterOrEqual => "i32.ge_s",
+                    Operator::EqualEqual => "i32.eq",
+                    Operator::NotEqual => "i32.ne",
+                };
+                self.emit(instruction.to_string());
+            },
+            Node::Block(x) => {
+                if x.elements.is_empty() {
+                    self.emit("i32.const 0".to_string());
+                }
+                for (i, element) in x.elements.iter().enumerate() {
+                    if i > 0 {
+                        self.emit("drop".to_string());
+                    }
+                    self.expression(element)?;
+                }
+            },
+            Node::While(x) => {
+                self.open("block");
+                self.open("loop");
+                self.expression(&x.condition)?;
+                self.emit("i32.eqz".to_string());
+                self.emit("br_if 1".to_string());
+                self.expression(&x.body)?;
+                self.emit("drop".to_string());
+                self.emit("br 0".to_string());
+                self.close("end");
+                self.close("end");
+                self.emit("i32.const 1".to_string());
+            },
+            Node::If(x) => {
+                self.expression(&x.condition)?;
+                self.open("if (result i32)");
+                self.expression(&x.then_clause)?;
+                self.close("else");
+                self.depth += 1;
+                match &x.else_clause {
+                    Some(clause) => self.expression(clause)?,
+                    None => self.emit("i32.const 1".to_string()),
+                }
+                self.close("end");
+            },
+            Node::Println(x) => {
+                self.expression(&x.body)?;
+                self.emit("call $println".to_string());
+                self.emit("i32.const 0".to_string());
+            },
+            Node::Call(_) | Node::LabelledCall(_) => {
+                let (function, args) = self.layout.call(&node, expression.span())?;
+                let count = args.len();
+                for arg in args {
+                    self.expression(arg)?;
+                }
+                // インタプリタと同じく、実引数を呼び出し元の同じ名前の変数にも代入する。
+                // 積んだ実引数をいったん $c.* に移してから代入し、積み直す
+                let bindings = self.layout.bindings(&node, self.function);
+                if !bindings.is_empty() {
+                    let first = self.calls;
+                    self.calls += count;
+                    for i in (first..first + count).rev() {
+                        self.emit(format!("local.set $c.{}", i));
+                    }
+                    for (i, variable) in bindings.into_iter().enumerate() {
+                        let variable = self.name(variable);
+                        self.emit(format!("local.get $c.{}", first + i));
+                        self.emit("i64.extend_i32_s".to_string());
+                        self.emit(self.set(&variable));
+                    }
+                    for i in first..first + count {
+                        self.emit(format!("local.get $c.{}", i));
+                    }
+                }
+                self.emit(format!("call {}", function_name(&function.name)));
+            },
+            Node::EnvironmentVariable(x) => {
+                let name = self.data.string(&x.name);
+                self.emit(name);
+                self.emit("call $env".to_string());
+                self.emit("local.tee $env".to_string());
+                self.emit(format!("i64.const {}", UNSET));
+                self.emit("i64.eq".to_string());
+                self.open("if (result i32)");
+                match &x.default {
+                    Some(default) => self.expression(default)?,
+                    None => self.fail(&format!("environment variable {} is not set.", x.name)),
+                }
+                self.close("else");
+                self.depth += 1;
+                self.emit("local.get $env".to_string());
+                self.emit("i32.wrap_i64".to_string());
+                self.close("end");
+            },
+            Node::Assert(x) => {
+                self.expression(&x.left)?;
+                let message = match &x.right {
+                    Some(right) => {
+                        self.expression(right)?;
+                        self.emit("i32.ne".to_string());
+                        "assertion failed: left == right"
+                    },
+                    None => {
+                        self.emit("i32.eqz".to_string());
+                        "assertion failed"
+                    },
+                };
+                self.open("if");
+                self.fail(message);
+                self.close("end");
+                self.emit("i32.const 1".to_string());
+            },
+            // 腕ごとの block を抜けると次の腕を試す。合った腕は本体の値を持って外側の block を抜ける
+            Node::Match(x) => {
+                self.expression(&x.value)?;
+                let value = format!("$m.{}", self.matches);
+                self.matches += 1;
+                self.emit(format!("local.set {}", value));
+                self.open("block (result i32)");
+                for arm in x.arms.iter() {
+                    self.open("block");
+                    let mut saved = None;
+                    match &arm.pattern {
+                        ast::Pattern::Integers(values) => {
+                            for (i, y) in values.iter().enumerate() {
+                                self.emit(format!("local.get {}", value));
+                                self.emit(format!("i32.const {}", y));
+                                self.emit("i32.eq".to_string());
+                                if i > 0 {
+                                    self.emit("i32.or".to_string());
+                                }
+                            }
+                            self.emit("i32.eqz".to_string());
+                            self.emit("br_if 0".to_string());
+                        },
+                        ast::Pattern::Binding { slot, .. } => {
+                            let variable = self.variable(slot.get());
+                            // ガードが偽なら変数を match の前の値に戻すので、前の値を取っておく
+                            if arm.guard.is_some() {
+                                let save = format!("$s.{}", self.saves);
+                                self.saves += 1;
+                                self.emit(self.get(&variable));
+                                self.emit(format!("local.set {}", save));
+                                saved = Some((variable.clone(), save));
+                            }
+                            self.emit(format!("local.get {}", value));
+                            self.emit("i64.extend_i32_s".to_string());
+                            self.emit(self.set(&variable));
+                        },
+                        ast::Pattern::Wildcard => {},
+                        ast::Pattern::Constructor { .. } => return Err(unsupported_enum(expression.span())),
+                    }
+                    if let Some(guard) = &arm.guard {
+                        self.expression(guard)?;
+                        self.emit("i32.eqz".to_string());
+                        match saved {
+                            Some((variable, save)) => {
+                                self.open("if");
+                                self.emit(format!("local.get {}", save));
+                                self.emit(self.set(&variable));
+                                self.emit("br 1".to_string());
+                                self.close("end");
+                            },
+                            None => self.emit("br_if 0".to_string()),
+                        }
+                    }
+                    self.expression(&arm.body)?;
+                    self.emit("br 1".to_string());
+                    self.close("end");
+                }
+                self.data.no_match = true;
+                self.emit(format!("local.get {}", value));
+                self.emit("call $no_match".to_string());
+                self.emit("unreachable".to_string());
+                self.close("end");
+            },
+            Node::Field(_) | Node::FieldAssignment(_) | Node::Construct(_) => return Err(unsupported_record(expression.span())),
+            Node::Throw(_) | Node::Try(_) => return Err(unsupported_exception(expression.span())),
+        }
+        Ok(())
+    }
+}
+
+// 変数の読み出しと、オーバーフローとゼロ除算を調べる演算
+fn helpers(data: &mut Data) -> String {
+    let overflow = data.string("integer overflow").replace('\n', " ");
+    let division = data.string("division by zero").replace('\n', " ");
+    let mut out = String::new();
+    out.push_str("  (func $read (param $value i64) (param $message i32) (param $length i32) (result i32)\n");
+    out.push_str(&format!("    (if (i64.eq (local.get $value) (i64.const {}))\n", UNSET));
+    out.push_str("      (then (call $fail (local.get $message) (local.get $length)) (unreachable)))\n");
+    out.push_str("    (i32.wrap_i64 (local.get $value)))\n");
+    out.push_str("  (func $check (param $value i64) (result i32)\n");
+    out.push_str("    (if (i32.or (i64.lt_s (local.get $value) (i64.const -2147483648)) (i64.gt_s (local.get $value) (i64.const 2147483647)))\n");
+    out.push_str(&format!("      (then {} call $fail unreachable))\n", overflow));
+    out.push_str("    (i32.wrap_i64 (local.get $value)))\n");
+    for (name, instruction) in [("add", "i64.add"), ("sub", "i64.sub"), ("mul", "i64.mul")] {
+        out.push_str(&format!("  (func ${} (param $lhs i32) (param $rhs i32) (result i32)\n", name));
+        out.push_str(&format!("    (call $check ({} (i64.extend_i32_s (local.get $lhs)) (i64.extend_i32_s (local.get $rhs)))))\n", instruction));
+    }
+    out.push_str("  (func $div (param $lhs i32) (param $rhs i32) (result i32)\n");
+    out.push_str("    (if (i32.eqz (local.get $rhs))\n");
+    out.push_str(&format!("      (then {} call $fail unreachable))\n", division));
+    out.push_str("    (call $check (i64.div_s (i64.extend_i32_s (local.get $lhs)) (i64.extend_i32_s (local.get $rhs)))))\n");
+    if data.no_match {
+        out.push_str(&no_match(data));
+    }
+    out
+}
+
+// メッセージの後ろに値を十進で書き込んでから fail を呼ぶ。$length は符号と桁の数
+fn no_match(data: &mut Data) -> String {
+    let prefix = "no match arm for value ";
+    let offset = data.reserve(prefix, 11);
+    let digits = offset + prefix.len();
+    let mut out = String::new();
+    out.push_str("  (func $no_match (param $value i32)\n");
+    out.push_str("    (local $n i64) (local $m i64) (local $length i32) (local $position i32)\n");
+    out.push_str("    (local.set $n (i64.extend_i32_s (local.get $value)))\n");
+    out.push_str("    (if (i64.lt_s (local.get $n) (i64.const 0))\n");
+    out.push_str(&format!("      (then (i32.store8 (i32.const {}) (i32.const 45)) (local.set $n (i64.sub (i64.const 0) (local.get $n))) (local.set $length (i32.const 1))))\n", digits));
+    out.push_str("    (local.set $m (local.get $n))\n");
+    out.push_str("    (loop $count\n");
+    out.push_str("      (local.set $length (i32.add (local.get $length) (i32.const 1)))\n");
+    out.push_str("      (local.set $m (i64.div_u (local.get $m) (i64.const 10)))\n");
+    out.push_str("      (br_if $count (i64.ne (local.get $m) (i64.const 0))))\n");
+    out.push_str(&format!("    (local.set $position (i32.add (i32.const {}) (local.get $length)))\n", digits));
+    out.push_str("    (loop $digit\n");
+    out.push_str("      (local.set $position (i32.sub (local.get $position) (i32.const 1)))\n");
+    out.push_str("      (i32.store8 (local.get $position) (i32.add (i32.const 48) (i32.wrap_i64 (i64.rem_u (local.get $n) (i64.const 10)))))\n");
+    out.push_str("      (local.set $n (i64.div_u (local.get $n) (i64.const 10)))\n");
+    out.push_str("      (br_if $digit (i64.ne (local.get $n) (i64.const 0))))\n");
+    out.push_str(&format!("    (call $fail (i32.const {}) (i32.add (i32.const {}) (local.get $length)))\n", offset, prefix.len()));
+    out.push_str("    unreachable)\n");
+    out
+}
+
+// 初期化してから toys の main を呼ぶ関数を main として export する
+fn entry(layout: &Layout) -> String {
+    let main = layout.function("main").unwrap();
+    let parameters: String = main.args.iter().map(|_| " (param i32)").collect();
+    let mut out = format!("  (func (export \"main\"){} (result i32)\n    call $initialize\n", parameters);
+    for i in 0..main.args.len() {
+        out.push_str(&format!("    local.get {}\n", i));
+    }
+    out.push_str(&format!("    call {})\n", function_name(&main.name)));
+    out
+}
+
+fn function_name(name: &str) -> String {
+    format!("$f.{}", name)
+}
+
+fn global(name: &str) -> String {
+    format!("$g.{}", name)
+}
+
+// 表示できる ASCII 以外と " と \ は \hh で書く
+fn escape(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| match *x {
+        0x20..=0x7e if *x != b'"' && *x != b'\\' => (*x as char).to_string(),
+        _ => format!("\\{:02x}", x),
+    }).collect()
+}
@@ -0,0 +1,632 @@
+use super::{mangle, unsupported_enum, unsupported_exception, unsupported_record, CodegenError, Layout, Variable};
+use crate::ast;
+use crate::ast::{FunctionDefinition, Node, Operator};
+
+// 代入前の変数を表す値。変数は 8 バイトに置き、この値なら読んだときにエラーにする
+const UNSET: &str = "-9223372036854775808";
+
+// エラーを報告して終了する処理と、コマンドライン引数を整数として読む処理。
+// toys_fail は %rdi の書式と %rsi, %rdx, %rcx の値で標準エラー出力に書き、1 で終了する。
+// どこから呼ばれてもよいよう、スタックを揃え直してから libc を呼ぶ
+const RUNTIME: &str = r#"    .text
+toys_fail:
+    andq $-16, %rsp
+    movq %rdi, %rbx
+    movq %rsi, %r12
+    movq %rdx, %r13
+    movq %rcx, %r14
+    xorl %edi, %edi
+    call fflush@PLT
+    movl $2, %edi
+    movq %rbx, %rsi
+    movq %r12, %rdx
+    movq %r13, %rcx
+    movq %r14, %r8
+    xorl %eax, %eax
+    call dprintf@PLT
+    movl $1, %edi
+    call exit@PLT
+
+# %rdi の文字列を整数として読み、%eax に値を、読めたら %edx に 1 を返す。%esi が 1 なら前後の空白を許す
+toys_parse:
+    pushq %rbp
+    movq %rsp, %rbp
+    pushq %rbx
+    pushq %r12
+    subq $16, %rsp
+    andq $-16, %rsp
+    movl %esi, %r12d
+    movq %rdi, %rbx
+    testl %r12d, %r12d
+    je .Lparse_sign
+.Lparse_leading:
+    movzbl (%rbx), %r11d
+    call toys_space
+    jne .Lparse_sign
+    incq %rbx
+    jmp .Lparse_leading
+.Lparse_sign:
+    movzbl (%rbx), %eax
+    cmpl $45, %eax
+    je .Lparse_signed
+    cmpl $43, %eax
+    jne .Lparse_digit
+.Lparse_signed:
+    movzbl 1(%rbx), %eax
+.Lparse_digit:
+    subl $48, %eax
+    cmpl $9, %eax
+    ja .Lparse_invalid
+    movq %rbx, %rdi
+    leaq -32(%rbp), %rsi
+    movl $10, %edx
+    call strtol@PLT
+    movq -32(%rbp), %rcx
+    testl %r12d, %r12d
+    je .Lparse_end
+.Lparse_trailing:
+    movzbl (%rcx), %r11d
+    call toys_space
+    jne .Lparse_end
+    incq %rcx
+    jmp .Lparse_trailing
+.Lparse_end:
+    cmpb $0, (%rcx)
+    jne .Lparse_invalid
+    movslq %eax, %rcx
+    cmpq %rax, %rcx
+    jne .Lparse_invalid
+    movl $1, %edx
+    jmp .Lparse_done
+.Lparse_invalid:
+    xorl %edx, %edx
+.Lparse_done:
+    movq -8(%rbp), %rbx
+    movq -16(%rbp), %r12
+    leave
+    ret
+
+# %r11d の文字が空白（タブや改行を含む）なら ZF を立てる
+toys_space:
+    cmpl $32, %r11d
+    je .Lspace_done
+    subl $9, %r11d
+    cmpl $4, %r11d
+    ja .Lspace_no
+    cmpl %r11d, %r11d
+    ret
+.Lspace_no:
+    testq %rsp, %rsp
+.Lspace_done:
+    ret
+
+# %rdi の名前の環境変数を整数として %rax に返す。未設定なら代入前の値を返し、整数でなければエラーにする
+toys_env:
+    pushq %rbp
+    movq %rsp, %rbp
+    pushq %rbx
+    pushq %r12
+    andq $-16, %rsp
+    movq %rdi, %r12
+    call getenv@PLT
+    testq %rax, %rax
+    jne .Lenv_set
+    movabsq $-9223372036854775808, %rax
+    jmp .Lenv_done
+.Lenv_set:
+    movq %rax, %rbx
+    movq %rax, %rdi
+    movl $1, %esi
+    call toys_parse
+    testl %edx, %edx
+    jne .Lenv_valid
+    leaq .Lenv_invalid(%rip), %rdi
+    movq %r12, %rsi
+    movq %rbx, %rdx
+    call toys_fail
+.Lenv_valid:
+    movslq %eax, %rax
+.Lenv_done:
+    movq -8(%rbp), %rbx
+    movq -16(%rbp), %r12
+    leave
+    ret
+
+    .section .rodata
+.Lenv_invalid:
+    .string "error: environment variable %s is not an integer: %s\n"
+    .text
+"#;
+
+// プログラムを x86-64 の System V ABI に従う GNU as のアセンブリにする。cc でリンクすると
+// libc の main として動き、コマンドライン引数を toys の main に渡してその返り値を終了コードにする。
+// toys の関数どうしは、実引数をすべてスタックに積んで呼び出す
+pub fn generate(program: &ast::Program) -> Result<String, CodegenError> {
+    let layout = Layout::new(program)?;
+    let mut strings = Strings::default();
+    let mut labels = 0;
+    let mut text = String::new();
+    for function in layout.functions.iter() {
+        let mut generator = Generator::new(&layout, Some(function), &mut strings, &mut labels);
+        text.push_str(&generator.function(function)?);
+    }
+    text.push_str(&Generator::new(&layout, None, &mut strings, &mut labels).initializer()?);
+    text.push_str(&entry(&layout, &mut strings));
+
+    let mut out = String::from(RUNTIME);
+    out.push('\n');
+    out.push_str(&text);
+    out.push_str("\n    .data\n    .p2align 3\n");
+    for name in layout.globals.iter() {
+        out.push_str(&format!("{}:\n    .quad {}\n", global(name), UNSET));
+    }
+    out.push_str("\n    .section .rodata\n");
+    for (i, text) in strings.texts.iter().enumerate() {
+        out.push_str(&format!(".Lstring{}:\n    .string \"{}\"\n", i, escape(text)));
+    }
+    out.push_str("\n    .section .note.GNU-stack,\"\",@progbits\n");
+    Ok(out)
+}
+
+#[derive(Default)]
+struct Strings {
+    texts: Vec<String>,
+}
+
+impl Strings {
+    // 文字列を読み取り専用のデータに置き、そのラベルを返す
+    fn label(&mut self, text: &str) -> String {
+        let index = match self.texts.iter().position(|x| x == text) {
+            Some(x) => x,
+            None => {
+                self.texts.push(text.to_string());
+                self.texts.len() - 1
+            },
+        };
+        format!(".Lstring{}", index)
+    }
+}
+
+struct Generator<'a, 'b> {
+    layout: &'a Layout<'a>,
+    function: Option<&'a FunctionDefinition>,
+    strings: &'b mut Strings,
+    labels: &'b mut usize,
+    lines: Vec<String>,
+    pushed: usize, // フレームを作った後にスタックに積んだ 8 バイトの数。呼び出しの前にスタックを 16 バイトに揃えるのに使う
+}
+
+impl<'a, 'b> Generator<'a, 'b> {
+    fn new(layout: &'a Layout<'a>, function: Option<&'a FunctionDefinition>, strings: &'b mut Strings, labels: &'b mut usize) -> Self {
+        Self { layout, function, strings, labels, lines: Vec::new(), pushed: 0 }
+    }
+
+    // ローカル変数は -8(%rbp) から順に置く。実引数は最後のものが 16(%rbp) にある
+    fn function(&mut self, function: &FunctionDefinition) -> Result<String, CodegenError> {
+        let arity = function.args.len();
+        let argument = |i: usize| 16 + 8 * (arity - 1 - i);
+        let locals = if function.is_main() { 0 } else { function.locals.borrow().len() };
+        let name = function_name(&function.name);
+        self.label(&name);
+        self.prologue(locals);
+        if function.is_main() {
+            for (i, slot) in function.global_parameters.borrow().iter().enumerate() {
+                self.emit(format!("movslq {}(%rbp), %rax", argument(i)));
+                self.emit(format!("movq %rax, {}(%rip)", global(&self.layout.globals[slot.index])));
+            }
+        } else {
+            for i in 0..locals {
+                if i < arity {
+                    self.emit(format!("movslq {}(%rbp), %rax", argument(i)));
+                } else {
+                    self.emit(format!("movabsq ${}, %rax", UNSET));
+                }
+                self.emit(format!("movq %rax, {}", local(i)));
+            }
+        }
+        self.expression(&function.body)?;
+        self.emit("leave".to_string());
+        self.emit("ret".to_string());
+        Ok(self.finish())
+    }
+
+    // グローバル変数の初期化式を定義の順に評価する
+    fn initializer(&mut self) -> Result<String, CodegenError> {
+        self.label("toys_initialize");
+        self.prologue(0);
+        for definition in self.layout.initializers.iter() {
+            self.expression(&definition.body)?;
+            let variable = self.variable(definition.slot.get());
+            self.emit("movslq %eax, %rax".to_string());
+            self.emit(format!("movq %rax, {}", variable));
+        }
+        self.emit("leave".to_string());
+        self.emit("ret".to_string());
+        Ok(self.finish())
+    }
+
+    fn prologue(&mut self, locals: usize) {
+        self.emit("pushq %rbp".to_string());
+        self.emit("movq %rsp, %rbp".to_string());
+        if locals > 0 {
+            self.emit(format!("subq ${}, %rsp", (locals * 8).div_ceil(16) * 16));
+        }
+    }
+
+    fn finish(&self) -> String {
+        self.lines.iter().map(|x| format!("{}\n", x)).collect::<String>() + "\n"
+    }
+
+    fn emit(&mut self, instruction: String) {
+        self.lines.push(format!("    {}", instruction));
+    }
+
+    fn label(&mut self, label: &str) {
+        self.lines.push(format!("{}:", label));
+    }
+
+    fn fresh(&mut self) -> String {
+        *self.labels += 1;
+        format!(".L{}", self.labels)
+    }
+
+    fn push(&mut self) {
+        self.emit("pushq %rax".to_string());
+        self.pushed += 1;
+    }
+
+    fn pop(&mut self, register: &str) {
+        self.emit(format!("popq {}", register));
+        self.pushed -= 1;
+    }
+
+    fn variable(&self, slot: Option<ast::Slot>) -> String {
+        self.name(self.layout.variable(slot, self.function))
+    }
+
+    fn name(&self, variable: Variable) -> String {
+        match variable {
+            Variable::Local(i) => local(i),
+            Variable::Global(i) => format!("{}(%rip)", global(&self.layout.globals[i])),
+        }
+    }
+
+    // 書式と値を渡して toys_fail を呼ぶ
+    fn fail(&mut self, format: &str) {
+        let label = self.strings.label(format);
+        self.emit(format!("leaq {}(%rip), %rdi", label));
+        self.emit("call toys_fail".to_string());
+    }
+
+    // 演算の結果があふれたら、左辺を %esi、右辺を %ecx に置いてエラーにする
+    fn overflow(&mut self, operator: Operator) {
+        let ok = self.fresh();
+        self.emit(format!("jno {}", ok));
+        let symbol = self.strings.label(&operator.to_string());
+        self.emit(format!("leaq {}(%rip), %rdx", symbol));
+        self.fail("error: integer overflow: %d %s %d\n");
+        self.label(&ok);
+    }
+
+    // 式の値を %eax に求める命令を出力する
+    fn expression(&mut self, expression: &ast::Expression) -> Result<(), CodegenError> {
+        let node = expression.node();
+        match &node {
+            Node::Integer(x) => self.emit(format!("movl ${}, %eax", x.value)),
+            Node::Identifier(x) => {
+                let variable = self.variable(x.slot.get());
+                let ok = self.fresh();
+                self.emit(format!("movq {}, %rax", variable));
+                self.emit(format!("movabsq ${}, %rcx", UNSET));
+                self.emit("cmpq %rcx, %rax".to_string());
+                self.emit(format!("jne {}", ok));
+                let name = self.strings.label(&x.name);
+                self.emit(format!("leaq {}(%rip), %rsi", name));
+                self.fail("error: undefined variable: %s\n");
+                self.label(&ok);
+            },
+            Node::Assignment(x) => {
+                self.expression(&x.expression)?;
+                let variable = self.variable(x.slot.get());
+                self.emit("movslq %eax, %rcx".to_string());
+                self.emit(format!("movq %rcx, {}", variable));
+            },
+            Node::Binary(x) => {
+                self.expression(&x.lhs)?;
+                self.push();
+                self.expression(&x.rhs)?;
+                self.emit("movl %eax, %ecx".to_string());
+                self.pop("%rax");
+                self.binary(x.operator);
+            },
+            Node::Block(x) => {
+                if x.elements.is_empty() {
+                    self.emit("movl $0, %eax".to_string());
+                }
+                for element in x.elements.iter() {
+                    self.expression(element)?;
+                }
+            },
+            Node::While(x) => {
+                let (start, end) = (self.fresh(), self.fresh());
+                self.label(&start);
+                self.expression(&x.condition)?;
+                self.emit("testl %eax, %eax".to_string());
+                self.emit(format!("je {}", end));
+                self.expression(&x.body)?;
+                self.emit(format!("jmp {}", start));
+                self.label(&end);
+                self.emit("movl $1, %eax".to_string());
+            },
+            Node::If(x) => {
+                let (otherwise, end) = (self.fresh(), self.fresh());
+                self.expression(&x.condition)?;
+                self.emit("testl %eax, %eax".to_string());
+                self.emit(format!("je {}", otherwise));
+                self.expression(&x.then_clause)?;
+                self.emit(format!("jmp {}", end));
+                self.label(&otherwise);
+                match &x.else_clause {
+                    Some(clause) => self.expression(clause)?,
+                    None => self.emit("movl $1, %eax".to_string()),
+                }
+                self.label(&end);
+            },
+            Node::Println(x) => {
+                self.expression(&x.body)?;
+                let format = self.strings.label("%d\n");
+                self.emit("movl %eax, %esi".to_string());
+                self.emit(format!("leaq {}(%rip), %rdi", format));
+                self.emit("xorl %eax, %eax".to_string());
+                self.aligned("call printf@PLT");
+                self.emit("movl $0, %eax".to_string());
+            },
+            Node::Call(_) | Node::LabelledCall(_) => {
+                let (function, args) = self.layout.call(&node, expression.span())?;
+                // 実引数を積み終えたときにスタックが 16 バイトに揃うよう、必要なら先に 8 バイト空ける
+                let padding = (self.pushed + args.len()) % 2;
+                if padding == 1 {
+                    self.emit("subq $8, %rsp".to_string());
+                    self.pushed += 1;
+                }
+                let count = args.len();
+                for arg in args {
+                    self.expression(arg)?;
+                    self.push();
+                }
+                // インタプリタと同じく、実引数を呼び出し元の同じ名前の変数にも代入する
+                for (i, variable) in self.layout.bindings(&node, self.function).into_iter().enumerate() {
+                    self.emit(format!("movslq {}(%rsp), %rcx", 8 * (count - 1 - i)));
+                    self.emit(format!("movq %rcx, {}", self.name(variable)));
+                }
+                self.emit(format!("call {}", function_name(&function.name)));
+                if count + padding > 0 {
+                    self.emit(format!("addq ${}, %rsp", 8 * (count + padding)));
+                }
+                self.pushed -= count + padding;
+            },
+            Node::EnvironmentVariable(x) => {
+                let end = self.fresh();
+                let name = self.strings.label(&x.name);
+                self.emit(format!("leaq {}(%rip), %rdi", name));
+                self.aligned("call toys_env");
+                self.emit(format!("movabsq ${}, %rcx", UNSET));
+                self.emit("cmpq %rcx, %rax".to_string());
+                self.emit(format!("jne {}", end));
+                match &x.default {
+                    Some(default) => self.expression(default)?,
+                    None => {
+                        self.emit(format!("leaq {}(%rip), %rsi", name));
+                        self.fail("error: environment variable %s is not set.\n");
+                    },
+                }
+                self.label(&end);
+            },
+            Node::Assert(x) => {
+                let ok = self.fresh();
+                self.expression(&x.left)?;
+                match &x.right {
+                    Some(right) => {
+                        self.push();
+                        self.expression(right)?;
+                        self.emit("movl %eax, %edx".to_string());
+                        self.pop("%rax");
+                        self.emit("cmpl %edx, %eax".to_string());
+                        self.emit(format!("je {}", ok));
+                        self.emit("movl %eax, %esi".to_string());
+                        self.fail("error: assertion failed: left == right\n  left: %d\n right: %d\n");
+                    },
+                    None => {
+                        self.emit("testl %eax, %eax".to_string());
+                        self.emit(format!("jne {}", ok));
+                        self.fail("error: assertion failed\n");
+                    },
+                }
+                self.label(&ok);
+                self.emit("movl $1, %eax".to_string());
+            },
+            // 値はスタックの先頭に置いておき、合わない腕は次の腕のラベルへ飛ぶ
+            Node::Match(x) => {
+                let end = self.fresh();
+                self.expression(&x.value)?;
+                self.push();
+                for arm in x.arms.iter() {
+                    let next = self.fresh();
+                    let mut saved = None;
+                    match &arm.pattern {
+                        ast::Pattern::Integers(values) => {
+                            let matched = self.fresh();
+                            self.emit("movl (%rsp), %eax".to_string());
+                            for y in values.iter() {
+                                self.emit(format!("cmpl ${}, %eax", y));
+                                self.emit(format!("je {}", matched));
+                            }
+                            self.emit(format!("jmp {}", next));
+                            self.label(&matched);
+                        },
+                        ast::Pattern::Binding { slot, .. } if arm.guard.is_some() => {
+                            // ガードが偽なら変数を match の前の値に戻すので、前の値を積んでおく
+                            let variable = self.variable(slot.get());
+                            self.emit(format!("movq {}, %rax", variable));
+                            self.push();
+                            self.emit("movslq 8(%rsp), %rax".to_string());
+                            self.emit(format!("movq %rax, {}", variable));
+                            saved = Some((variable, self.fresh()));
+                        },
+                        ast::Pattern::Binding { slot, .. } => {
+                            let variable = self.variable(slot.get());
+                            self.emit("movslq (%rsp), %rax".to_string());
+                            self.emit(format!("movq %rax, {}", variable));
+                        },
+                        ast::Pattern::Wildcard => {},
+                        ast::Pattern::Constructor { .. } => return Err(unsupported_enum(expression.span())),
+                    }
+                    if let Some(guard) = &arm.guard {
+                        self.expression(guard)?;
+                        self.emit("testl %eax, %eax".to_string());
+                        self.emit(format!("je {}", saved.as_ref().map_or(&next, |x| &x.1)));
+                    }
+                    if saved.is_some() {
+                        self.pop("%rcx");
+                    }
+                    self.expression(&arm.body)?;
+                    self.emit(format!("jmp {}", end));
+                    if let Some((variable, restore)) = saved {
+                        // ガードが偽で飛んできたときは、前の値がまだ積まれている
+                        self.label(&restore);
+                        self.emit("popq %rcx".to_string());
+                        self.emit(format!("movq %rcx, {}", variable));
+                    }
+                    self.label(&next);
+                }
+                self.emit("movl (%rsp), %esi".to_string());
+                self.fail("error: no match arm for value %d\n");
+                self.label(&end);
+                self.pop("%rcx");
+            },
+            Node::Field(_) | Node::FieldAssignment(_) | Node::Construct(_) => return Err(unsupported_record(expression.span())),
+            Node::Throw(_) | Node::Try(_) => return Err(unsupported_exception(expression.span())),
+        }
+        Ok(())
+    }
+
+    // 左辺が %eax、右辺が %ecx にある
+    fn binary(&mut self, operator: Operator) {
+        let comparison = match operator {
+            Operator::ADD | Operator::SUBTRACT | Operator::MULTIPLY => {
+                let instruction = match operator {
+                    Operator::ADD => "addl",
+                    Operator::SUBTRACT => "subl",
+                    _ => "imull",
+                };
+                self.emit("movl %eax, %esi".to_string());
+                self.emit(format!("{} %ecx, %eax", instruction));
+                self.overflow(operator);
+                return;
+            },
+            Operator::DIVIDE => {
+                let (nonzero, ok) = (self.fresh(), self.fresh());
+                self.emit("movl %eax, %esi".to_string());
+                self.emit("testl %ecx, %ecx".to_string());
+                self.emit(format!("jne {}", nonzero));
+                self.fail("error: division by zero: %d / 0\n");
+                self.label(&nonzero);
+                // -2147483648 / -1 は idiv が例外になるので先に調べる
+                self.emit("cmpl $-1, %ecx".to_string());
+                self.emit(format!("jne {}", ok));
+                self.emit("cmpl $-2147483648, %eax".to_string());
+                self.emit(format!("jne {}", ok));
+                let symbol = self.strings.label("/");
+                self.emit(format!("leaq {}(%rip), %rdx", symbol));
+                self.fail("error: integer overflow: %d %s %d\n");
+                self.label(&ok);
+                self.emit("cltd".to_string());
+                self.emit("idivl %ecx".to_string());
+                return;
+            },
+            Operator::LessThan => "setl",
+            Operator::LessOrEqual => "setle",
+            Operator::GreaterThan => "setg",
+            Operator::GreaterOrEqual => "setge",
+            Operator::EqualEqual => "sete",
+            Operator::NotEqual => "setne",
+        };
+        self.emit("cmpl %ecx, %eax".to_string());
+        self.emit(format!("{} %al", comparison));
+        self.emit("movzbl %al, %eax".to_string());
+    }
+
+    // スタックを 16 バイトに揃えて libc の関数を呼ぶ
+    fn aligned(&mut self, call: &str) {
+        if self.pushed % 2 == 1 {
+            self.emit("subq $8, %rsp".to_string());
+            self.emit(call.to_string());
+            self.emit("addq $8, %rsp".to_string());
+        } else {
+            self.emit(call.to_string());
+        }
+    }
+}
+
+// libc から呼ばれる main。コマンドライン引数を確かめてからグローバル変数を初期化し、toys の main を呼ぶ。
+// 整数でない引数は toys run と同じく 2 で終了する。仮引数より多い引数は捨て、足りない仮引数は 0 にする。
+// toys の main の返り値も toys run と同じ終了コードにする
+fn entry(layout: &Layout, strings: &mut Strings) -> String {
+    let main = layout.function("main").unwrap();
+    let arity = main.args.len();
+    let invalid = strings.label("error: argument %s is not an integer\n");
+    let mut lines: Vec<String> = vec![
+        "    .globl main", "main:",
+        "pushq %rbp", "movq %rsp, %rbp", "pushq %rbx", "pushq %r12", "pushq %r13", "pushq %r14",
+        "movslq %edi, %rbx", "movq %rsi, %r12", "movq $1, %r13",
+        ".Lmain_check:",
+        "cmpq %rbx, %r13", "jge .Lmain_checked",
+        "movq (%r12,%r13,8), %rdi", "xorl %esi, %esi", "call toys_parse", "testl %edx, %edx", "jne .Lmain_next",
+        "movq (%r12,%r13,8), %rdx", &format!("leaq {}(%rip), %rsi", invalid), "movl $2, %edi", "xorl %eax, %eax",
+        "call dprintf@PLT", "movl $2, %eax", "jmp .Lmain_return",
+        ".Lmain_next:",
+        "incq %r13", "jmp .Lmain_check",
+        ".Lmain_checked:",
+        "call toys_initialize",
+    ].into_iter().map(|x| x.to_string()).collect();
+    if arity % 2 == 1 {
+        lines.push("subq $8, %rsp".to_string());
+    }
+    for i in 1..=arity {
+        lines.push("xorl %eax, %eax".to_string());
+        lines.push(format!("cmpq ${}, %rbx", i));
+        lines.push(format!("jle .Lmain_arg{}", i));
+        lines.push(format!("movq {}(%r12), %rdi", 8 * i));
+        lines.push("xorl %esi, %esi".to_string());
+        lines.push("call toys_parse".to_string());
+        lines.push(format!(".Lmain_arg{}:", i));
+        lines.push("pushq %rax".to_string());
+    }
+    lines.push(format!("call {}", function_name(&main.name)));
+    // 終了コードは 255 より大きい値を 255、負の値を 1 にする
+    for x in ["movl $1, %ecx", "testl %eax, %eax", "cmovsl %ecx, %eax", "movl $255, %ecx", "cmpl %ecx, %eax", "cmovgl %ecx, %eax"] {
+        lines.push(x.to_string());
+    }
+    lines.push(".Lmain_return:".to_string());
+    for x in ["leaq -32(%rbp), %rsp", "popq %r14", "popq %r13", "popq %r12", "popq %rbx", "popq %rbp", "ret"] {
+        lines.push(x.to_string());
+    }
+    // ラベルと指示以外は字下げする
+    lines.iter().map(|x| if x.ends_with(':') || x.starts_with(' ') { format!("{}\n", x) } else { format!("    {}\n", x) }).collect()
+}
+
+fn function_name(name: &str) -> String {
+    format!("toys_f_{}", mangle(name))
+}
+
+fn global(name: &str) -> String {
+    format!("toys_g_{}", mangle(name))
+}
+
+fn local(index: usize) -> String {
+    format!("-{}(%rbp)", 8 * (index + 1))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,224 @@
+use crate::ast;
+use crate::ast::{Definition, Environment, ExpressionTrait, FunctionDefinition, IfExpression, Node, RuntimeError, Value, WhileExpression};
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BranchKind {
+    If,    // then 節と else 節（else がなければ何もしない側）
+    While, // 本体を実行したか、一度も実行せずに抜けたか
+}
+
+// 分岐ごとの記録。counts[0] は then 節または本体、counts[1] は else 節または本体を飛ばした回数
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    pub kind: BranchKind,
+    pub line: usize,
+    pub counts: [usize; 2],
+    pub reached: usize, // 分岐そのものを評価した回数
+}
+
+#[derive(Default)]
+struct State {
+    functions: BTreeMap<String, (usize, usize)>, // 名前ごとの (定義の行, 呼び出し回数)
+    lines: BTreeMap<usize, usize>,
+    branches: BTreeMap<usize, Branch>, // 分岐の式の開始位置ごと
+    imported: Vec<bool>,               // 呼び出し中の関数が取り込んだモジュールのものか
+}
+
+// 実行した行、if の各節、while の本体を記録する。
+// 取り込んだモジュールの関数は別のファイルにあるので数えない
+pub struct Coverage {
+    state: RefCell<State>,
+}
+
+impl Coverage {
+    // 実行できる行と分岐をあらかじめ集め、実行されなかったものも数えられるようにする
+    pub fn new(program: &ast::Program) -> Self {
+        let mut state = State::default();
+        for toplevel in program.definitions.iter() {
+            if let Definition::Function(x) = toplevel.definition() {
+                if x.name.contains('.') {
+                    continue;
+                }
+                let line = x.span.map(|y| y.line).unwrap_or(0);
+                state.functions.insert(x.name.clone(), (line, 0));
+                collect(&x.body, &mut state);
+            }
+        }
+        Self { state: RefCell::new(state) }
+    }
+
+    // (行, 実行回数) を行の順に並べる
+    pub fn lines(&self) -> Vec<(usize, usize)> {
+        self.state.borrow().lines.iter().map(|(k, v)| (*k, *v)).collect()
+    }
+    pub fn branches(&self) -> Vec<Branch> {
+        self.state.borrow().branches.values().cloned().collect()
+    }
+    // (名前, 定義の行, 呼び出し回数) を定義の順に並べる
+    pub fn functions(&self) -> Vec<(String, usize, usize)> {
+        let mut functions: Vec<(String, usize, usize)> = self.state.borrow().functions.iter().map(|(k, v)| (k.clone(), v.0, v.1)).collect();
+        functions.sort_by_key(|x| x.1);
+        functions
+    }
+
+    pub fn summary(&self) -> String {
+        let lines = self.lines();
+        let branches = self.branches();
+        let functions = self.functions();
+        let branch_total = branches.len() * 2;
+        let branch_hit: usize = branches.iter().map(|x| x.counts.iter().filter(|y| **y > 0).count()).sum();
+        let mut out = String::new();
+        out.push_str(&ratio("lines", lines.iter().filter(|x| x.1 > 0).count(), lines.len()));
+        out.push_str(&ratio("branches", branch_hit, branch_total));
+        out.push_str(&ratio("functions", functions.iter().filter(|x| x.2 > 0).count(), functions.len()));
+        let uncovered: Vec<String> = lines.iter().filter(|x| x.1 == 0).map(|x| x.0.to_string()).collect();
+        if !uncovered.is_empty() {
+            out.push_str(&format!("uncovered lines: {}\n", uncovered.join(", ")));
+        }
+        let mut missed = Vec::new();
+        for branch in branches.iter() {
+            let names = match branch.kind {
+                BranchKind::If => ["then", "else"],
+                BranchKind::While => ["loop body", "loop skipped"],
+            };
+            for (count, name) in branch.counts.iter().zip(names) {
+                if *count == 0 {
+                    missed.push(format!("line {} {}", branch.line, name));
+                }
+            }
+        }
+        if !missed.is_empty() {
+            out.push_str(&format!("uncovered branches: {}\n", missed.join(", ")));
+        }
+        out
+    }
+
+    // lcov のトレースファイル（.info）の形式。source はソースファイルのパス
+    pub fn lcov(&self, source: &str) -> String {
+        let mut out = format!("TN:\nSF:{}\n", source);
+        let functions = self.functions();
+        for (name, line, _) in functions.iter() {
+            out.push_str(&format!("FN:{},{}\n", line, name));
+        }
+        for (name, _, calls) in functions.iter() {
+            out.push_str(&format!("FNDA:{},{}\n", calls, name));
+        }
+        out.push_str(&format!("FNF:{}\nFNH:{}\n", functions.len(), functions.iter().filter(|x| x.2 > 0).count()));
+        let branches = self.branches();
+        let mut hit = 0;
+        for (block, branch) in branches.iter().enumerate() {
+            for (i, count) in branch.counts.iter().enumerate() {
+                // 分岐に一度も到達していなければ "-" にする
+                let taken = if branch.reached == 0 { "-".to_string() } else { count.to_string() };
+                out.push_str(&format!("BRDA:{},{},{},{}\n", branch.line, block, i, taken));
+                if *count > 0 {
+                    hit += 1;
+                }
+            }
+        }
+        out.push_str(&format!("BRF:{}\nBRH:{}\n", branches.len() * 2, hit));
+        let lines = self.lines();
+        for (line, hits) in lines.iter() {
+            out.push_str(&format!("DA:{},{}\n", line, hits));
+        }
+        out.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", lines.len(), lines.iter().filter(|x| x.1 > 0).count()));
+        out
+    }
+
+    fn recording(&self) -> bool {
+        self.state.borrow().imported.last() == Some(&false)
+    }
+    fn branch_at(&self, expression: &dyn ExpressionTrait) -> Option<std::cell::RefMut<'_, Branch>> {
+        let start = expression.span()?.start;
+        std::cell::RefMut::filter_map(self.state.borrow_mut(), |x| x.branches.get_mut(&start)).ok()
+    }
+}
+
+impl ast::Hook for Coverage {
+    fn statement(
+        &self,
+        expression: &dyn ExpressionTrait,
+        _v: &Rc<Environment>,
+        _f: &HashMap<String, &FunctionDefinition>
+    ) -> Result<(), RuntimeError> {
+        if !self.recording() {
+            return Ok(());
+        }
+        if let Some(span) = expression.span() {
+            *self.state.borrow_mut().lines.entry(span.line).or_insert(0) += 1;
+        }
+        // while の本体を一度も実行しなかった回数は、while に着いた回数から数える
+        if let Node::While(_) = expression.node() {
+            if let Some(mut branch) = self.branch_at(expression) {
+                branch.reached += 1;
+                branch.counts[1] += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn enter(&self, function: &FunctionDefinition, _v: &Rc<Environment>) {
+        let mut state = self.state.borrow_mut();
+        let imported = function.name.contains('.');
+        state.imported.push(imported);
+        if let Some(x) = state.functions.get_mut(&function.name) {
+            x.1 += 1;
+        }
+    }
+
+    fn leave(&self, _function: &FunctionDefinition, _result: &Result<Value, RuntimeError>, _f: &HashMap<String, &FunctionDefinition>) {
+        self.state.borrow_mut().imported.pop();
+    }
+
+    fn branch(&self, expression: &IfExpression, _condition: i32, taken: bool) {
+        if !self.recording() {
+            return;
+        }
+        if let Some(mut branch) = self.branch_at(expression) {
+            branch.reached += 1;
+            branch.counts[if taken { 0 } else { 1 }] += 1;
+        }
+    }
+
+    fn iteration(&self, expression: &WhileExpression, count: usize) {
+        if count != 1 || !self.recording() {
+            return;
+        }
+        if let Some(mut branch) = self.branch_at(expression) {
+            branch.counts[0] += 1;
+            branch.counts[1] = branch.counts[1].saturating_sub(1);
+        }
+    }
+}
+
+fn collect(expression: &ast::Expression, state: &mut State) {
+    let node = expression.node();
+    match node {
+        Node::Block(x) => {
+            for element in x.elements.iter() {
+                if let Some(span) = element.span() {
+                    state.lines.entry(span.line).or_insert(0);
+                }
+            }
+        },
+        Node::If(_) | Node::While(_) => {
+            if let Some(span) = expression.span() {
+                let kind = if let Node::If(_) = node { BranchKind::If } else { BranchKind::While };
+                state.branches.insert(span.start, Branch { kind, line: span.line, counts: [0, 0], reached: 0 });
+            }
+        },
+        _ => {},
+    }
+    for child in node.children() {
+        collect(child, state);
+    }
+}
+
+fn ratio(label: &str, hit: usize, total: usize) -> String {
+    let percent = if total == 0 { 100.0 } else { hit as f64 * 100.0 / total as f64 };
+    format!("{:<10} {}/{} ({:.1}%)\n", format!("{}:", label), hit, total, percent)
+}
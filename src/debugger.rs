@@ -0,0 +1,430 @@
+use crate::ast;
+use crate::ast::{Environment, ErrorKind, ExpressionTrait, FunctionDefinition, RuntimeError, Value};
+use crate::interpreter::Interpreter;
+use crate::parser;
+use crate::resolver::Resolver;
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+const HELP: &str = "commands:
+  s, step             次の文まで進む（関数呼び出しの中に入る）
+  n, next             同じ関数の次の文まで進む（呼び出しは飛ばす）
+  o, out, finish      今の関数から戻るまで進む
+  c, continue         次のブレークポイントまで進む
+  b, break <行|関数>  ブレークポイントを置く
+  d, delete <行|関数> ブレークポイントを消す
+  breakpoints         ブレークポイントの一覧
+  bt, backtrace       呼び出し履歴
+  env                 今の環境とその先の環境の変数
+  p, print <式>       今のフレームで式を評価する
+  l, list             今の行の前後を表示する
+  q, quit             実行をやめる
+空行は直前のコマンドを繰り返す";
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Breakpoint {
+    Line(usize),
+    Function(String),
+}
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Line(x) => write!(f, "line {}", x),
+            Breakpoint::Function(x) => write!(f, "function {}", x),
+        }
+    }
+}
+
+// 次に止まる条件。深さは呼び出し中の関数の数
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Continue,
+    StepInto,
+    StepOver(usize), // この深さ以下の文で止まる
+    StepOut(usize),  // この深さより浅い文で止まる
+}
+
+// toys の呼び出し履歴の 1 段
+struct Frame {
+    function: String,
+    arguments: Vec<(String, Option<Value>)>, // 呼び出されたときの実引数
+    locals: Option<Vec<String>>,           // main はグローバル環境で動くので None
+    environment: Rc<Environment>,
+    line: Option<usize>,
+}
+
+struct State {
+    mode: Mode,
+    breakpoints: BTreeSet<Breakpoint>,
+    frames: Vec<Frame>,
+    entered: bool, // 関数のブレークポイントに入ったので次の文で止まる
+    failed: bool,  // 実行時エラーを表示済み
+    last_command: String,
+}
+
+enum Action {
+    Resume(Mode),
+    Quit,
+}
+
+// 標準入力などから読んだコマンドでプログラムの実行を止めたり進めたりする
+pub struct Debugger<R, W> {
+    lines: Vec<String>,
+    resolver: Rc<RefCell<Resolver>>,
+    state: RefCell<State>,
+    evaluating: Cell<bool>, // print の評価中はフックを無視する
+    input: RefCell<R>,
+    output: RefCell<W>,
+}
+
+impl<R: BufRead, W: Write> Debugger<R, W> {
+    // 実行を始めると main の最初の文で止まる
+    pub fn new(source: &str, resolver: Rc<RefCell<Resolver>>, input: R, output: W) -> Self {
+        Self {
+            lines: source.lines().map(|x| x.to_string()).collect(),
+            resolver,
+            state: RefCell::new(State {
+                mode: Mode::StepInto,
+                breakpoints: BTreeSet::new(),
+                frames: Vec::new(),
+                entered: false,
+                failed: false,
+                last_command: String::new(),
+            }),
+            evaluating: Cell::new(false),
+            input: RefCell::new(input),
+            output: RefCell::new(output),
+        }
+    }
+    pub fn add_breakpoint(&self, breakpoint: Breakpoint) {
+        self.state.borrow_mut().breakpoints.insert(breakpoint);
+    }
+    pub fn into_output(self) -> W {
+        self.output.into_inner()
+    }
+
+    fn write(&self, text: &str) {
+        let mut output = self.output.borrow_mut();
+        // 出力先に書けなくても実行は続ける
+        let _ = writeln!(output, "{}", text);
+        let _ = output.flush();
+    }
+
+    fn read_command(&self) -> Option<String> {
+        {
+            let mut output = self.output.borrow_mut();
+            let _ = write!(output, "(toys) ");
+            let _ = output.flush();
+        }
+        let mut line = String::new();
+        match self.input.borrow_mut().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim().to_string()),
+        }
+    }
+
+    // コマンドを読んで実行し、実行を再開するまで繰り返す
+    fn prompt(&self, f: &HashMap<String, &FunctionDefinition>) -> Action {
+        loop {
+            let command = match self.read_command() {
+                Some(x) => x,
+                None => {
+                    // 入力が尽きたら最後まで止まらずに実行する
+                    self.write("");
+                    self.state.borrow_mut().breakpoints.clear();
+                    return Action::Resume(Mode::Continue);
+                },
+            };
+            let command = {
+                let mut state = self.state.borrow_mut();
+                if command.is_empty() {
+                    state.last_command.clone()
+                } else {
+                    state.last_command = command.clone();
+                    command
+                }
+            };
+            let (name, argument) = match command.find(char::is_whitespace) {
+                Some(i) => (&command[..i], command[i..].trim()),
+                None => (command.as_str(), ""),
+            };
+            let depth = self.state.borrow().frames.len();
+            match name {
+                "s" | "step" => return Action::Resume(Mode::StepInto),
+                "n" | "next" => return Action::Resume(Mode::StepOver(depth)),
+                "o" | "out" | "finish" => return Action::Resume(Mode::StepOut(depth)),
+                "c" | "continue" => return Action::Resume(Mode::Continue),
+                "q" | "quit" => return Action::Quit,
+                "b" | "break" => match self.breakpoint(argument) {
+                    Some(x) => {
+                        self.write(&format!("breakpoint at {}", x));
+                        self.add_breakpoint(x);
+                    },
+                    None => self.write("usage: break <line|function>"),
+                },
+                "d" | "delete" => match self.breakpoint(argument) {
+                    Some(x) if self.state.borrow_mut().breakpoints.remove(&x) => self.write(&format!("deleted breakpoint at {}", x)),
+                    Some(x) => self.write(&format!("no breakpoint at {}", x)),
+                    None => self.write("usage: delete <line|function>"),
+                },
+                "breakpoints" => {
+                    let state = self.state.borrow();
+                    if state.breakpoints.is_empty() {
+                        self.write("no breakpoints");
+                    }
+                    for breakpoint in state.breakpoints.iter() {
+                        self.write(&breakpoint.to_string());
+                    }
+                },
+                "bt" | "backtrace" => self.backtrace(),
+                "env" => self.environment(),
+                "p" | "print" => self.print(argument, f),
+                "l" | "list" => self.list(),
+                "h" | "help" => self.write(HELP),
+                "" => {},
+                x => self.write(&format!("unknown command {}; type help for a list of commands", x)),
+            }
+        }
+    }
+
+    fn breakpoint(&self, argument: &str) -> Option<Breakpoint> {
+        if argument.is_empty() {
+            return None;
+        }
+        Some(match argument.parse() {
+            Ok(line) => Breakpoint::Line(line),
+            Err(_) => Breakpoint::Function(argument.to_string()),
+        })
+    }
+
+    // 取り込んだモジュールの関数は別のファイルにあるので、行を表示しない
+    fn location(&self, frame: &Frame) -> String {
+        match frame.line {
+            Some(line) if !frame.function.contains('.') => match self.lines.get(line - 1) {
+                Some(text) => format!("{} at line {}\n{:>4} | {}", frame.function, line, line, text),
+                None => format!("{} at line {}", frame.function, line),
+            },
+            _ => frame.function.clone(),
+        }
+    }
+
+    fn backtrace(&self) {
+        let state = self.state.borrow();
+        for (i, frame) in state.frames.iter().rev().enumerate() {
+            let arguments: Vec<String> = frame.arguments.iter().map(|(name, value)| format!("{} = {}", name, show(value.clone()))).collect();
+            let line = frame.line.map(|x| format!(" at line {}", x)).unwrap_or_default();
+            self.write(&format!("#{} {}({}){}", i, frame.function, arguments.join(", "), line));
+        }
+    }
+
+    // 今のフレームの環境から next をたどって、各環境の変数を表示する
+    fn environment(&self) {
+        let state = self.state.borrow();
+        let frame = match state.frames.last() {
+            Some(x) => x,
+            None => return,
+        };
+        let resolver = self.resolver.borrow();
+        let mut environment = Some(&frame.environment);
+        let mut first = true;
+        while let Some(current) = environment {
+            let (label, names) = match current.next() {
+                Some(_) => (frame.function.as_str(), frame.locals.clone().unwrap_or_default()),
+                None => ("global", resolver.global_names().into_iter().map(|x| x.to_string()).collect()),
+            };
+            let bindings = current.bindings();
+            let variables: Vec<String> = names.iter().enumerate()
+                .map(|(i, name)| format!("{} = {}", name, show(bindings.get(i).cloned().flatten())))
+                .collect();
+            self.write(&format!("{}{}: {}", if first { "" } else { "next " }, label, variables.join(", ")));
+            environment = current.next();
+            first = false;
+        }
+    }
+
+    fn print(&self, argument: &str, f: &HashMap<String, &FunctionDefinition>) {
+        if argument.is_empty() {
+            self.write("usage: print <expression>");
+            return;
+        }
+        let code = if argument.ends_with(';') || argument.ends_with('}') { argument.to_string() } else { format!("{};", argument) };
+        // `モジュール名.x` はフィールドではなく取り込んだモジュールの変数として読む
+        let modules = self.resolver.borrow().global_names().into_iter()
+            .filter_map(|x| x.split_once('.').map(|y| y.0.to_string()))
+            .collect();
+        let lines = match parser::parse_lines_in(&code, modules) {
+            Ok(x) => x,
+            Err(e) => return self.write(&format!("error: {}", e)),
+        };
+        let (locals, environment) = match self.state.borrow().frames.last() {
+            Some(x) => (x.locals.clone(), Rc::clone(&x.environment)),
+            None => return,
+        };
+        let mut result = Ok(Value::Integer(0));
+        self.evaluating.set(true);
+        for line in lines.iter() {
+            self.resolver.borrow_mut().resolve_in_scope(line, locals.as_deref());
+            result = line.eval(&environment, f);
+            if result.is_err() {
+                break;
+            }
+        }
+        self.evaluating.set(false);
+        match result {
+            Ok(x) => self.write(&format!("= {}", x)),
+            Err(e) => self.write(&format!("error: {}", e)),
+        }
+    }
+
+    fn list(&self) {
+        let state = self.state.borrow();
+        let current = match state.frames.last().and_then(|x| x.line) {
+            Some(x) => x,
+            None => return,
+        };
+        let first = current.saturating_sub(3).max(1);
+        let last = (current + 3).min(self.lines.len());
+        for line in first..=last {
+            let marker = if line == current { "=>" } else { "  " };
+            let breakpoint = if state.breakpoints.contains(&Breakpoint::Line(line)) { "*" } else { " " };
+            self.write(&format!("{}{}{:>4} | {}", marker, breakpoint, line, self.lines[line - 1]));
+        }
+    }
+
+    // 止まった場所を表示してコマンドを待つ
+    fn stop(&self, f: &HashMap<String, &FunctionDefinition>) -> Result<(), RuntimeError> {
+        let location = match self.state.borrow().frames.last() {
+            Some(x) => self.location(x),
+            None => return Ok(()),
+        };
+        self.write(&location);
+        match self.prompt(f) {
+            Action::Resume(mode) => {
+                self.state.borrow_mut().mode = mode;
+                Ok(())
+            },
+            Action::Quit => Err(RuntimeError::new(ErrorKind::Interrupted, "execution stopped by the debugger.".to_string())),
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> ast::Hook for Debugger<R, W> {
+    fn statement(
+        &self,
+        expression: &dyn ExpressionTrait,
+        _v: &Rc<Environment>,
+        f: &HashMap<String, &FunctionDefinition>
+    ) -> Result<(), RuntimeError> {
+        let span = match expression.span() {
+            Some(x) if !self.evaluating.get() => x,
+            _ => return Ok(()),
+        };
+        let stop = {
+            let mut state = self.state.borrow_mut();
+            let depth = state.frames.len();
+            let imported = match state.frames.last_mut() {
+                Some(frame) => {
+                    frame.line = Some(span.line);
+                    frame.function.contains('.')
+                },
+                None => return Ok(()),
+            };
+            let stop = match state.mode {
+                Mode::StepInto => true,
+                Mode::StepOver(x) => depth <= x,
+                Mode::StepOut(x) => depth < x,
+                Mode::Continue => state.entered || (!imported && state.breakpoints.contains(&Breakpoint::Line(span.line))),
+            };
+            if stop {
+                state.entered = false;
+            }
+            stop
+        };
+        if stop {
+            self.stop(f)?;
+        }
+        Ok(())
+    }
+
+    fn enter(&self, function: &FunctionDefinition, environment: &Rc<Environment>) {
+        if self.evaluating.get() {
+            return;
+        }
+        let locals = if function.is_main() { None } else { Some(function.locals.borrow().clone()) };
+        let arguments = function.arguments(environment);
+        let mut state = self.state.borrow_mut();
+        state.frames.push(Frame {
+            function: function.name.clone(),
+            arguments,
+            locals,
+            environment: Rc::clone(environment),
+            line: None,
+        });
+        if state.breakpoints.contains(&Breakpoint::Function(function.name.clone())) {
+            state.entered = true;
+        }
+    }
+
+    fn leave(&self, function: &FunctionDefinition, result: &Result<Value, RuntimeError>, f: &HashMap<String, &FunctionDefinition>) {
+        if self.evaluating.get() {
+            return;
+        }
+        let depth = self.state.borrow().frames.len();
+        match result {
+            Err(e) if e.kind != ErrorKind::Interrupted && !self.state.borrow().failed => {
+                // エラーが起きたフレームで止まり、変数などを調べられるようにする
+                self.state.borrow_mut().failed = true;
+                self.write(&format!("error: {}", e));
+                let location = self.state.borrow().frames.last().map(|x| self.location(x));
+                if let Some(location) = location {
+                    self.write(&location);
+                }
+                // 実行はもう再開できないので、どのコマンドでもエラーを呼び出し元に返す
+                let _ = self.prompt(f);
+            },
+            Ok(value) if self.state.borrow().mode == Mode::StepOut(depth) => {
+                self.write(&format!("{} returned {}", function.name, value));
+            },
+            _ => {},
+        }
+        self.state.borrow_mut().frames.pop();
+    }
+}
+
+fn show(value: Option<Value>) -> String {
+    match value {
+        Some(x) => x.to_string(),
+        None => "<unset>".to_string(),
+    }
+}
+
+// デバッガの下でプログラムを実行し、結果とデバッガの出力先を返す
+pub fn debug<R: BufRead + 'static, W: Write + 'static>(
+    program: &ast::Program,
+    source: &str,
+    args: Vec<i32>,
+    breakpoints: Vec<Breakpoint>,
+    input: R,
+    output: W
+) -> (Result<i32, RuntimeError>, W) {
+    let mut interpreter = Interpreter::new();
+    let debugger = Rc::new(Debugger::new(source, interpreter.resolver(), input, output));
+    for breakpoint in breakpoints {
+        debugger.add_breakpoint(breakpoint);
+    }
+    interpreter.set_hook(debugger.clone());
+    let result = interpreter.call_main_with_args(program, args);
+    match &result {
+        Ok(x) => debugger.write(&format!("program exited with {}", x)),
+        Err(e) => debugger.write(&format!("program stopped: {}", e)),
+    }
+    drop(interpreter);
+    match Rc::try_unwrap(debugger) {
+        Ok(x) => (result, x.into_output()),
+        Err(_) => unreachable!("the interpreter no longer holds the debugger"),
+    }
+}
@@ -0,0 +1,424 @@
+use crate::ast::{Comment, CommentKind};
+use crate::parser;
+use crate::parser::{ParseError, Rule};
+
+use pest::iterators::Pair;
+
+const INDENT: &str = "    ";
+
+// ソースを標準の書式に整える。AST ではなく構文木から出力するので、
+// for 文やコメント、括弧は書いたとおりに残る
+pub fn format(contents: &str) -> Result<String, ParseError> {
+    let comments: Vec<Comment> = parser::parse_comments(contents)?
+        .into_iter()
+        .filter(|x| x.kind != CommentKind::Doc)
+        .collect();
+    let program = parser::parse_tree(Rule::program, contents)?.next().unwrap();
+    let mut formatter = Formatter {
+        source: contents,
+        lines: Vec::new(),
+        indent: 0,
+        comments,
+        next_comment: 0,
+    };
+    formatter.program(program);
+    let mut formatted = formatter.lines.join("\n");
+    formatted.push('\n');
+    Ok(formatted)
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    lines: Vec<String>,
+    indent: usize,
+    comments: Vec<Comment>,
+    next_comment: usize,
+}
+impl<'a> Formatter<'a> {
+    fn program(&mut self, pair: Pair<Rule>) {
+        let mut previous: Option<Rule> = None;
+        for item in pair.into_inner() {
+            let rule = match item.as_rule() {
+                Rule::topLevelDefinition => item.clone().into_inner().next().unwrap().as_rule(),
+                Rule::shebang => {
+                    self.lines.push(item.as_str().trim_end().to_string());
+                    continue;
+                },
+                Rule::EOI => break,
+                x => x,
+            };
+            if let Some(p) = previous {
+                // import の並びと、続けて書かれたグローバル変数の間だけは空行を入れない
+                let grouped = p == rule && (rule == Rule::importDeclaration
+                    || rule == Rule::globalVariableDefinition && !self.blank_line_before(item.as_span().start()));
+                if !grouped {
+                    self.lines.push(String::new());
+                }
+            }
+            self.leading_comments(item.as_span().start());
+            match rule {
+                Rule::importDeclaration => {
+                    let target = item.clone().into_inner().next().unwrap();
+                    self.lines.push(format!("import {};", target.as_str()));
+                },
+                _ => self.toplevel(item.clone().into_inner().next().unwrap()),
+            }
+            self.trailing_comments(item.as_span().end());
+            previous = Some(rule);
+        }
+        let rest = self.source.len();
+        if self.next_comment < self.comments.len() && !self.lines.is_empty() {
+            self.lines.push(String::new());
+        }
+        self.leading_comments(rest);
+    }
+
+    fn toplevel(&mut self, pair: Pair<Rule>) {
+        let rule = pair.as_rule();
+        let mut inner = pair.into_inner().peekable();
+        while let Some(doc) = inner.next_if(|x| x.as_rule() == Rule::docComment) {
+            self.lines.push(doc.as_str().trim_end().to_string());
+        }
+        let name = inner.next().unwrap().as_str();
+        match rule {
+            // struct も record にそろえる
+            Rule::recordDefinition => {
+                let fields: Vec<String> = inner.map(|x| {
+                    let mut inner = x.into_inner();
+                    format!("{}{}", inner.next().unwrap().as_str(), annotation(inner.next()))
+                }).collect();
+                self.lines.push(format!("record {}({})", name, fields.join(", ")));
+            },
+            // バリアントは一行に並べ、値のないバリアントの () は省く
+            Rule::enumDefinition => {
+                let variants: Vec<String> = inner.map(|x| {
+                    let mut inner = x.into_inner();
+                    let variant = inner.next().unwrap().as_str();
+                    let fields: Vec<String> = inner.map(|y| {
+                        let mut inner = y.into_inner();
+                        format!("{}{}", inner.next().unwrap().as_str(), annotation(inner.next()))
+                    }).collect();
+                    if fields.is_empty() { variant.to_string() } else { format!("{}({})", variant, fields.join(", ")) }
+                }).collect();
+                if variants.is_empty() {
+                    self.lines.push(format!("enum {} {{}}", name));
+                } else {
+                    self.lines.push(format!("enum {} {{ {} }}", name, variants.join(", ")));
+                }
+            },
+            Rule::functionDefinition => {
+                let rest: Vec<Pair<Rule>> = inner.collect();
+                let (body, rest) = rest.split_last().unwrap();
+                let args: Vec<String> = rest.iter().filter(|x| x.as_rule() == Rule::parameter).map(|x| {
+                    let mut inner = x.clone().into_inner();
+                    format!("{}{}", inner.next().unwrap().as_str(), annotation(inner.next()))
+                }).collect();
+                let returns = annotation(rest.iter().find(|x| x.as_rule() == Rule::typeAnnotation).cloned());
+                let head = format!("define {}({}){} ", name, args.join(", "), returns);
+                self.statement(head, body.clone());
+            },
+            Rule::globalVariableDefinition => {
+                let annotation = annotation(inner.next_if(|x| x.as_rule() == Rule::typeAnnotation));
+                let value = expression(inner.next().unwrap());
+                self.lines.push(format!("global {}{} = {};", name, annotation, value));
+            },
+            Rule::testDefinition => {
+                let head = format!("test {} ", name);
+                self.statement(head, inner.next().unwrap());
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    // ブロック内の一行。前後のコメントもここで出力する
+    fn line(&mut self, pair: Pair<Rule>, first: bool) {
+        let span = pair.as_span();
+        if !first && self.blank_line_before(self.first_position(span.start())) {
+            self.lines.push(String::new());
+        }
+        self.leading_comments(span.start());
+        self.statement(self.indentation(), pair.into_inner().next().unwrap());
+        self.trailing_comments(span.end());
+    }
+
+    // prefix は出力中の行の先頭部分。if や while の本体は条件と同じ行に続ける
+    fn statement(&mut self, prefix: String, pair: Pair<Rule>) {
+        match pair.as_rule() {
+            Rule::line => self.statement(prefix, pair.into_inner().next().unwrap()),
+            Rule::println => {
+                let body = pair.into_inner().next().unwrap();
+                self.value(format!("{}println(", prefix), body, ");");
+            },
+            Rule::assignment => {
+                let mut inner = pair.into_inner();
+                let name = inner.next().unwrap().as_str();
+                self.value(format!("{}{} = ", prefix, name), inner.next().unwrap(), ";");
+            },
+            Rule::fieldAssignment => {
+                let mut inner: Vec<Pair<Rule>> = pair.into_inner().collect();
+                let value = inner.pop().unwrap();
+                let target: Vec<&str> = inner.iter().map(|x| x.as_str()).collect();
+                self.value(format!("{}{} = ", prefix, target.join(".")), value, ";");
+            },
+            Rule::expressionLine => {
+                let body = pair.into_inner().next().unwrap();
+                self.value(prefix, body, ";");
+            },
+            Rule::matchExpression => {
+                let end = pair.as_span().end() - 1;
+                let mut inner = pair.into_inner();
+                let value = expression(inner.next().unwrap());
+                self.lines.push(format!("{}match ({}) {{", prefix, value));
+                self.indent += 1;
+                for arm in inner {
+                    let span = arm.as_span();
+                    self.leading_comments(span.start());
+                    let (head, body) = match_arm(arm);
+                    let head = format!("{}{} => ", self.indentation(), head);
+                    match body.as_rule() {
+                        Rule::blockExpression => self.statement(head, body),
+                        _ => self.lines.push(format!("{}{}", head, expression(body))),
+                    }
+                    self.lines.last_mut().unwrap().push(',');
+                    self.trailing_comments(span.end());
+                }
+                self.leading_comments(end);
+                self.indent -= 1;
+                self.lines.push(format!("{}}}", self.indentation()));
+            },
+            Rule::blockExpression => {
+                let end = pair.as_span().end() - 1;
+                self.lines.push(format!("{}{{", prefix));
+                self.indent += 1;
+                for (i, line) in pair.into_inner().enumerate() {
+                    self.line(line, i == 0);
+                }
+                self.leading_comments(end);
+                self.indent -= 1;
+                self.lines.push(format!("{}}}", self.indentation()));
+            },
+            Rule::ifExpression => {
+                let mut inner = pair.into_inner();
+                let condition = expression(inner.next().unwrap());
+                self.statement(format!("{}if ({}) ", prefix, condition), inner.next().unwrap());
+                if let Some(else_clause) = inner.next() {
+                    let last = self.lines.pop().unwrap();
+                    self.statement(format!("{} else ", last), else_clause);
+                }
+            },
+            Rule::whileExpression => {
+                let mut inner = pair.into_inner();
+                let condition = expression(inner.next().unwrap());
+                self.statement(format!("{}while ({}) ", prefix, condition), inner.next().unwrap());
+            },
+            Rule::forInExpression => {
+                let mut inner = pair.into_inner();
+                let name = inner.next().unwrap().as_str();
+                let from = expression(inner.next().unwrap());
+                let to = expression(inner.next().unwrap());
+                self.statement(format!("{}for ({} in {} to {}) ", prefix, name, from, to), inner.next().unwrap());
+            },
+            Rule::throwStatement => {
+                let value = pair.into_inner().find(|x| x.as_rule() == Rule::expression).unwrap();
+                self.value(format!("{}throw ", prefix), value, ";");
+            },
+            // catch と finally は前のブロックの閉じ括弧と同じ行に続ける
+            Rule::tryExpression => {
+                let mut inner = pair.into_inner();
+                self.statement(format!("{}try ", prefix), inner.next().unwrap());
+                for clause in inner {
+                    let last = self.lines.pop().unwrap();
+                    let mut parts = clause.clone().into_inner();
+                    match clause.as_rule() {
+                        Rule::catchClause => {
+                            let name = parts.next().unwrap().as_str();
+                            self.statement(format!("{} catch ({}) ", last, name), parts.next().unwrap());
+                        },
+                        _ => self.statement(format!("{} finally ", last), parts.next().unwrap()),
+                    }
+                }
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    // 値がそのまま match や try なら複数行に分けて書き、suffix を閉じ括弧の後に続ける
+    fn value(&mut self, prefix: String, pair: Pair<Rule>, suffix: &str) {
+        match single_match(pair.clone()) {
+            Some(x) => {
+                self.statement(prefix, x);
+                self.lines.last_mut().unwrap().push_str(suffix);
+            },
+            None => self.lines.push(format!("{}{}{}", prefix, expression(pair), suffix)),
+        }
+    }
+
+    // position より前にあるコメントを、それぞれ独立した行として出力する
+    fn leading_comments(&mut self, position: usize) {
+        let mut first = true;
+        while self.next_comment < self.comments.len() && self.comments[self.next_comment].offset < position {
+            let offset = self.comments[self.next_comment].offset;
+            if !first && self.blank_line_before(offset) {
+                self.lines.push(String::new());
+            }
+            let text = self.comments[self.next_comment].text.trim_end().to_string();
+            self.lines.push(format!("{}{}", self.indentation(), text));
+            self.next_comment += 1;
+            first = false;
+        }
+    }
+
+    // 文の途中や、文と同じ行の末尾にあるコメントは、出力した行の末尾に付ける
+    fn trailing_comments(&mut self, end: usize) {
+        let mut position = end;
+        while self.next_comment < self.comments.len() {
+            let comment = &self.comments[self.next_comment];
+            if comment.offset >= end {
+                // 間に改行やコードがあるなら次の文のコメント
+                let gap = &self.source[position..comment.offset];
+                if gap.contains('\n') || !gap.trim().is_empty() {
+                    break;
+                }
+                position = comment.offset + comment.text.len();
+            }
+            let text = comment.text.trim_end().to_string();
+            let last = self.lines.last_mut().unwrap();
+            last.push(' ');
+            last.push_str(&text);
+            self.next_comment += 1;
+        }
+    }
+
+    // 行の前に置かれるコメントがあれば、その位置を行の始まりとみなす
+    fn first_position(&self, start: usize) -> usize {
+        match self.comments.get(self.next_comment) {
+            Some(x) if x.offset < start => x.offset,
+            _ => start,
+        }
+    }
+
+    fn blank_line_before(&self, position: usize) -> bool {
+        let before = &self.source[..position];
+        let gap = &before[before.trim_end().len()..];
+        gap.matches('\n').count() >= 2
+    }
+
+    fn indentation(&self) -> String {
+        INDENT.repeat(self.indent)
+    }
+}
+
+// 型注釈は `: Int` の形にそろえる
+fn annotation(pair: Option<Pair<Rule>>) -> String {
+    match pair {
+        Some(x) => format!(": {}", x.into_inner().next().unwrap().as_str()),
+        None => String::new(),
+    }
+}
+
+// 括弧も演算子もない match や try の式
+fn single_match(pair: Pair<Rule>) -> Option<Pair<Rule>> {
+    match pair.as_rule() {
+        Rule::matchExpression | Rule::tryExpression => Some(pair),
+        Rule::primary if pair.as_str().starts_with('(') => None,
+        Rule::expression | Rule::comparative | Rule::additive | Rule::multitive | Rule::postfix | Rule::primary => {
+            let mut inner = pair.into_inner();
+            let first = inner.next().unwrap();
+            if inner.next().is_some() { None } else { single_match(first) }
+        },
+        _ => None,
+    }
+}
+
+// 腕の `パターン if ガード` の部分と本体
+fn match_arm(pair: Pair<Rule>) -> (String, Pair<Rule>) {
+    let mut inner = pair.into_inner();
+    let mut head = pattern(inner.next().unwrap());
+    let mut body = inner.next().unwrap();
+    if body.as_rule() == Rule::matchGuard {
+        head = format!("{} if {}", head, expression(body.into_inner().next().unwrap()));
+        body = inner.next().unwrap();
+    }
+    (head, body)
+}
+
+fn pattern(pair: Pair<Rule>) -> String {
+    let inner = pair.into_inner();
+    match inner.peek().map(|x| x.as_rule()) {
+        Some(Rule::constructorPattern) => {
+            let mut inner = inner.peek().unwrap().into_inner();
+            let name = inner.next().unwrap().as_str();
+            let arguments: Vec<String> = inner.map(pattern).collect();
+            if arguments.is_empty() { name.to_string() } else { format!("{}({})", name, arguments.join(", ")) }
+        },
+        _ => inner.map(|x| x.as_str()).collect::<Vec<&str>>().join(" | "),
+    }
+}
+
+fn expression(pair: Pair<Rule>) -> String {
+    match pair.as_rule() {
+        Rule::expression => expression(pair.into_inner().next().unwrap()),
+        Rule::comparative | Rule::additive | Rule::multitive => {
+            let operands: Vec<String> = pair.into_inner().map(|x| match x.as_rule() {
+                Rule::comparative_verb | Rule::additive_verb | Rule::multitive_verb => x.as_str().to_string(),
+                _ => expression(x),
+            }).collect();
+            operands.join(" ")
+        },
+        // 先頭の値に続くのはフィールドの名前
+        Rule::postfix => {
+            let mut inner = pair.into_inner();
+            let value = expression(inner.next().unwrap());
+            inner.fold(value, |x, field| format!("{}.{}", x, field.as_str()))
+        },
+        Rule::primary => {
+            let parenthesized = pair.as_str().starts_with('(');
+            let inner = expression(pair.into_inner().next().unwrap());
+            if parenthesized {
+                format!("({})", inner)
+            } else {
+                inner
+            }
+        },
+        Rule::environmentVariable => {
+            let args: Vec<String> = pair.into_inner().map(expression).collect();
+            format!("env({})", args.join(", "))
+        },
+        Rule::assertion => {
+            let args: Vec<String> = pair.into_inner().map(expression).collect();
+            let name = if args.len() == 2 { "assert_eq" } else { "assert" };
+            format!("{}({})", name, args.join(", "))
+        },
+        Rule::functionCall => {
+            let mut inner = pair.into_inner();
+            let name = inner.next().unwrap().as_str();
+            let args: Vec<String> = inner.map(expression).collect();
+            format!("{}({})", name, args.join(", "))
+        },
+        Rule::labelledCall => {
+            let mut inner = pair.into_inner();
+            let name = inner.next().unwrap().as_str();
+            let args: Vec<String> = inner.map(|x| {
+                let mut parameter = x.into_inner();
+                let label = parameter.next().unwrap().as_str();
+                format!("{} = {}", label, expression(parameter.next().unwrap()))
+            }).collect();
+            format!("{}[{}]", name, args.join(", "))
+        },
+        // 式の途中の match は一行に書く
+        Rule::matchExpression => {
+            let mut inner = pair.into_inner();
+            let value = expression(inner.next().unwrap());
+            let arms: Vec<String> = inner.map(|x| {
+                let (head, body) = match_arm(x);
+                format!("{} => {}", head, expression(body))
+            }).collect();
+            if arms.is_empty() {
+                format!("match ({}) {{}}", value)
+            } else {
+                format!("match ({}) {{ {} }}", value, arms.join(", "))
+            }
+        },
+        _ => pair.as_str().to_string(),
+    }
+}
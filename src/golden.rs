@@ -0,0 +1,212 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::ast::Definition;
+use crate::parser;
+
+// 一つのプログラムを実行した結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub stdout: String,
+    pub stderr: String,
+    pub status: i32,
+}
+
+impl Outcome {
+    // .err に書く内容。終了コードが 0 でなければ最後の行に書き足す
+    pub fn errors(&self) -> String {
+        if self.status == 0 {
+            self.stderr.clone()
+        } else {
+            format!("{}[exit status: {}]\n", self.stderr, self.status)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Passed,
+    Failed(String), // 期待と違った出力の差分
+    Blessed,        // 期待を書き換えた
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenResult {
+    pub path: PathBuf,
+    pub verdict: Verdict,
+}
+
+// ディレクトリの下の .toys をすべて toys run で実行し、隣の .out（標準出力）と
+// .err（標準エラー出力と終了コード）に書いた期待と比べる。
+// 隣に .args があれば、空白で区切った中身を main の引数にする。
+// main のないプログラムは toys test で実行する
+pub struct Harness {
+    toys: PathBuf, // 実行する toys のパス
+    bless: bool,
+}
+
+impl Harness {
+    pub fn new(toys: impl Into<PathBuf>) -> Self {
+        Self { toys: toys.into(), bless: false }
+    }
+    // 比べる代わりに、実行した結果で期待を書き換える
+    pub fn bless(mut self, bless: bool) -> Self {
+        self.bless = bless;
+        self
+    }
+
+    pub fn run_dir(&self, dir: &Path) -> io::Result<Vec<GoldenResult>> {
+        let mut results = Vec::new();
+        for path in programs(dir)? {
+            let verdict = self.check(&path)?;
+            results.push(GoldenResult { path, verdict });
+        }
+        Ok(results)
+    }
+
+    pub fn check(&self, path: &Path) -> io::Result<Verdict> {
+        let outcome = self.run(path)?;
+        let out = path.with_extension("out");
+        let err = path.with_extension("err");
+        if self.bless {
+            fs::write(&out, &outcome.stdout)?;
+            // 標準エラー出力がなく 0 で終了したなら .err は置かない
+            let errors = outcome.errors();
+            if !errors.is_empty() {
+                fs::write(&err, errors)?;
+            } else if err.exists() {
+                fs::remove_file(&err)?;
+            }
+            return Ok(Verdict::Blessed);
+        }
+        let mut diff = String::new();
+        for (name, file, actual) in [("stdout", &out, outcome.stdout.clone()), ("stderr", &err, outcome.errors())] {
+            let expected = read_expectation(file)?;
+            if expected != actual {
+                diff.push_str(&format!("{} differs from {}:\n{}", name, file.display(), line_diff(&expected, &actual)));
+            }
+        }
+        Ok(if diff.is_empty() { Verdict::Passed } else { Verdict::Failed(diff) })
+    }
+
+    // 出力に載るファイル名が実行する場所によって変わらないよう、プログラムのあるディレクトリで実行する。
+    // TOYS_ で始まる環境変数は結果を変えるので引き継がない
+    pub fn run(&self, path: &Path) -> io::Result<Outcome> {
+        let dir = path.parent().filter(|x| !x.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+        let file = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?;
+        let mut command = Command::new(&self.toys);
+        let subcommand = if has_main(path) { "run" } else { "test" };
+        command.current_dir(dir).arg(subcommand).arg(file);
+        if let Ok(args) = fs::read_to_string(path.with_extension("args")) {
+            command.args(args.split_whitespace());
+        }
+        for (key, _) in std::env::vars_os() {
+            if key.to_string_lossy().starts_with("TOYS_") {
+                command.env_remove(key);
+            }
+        }
+        let output = command.output()?;
+        Ok(Outcome {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            status: output.status.code().unwrap_or(-1),
+        })
+    }
+}
+
+// 構文エラーのあるプログラムは、エラーが出るよう toys run で実行する
+fn has_main(path: &Path) -> bool {
+    let program = match fs::read_to_string(path).ok().and_then(|x| parser::parse(&x).ok()) {
+        Some(x) => x,
+        None => return true,
+    };
+    program.definitions.iter().any(|x| matches!(x.definition(), Definition::Function(f) if f.name == "main"))
+}
+
+// ディレクトリの下の .toys を名前の順に集める
+pub fn programs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?.map(|x| x.map(|y| y.path())).collect::<io::Result<_>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            found.extend(programs(&path)?);
+        } else if path.extension().is_some_and(|x| x == "toys") {
+            found.push(path);
+        }
+    }
+    Ok(found)
+}
+
+pub fn report(results: &[GoldenResult]) -> String {
+    let mut out = format!("\nrunning {} golden tests\n", results.len());
+    for result in results.iter() {
+        let status = match result.verdict {
+            Verdict::Passed => "ok",
+            Verdict::Failed(_) => "FAILED",
+            Verdict::Blessed => "blessed",
+        };
+        out.push_str(&format!("golden {} ... {}\n", result.path.display(), status));
+    }
+    let failures: Vec<&GoldenResult> = results.iter().filter(|x| matches!(x.verdict, Verdict::Failed(_))).collect();
+    if !failures.is_empty() {
+        out.push_str("\nfailures:\n");
+        for result in failures.iter() {
+            if let Verdict::Failed(diff) = &result.verdict {
+                out.push_str(&format!("\n---- {} ----\n{}", result.path.display(), diff));
+            }
+        }
+    }
+    let count = |verdict: fn(&Verdict) -> bool| results.iter().filter(|x| verdict(&x.verdict)).count();
+    out.push_str(&format!(
+        "\ngolden result: {}. {} passed; {} failed; {} blessed\n",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        count(|x| *x == Verdict::Passed),
+        failures.len(),
+        count(|x| *x == Verdict::Blessed),
+    ));
+    out
+}
+
+// 期待のファイルがなければ、何も出力しないことを期待する
+fn read_expectation(path: &Path) -> io::Result<String> {
+    match fs::read_to_string(path) {
+        Ok(x) => Ok(x),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e),
+    }
+}
+
+// 最長共通部分列で行ごとの差分を取り、期待にだけある行に -、結果にだけある行に + を付ける
+fn line_diff(expected: &str, actual: &str) -> String {
+    let a: Vec<&str> = expected.lines().collect();
+    let b: Vec<&str> = actual.lines().collect();
+    let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            table[i][j] = if a[i] == b[j] { table[i + 1][j + 1] + 1 } else { table[i + 1][j].max(table[i][j + 1]) };
+        }
+    }
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            out.push_str(&format!("  {}\n", a[i]));
+            i += 1;
+            j += 1;
+        } else if j < b.len() && (i == a.len() || table[i][j + 1] >= table[i + 1][j]) {
+            out.push_str(&format!("+ {}\n", b[j]));
+            j += 1;
+        } else {
+            out.push_str(&format!("- {}\n", a[i]));
+            i += 1;
+        }
+    }
+    // 改行の有無だけが違う場合も分かるようにする
+    if out.lines().all(|x| x.starts_with("  ")) {
+        out.push_str("(the final newline differs)\n");
+    }
+    out
+}
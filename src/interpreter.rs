@@ -1,34 +1,91 @@
 #![allow(dead_code)]
 
 use crate::ast;
+use crate::resolver::Resolver;
+use crate::tracer::Tracer;
 
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
 pub struct Interpreter<'a> {
     pub variable_environment: Rc<ast::Environment>,
-    pub function_environment: HashMap<String, &'a ast::FunctionDefinition>
+    pub function_environment: HashMap<String, &'a ast::FunctionDefinition>,
+    resolver: Rc<RefCell<Resolver>>,
+    hook: Option<Rc<dyn ast::Hook>>,
 }
+
+impl<'a> Default for Interpreter<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<'a> Interpreter<'a> {
     pub fn new() -> Self {
         Self {
             variable_environment: ast::Environment::new(),
             function_environment: HashMap::new(),
+            resolver: Rc::new(RefCell::new(Resolver::new())),
+            hook: None,
         }
     }
-    pub fn interpret(&self, expression: &ast::Expression) -> i32 {
-        expression.eval(&self.variable_environment, &self.function_environment)
+    // 実行中の各文と関数の出入りを hook に知らせる
+    pub fn set_hook(&mut self, hook: Rc<dyn ast::Hook>) {
+        self.hook = Some(hook);
+    }
+    // 評価した関数や代入などを標準エラー出力に書く。functions が空でなければその関数の中だけを記録する
+    pub fn set_trace(&mut self, functions: Vec<String>) {
+        self.set_hook(Rc::new(Tracer::only(std::io::stderr(), functions)));
+    }
+    // デバッガなどが実行中に式を解決できるよう、同じ Resolver を共有する
+    pub fn resolver(&self) -> Rc<RefCell<Resolver>> {
+        Rc::clone(&self.resolver)
+    }
+    pub fn interpret(&self, expression: &ast::Expression) -> Result<i32, ast::RuntimeError> {
+        self.resolver.borrow_mut().resolve_expression(expression);
+        self.with_hook(|| expression.eval(&self.variable_environment, &self.function_environment))?
+            .integer(|| "value of expression".to_string())
     }
-    pub fn call_main(&mut self, program: &'a ast::Program) -> i32 {
+    pub fn call_main(&mut self, program: &'a ast::Program) -> Result<i32, ast::RuntimeError> {
+        self.call_main_with_args(program, Vec::new())
+    }
+    // コマンドライン引数は main の仮引数に渡される。仮引数より多い引数は捨て、足りない仮引数は 0 にする。
+    // main の値は終了コードになるので Int でなければならない
+    pub fn call_main_with_args(&mut self, program: &'a ast::Program, args: Vec<i32>) -> Result<i32, ast::RuntimeError> {
+        self.resolver.borrow_mut().resolve_program(program);
         let toplevels = &program.definitions;
-        for toplevel in toplevels.into_iter() {
-            toplevel.eval(&self.variable_environment, &mut self.function_environment);
+        for toplevel in toplevels.iter() {
+            toplevel.eval(&self.variable_environment, &mut self.function_environment)?;
         }
         let main_function = self.function_environment.get("main");
-        if main_function.is_some() {
-            main_function.unwrap().body.eval(&self.variable_environment, &self.function_environment)
-        } else {
-            panic!("This program doesn't have main function.");
+        match main_function {
+            Some(x) => {
+                let mut args: Vec<ast::Value> = args.into_iter().take(x.args.len()).map(ast::Value::Integer).collect();
+                args.resize(x.args.len(), ast::Value::Integer(0));
+                self.with_hook(|| x.call(args, &self.variable_environment, &self.function_environment))?
+                    .integer(|| "return value of function main".to_string())
+            },
+            None => Err(ast::RuntimeError::new(
+                ast::ErrorKind::MissingMain,
+                "This program doesn't have main function.".to_string()
+            )),
+        }
+    }
+
+    // テストの本体を main と同じくグローバル環境で実行する。テストごとに新しい Interpreter を使う
+    pub fn run_test(&mut self, program: &'a ast::Program, test: &ast::TestDefinition) -> Result<ast::Value, ast::RuntimeError> {
+        self.resolver.borrow_mut().resolve_program(program);
+        for toplevel in program.definitions.iter() {
+            toplevel.eval(&self.variable_environment, &mut self.function_environment)?;
+        }
+        self.with_hook(|| test.body.eval(&self.variable_environment, &self.function_environment))
+    }
+
+    fn with_hook<T>(&self, f: impl FnOnce() -> T) -> T {
+        match &self.hook {
+            Some(hook) => ast::with_hook(Rc::clone(hook), f),
+            None => f(),
         }
     }
 }
@@ -46,7 +103,7 @@ mod tests {
             ast::Ast::integer(20)
         );
         let i = Interpreter::new();
-        assert_eq!(30, i.interpret(&e));
+        assert_eq!(Ok(30), i.interpret(&e));
     }
     #[test]
     fn test2() {
@@ -58,21 +115,21 @@ mod tests {
             )
         );
         let i = Interpreter::new();
-        assert_eq!(42, i.interpret(&e));
+        assert_eq!(Ok(42), i.interpret(&e));
     }
     #[test]
     fn test_assignment() {
         let i = Interpreter::new();
         let a: ast::Expression = ast::Ast::assignment("a".to_string(), ast::Ast::integer(10));
-        i.interpret(&a);
+        i.interpret(&a).unwrap();
         let b: ast::Expression = ast::Ast::assignment("b".to_string(), ast::Ast::integer(20));
-        i.interpret(&b);
+        i.interpret(&b).unwrap();
         let e: ast::Expression = ast::Ast::add(
             ast::Ast::symbol("a".to_string()),
             ast::Ast::symbol("b".to_string())
         );
         
-        assert_eq!(30, i.interpret(&e));
+        assert_eq!(Ok(30), i.interpret(&e));
     }
     #[test]
     fn test_factorial() {
@@ -122,7 +179,7 @@ mod tests {
         toplevels.push_back(main);
         toplevels.push_back(fact);
         let mut i = Interpreter::new();
-        let result = i.call_main(&ast::Program { definitions: toplevels });
-        assert_eq!(120, result);
+        let result = i.call_main(&ast::Program { imports: LinkedList::new(), definitions: toplevels });
+        assert_eq!(Ok(120), result);
     }
 }
@@ -0,0 +1,370 @@
+use std::fmt;
+
+// AST の書き出しや LSP の通信に使う最小限の JSON 表現
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>), // キーの順序を保つ
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonError {
+    pub message: String,
+    pub offset: usize,
+}
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid JSON at offset {}: {}", self.offset, self.message)
+    }
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, JsonError> {
+        let mut parser = JsonParser { text: text.as_bytes(), position: 0 };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.position != text.len() {
+            return Err(parser.error("unexpected trailing characters"));
+        }
+        Ok(value)
+    }
+    pub fn object(members: Vec<(&str, Json)>) -> Json {
+        Json::Object(members.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|x| x.0 == key).map(|x| &x.1),
+            _ => None,
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(x) => Some(x),
+            _ => None,
+        }
+    }
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(x) if x.fract() == 0.0 => Some(*x as i64),
+            _ => None,
+        }
+    }
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(x) => Some(*x),
+            _ => None,
+        }
+    }
+    pub fn as_array(&self) -> Option<&Vec<Json>> {
+        match self {
+            Json::Array(x) => Some(x),
+            _ => None,
+        }
+    }
+    pub fn is_null(&self) -> bool {
+        *self == Json::Null
+    }
+    // 字下げして複数行で書き出す
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(0, &mut out);
+        out
+    }
+
+    fn write_pretty(&self, depth: usize, out: &mut String) {
+        let indent = "  ".repeat(depth + 1);
+        match self {
+            Json::Array(items) if !items.is_empty() => {
+                out.push_str("[\n");
+                for (i, item) in items.iter().enumerate() {
+                    out.push_str(&indent);
+                    item.write_pretty(depth + 1, out);
+                    out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+                }
+                out.push_str(&"  ".repeat(depth));
+                out.push(']');
+            },
+            Json::Object(members) if !members.is_empty() => {
+                out.push_str("{\n");
+                for (i, (key, value)) in members.iter().enumerate() {
+                    out.push_str(&indent);
+                    out.push_str(&format!("{}: ", Json::String(key.clone())));
+                    value.write_pretty(depth + 1, out);
+                    out.push_str(if i + 1 < members.len() { ",\n" } else { "\n" });
+                }
+                out.push_str(&"  ".repeat(depth));
+                out.push('}');
+            },
+            _ => out.push_str(&self.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(x) => write!(f, "{}", x),
+            Json::Number(x) => write!(f, "{}", x),
+            Json::String(x) => {
+                write!(f, "\"")?;
+                for c in x.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\t' => write!(f, "\\t")?,
+                        c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            },
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", Json::String(key.clone()), value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(x: bool) -> Self {
+        Json::Bool(x)
+    }
+}
+impl From<i32> for Json {
+    fn from(x: i32) -> Self {
+        Json::Number(x as f64)
+    }
+}
+impl From<usize> for Json {
+    fn from(x: usize) -> Self {
+        Json::Number(x as f64)
+    }
+}
+impl From<&str> for Json {
+    fn from(x: &str) -> Self {
+        Json::String(x.to_string())
+    }
+}
+impl From<String> for Json {
+    fn from(x: String) -> Self {
+        Json::String(x)
+    }
+}
+impl From<Vec<Json>> for Json {
+    fn from(x: Vec<Json>) -> Self {
+        Json::Array(x)
+    }
+}
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(x: Option<T>) -> Self {
+        match x {
+            Some(x) => x.into(),
+            None => Json::Null,
+        }
+    }
+}
+
+struct JsonParser<'a> {
+    text: &'a [u8],
+    position: usize,
+}
+impl<'a> JsonParser<'a> {
+    fn error(&self, message: &str) -> JsonError {
+        JsonError { message: message.to_string(), offset: self.position }
+    }
+    fn whitespace(&mut self) {
+        while self.position < self.text.len() && b" \t\r\n".contains(&self.text[self.position]) {
+            self.position += 1;
+        }
+    }
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.position).copied()
+    }
+    fn expect(&mut self, c: u8) -> Result<(), JsonError> {
+        self.whitespace();
+        if self.peek() == Some(c) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c as char)))
+        }
+    }
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, JsonError> {
+        if self.text[self.position..].starts_with(word.as_bytes()) {
+            self.position += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("unexpected character"))
+        }
+    }
+    fn value(&mut self) -> Result<Json, JsonError> {
+        self.whitespace();
+        match self.peek() {
+            Some(b'n') => self.keyword("null", Json::Null),
+            Some(b't') => self.keyword("true", Json::Bool(true)),
+            Some(b'f') => self.keyword("false", Json::Bool(false)),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b'[') => {
+                self.position += 1;
+                let mut items = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(b']') {
+                    self.position += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b',') => self.position += 1,
+                        Some(b']') => {
+                            self.position += 1;
+                            return Ok(Json::Array(items));
+                        },
+                        _ => return Err(self.error("expected ',' or ']'")),
+                    }
+                }
+            },
+            Some(b'{') => {
+                self.position += 1;
+                let mut members = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(b'}') {
+                    self.position += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.whitespace();
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    members.push((key, self.value()?));
+                    self.whitespace();
+                    match self.peek() {
+                        Some(b',') => self.position += 1,
+                        Some(b'}') => {
+                            self.position += 1;
+                            return Ok(Json::Object(members));
+                        },
+                        _ => return Err(self.error("expected ',' or '}'")),
+                    }
+                }
+            },
+            Some(c) if c == b'-' || c.is_ascii_digit() => {
+                let start = self.position;
+                self.position += 1;
+                while self.peek().is_some_and(|x| x.is_ascii_digit() || b".eE+-".contains(&x)) {
+                    self.position += 1;
+                }
+                let number = std::str::from_utf8(&self.text[start..self.position]).unwrap();
+                number.parse().map(Json::Number).map_err(|_| JsonError { message: "invalid number".to_string(), offset: start })
+            },
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+    fn string(&mut self) -> Result<String, JsonError> {
+        if self.peek() != Some(b'"') {
+            return Err(self.error("expected string"));
+        }
+        self.position += 1;
+        let mut bytes = Vec::new();
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.position += 1;
+                    return String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8"));
+                },
+                Some(b'\\') => {
+                    self.position += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let mut code = self.hex4()?;
+                            // サロゲートペアは続く \u と組み合わせる
+                            if (0xd800..0xdc00).contains(&code) && self.text[self.position + 1..].starts_with(b"\\u") {
+                                self.position += 2;
+                                let low = self.hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(self.error("invalid unicode escape"));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))?
+                        },
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    self.position += 1;
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(escaped.encode_utf8(&mut buffer).as_bytes());
+                },
+                Some(c) => {
+                    bytes.push(c);
+                    self.position += 1;
+                },
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+    // \u に続く 4 桁を読み、最後の桁の位置で止まる
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let digits = self.text.get(self.position + 1..self.position + 5).ok_or_else(|| self.error("invalid unicode escape"))?;
+        let code = std::str::from_utf8(digits).ok()
+            .and_then(|x| u32::from_str_radix(x, 16).ok())
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.position += 4;
+        Ok(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let text = r#"{"a":[1,-2.5,true,null],"b":{"c":"x\"y\\né😀"},"d":[]}"#;
+        let value = Json::parse(text).unwrap();
+        assert_eq!(value.get("b").unwrap().get("c").unwrap().as_str(), Some("x\"y\\n\u{e9}\u{1f600}"));
+        assert_eq!(value.get("a").unwrap().as_array().unwrap()[0].as_i64(), Some(1));
+        assert_eq!(Json::parse(&value.to_string()).unwrap(), value);
+        assert_eq!(Json::parse(&value.pretty()).unwrap(), value);
+    }
+    #[test]
+    fn test_errors() {
+        assert!(Json::parse("{\"a\" 1}").is_err());
+        assert!(Json::parse("[1,]").is_err());
+        assert!(Json::parse("\"abc").is_err());
+        assert_eq!(Json::parse("[1] x").err().unwrap().offset, 4);
+    }
+}
@@ -1,22 +1,115 @@
 pub mod ast;
+pub mod checker;
+pub mod codegen;
+pub mod coverage;
+pub mod debugger;
+pub mod formatter;
+pub mod golden;
 pub mod interpreter;
+pub mod json;
+pub mod lsp;
+pub mod module;
+pub mod optimizer;
 pub mod parser;
+pub mod printer;
+pub mod profiler;
+pub mod resolver;
+pub mod serialize;
+pub mod testing;
+pub mod tracer;
+pub mod typeck;
 
 extern crate pest;
 #[macro_use]
 extern crate pest_derive;
 
-use std::collections::LinkedList;
+use std::fmt;
 
-pub fn execute_program(program: &String) -> i32 {
-    let parsed = parser::parse(&program);
+pub struct Options {
+    pub optimize: bool,
+    pub trace: Option<Vec<String>>, // 実行の記録を標準エラー出力に書く。関数名を並べるとその中だけを記録する
+}
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            optimize: true,
+            trace: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Codegen(codegen::CodegenError),
+    Module(module::ModuleError),
+    Parse(parser::ParseError),
+    Runtime(ast::RuntimeError),
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Codegen(e) => write!(f, "{}", e),
+            Error::Module(e) => write!(f, "{}", e),
+            Error::Parse(e) => write!(f, "{}", e),
+            Error::Runtime(e) => write!(f, "{}", e),
+        }
+    }
+}
+impl From<codegen::CodegenError> for Error {
+    fn from(e: codegen::CodegenError) -> Self {
+        Error::Codegen(e)
+    }
+}
+impl From<module::ModuleError> for Error {
+    fn from(e: module::ModuleError) -> Self {
+        Error::Module(e)
+    }
+}
+impl From<parser::ParseError> for Error {
+    fn from(e: parser::ParseError) -> Self {
+        Error::Parse(e)
+    }
+}
+impl From<ast::RuntimeError> for Error {
+    fn from(e: ast::RuntimeError) -> Self {
+        Error::Runtime(e)
+    }
+}
+
+pub fn execute_program(program: &str) -> i32 {
+    execute_program_with(program, &Options::default())
+}
+
+pub fn execute_program_with(program: &str, options: &Options) -> i32 {
+    run_program(program, options).unwrap_or_else(|e| panic!("{}", e))
+}
+
+pub fn execute_lines(lines: &str) -> i32 {
+    run_lines(lines).unwrap_or_else(|e| panic!("{}", e))
+}
+
+pub fn run_program(program: &str, options: &Options) -> Result<i32, Error> {
+    run_program_with_args(program, options, Vec::new())
+}
+
+pub fn run_program_with_args(program: &str, options: &Options, args: Vec<i32>) -> Result<i32, Error> {
+    let mut parsed = module::Linker::new(module::FileLoader).link_source(program, None)?;
+    if options.optimize {
+        parsed = optimizer::optimize(&parsed);
+    }
     let mut i = interpreter::Interpreter::new();
-    i.call_main(&parsed)
+    if let Some(functions) = &options.trace {
+        i.set_trace(functions.clone());
+    }
+    Ok(i.call_main_with_args(&parsed, args)?)
 }
 
-pub fn execute_lines(lines: &String) -> i32 {
-    let parsed = parser::parse_lines(&lines);
+pub fn run_lines(lines: &str) -> Result<i32, Error> {
+    let parsed = parser::parse_lines(lines)?;
     let i = interpreter::Interpreter::new();
-    let results: LinkedList<i32> = parsed.iter().map(|x| -> i32 { i.interpret(x)} ).collect();
-    results.into_iter().last().unwrap_or_else(|| 0)
+    let mut result = 0;
+    for line in parsed.iter() {
+        result = i.interpret(&optimizer::optimize_expression(line))?;
+    }
+    Ok(result)
 }
@@ -0,0 +1,495 @@
+use crate::ast::{annotated, Definition, Span};
+use crate::checker;
+use crate::checker::Severity;
+use crate::json::Json;
+use crate::module;
+use crate::parser;
+
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+const KEYWORDS: [&str; 22] = ["define", "global", "import", "if", "else", "while", "for", "in", "to", "println", "env", "match", "record", "struct", "enum", "throw", "try", "catch", "finally", "test", "assert", "assert_eq"];
+
+// LSP で決められている種類の番号
+const DIAGNOSTIC_ERROR: i32 = 1;
+const DIAGNOSTIC_WARNING: i32 = 2;
+const SYMBOL_FUNCTION: i32 = 12;
+const SYMBOL_VARIABLE: i32 = 13;
+const COMPLETION_FUNCTION: i32 = 3;
+const COMPLETION_FIELD: i32 = 5;
+const COMPLETION_VARIABLE: i32 = 6;
+const COMPLETION_KEYWORD: i32 = 14;
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+
+// 関数（parameters が Some）またはグローバル変数の定義。signature は型注釈を含めた定義の先頭部分
+#[derive(Clone)]
+struct Symbol {
+    name: String,
+    parameters: Option<Vec<String>>,
+    signature: String,
+    doc: Option<String>,
+    span: Span,
+    name_offset: usize,
+}
+
+struct Document {
+    text: String,
+    symbols: Vec<Symbol>, // 編集中で構文エラーがあるときは、最後に解析できたときのものを使う
+}
+
+// 標準入出力の JSON-RPC で動く toys の言語サーバ。
+// handle はメッセージを一つ受け取り、送り返すメッセージを返すので、通信なしでも試せる
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+    exit_code: Option<i32>,
+}
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    // exit 通知を受け取った後は、プロセスの終了コードを返す
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(|x| x.as_str()).unwrap_or("");
+        let params = message.get("params").cloned().unwrap_or(Json::Null);
+        match message.get("id") {
+            // クライアントからの応答は使わない
+            Some(_) if method.is_empty() => Vec::new(),
+            Some(id) => {
+                let result = if self.shutdown {
+                    Err((INVALID_REQUEST, "server is shutting down".to_string()))
+                } else {
+                    self.request(method, &params)
+                };
+                vec![response(id.clone(), result)]
+            },
+            None => self.notification(method, &params),
+        }
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, (i32, String)> {
+        match method {
+            "initialize" => Ok(Json::object(vec![
+                ("capabilities", Json::object(vec![
+                    ("textDocumentSync", 1.into()),
+                    ("definitionProvider", true.into()),
+                    ("hoverProvider", true.into()),
+                    ("documentSymbolProvider", true.into()),
+                    ("completionProvider", Json::object(vec![
+                        ("triggerCharacters", vec!["[".into(), ",".into()].into()),
+                    ])),
+                ])),
+                ("serverInfo", Json::object(vec![("name", "toys".into())])),
+            ])),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            },
+            "textDocument/definition" => Ok(self.definition(params)),
+            "textDocument/hover" => Ok(self.hover(params)),
+            "textDocument/documentSymbol" => Ok(self.document_symbols(params)),
+            "textDocument/completion" => Ok(self.completion(params)),
+            _ => Err((METHOD_NOT_FOUND, format!("method not found: {}", method))),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = params.get("textDocument").and_then(|x| x.get("uri")).and_then(|x| x.as_str()).unwrap_or("").to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").and_then(|x| x.get("text")).and_then(|x| x.as_str()).unwrap_or("");
+                self.update(uri, text.to_string())
+            },
+            // 同期方法に全文（textDocumentSync = 1）を指定しているので、最後の変更が文書全体になる
+            "textDocument/didChange" => {
+                let text = params.get("contentChanges")
+                    .and_then(|x| x.as_array())
+                    .and_then(|x| x.last())
+                    .and_then(|x| x.get("text"))
+                    .and_then(|x| x.as_str());
+                match text {
+                    Some(text) => self.update(uri, text.to_string()),
+                    None => Vec::new(),
+                }
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                vec![publish_diagnostics(&uri, Vec::new())]
+            },
+            "exit" => {
+                self.exit_code = Some(if self.shutdown { 0 } else { 1 });
+                Vec::new()
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    fn update(&mut self, uri: String, text: String) -> Vec<Json> {
+        let (symbols, diagnostics) = analyze(&uri, &text);
+        let symbols = match (symbols, self.documents.remove(&uri)) {
+            (Some(x), _) => x,
+            (None, Some(previous)) => previous.symbols,
+            (None, None) => Vec::new(),
+        };
+        self.documents.insert(uri.clone(), Document { text, symbols });
+        vec![publish_diagnostics(&uri, diagnostics)]
+    }
+
+    // カーソル位置の文書と、その位置のオフセットを返す
+    fn locate<'a>(&'a self, params: &'a Json) -> Option<(&'a str, &'a Document, usize)> {
+        let uri = params.get("textDocument")?.get("uri")?.as_str()?;
+        let document = self.documents.get(uri)?;
+        let offset = offset_at(&document.text, params.get("position")?);
+        Some((uri, document, offset))
+    }
+
+    fn definition(&self, params: &Json) -> Json {
+        let (uri, document, offset) = match self.locate(params) {
+            Some(x) => x,
+            None => return Json::Null,
+        };
+        let (word, _, _) = word_at(&document.text, offset);
+        match document.symbols.iter().find(|x| x.name == word) {
+            Some(symbol) => Json::object(vec![
+                ("uri", uri.into()),
+                ("range", range(&document.text, symbol.name_offset, symbol.name_offset + symbol.name.len())),
+            ]),
+            None => Json::Null,
+        }
+    }
+
+    fn hover(&self, params: &Json) -> Json {
+        let (_, document, offset) = match self.locate(params) {
+            Some(x) => x,
+            None => return Json::Null,
+        };
+        let (word, start, end) = word_at(&document.text, offset);
+        match document.symbols.iter().find(|x| x.name == word) {
+            Some(symbol) => {
+                let mut value = format!("```toys\n{}\n```", symbol.signature);
+                if let Some(doc) = &symbol.doc {
+                    value.push_str("\n\n");
+                    value.push_str(doc);
+                }
+                Json::object(vec![
+                    ("contents", Json::object(vec![("kind", "markdown".into()), ("value", value.into())])),
+                    ("range", range(&document.text, start, end)),
+                ])
+            },
+            None => Json::Null,
+        }
+    }
+
+    fn document_symbols(&self, params: &Json) -> Json {
+        let document = match params.get("textDocument").and_then(|x| x.get("uri")).and_then(|x| x.as_str()).and_then(|x| self.documents.get(x)) {
+            Some(x) => x,
+            None => return Json::Null,
+        };
+        let text = &document.text;
+        document.symbols.iter().map(|x| Json::object(vec![
+            ("name", x.name.as_str().into()),
+            ("detail", x.signature.as_str().into()),
+            ("kind", if x.parameters.is_some() { SYMBOL_FUNCTION } else { SYMBOL_VARIABLE }.into()),
+            ("range", range(text, x.span.start, x.span.end)),
+            ("selectionRange", range(text, x.name_offset, x.name_offset + x.name.len())),
+        ])).collect::<Vec<Json>>().into()
+    }
+
+    fn completion(&self, params: &Json) -> Json {
+        let (_, document, offset) = match self.locate(params) {
+            Some(x) => x,
+            None => return Json::Null,
+        };
+        // `f[` の後ろではラベル（f の仮引数名）を補完する
+        if let Some(name) = labelled_call_before(&document.text, offset) {
+            let parameters = document.symbols.iter().find(|x| x.name == name).and_then(|x| x.parameters.clone());
+            return parameters.unwrap_or_default().iter().map(|x| Json::object(vec![
+                ("label", x.as_str().into()),
+                ("kind", COMPLETION_FIELD.into()),
+                ("detail", format!("parameter of {}", name).into()),
+                ("insertText", format!("{} = ", x).into()),
+            ])).collect::<Vec<Json>>().into();
+        }
+        let mut items: Vec<Json> = document.symbols.iter().map(|x| Json::object(vec![
+            ("label", x.name.as_str().into()),
+            ("kind", if x.parameters.is_some() { COMPLETION_FUNCTION } else { COMPLETION_VARIABLE }.into()),
+            ("detail", x.signature.as_str().into()),
+            ("documentation", x.doc.clone().into()),
+        ])).collect();
+        items.extend(KEYWORDS.iter().map(|x| Json::object(vec![
+            ("label", (*x).into()),
+            ("kind", COMPLETION_KEYWORD.into()),
+        ])));
+        items.into()
+    }
+}
+
+// 構文エラー、または import を解決したうえでの静的検査の結果を診断として返す
+fn analyze(uri: &str, text: &str) -> (Option<Vec<Symbol>>, Vec<Json>) {
+    let program = match parser::parse(text) {
+        Ok(x) => x,
+        Err(e) => {
+            let offset = offset_of_line_column(text, e.line, e.column);
+            return (None, vec![diagnostic(text, offset, offset, DIAGNOSTIC_ERROR, &e.message)]);
+        },
+    };
+    let symbols = collect_symbols(text, &program);
+
+    let mut linker = module::Linker::new(module::FileLoader);
+    if let Some(paths) = env::var_os("TOYS_PATH") {
+        for path in env::split_paths(&paths) {
+            linker.add_search_path(path);
+        }
+    }
+    let path = file_path(uri);
+    let linked = match linker.link_source(text, path.as_deref()) {
+        Ok(x) => x,
+        Err(e) => {
+            let offset = text.find("import").unwrap_or(0);
+            return (Some(symbols), vec![diagnostic(text, offset, offset, DIAGNOSTIC_ERROR, &e.to_string())]);
+        },
+    };
+    let diagnostics = checker::check_program(&linked).into_iter()
+        .filter(|x| !x.is_imported())
+        .filter_map(|x| {
+            let severity = match x.severity {
+                Severity::Error => DIAGNOSTIC_ERROR,
+                Severity::Warning => DIAGNOSTIC_WARNING,
+            };
+            x.span.map(|span| diagnostic(text, span.start, span.end, severity, &x.message))
+        })
+        .collect();
+    (Some(symbols), diagnostics)
+}
+
+fn collect_symbols(text: &str, program: &crate::ast::Program) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    for toplevel in program.definitions.iter() {
+        let (name, parameters, signature, doc, span, keyword) = match toplevel.definition() {
+            Definition::Function(x) => (&x.name, Some(x.args.iter().cloned().collect()), format!("define {}", x.signature()), &x.doc, x.span, "define"),
+            Definition::Global(x) => (&x.name, None, format!("global {}", annotated(&x.name, x.type_annotation.as_deref())), &x.doc, x.span, "global"),
+            Definition::Record(x) => {
+                // record と struct のどちらで書かれていても名前の位置を探せるようにする
+                let source = x.constructor.span.and_then(|y| text.get(y.start..y.end)).unwrap_or("");
+                let keyword = if source.contains("struct") && !source.contains("record") { "struct" } else { "record" };
+                (&x.constructor.name, Some(x.fields().iter().cloned().collect()), format!("record {}", x.constructor.signature()), &x.constructor.doc, x.constructor.span, keyword)
+            },
+            // バリアントは { の後ろから名前を探す
+            Definition::Enum(x) => {
+                for variant in x.variants.iter() {
+                    if let Some(span) = x.span {
+                        symbols.push(Symbol {
+                            name: variant.name.clone(),
+                            parameters: Some(variant.args.iter().cloned().collect()),
+                            signature: format!("{}: {}", variant.signature(), x.name),
+                            doc: x.doc.clone(),
+                            span,
+                            name_offset: name_offset(text, span, "{", &variant.name),
+                        });
+                    }
+                }
+                (&x.name, None, format!("enum {}", x.signature()), &x.doc, x.span, "enum")
+            },
+            Definition::Test(_) => continue,
+        };
+        if let Some(span) = span {
+            symbols.push(Symbol {
+                name: name.clone(),
+                parameters,
+                signature,
+                doc: doc.clone(),
+                span,
+                name_offset: name_offset(text, span, keyword, name),
+            });
+        }
+    }
+    symbols
+}
+
+// 定義の範囲はドキュメントコメントから始まるので、それを読み飛ばしてキーワードの後の名前を探す
+fn name_offset(text: &str, span: Span, keyword: &str, name: &str) -> usize {
+    let mut offset = span.start;
+    while text[offset..].starts_with("///") {
+        offset += text[offset..].find('\n').map_or(text.len() - offset, |x| x + 1);
+        offset += text[offset..].len() - text[offset..].trim_start().len();
+    }
+    let after_keyword = offset + text[offset..].find(keyword).map_or(0, |x| x + keyword.len());
+    after_keyword + text[after_keyword..].find(name).unwrap_or(0)
+}
+
+// file:// の URI をパスに直す（import を読み込み元のファイルからの相対パスで探すため）
+fn file_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' { path.get(i + 1..i + 3).and_then(|x| u8::from_str_radix(x, 16).ok()) } else { None };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8(decoded).ok().map(PathBuf::from)
+}
+
+fn word_at(text: &str, offset: usize) -> (&str, usize, usize) {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+    let start = text[..offset].rfind(|c: char| !is_word(c)).map_or(0, |x| x + 1);
+    let end = text[offset..].find(|c: char| !is_word(c)).map_or(text.len(), |x| offset + x);
+    (&text[start..end], start, end)
+}
+
+// カーソルが閉じていない `name[` の中にあれば name を返す
+fn labelled_call_before(text: &str, offset: usize) -> Option<&str> {
+    let mut depth = 0;
+    for (i, c) in text[..offset].char_indices().rev() {
+        match c {
+            ']' => depth += 1,
+            '[' if depth > 0 => depth -= 1,
+            '[' => {
+                let before = text[..i].trim_end();
+                let (name, _, _) = word_at(before, before.len());
+                return if name.is_empty() { None } else { Some(name) };
+            },
+            ';' | '{' | '}' => return None,
+            _ => {},
+        }
+    }
+    None
+}
+
+// LSP の位置は 0 始まりの行と、UTF-16 の単位で数えた列で表す
+fn position_at(text: &str, offset: usize) -> Json {
+    // 古い解析結果の位置が文書の外を指すこともある
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |x| x + 1);
+    let character: usize = before[line_start..].chars().map(|c| c.len_utf16()).sum();
+    Json::object(vec![("line", before.matches('\n').count().into()), ("character", character.into())])
+}
+
+fn offset_at(text: &str, position: &Json) -> usize {
+    let number = |key: &str| position.get(key).and_then(|x| x.as_i64()).unwrap_or(0).max(0) as usize;
+    let mut offset = 0;
+    for _ in 0..number("line") {
+        match text[offset..].find('\n') {
+            Some(x) => offset += x + 1,
+            None => return text.len(),
+        }
+    }
+    let character = number("character");
+    let mut units = 0;
+    for (i, c) in text[offset..].char_indices() {
+        if units >= character || c == '\n' {
+            return offset + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+// 構文エラーの行と列（どちらも 1 始まりで、列は文字単位）をオフセットに直す
+fn offset_of_line_column(text: &str, line: usize, column: usize) -> usize {
+    let line_start: usize = text.split_inclusive('\n').take(line - 1).map(|x| x.len()).sum();
+    text[line_start..].char_indices().nth(column - 1).map_or(text.len(), |x| line_start + x.0)
+}
+
+fn range(text: &str, start: usize, end: usize) -> Json {
+    Json::object(vec![("start", position_at(text, start)), ("end", position_at(text, end))])
+}
+
+fn diagnostic(text: &str, start: usize, end: usize, severity: i32, message: &str) -> Json {
+    Json::object(vec![
+        ("range", range(text, start, end)),
+        ("severity", severity.into()),
+        ("source", "toys".into()),
+        ("message", message.into()),
+    ])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/publishDiagnostics".into()),
+        ("params", Json::object(vec![("uri", uri.into()), ("diagnostics", diagnostics.into())])),
+    ])
+}
+
+fn response(id: Json, result: Result<Json, (i32, String)>) -> Json {
+    match result {
+        Ok(x) => Json::object(vec![("jsonrpc", "2.0".into()), ("id", id), ("result", x)]),
+        Err((code, message)) => Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", id),
+            ("error", Json::object(vec![("code", code.into()), ("message", message.into())])),
+        ]),
+    }
+}
+
+// Content-Length ヘッダの付いたメッセージを一つ読む。入力が終わっていれば None を返す
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    String::from_utf8(body).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message<W: Write>(writer: &mut W, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+// exit 通知か入力の終わりまでメッセージを処理し、プロセスの終了コードを返す
+pub fn serve<R: BufRead, W: Write>(mut reader: R, mut writer: W) -> io::Result<i32> {
+    let mut server = Server::new();
+    while let Some(body) = read_message(&mut reader)? {
+        let replies = match Json::parse(&body) {
+            Ok(message) => server.handle(&message),
+            Err(e) => vec![response(Json::Null, Err((PARSE_ERROR, e.to_string())))],
+        };
+        for reply in replies.iter() {
+            write_message(&mut writer, reply)?;
+        }
+        if let Some(code) = server.exit_code() {
+            return Ok(code);
+        }
+    }
+    Ok(if server.shutdown { 0 } else { 1 })
+}
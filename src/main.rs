@@ -1,20 +1,504 @@
 use toys_in_rust::*;
 
+use std::collections::HashMap;
 use std::env;
-use std::fs::File;
-use std::io::prelude::*;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+
+const USAGE: &str = "usage: toys [run] [--no-optimize] [--json] [--trace[=f,g...]] [--profile] [--profile-folded=<out>] [--profile-chrome=<out>]
+                 [--coverage] [--lcov=<out>] <file> [integer args...]
+       toys run -e <code>
+       toys check [--types] <file>
+       toys test <file> [name filter]
+       toys golden [--bless] <directory>
+       toys build [--target c|js|wat|x86_64] [-o <out>] <file>
+       toys fmt [-w] <file>
+       toys ast [--optimize] [--format tree|json|sexpr] <file>
+       toys tokens <file>
+       toys debug [-b <line|function>]... <file> [integer args...]
+       toys lsp
+
+<file> に - を指定すると標準入力から読み込む。
+run の引数は main の仮引数に渡され、main の返り値が終了コードになる。
+run --json は toys ast --format json で書き出した構文木を実行する。
+run --trace は関数の出入りや代入などを標準エラー出力に書く（--trace=f,g ならその関数の中だけ）。
+run --profile は関数ごとの呼び出し回数と時間、行ごとの実行回数を標準エラー出力に書く。
+--profile-folded は flamegraph 用の folded stacks を、--profile-chrome は Chrome の trace event の JSON を書き出す。
+run --coverage は実行した行と分岐の割合を標準エラー出力に書き、--lcov は lcov 形式のファイルを書き出す。
+check --types は Hindley–Milner の型推論を行い、関数とグローバル変数の型を表示する。
+build はプログラムを他の言語に変換して標準出力（-o があればそのファイル）に書く。c は cc でそのままコンパイルでき、js は ES モジュールに（-o があれば Source Map も書き出す）、wat は WebAssembly のテキスト形式のモジュールに、x86_64 は cc でアセンブルできるアセンブリになる。
+golden はディレクトリの下の .toys を実行し、標準出力を .out、標準エラー出力と終了コードを .err と比べる（--bless なら書き換える）。
+debug はコマンドを標準入力から読むステップ実行のデバッガで、help でコマンドの一覧を表示する";
+
+// 利用方法の誤りは 2、プログラムの誤りは 1 で終了する。
+// 正常に終了した場合は main の返り値を終了コードにする
+enum Failure {
+    Usage(String),
+    Error,
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        panic!("specify a file");
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result: Result<i32, Failure> = match args.first().map(|x| x.as_str()) {
+        Some("run") => run(&args[1..]),
+        Some("check") => check(&args[1..]).map(|_| 0),
+        Some("test") => test(&args[1..]).map(|_| 0),
+        Some("golden") => golden(&args[1..]).map(|_| 0),
+        Some("build") => build(&args[1..]).map(|_| 0),
+        Some("fmt") => fmt(&args[1..]).map(|_| 0),
+        Some("ast") => ast(&args[1..]).map(|_| 0),
+        Some("tokens") => tokens(&args[1..]).map(|_| 0),
+        Some("debug") => debug(&args[1..]),
+        Some("lsp") => lsp(),
+        Some("-h") | Some("--help") | Some("help") => {
+            println!("{}", USAGE);
+            Ok(0)
+        },
+        Some(_) => run(&args),
+        None => Err(Failure::Usage("no command given".to_string())),
+    };
+    match result {
+        Ok(status) => process::exit(exit_status(status)),
+        Err(Failure::Usage(message)) => {
+            eprintln!("error: {}\n{}", message, USAGE);
+            process::exit(2);
+        },
+        Err(Failure::Error) => process::exit(1),
+    }
+}
+
+// 終了コードは 0 から 255 までしか表せない。下位 8 ビットだけを使うと 256 が成功になってしまうので、
+// 255 より大きい値は 255、負の値は 1 にする
+fn exit_status(value: i32) -> i32 {
+    match value {
+        0..=255 => value,
+        x if x > 255 => 255,
+        _ => 1,
+    }
+}
+
+fn run(args: &[String]) -> Result<i32, Failure> {
+    let mut options = Options::default();
+    let mut json = false;
+    let mut profile = false;
+    let mut folded: Option<&str> = None;
+    let mut chrome: Option<&str> = None;
+    let mut coverage = false;
+    let mut lcov: Option<&str> = None;
+    let mut rest = args;
+    while let Some(flag) = rest.first() {
+        match flag.as_str() {
+            "--no-optimize" => options.optimize = false,
+            "--profile" => profile = true,
+            x if x.starts_with("--profile-folded=") => folded = Some(&x["--profile-folded=".len()..]),
+            x if x.starts_with("--profile-chrome=") => chrome = Some(&x["--profile-chrome=".len()..]),
+            "--coverage" => coverage = true,
+            x if x.starts_with("--lcov=") => lcov = Some(&x["--lcov=".len()..]),
+            "--trace" => options.trace = Some(Vec::new()),
+            x if x.starts_with("--trace=") => options.trace = Some(x["--trace=".len()..].split(',').map(|y| y.to_string()).collect()),
+            "--json" => json = true,
+            "-e" => {
+                let code = rest.get(1).ok_or_else(|| Failure::Usage("-e requires code".to_string()))?;
+                let value = run_lines(code).map_err(|e| report("-e", code, &e))?;
+                println!("{}", value);
+                return Ok(0);
+            },
+            "-" => break,
+            x if x.starts_with('-') => return Err(Failure::Usage(format!("unknown option {}", x))),
+            _ => break,
+        }
+        rest = &rest[1..];
+    }
+    let profile = profile || folded.is_some() || chrome.is_some();
+    let coverage = coverage || lcov.is_some();
+    if [options.trace.is_some(), profile, coverage].iter().filter(|x| **x).count() > 1 {
+        return Err(Failure::Usage("--trace, --profile and --coverage cannot be combined".to_string()));
+    }
+    // 最適化で消える分岐や行も数えられるよう、プロファイルとカバレッジは最適化せずに測る
+    if profile || coverage {
+        options.optimize = false;
+    }
+    let filename = rest.first().ok_or_else(|| Failure::Usage("no input file".to_string()))?;
+    let script_args = rest[1..].iter()
+        .map(|x| x.parse().map_err(|_| Failure::Usage(format!("argument {} is not an integer", x))))
+        .collect::<Result<Vec<i32>, Failure>>()?;
+    let (name, contents) = read_source(filename)?;
+    let (mut program, modules) = if json {
+        let program = serialize::from_json(&contents).map_err(|e| {
+            eprintln!("{}: error: {}", name, e);
+            Failure::Error
+        })?;
+        if !program.imports.is_empty() {
+            eprintln!("{}: error: import is not supported in JSON programs", name);
+            return Err(Failure::Error);
+        }
+        (program, HashMap::new())
+    } else {
+        link_modules(filename, &name, &contents)?
+    };
+    if options.optimize {
+        program = optimizer::optimize(&program);
+    }
+    let mut i = interpreter::Interpreter::new();
+    if let Some(functions) = options.trace {
+        i.set_trace(functions);
+    }
+    let profiler = if profile { Some(Rc::new(profiler::Profiler::new())) } else { None };
+    if let Some(x) = &profiler {
+        i.set_hook(x.clone());
+    }
+    let coverage = if coverage { Some(Rc::new(coverage::Coverage::new(&program))) } else { None };
+    if let Some(x) = &coverage {
+        i.set_hook(x.clone());
+    }
+    let result = i.call_main_with_args(&program, script_args);
+    // 実行時エラーで止まっても、それまでの記録は書き出す
+    if let Some(profiler) = profiler {
+        eprint!("{}", profiler.report());
+        write_output(folded, &profiler.folded())?;
+        write_output(chrome, &profiler.chrome_trace().to_string())?;
+    }
+    if let Some(coverage) = coverage {
+        eprint!("{}", coverage.summary());
+        write_output(lcov, &coverage.lcov(&name))?;
+    }
+    result.map_err(|e| report_runtime(&name, &modules, &e))
+}
+
+// --types なら型も推論し、関数とグローバル変数の型を標準出力に書く
+fn check(args: &[String]) -> Result<(), Failure> {
+    let types = args.first().is_some_and(|x| x == "--types");
+    let filename = single_file(if types { &args[1..] } else { args })?;
+    let (name, contents) = read_source(filename)?;
+    let program = link(filename, &name, &contents)?;
+    let mut diagnostics = checker::check_program(&program);
+    if types && !diagnostics.iter().any(|x| x.severity == checker::Severity::Error) {
+        let typing = typeck::check_program(&program);
+        if typing.diagnostics.is_empty() {
+            for (function, scheme) in typing.functions.iter() {
+                println!("{} : {}", function, scheme);
+            }
+            for (global, ty) in typing.globals.iter() {
+                println!("{} : {}", global, ty);
+            }
+        }
+        diagnostics.extend(typing.diagnostics);
+    }
+    for diagnostic in diagnostics.iter() {
+        match diagnostic.span {
+            Some(span) if !diagnostic.is_imported() => eprintln!("{}:{}:{}: {}", name, span.line, span.column, diagnostic),
+            _ => eprintln!("{}: {}", name, diagnostic),
+        }
+    }
+    if diagnostics.iter().any(|x| x.severity == checker::Severity::Error) {
+        return Err(Failure::Error);
+    }
+    Ok(())
+}
+
+// test "名前" { ... } をそれぞれ新しいインタプリタで実行する。一つでも失敗すれば 1 で終了する
+fn test(args: &[String]) -> Result<(), Failure> {
+    let (filename, filter) = match args {
+        [file] => (file, None),
+        [file, filter] => (file, Some(filter.as_str())),
+        [] => return Err(Failure::Usage("no input file".to_string())),
+        _ => return Err(Failure::Usage("too many arguments".to_string())),
+    };
+    let (name, contents) = read_source(filename)?;
+    let program = link(filename, &name, &contents)?;
+    let results = testing::run_tests(&program, filter);
+    let filtered = testing::tests(&program).len() - results.len();
+    print!("{}", testing::report(&name, &results, filtered));
+    if results.iter().any(|x| !x.passed()) {
+        return Err(Failure::Error);
+    }
+    Ok(())
+}
+
+// 期待と違う出力が一つでもあれば 1 で終了する
+fn golden(args: &[String]) -> Result<(), Failure> {
+    let bless = args.first().is_some_and(|x| x == "--bless");
+    let dir = single_file(if bless { &args[1..] } else { args })?;
+    let toys = env::current_exe().map_err(|e| {
+        eprintln!("error: cannot find the toys executable: {}", e);
+        Failure::Error
+    })?;
+    let results = golden::Harness::new(toys).bless(bless).run_dir(Path::new(dir)).map_err(|e| {
+        eprintln!("error: {}: {}", dir, e);
+        Failure::Error
+    })?;
+    print!("{}", golden::report(&results));
+    if results.iter().any(|x| matches!(x.verdict, golden::Verdict::Failed(_))) {
+        return Err(Failure::Error);
+    }
+    Ok(())
+}
+
+// 実行するときと同じく、取り込んだモジュールをつなげて最適化してから変換する
+fn build(args: &[String]) -> Result<(), Failure> {
+    let mut target = "c";
+    let mut output: Option<&str> = None;
+    let mut rest = args;
+    loop {
+        match rest.first().map(|x| x.as_str()) {
+            Some("--target") => {
+                target = match rest.get(1).map(|x| x.as_str()) {
+                    Some(x @ ("c" | "js" | "wat" | "x86_64")) => x,
+                    _ => return Err(Failure::Usage("--target must be c, js, wat or x86_64".to_string())),
+                };
+                rest = &rest[1..];
+            },
+            Some("-o") => {
+                output = Some(rest.get(1).ok_or_else(|| Failure::Usage("-o requires a file".to_string()))?);
+                rest = &rest[1..];
+            },
+            _ => break,
+        }
+        rest = &rest[1..];
+    }
+    let filename = single_file(rest)?;
+    let (name, contents) = read_source(filename)?;
+    let program = optimizer::optimize(&link(filename, &name, &contents)?);
+    if target == "js" {
+        return build_js(&program, output, &name, &contents);
+    }
+    let code = match target {
+        "wat" => codegen::wat::generate(&program),
+        "x86_64" => codegen::x86_64::generate(&program),
+        _ => codegen::c::generate(&program),
+    }.map_err(|e| report(&name, &contents, &Error::Codegen(e)))?;
+    match output {
+        Some(_) => write_output(output, &code),
+        None => {
+            print!("{}", code);
+            Ok(())
+        },
+    }
+}
+
+// -o で書き出すときは、隣に Source Map（出力先 + .map）も書き出す
+fn build_js(program: &ast::Program, output: Option<&str>, name: &str, contents: &str) -> Result<(), Failure> {
+    let module = codegen::js::generate(program, name).map_err(|e| report(name, contents, &Error::Codegen(e)))?;
+    match output {
+        Some(path) => {
+            let map = format!("{}.map", path);
+            let file = Path::new(path).file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+            write_output(output, &format!("{}//# sourceMappingURL={}.map\n", module.code, file))?;
+            write_output(Some(&map), &format!("{}\n", module.source_map(&file, contents)))
+        },
+        None => {
+            print!("{}", module.code);
+            Ok(())
+        },
+    }
+}
+
+fn fmt(args: &[String]) -> Result<(), Failure> {
+    let write = args.first().is_some_and(|x| x == "-w");
+    let filename = single_file(if write { &args[1..] } else { args })?;
+    if write && filename == "-" {
+        return Err(Failure::Usage("-w cannot be used with standard input".to_string()));
+    }
+    let (name, contents) = read_source(filename)?;
+    let formatted = formatter::format(&contents).map_err(|e| report(&name, &contents, &Error::Parse(e)))?;
+    if write {
+        if formatted != contents {
+            fs::write(filename, formatted).map_err(|e| {
+                eprintln!("error: cannot write {}: {}", name, e);
+                Failure::Error
+            })?;
+        }
+    } else {
+        print!("{}", formatted);
+    }
+    Ok(())
+}
+
+fn ast(args: &[String]) -> Result<(), Failure> {
+    let mut optimize = false;
+    let mut format = "tree";
+    let mut rest = args;
+    loop {
+        match rest.first().map(|x| x.as_str()) {
+            Some("--optimize") => optimize = true,
+            Some("--format") => {
+                format = match rest.get(1).map(|x| x.as_str()) {
+                    Some(x @ ("tree" | "json" | "sexpr")) => x,
+                    _ => return Err(Failure::Usage("--format must be tree, json or sexpr".to_string())),
+                };
+                rest = &rest[1..];
+            },
+            _ => break,
+        }
+        rest = &rest[1..];
+    }
+    let filename = single_file(rest)?;
+    let (name, contents) = read_source(filename)?;
+    let mut program = parser::parse(&contents).map_err(|e| report(&name, &contents, &Error::Parse(e)))?;
+    if optimize {
+        program = optimizer::optimize(&program);
+    }
+    match format {
+        "json" => println!("{}", serialize::to_json(&program)),
+        "sexpr" => println!("{}", serialize::to_sexpr(&program)),
+        _ => print!("{}", printer::dump_program(&program)),
+    }
+    Ok(())
+}
+
+fn tokens(args: &[String]) -> Result<(), Failure> {
+    let filename = single_file(args)?;
+    let (name, contents) = read_source(filename)?;
+    let tokens = parser::tokenize(&contents).map_err(|e| report(&name, &contents, &Error::Parse(e)))?;
+    for token in tokens.iter() {
+        println!("{}:{}\t{}\t{}", token.line, token.column, token.kind, token.text);
     }
-    let filename = &args[1];
-    let mut f = File::open(filename).expect("file not found");
+    Ok(())
+}
+
+// 最適化すると文と行の対応が崩れるので、最適化せずに実行する
+fn debug(args: &[String]) -> Result<i32, Failure> {
+    let mut breakpoints = Vec::new();
+    let mut rest = args;
+    while let Some(flag) = rest.first() {
+        match flag.as_str() {
+            "-b" | "--break" => {
+                let target = rest.get(1).ok_or_else(|| Failure::Usage(format!("{} requires a line or a function", flag)))?;
+                breakpoints.push(match target.parse() {
+                    Ok(line) => debugger::Breakpoint::Line(line),
+                    Err(_) => debugger::Breakpoint::Function(target.clone()),
+                });
+                rest = &rest[1..];
+            },
+            x if x.starts_with('-') => return Err(Failure::Usage(format!("unknown option {}", x))),
+            _ => break,
+        }
+        rest = &rest[1..];
+    }
+    let filename = rest.first().ok_or_else(|| Failure::Usage("no input file".to_string()))?;
+    if filename == "-" {
+        return Err(Failure::Usage("debug reads commands from standard input, so the program must be a file".to_string()));
+    }
+    let script_args = rest[1..].iter()
+        .map(|x| x.parse().map_err(|_| Failure::Usage(format!("argument {} is not an integer", x))))
+        .collect::<Result<Vec<i32>, Failure>>()?;
+    let (name, contents) = read_source(filename)?;
+    let program = link(filename, &name, &contents)?;
+    let input = std::io::BufReader::new(std::io::stdin());
+    let (result, _) = debugger::debug(&program, &contents, script_args, breakpoints, input, std::io::stdout());
+    result.map_err(|_| Failure::Error)
+}
 
-    let mut program = String::new();
-    f.read_to_string(&mut program)
-        .expect("something went wrong reading the file");
+// エディタとは標準入出力で通信する
+fn lsp() -> Result<i32, Failure> {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    lsp::serve(stdin.lock(), stdout.lock()).map_err(|e| {
+        eprintln!("error: {}", e);
+        Failure::Error
+    })
+}
 
-    execute_program(&program);
+fn write_output(path: Option<&str>, contents: &str) -> Result<(), Failure> {
+    match path {
+        Some(path) => fs::write(path, contents).map_err(|e| {
+            eprintln!("error: cannot write {}: {}", path, e);
+            Failure::Error
+        }),
+        None => Ok(()),
+    }
+}
+
+fn single_file(args: &[String]) -> Result<&String, Failure> {
+    match args {
+        [x] => Ok(x),
+        [] => Err(Failure::Usage("no input file".to_string())),
+        _ => Err(Failure::Usage("too many arguments".to_string())),
+    }
+}
+
+fn read_source(filename: &str) -> Result<(String, String), Failure> {
+    if filename == "-" {
+        let mut contents = String::new();
+        return match std::io::stdin().read_to_string(&mut contents) {
+            Ok(_) => Ok(("<stdin>".to_string(), contents)),
+            Err(e) => {
+                eprintln!("error: cannot read standard input: {}", e);
+                Err(Failure::Error)
+            },
+        };
+    }
+    match fs::read_to_string(filename) {
+        Ok(contents) => Ok((filename.to_string(), contents)),
+        Err(e) => {
+            eprintln!("error: cannot read {}: {}", filename, e);
+            Err(Failure::Error)
+        },
+    }
+}
+
+fn link(filename: &str, name: &str, contents: &str) -> Result<ast::Program, Failure> {
+    link_modules(filename, name, contents).map(|(program, _)| program)
+}
+
+// 取り込んだモジュールの名前とファイルも返す
+fn link_modules(filename: &str, name: &str, contents: &str) -> Result<(ast::Program, HashMap<String, PathBuf>), Failure> {
+    let mut linker = module::Linker::new(module::FileLoader);
+    if let Some(paths) = env::var_os("TOYS_PATH") {
+        for path in env::split_paths(&paths) {
+            linker.add_search_path(path);
+        }
+    }
+    let path = if filename == "-" { None } else { Some(Path::new(filename)) };
+    let program = linker.link_source(contents, path).map_err(|e| report(name, contents, &Error::Module(e)))?;
+    Ok((program, linker.modules().clone()))
+}
+
+// エラーを `ファイル:行:列: error: 内容` の形で標準エラー出力に書く
+fn report(name: &str, contents: &str, error: &Error) -> Failure {
+    match error {
+        Error::Parse(e) => eprintln!("{}:{}:{}: error: {}\n{}", name, e.line, e.column, e.message, excerpt(contents, e.line, e.column)),
+        Error::Module(module::ModuleError::Parse(file, e)) => {
+            let source = fs::read_to_string(file).unwrap_or_default();
+            eprintln!("{}:{}:{}: error: {}\n{}", file, e.line, e.column, e.message, excerpt(&source, e.line, e.column));
+        },
+        Error::Module(e) => eprintln!("{}: error: {}", name, e),
+        Error::Codegen(e) => match e.span {
+            Some(span) => eprintln!("{}:{}:{}: error: {}\n{}", name, span.line, span.column, e, excerpt(contents, span.line, span.column)),
+            None => eprintln!("{}: error: {}", name, e),
+        },
+        Error::Runtime(e) => return report_runtime(name, &HashMap::new(), e),
+    }
+    Failure::Error
+}
+
+// 位置はその関数を定義したファイルのもの。取り込んだモジュールの関数なら `モジュール名.` から
+// モジュールのファイルを引き、分からなければ関数の名前だけを書く
+fn report_runtime(name: &str, modules: &HashMap<String, PathBuf>, error: &ast::RuntimeError) -> Failure {
+    let file = |function: &str| match function.split_once('.') {
+        Some((module, _)) => modules.get(module).map(|x| x.display().to_string()),
+        None => Some(name.to_string()),
+    };
+    let innermost = error.trace.first().map_or(Some(name.to_string()), |x| file(&x.function));
+    match (error.span, innermost) {
+        (Some(span), Some(file)) => eprintln!("{}:{}:{}: error: {}", file, span.line, span.column, error),
+        _ => eprintln!("{}: error: {}", name, error),
+    }
+    for (function, span) in error.stack_trace() {
+        match (span, file(function)) {
+            (Some(span), Some(file)) => eprintln!("    at {} ({}:{}:{})", function, file, span.line, span.column),
+            _ => eprintln!("    at {}", function),
+        }
+    }
+    Failure::Error
+}
+
+fn excerpt(contents: &str, line: usize, column: usize) -> String {
+    match contents.lines().nth(line - 1) {
+        Some(text) => format!("{:>4} | {}\n     | {}^", line, text, " ".repeat(column - 1)),
+        None => String::new(),
+    }
 }
@@ -0,0 +1,180 @@
+use crate::ast;
+use crate::parser;
+use crate::parser::ParseError;
+
+use std::collections::HashMap;
+use std::collections::LinkedList;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+// モジュールのソースを読み込む。テストではメモリ上のファイルを使えるように抽象化している
+pub trait ModuleLoader {
+    fn read(&self, path: &Path) -> Option<String>;
+}
+
+pub struct FileLoader;
+impl ModuleLoader for FileLoader {
+    fn read(&self, path: &Path) -> Option<String> {
+        fs::read_to_string(path).ok()
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryLoader {
+    files: HashMap<PathBuf, String>,
+}
+impl ModuleLoader for MemoryLoader {
+    fn read(&self, path: &Path) -> Option<String> {
+        self.files.get(path).cloned()
+    }
+}
+impl MemoryLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn add_file(&mut self, path: &str, contents: &str) {
+        self.files.insert(normalize(Path::new(path)), contents.to_string());
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ModuleError {
+    NotFound(String),
+    Cycle(Vec<PathBuf>),
+    NameConflict(String, PathBuf, PathBuf),
+    Parse(String, ParseError),
+}
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModuleError::NotFound(name) => write!(f, "module {} not found.", name),
+            ModuleError::Cycle(paths) => {
+                let names: Vec<String> = paths.iter().map(|x| x.display().to_string()).collect();
+                write!(f, "import cycle detected: {}", names.join(" -> "))
+            },
+            ModuleError::NameConflict(name, a, b) => {
+                write!(f, "module name {} is used by both {} and {}", name, a.display(), b.display())
+            },
+            ModuleError::Parse(file, e) => write!(f, "{}:{}", file, e),
+        }
+    }
+}
+
+// import をたどって読み込んだモジュールの定義を一つの Program にまとめる
+pub struct Linker<L: ModuleLoader> {
+    loader: L,
+    search_path: Vec<PathBuf>,
+    namespaces: HashMap<String, PathBuf>,
+    loading: Vec<PathBuf>,
+    definitions: LinkedList<ast::TopLevel>,
+}
+impl<L: ModuleLoader> Linker<L> {
+    pub fn new(loader: L) -> Self {
+        Self {
+            loader,
+            search_path: Vec::new(),
+            namespaces: HashMap::new(),
+            loading: Vec::new(),
+            definitions: LinkedList::new(),
+        }
+    }
+    pub fn add_search_path<P: AsRef<Path>>(&mut self, dir: P) {
+        self.search_path.push(dir.as_ref().to_path_buf());
+    }
+    pub fn link_file(&mut self, path: &Path) -> Result<ast::Program, ModuleError> {
+        let path = normalize(path);
+        let contents = self.loader.read(&path)
+            .ok_or_else(|| ModuleError::NotFound(path.display().to_string()))?;
+        self.link_source(&contents, Some(&path))
+    }
+    // path はソースのファイル名で、import の起点になる。None なら カレントディレクトリから探す
+    pub fn link_source(&mut self, contents: &str, path: Option<&Path>) -> Result<ast::Program, ModuleError> {
+        let base_dir = match path {
+            Some(x) => parent_dir(x),
+            None => PathBuf::new(),
+        };
+        let file_name = match path {
+            Some(x) => x.display().to_string(),
+            None => "<source>".to_string(),
+        };
+        if let Some(x) = path {
+            self.loading.push(normalize(x));
+        }
+        let mut program = parser::parse(contents).map_err(|e| ModuleError::Parse(file_name, e))?;
+        for import in program.imports.iter() {
+            self.load(import, &base_dir)?;
+        }
+        self.definitions.append(&mut program.definitions);
+        program.definitions = std::mem::take(&mut self.definitions);
+        Ok(program)
+    }
+    // 読み込んだモジュールのファイル。キーはモジュール名
+    pub fn modules(&self) -> &HashMap<String, PathBuf> {
+        &self.namespaces
+    }
+
+    fn load(&mut self, import: &ast::Import, base_dir: &Path) -> Result<(), ModuleError> {
+        let (spec, display_name) = match import {
+            ast::Import::Path(path) => (PathBuf::from(path), path.clone()),
+            ast::Import::Module(name) => (PathBuf::from(format!("{}.toys", name)), name.clone()),
+        };
+        let (path, contents) = self.find(&spec, base_dir)
+            .ok_or(ModuleError::NotFound(display_name))?;
+        if let Some(position) = self.loading.iter().position(|x| *x == path) {
+            let mut cycle = self.loading[position..].to_vec();
+            cycle.push(path);
+            return Err(ModuleError::Cycle(cycle));
+        }
+        let module_name = path.file_stem().unwrap().to_string_lossy().to_string();
+        match self.namespaces.get(&module_name) {
+            Some(loaded) if *loaded == path => return Ok(()),
+            Some(loaded) => return Err(ModuleError::NameConflict(module_name, loaded.clone(), path)),
+            None => {},
+        }
+
+        self.loading.push(path.clone());
+        let mut module = parser::parse_module(&contents, &module_name)
+            .map_err(|e| ModuleError::Parse(path.display().to_string(), e))?;
+        let module_dir = parent_dir(&path);
+        for x in module.imports.iter() {
+            self.load(x, &module_dir)?;
+        }
+        self.loading.pop();
+        self.namespaces.insert(module_name, path);
+        self.definitions.append(&mut module.definitions);
+        Ok(())
+    }
+
+    fn find(&self, spec: &Path, base_dir: &Path) -> Option<(PathBuf, String)> {
+        let candidates = std::iter::once(base_dir).chain(self.search_path.iter().map(|x| x.as_path()));
+        for dir in candidates {
+            let path = normalize(&dir.join(spec));
+            if let Some(contents) = self.loader.read(&path) {
+                return Some((path, contents));
+            }
+        }
+        None
+    }
+}
+
+fn parent_dir(path: &Path) -> PathBuf {
+    path.parent().map(|x| x.to_path_buf()).unwrap_or_default()
+}
+
+// `a/./b/../c.toys` を `a/c.toys` にする。同じモジュールを一度だけ読み込むためのキーとして使う
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                if normalized.file_name().is_none() || !normalized.pop() {
+                    normalized.push("..");
+                }
+            },
+            x => normalized.push(x.as_os_str()),
+        }
+    }
+    normalized
+}
@@ -0,0 +1,199 @@
+use crate::ast;
+use crate::ast::{Definition, Node, Operator};
+
+use std::collections::LinkedList;
+
+// 定数式の畳み込みと、条件が定数の分岐・ループの除去を行う
+pub fn optimize(program: &ast::Program) -> ast::Program {
+    ast::Program {
+        imports: program.imports.clone(),
+        definitions: program.definitions.iter().map(|x| optimize_toplevel(x)).collect(),
+    }
+}
+
+fn optimize_toplevel(toplevel: &ast::TopLevel) -> ast::TopLevel {
+    match toplevel.definition() {
+        Definition::Function(x) => {
+            let mut definition = ast::Ast::define_function(
+                x.name.clone(),
+                x.args.clone(),
+                optimize_expression(&x.body)
+            );
+            definition.doc = x.doc.clone();
+            definition.parameter_types = x.parameter_types.clone();
+            definition.return_type = x.return_type.clone();
+            definition.span = x.span;
+            definition
+        },
+        Definition::Global(x) => {
+            let mut definition = ast::GlobalVariableDefinition::new(x.name.clone(), optimize_expression(&x.body));
+            definition.doc = x.doc.clone();
+            definition.type_annotation = x.type_annotation.clone();
+            definition.span = x.span;
+            Box::new(definition)
+        },
+        Definition::Test(x) => {
+            let mut definition = ast::Ast::define_test(x.name.clone(), optimize_expression(&x.body));
+            definition.span = x.span;
+            definition
+        },
+        Definition::Record(x) => {
+            let mut definition = ast::Ast::define_record(x.name().to_string(), x.fields().clone());
+            definition.constructor.doc = x.constructor.doc.clone();
+            definition.constructor.parameter_types = x.constructor.parameter_types.clone();
+            definition.constructor.span = x.constructor.span;
+            definition
+        },
+        Definition::Enum(x) => {
+            let variants = x.variants.iter().map(|y| (y.name.clone(), y.args.clone())).collect();
+            let mut definition = ast::Ast::define_enum(x.name.clone(), variants);
+            for (variant, original) in definition.variants.iter_mut().zip(x.variants.iter()) {
+                variant.parameter_types = original.parameter_types.clone();
+                variant.span = original.span;
+            }
+            definition.doc = x.doc.clone();
+            definition.span = x.span;
+            definition
+        },
+    }
+}
+
+// 畳み込んだ結果のノードには元の式の位置を引き継ぐ
+pub fn optimize_expression(expression: &ast::Expression) -> ast::Expression {
+    let optimized = optimize_node(expression);
+    if let (None, Some(span)) = (optimized.span(), expression.span()) {
+        optimized.set_span(span);
+    }
+    optimized
+}
+
+fn optimize_node(expression: &ast::Expression) -> ast::Expression {
+    match expression.node() {
+        Node::Binary(x) => {
+            let lhs = optimize_expression(&x.lhs);
+            let rhs = optimize_expression(&x.rhs);
+            match (constant(&lhs), constant(&rhs)) {
+                (Some(l), Some(r)) => match fold(x.operator, l, r) {
+                    Some(value) => ast::Ast::integer(value),
+                    None => ast::Ast::binary(x.operator, lhs, rhs),
+                },
+                _ => ast::Ast::binary(x.operator, lhs, rhs),
+            }
+        },
+        Node::Integer(x) => ast::Ast::integer(x.value),
+        Node::Assignment(x) => ast::Ast::assignment(x.name.clone(), optimize_expression(&x.expression)),
+        Node::Identifier(x) => ast::Ast::symbol(x.name.clone()),
+        Node::Block(x) => optimize_block(&x.elements),
+        Node::While(x) => {
+            let condition = optimize_expression(&x.condition);
+            match constant(&condition) {
+                // while は常に 1 を返す
+                Some(0) => ast::Ast::integer(1),
+                _ => ast::Ast::while_expr(condition, optimize_expression(&x.body)),
+            }
+        },
+        Node::If(x) => {
+            let condition = optimize_expression(&x.condition);
+            match constant(&condition) {
+                Some(0) => match &x.else_clause {
+                    Some(e) => optimize_expression(e),
+                    None => ast::Ast::integer(1),
+                },
+                Some(_) => optimize_expression(&x.then_clause),
+                None => ast::Ast::if_expr(
+                    condition,
+                    optimize_expression(&x.then_clause),
+                    x.else_clause.as_ref().map(optimize_expression)
+                ),
+            }
+        },
+        Node::Println(x) => ast::Ast::println(optimize_expression(&x.body)),
+        Node::Call(x) => ast::Ast::call(x.name.clone(), x.args.iter().map(optimize_expression).collect()),
+        Node::LabelledCall(x) => ast::Ast::labelled_call(
+            x.name.clone(),
+            x.args.iter().map(|y| *ast::Ast::labelled_parameter(y.name.clone(), optimize_expression(&y.parameter))).collect()
+        ),
+        Node::EnvironmentVariable(x) => ast::Ast::environment_variable(x.name.clone(), x.default.as_ref().map(optimize_expression)),
+        Node::Assert(x) => ast::Ast::assert(optimize_expression(&x.left), x.right.as_ref().map(optimize_expression)),
+        Node::Match(x) => ast::Ast::match_expr(
+            optimize_expression(&x.value),
+            x.arms.iter().map(|y| ast::MatchArm {
+                pattern: copy_pattern(&y.pattern),
+                guard: y.guard.as_ref().map(optimize_expression),
+                body: optimize_expression(&y.body),
+            }).collect()
+        ),
+        Node::Field(x) => ast::Ast::field(optimize_expression(&x.value), x.field.clone()),
+        Node::FieldAssignment(x) => ast::Ast::field_assignment(x.name.clone(), x.path.clone(), optimize_expression(&x.expression)),
+        Node::Construct(x) => ast::Ast::construct(x.name.clone(), x.enumeration.clone()),
+        Node::Throw(x) => ast::Ast::throw(optimize_expression(&x.value)),
+        Node::Try(x) => ast::Ast::try_expr(
+            optimize_expression(&x.body),
+            x.catch_clause.as_ref().map(|y| (y.name.clone(), optimize_expression(&y.body))),
+            x.finally_clause.as_ref().map(optimize_expression)
+        ),
+    }
+}
+
+fn copy_pattern(pattern: &ast::Pattern) -> ast::Pattern {
+    match pattern {
+        ast::Pattern::Integers(x) => ast::Pattern::Integers(x.clone()),
+        ast::Pattern::Binding { name, .. } => ast::Pattern::binding(name.clone()),
+        ast::Pattern::Wildcard => ast::Pattern::Wildcard,
+        ast::Pattern::Constructor { name, arguments } => ast::Pattern::Constructor {
+            name: name.clone(),
+            arguments: arguments.iter().map(copy_pattern).collect(),
+        },
+    }
+}
+
+// ブロックはスコープを作らないので、入れ子のブロックは外側に展開できる。
+// 最後の要素以外の定数は値が捨てられるだけなので取り除く
+fn optimize_block(elements: &LinkedList<ast::Expression>) -> ast::Expression {
+    let mut flattened: LinkedList<ast::Expression> = LinkedList::new();
+    flatten_block(elements, &mut flattened);
+    let last = flattened.pop_back();
+    let mut block: LinkedList<ast::Expression> = flattened.into_iter().filter(|x| constant(x).is_none()).collect();
+    match last {
+        Some(x) if block.is_empty() => x,
+        Some(x) => {
+            block.push_back(x);
+            ast::Ast::block(block)
+        },
+        None => ast::Ast::block(block),
+    }
+}
+
+fn flatten_block(elements: &LinkedList<ast::Expression>, flattened: &mut LinkedList<ast::Expression>) {
+    for e in elements.iter() {
+        match e.node() {
+            // 空のブロックの値は 0
+            Node::Block(x) if x.elements.is_empty() => flattened.push_back(ast::Ast::integer(0)),
+            Node::Block(x) => flatten_block(&x.elements, flattened),
+            _ => flattened.push_back(optimize_expression(e)),
+        }
+    }
+}
+
+fn constant(expression: &ast::Expression) -> Option<i32> {
+    match expression.node() {
+        Node::Integer(x) => Some(x.value),
+        _ => None,
+    }
+}
+
+// 実行時と結果が変わらないよう、ゼロ除算やオーバーフローになる式は畳み込まない
+fn fold(operator: Operator, lhs: i32, rhs: i32) -> Option<i32> {
+    match operator {
+        Operator::ADD => lhs.checked_add(rhs),
+        Operator::SUBTRACT => lhs.checked_sub(rhs),
+        Operator::MULTIPLY => lhs.checked_mul(rhs),
+        Operator::DIVIDE => lhs.checked_div(rhs),
+        Operator::LessThan => Some((lhs < rhs) as i32),
+        Operator::LessOrEqual => Some((lhs <= rhs) as i32),
+        Operator::GreaterThan => Some((lhs > rhs) as i32),
+        Operator::GreaterOrEqual => Some((lhs >= rhs) as i32),
+        Operator::EqualEqual => Some((lhs == rhs) as i32),
+        Operator::NotEqual => Some((lhs != rhs) as i32),
+    }
+}
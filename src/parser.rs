@@ -5,86 +5,458 @@ use pest::Parser;
 #[grammar = "grammer.pest"]
 struct ToysParser;
 
+use std::collections::HashSet;
 use std::collections::LinkedList;
+use std::fmt;
 
-pub fn parse(contents: &String) -> ast::Program {
-    let mut pairs = ToysParser::parse(Rule::program, contents).unwrap_or_else(|e| panic!("{}", e));
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    pub snippet: String, // エラー箇所を示すソースの抜粋
+}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+impl From<pest::error::Error<Rule>> for ParseError {
+    fn from(e: pest::error::Error<Rule>) -> Self {
+        let (line, column) = match e.line_col {
+            pest::error::LineColLocation::Pos(x) => x,
+            pest::error::LineColLocation::Span(x, _) => x,
+        };
+        Self {
+            message: match &e.variant {
+                pest::error::ErrorVariant::ParsingError { positives, negatives } => {
+                    let names = |rules: &Vec<Rule>| rules.iter().map(|x| format!("{:?}", x)).collect::<Vec<String>>().join(", ");
+                    if positives.is_empty() {
+                        format!("unexpected {}", names(negatives))
+                    } else {
+                        format!("expected {}", names(positives))
+                    }
+                },
+                pest::error::ErrorVariant::CustomError { message } => message.clone(),
+            },
+            line,
+            column,
+            snippet: e.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: String,
+    pub text: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+// モジュール内で定義された名前を `モジュール名.名前` に修飾するための情報
+#[derive(Clone, Default)]
+struct Namespace {
+    prefix: Option<String>,
+    functions: HashSet<String>,
+    globals: HashSet<String>,
+    locals: HashSet<String>,
+    modules: HashSet<String>, // import したモジュールの名前。`名前.x` はフィールドではなくモジュールの変数になる
+    variants: HashSet<String>, // このファイルの引数のないバリアント。括弧を省いて書ける
+}
+impl Namespace {
+    fn qualify_definition(&self, name: &str) -> String {
+        match &self.prefix {
+            Some(prefix) => format!("{}.{}", prefix, name),
+            None => name.to_string(),
+        }
+    }
+    fn qualify_function(&self, name: &str) -> String {
+        if self.functions.contains(name) {
+            self.qualify_definition(name)
+        } else {
+            name.to_string()
+        }
+    }
+    fn qualify_variable(&self, name: &str) -> String {
+        if self.globals.contains(name) && !self.locals.contains(name) {
+            self.qualify_definition(name)
+        } else {
+            name.to_string()
+        }
+    }
+    fn with_locals(&self, locals: HashSet<String>) -> Namespace {
+        Namespace {
+            locals,
+            ..self.clone()
+        }
+    }
+}
+
+pub fn parse(contents: &str) -> Result<ast::Program, ParseError> {
+    parse_program(contents, Namespace::default())
+}
+
+pub fn parse_module(contents: &str, module_name: &str) -> Result<ast::Program, ParseError> {
+    parse_program(contents, Namespace {
+        prefix: Some(module_name.to_string()),
+        ..Namespace::default()
+    })
+}
+
+pub(crate) fn parse_tree(rule: Rule, contents: &str) -> Result<pest::iterators::Pairs<'_, Rule>, ParseError> {
+    Ok(ToysParser::parse(rule, contents)?)
+}
+
+fn parse_program(contents: &str, mut ns: Namespace) -> Result<ast::Program, ParseError> {
+    let mut pairs = parse_tree(Rule::program, contents)?;
+    let mut imports: LinkedList<ast::Import> = LinkedList::new();
     let mut toplevels: LinkedList<ast::TopLevel> = LinkedList::new();
     let pair = pairs.next().unwrap();
     match pair.as_rule() {
         Rule::program => {
-            let inner_pairs = pair.into_inner();
-            inner_pairs.for_each(|x| toplevels.push_back(construct_toplevel_ast(x)));
+            if ns.prefix.is_some() {
+                collect_toplevel_names(&pair, &mut ns);
+            }
+            ns.variants = nullary_variants(&pair);
+            ns.modules = pair.clone().into_inner()
+                .filter(|x| x.as_rule() == Rule::importDeclaration)
+                .map(|x| construct_import(x).module_name())
+                .collect();
+            for x in pair.into_inner() {
+                match x.as_rule() {
+                    Rule::importDeclaration => imports.push_back(construct_import(x)),
+                    Rule::shebang | Rule::EOI => {},
+                    // 取り込んだモジュールのテストはそのモジュールを toys test したときだけ実行する
+                    Rule::topLevelDefinition if ns.prefix.is_some() && is_test(&x) => {},
+                    _ => toplevels.push_back(construct_toplevel_ast(x, &ns)),
+                }
+            }
         },
         _ => unreachable!(),
     }
-    ast::Program { definitions: toplevels }
+    Ok(ast::Program { imports, definitions: toplevels })
+}
+
+pub fn parse_lines(contents: &str) -> Result<LinkedList<ast::Expression>, ParseError> {
+    parse_lines_in(contents, HashSet::new())
 }
 
-pub fn parse_lines(contents: &String) -> LinkedList<ast::Expression> {
-    let mut pairs = ToysParser::parse(Rule::lines, contents).unwrap_or_else(|e| panic!("{}", e));
+// modules は `名前.x` をモジュールの変数として読むモジュールの名前（デバッガで式を評価するときなど）
+pub fn parse_lines_in(contents: &str, modules: HashSet<String>) -> Result<LinkedList<ast::Expression>, ParseError> {
+    let mut pairs = parse_tree(Rule::lines, contents)?;
     let mut lines = LinkedList::new();
     let pair = pairs.next().unwrap();
     match pair.as_rule() {
         Rule::lines => {
-            for p in pair.into_inner() {
-                lines.push_back(construct_expression_ast(p));
+            let ns = Namespace {
+                modules,
+                ..Namespace::default()
+            };
+            for p in pair.into_inner().filter(|x| x.as_rule() != Rule::EOI) {
+                lines.push_back(construct_expression_ast(p, &ns));
             }
         },
         _ => unreachable!(),
     }
-    lines
+    Ok(lines)
+}
+
+pub fn parse_comments(contents: &str) -> Result<LinkedList<ast::Comment>, ParseError> {
+    let mut pairs = parse_tree(Rule::trivia, contents)?;
+    let mut comments = LinkedList::new();
+    for p in pairs.next().unwrap().into_inner() {
+        let kind = match p.as_rule() {
+            Rule::docComment => ast::CommentKind::Doc,
+            Rule::lineCommentTrivia => ast::CommentKind::Line,
+            Rule::blockCommentTrivia => ast::CommentKind::Block,
+            _ => continue,
+        };
+        let (line, column) = p.as_span().start_pos().line_col();
+        comments.push_back(ast::Comment {
+            kind,
+            text: p.as_str().to_string(),
+            offset: p.as_span().start(),
+            line,
+            column,
+        });
+    }
+    Ok(comments)
+}
+
+pub fn tokenize(contents: &str) -> Result<LinkedList<Token>, ParseError> {
+    let mut pairs = parse_tree(Rule::tokens, contents)?;
+    let mut tokens = LinkedList::new();
+    for p in pairs.next().unwrap().into_inner().filter(|x| x.as_rule() != Rule::EOI) {
+        let (line, column) = p.as_span().start_pos().line_col();
+        tokens.push_back(Token {
+            kind: format!("{:?}", p.as_rule()),
+            text: p.as_str().to_string(),
+            line,
+            column,
+        });
+    }
+    Ok(tokens)
+}
+
+fn construct_doc(docs: &LinkedList<pest::iterators::Pair<Rule>>) -> Option<String> {
+    if docs.is_empty() {
+        return None;
+    }
+    let lines: Vec<&str> = docs.iter().map(|x| {
+        let text = &x.as_str()[3..];
+        text.strip_prefix(' ').unwrap_or(text).trim_end()
+    }).collect();
+    Some(lines.join("\n"))
+}
+
+fn construct_import(pair: pest::iterators::Pair<Rule>) -> ast::Import {
+    let target = pair.into_inner().next().unwrap();
+    match target.as_rule() {
+        Rule::string => ast::Import::Path(unquote(target.as_str())),
+        Rule::identifier => ast::Import::Module(target.as_str().to_string()),
+        _ => unreachable!(),
+    }
+}
+
+fn unquote(quoted: &str) -> String {
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+fn is_test(toplevel: &pest::iterators::Pair<Rule>) -> bool {
+    toplevel.clone().into_inner().next().is_some_and(|x| x.as_rule() == Rule::testDefinition)
+}
+
+fn definition_name(pair: &pest::iterators::Pair<Rule>) -> String {
+    pair.clone().into_inner()
+        .find(|x| x.as_rule() == Rule::identifier)
+        .unwrap().as_str().to_string()
+}
+
+fn record_name(pair: &pest::iterators::Pair<Rule>) -> String {
+    pair.clone().into_inner()
+        .find(|x| x.as_rule() == Rule::typeName)
+        .unwrap().as_str().to_string()
+}
+
+fn variant_names(pair: &pest::iterators::Pair<Rule>) -> Vec<String> {
+    pair.clone().into_inner()
+        .filter(|x| x.as_rule() == Rule::variant)
+        .map(|x| x.into_inner().next().unwrap().as_str().to_string())
+        .collect()
+}
+
+fn nullary_variants(program: &pest::iterators::Pair<Rule>) -> HashSet<String> {
+    program.clone().into_inner()
+        .filter(|x| x.as_rule() == Rule::topLevelDefinition)
+        .map(|x| x.into_inner().next().unwrap())
+        .filter(|x| x.as_rule() == Rule::enumDefinition)
+        .flat_map(|x| x.into_inner().filter(|y| y.as_rule() == Rule::variant).collect::<Vec<_>>())
+        .filter(|x| x.clone().into_inner().count() == 1)
+        .map(|x| x.into_inner().next().unwrap().as_str().to_string())
+        .collect()
+}
+
+fn collect_toplevel_names(program: &pest::iterators::Pair<Rule>, ns: &mut Namespace) {
+    for toplevel in program.clone().into_inner() {
+        if toplevel.as_rule() != Rule::topLevelDefinition {
+            continue;
+        }
+        let definition = toplevel.into_inner().next().unwrap();
+        match definition.as_rule() {
+            Rule::functionDefinition => { ns.functions.insert(definition_name(&definition)); },
+            Rule::globalVariableDefinition => { ns.globals.insert(definition_name(&definition)); },
+            // レコードのコンストラクタは関数として呼ぶ
+            Rule::recordDefinition => { ns.functions.insert(record_name(&definition)); },
+            Rule::enumDefinition => ns.functions.extend(variant_names(&definition)),
+            Rule::testDefinition => {},
+            _ => unreachable!(),
+        }
+    }
 }
 
-fn construct_toplevel_ast(pair: pest::iterators::Pair<Rule>) -> ast::TopLevel {
+// 関数の仮引数と、本体で代入される変数（ローカル変数）を集める。
+// global で定義された変数への代入はグローバル変数を書き換えるのでローカルにしない
+fn collect_local_names(function: &pest::iterators::Pair<Rule>, globals: &HashSet<String>) -> HashSet<String> {
+    let mut locals = HashSet::new();
+    for x in function.clone().into_inner().flatten() {
+        match x.as_rule() {
+            Rule::assignment if globals.contains(&definition_name(&x)) => {},
+            Rule::assignment | Rule::forInExpression | Rule::bindingPattern | Rule::catchClause => {
+                locals.insert(definition_name(&x));
+            },
+            _ => {},
+        }
+    }
+    for x in function.clone().into_inner().filter(|x| x.as_rule() == Rule::parameter) {
+        locals.insert(x.into_inner().next().unwrap().as_str().to_string());
+    }
+    locals
+}
+
+fn construct_toplevel_ast(pair: pest::iterators::Pair<Rule>, ns: &Namespace) -> ast::TopLevel {
     match pair.as_rule() {
         Rule::topLevelDefinition => {
-            construct_toplevel_ast(pair.into_inner().next().unwrap())
+            construct_toplevel_ast(pair.into_inner().next().unwrap(), ns)
         },
         Rule::functionDefinition => {
-            let mut inner_pairs = pair.into_inner();
-            let name = inner_pairs.next().unwrap().as_str().to_string();
-            let (args, mut body): (LinkedList<pest::iterators::Pair<Rule>>, LinkedList<pest::iterators::Pair<Rule>>) = inner_pairs.partition(|x| x.as_rule() == Rule::identifier);
-            ast::Ast::define_function(
+            let ns = &ns.with_locals(collect_local_names(&pair, &ns.globals));
+            let span = span_of(&pair);
+            let (docs, inner_pairs): (LinkedList<pest::iterators::Pair<Rule>>, LinkedList<pest::iterators::Pair<Rule>>) = pair.into_inner().partition(|x| x.as_rule() == Rule::docComment);
+            let mut inner_pairs = inner_pairs.into_iter();
+            let name = ns.qualify_definition(inner_pairs.next().unwrap().as_str());
+            let (args, mut rest): (LinkedList<pest::iterators::Pair<Rule>>, LinkedList<pest::iterators::Pair<Rule>>) = inner_pairs.partition(|x| x.as_rule() == Rule::parameter);
+            let return_type = match rest.front().map(|x| x.as_rule()) {
+                Some(Rule::typeAnnotation) => Some(construct_type(rest.pop_front().unwrap())),
+                _ => None,
+            };
+            let (names, parameter_types): (LinkedList<String>, Vec<Option<String>>) = args.into_iter().map(|x| {
+                let mut inner = x.into_inner();
+                (inner.next().unwrap().as_str().to_string(), inner.next().map(construct_type))
+            }).unzip();
+            let mut definition = ast::Ast::define_function(
                 name,
-                args.into_iter().map(|x| x.as_str().to_string()).collect(),
-                construct_expression_ast(body.pop_front().unwrap())
-            )
+                names,
+                construct_expression_ast(rest.pop_front().unwrap(), ns)
+            );
+            definition.doc = construct_doc(&docs);
+            definition.parameter_types = parameter_types;
+            definition.return_type = return_type;
+            definition.span = Some(span);
+            definition
         },
         Rule::globalVariableDefinition => {
+            let span = span_of(&pair);
+            let (docs, inner_pairs): (LinkedList<pest::iterators::Pair<Rule>>, LinkedList<pest::iterators::Pair<Rule>>) = pair.into_inner().partition(|x| x.as_rule() == Rule::docComment);
+            let mut inner_pairs = inner_pairs.into_iter();
+            let name = ns.qualify_definition(inner_pairs.next().unwrap().as_str());
+            let mut next = inner_pairs.next().unwrap();
+            let type_annotation = if next.as_rule() == Rule::typeAnnotation {
+                let annotation = construct_type(next);
+                next = inner_pairs.next().unwrap();
+                Some(annotation)
+            } else {
+                None
+            };
+            let expr = construct_expression_ast(next, ns);
+            let mut definition = ast::GlobalVariableDefinition::new(name, expr);
+            definition.doc = construct_doc(&docs);
+            definition.type_annotation = type_annotation;
+            definition.span = Some(span);
+            Box::new(definition)
+        },
+        Rule::testDefinition => {
+            let span = span_of(&pair);
             let mut inner_pairs = pair.into_inner();
-            let name = inner_pairs.next().unwrap().as_str().to_string();
-            let expr = construct_expression_ast(inner_pairs.next().unwrap());
-            Box::new(ast::GlobalVariableDefinition::new(name, expr))
+            let name = unquote(inner_pairs.next().unwrap().as_str());
+            let mut definition = ast::Ast::define_test(name, construct_expression_ast(inner_pairs.next().unwrap(), ns));
+            definition.span = Some(span);
+            definition
+        },
+        Rule::recordDefinition => {
+            let span = span_of(&pair);
+            let name = ns.qualify_definition(&record_name(&pair));
+            let (docs, inner_pairs): (LinkedList<pest::iterators::Pair<Rule>>, LinkedList<pest::iterators::Pair<Rule>>) = pair.into_inner().partition(|x| x.as_rule() == Rule::docComment);
+            let (fields, field_types): (LinkedList<String>, Vec<Option<String>>) = inner_pairs.into_iter()
+                .filter(|x| x.as_rule() == Rule::parameter)
+                .map(|x| {
+                    let mut inner = x.into_inner();
+                    (inner.next().unwrap().as_str().to_string(), inner.next().map(construct_type))
+                })
+                .unzip();
+            let mut definition = ast::Ast::define_record(name, fields);
+            definition.constructor.doc = construct_doc(&docs);
+            definition.constructor.parameter_types = field_types;
+            definition.constructor.span = Some(span);
+            definition
+        },
+        Rule::enumDefinition => {
+            let span = span_of(&pair);
+            let name = ns.qualify_definition(&record_name(&pair));
+            let (docs, inner_pairs): (LinkedList<pest::iterators::Pair<Rule>>, LinkedList<pest::iterators::Pair<Rule>>) = pair.into_inner().partition(|x| x.as_rule() == Rule::docComment);
+            let mut field_types = Vec::new();
+            let variants = inner_pairs.into_iter()
+                .filter(|x| x.as_rule() == Rule::variant)
+                .map(|x| {
+                    let mut inner = x.into_inner();
+                    let variant = ns.qualify_definition(inner.next().unwrap().as_str());
+                    let (fields, types): (LinkedList<String>, Vec<Option<String>>) = inner.map(|y| {
+                        let mut inner = y.into_inner();
+                        (inner.next().unwrap().as_str().to_string(), inner.next().map(construct_type))
+                    }).unzip();
+                    field_types.push(types);
+                    (variant, fields)
+                })
+                .collect();
+            let mut definition = ast::Ast::define_enum(name, variants);
+            for (variant, types) in definition.variants.iter_mut().zip(field_types) {
+                variant.parameter_types = types;
+                variant.span = Some(span);
+            }
+            definition.doc = construct_doc(&docs);
+            definition.span = Some(span);
+            definition
         },
         _ => unreachable!(),
     }
 }
 
-fn construct_expression_ast(pair: pest::iterators::Pair<Rule>) -> ast::Expression {
+fn construct_pattern(pair: pest::iterators::Pair<Rule>, ns: &Namespace) -> ast::Pattern {
+    let pattern = pair.into_inner();
+    match pattern.peek().unwrap().as_rule() {
+        Rule::wildcardPattern => ast::Pattern::Wildcard,
+        Rule::bindingPattern => ast::Pattern::binding(pattern.as_str().to_string()),
+        Rule::constructorPattern => {
+            let mut inner = pattern.peek().unwrap().into_inner();
+            let name = ns.qualify_function(inner.next().unwrap().as_str());
+            ast::Pattern::Constructor { name, arguments: inner.map(|x| construct_pattern(x, ns)).collect() }
+        },
+        _ => ast::Pattern::Integers(pattern.map(|x| x.as_str().parse().unwrap()).collect()),
+    }
+}
+
+// `: Int` の型の名前
+fn construct_type(pair: pest::iterators::Pair<Rule>) -> String {
+    pair.into_inner().next().unwrap().as_str().to_string()
+}
+
+fn span_of(pair: &pest::iterators::Pair<Rule>) -> ast::Span {
+    let span = pair.as_span();
+    let (line, column) = span.start_pos().line_col();
+    ast::Span { start: span.start(), end: span.end(), line, column }
+}
+
+// 構文木の各ノードにソース上の位置を記録する。内側の規則で位置が決まったノードはそのままにする
+fn construct_expression_ast(pair: pest::iterators::Pair<Rule>, ns: &Namespace) -> ast::Expression {
+    let span = span_of(&pair);
+    let expression = construct_expression_node(pair, ns);
+    if expression.span().is_none() {
+        expression.set_span(span);
+    }
+    expression
+}
+
+fn construct_expression_node(pair: pest::iterators::Pair<Rule>, ns: &Namespace) -> ast::Expression {
     match pair.as_rule() {
         Rule::line => {
-            construct_expression_ast(pair.into_inner().next().unwrap())
+            construct_expression_ast(pair.into_inner().next().unwrap(), ns)
         },
         Rule::ifExpression => {
             let mut inner_pairs = pair.into_inner();
-            let condition = construct_expression_ast(inner_pairs.next().unwrap());
-            let then_clause = construct_expression_ast(inner_pairs.next().unwrap());
-            let else_clause =  match inner_pairs.next() {
-                Some(x) => Some(construct_expression_ast(x)),
-                None => None,
-            };
+            let condition = construct_expression_ast(inner_pairs.next().unwrap(), ns);
+            let then_clause = construct_expression_ast(inner_pairs.next().unwrap(), ns);
+            let else_clause =  inner_pairs.next().map(|x| construct_expression_ast(x, ns));
             ast::Ast::if_expr(condition, then_clause, else_clause)
         },
         Rule::whileExpression => {
             let mut inner_pairs = pair.into_inner();
-            let conditon = construct_expression_ast(inner_pairs.next().unwrap());
-            let body = construct_expression_ast(inner_pairs.next().unwrap());
+            let conditon = construct_expression_ast(inner_pairs.next().unwrap(), ns);
+            let body = construct_expression_ast(inner_pairs.next().unwrap(), ns);
             ast::Ast::while_expr(conditon, body)
         },
         Rule::blockExpression => {
             let inner_pairs = pair.into_inner();
-            let elements = inner_pairs.map(|x| construct_expression_ast(x)).collect();
+            let elements = inner_pairs.map(|x| construct_expression_ast(x, ns)).collect();
             ast::Ast::block(elements)
         },
         Rule::forInExpression => {
@@ -96,7 +468,7 @@ fn construct_expression_ast(pair: pest::iterators::Pair<Rule>) -> ast::Expressio
             let mut block: LinkedList<ast::Expression> = LinkedList::new();
             let mut inner_block: LinkedList<ast::Expression> = LinkedList::new();
             inner_block.push_back(
-                construct_expression_ast(body)
+                construct_expression_ast(body, ns)
             );
             inner_block.push_back(
                 ast::Ast::assignment(
@@ -110,14 +482,14 @@ fn construct_expression_ast(pair: pest::iterators::Pair<Rule>) -> ast::Expressio
             block.push_back(
                 ast::Ast::assignment(
                     loop_variable.as_str().to_string(),
-                    construct_expression_ast(from)
+                    construct_expression_ast(from, ns)
                 )
             );
             block.push_back(
                 ast::Ast::while_expr(
                     ast::Ast::less_or_equal(
                         ast::Ast::symbol(loop_variable.as_str().to_string()),
-                        construct_expression_ast(to)
+                        construct_expression_ast(to, ns)
                     ),
                     ast::Ast::block(inner_block)
                 )
@@ -127,21 +499,49 @@ fn construct_expression_ast(pair: pest::iterators::Pair<Rule>) -> ast::Expressio
         Rule::assignment => {
             let mut inner_pairs = pair.into_inner();
             let name = inner_pairs.next().unwrap().as_str().to_string();
-            let expr = construct_expression_ast(inner_pairs.next().unwrap());
+            let expr = construct_expression_ast(inner_pairs.next().unwrap(), ns);
             ast::Ast::assignment(name, expr)
         },
+        Rule::fieldAssignment => {
+            let mut inner_pairs: Vec<pest::iterators::Pair<Rule>> = pair.into_inner().collect();
+            let expr = construct_expression_ast(inner_pairs.pop().unwrap(), ns);
+            let name = ns.qualify_variable(inner_pairs[0].as_str());
+            let path = inner_pairs[1..].iter().map(|x| x.as_str().to_string()).collect();
+            ast::Ast::field_assignment(name, path, expr)
+        },
         Rule::expressionLine => {
-            construct_expression_ast(pair.into_inner().next().unwrap())
+            construct_expression_ast(pair.into_inner().next().unwrap(), ns)
+        },
+        Rule::throwStatement => {
+            let value = pair.into_inner().find(|x| x.as_rule() == Rule::expression).unwrap();
+            ast::Ast::throw(construct_expression_ast(value, ns))
+        },
+        Rule::tryExpression => {
+            let mut inner_pairs = pair.into_inner();
+            let body = construct_expression_ast(inner_pairs.next().unwrap(), ns);
+            let mut catch_clause = None;
+            let mut finally_clause = None;
+            for clause in inner_pairs {
+                match clause.as_rule() {
+                    Rule::catchClause => {
+                        let mut inner = clause.into_inner();
+                        let name = inner.next().unwrap().as_str().to_string();
+                        catch_clause = Some((name, construct_expression_ast(inner.next().unwrap(), ns)));
+                    },
+                    _ => finally_clause = Some(construct_expression_ast(clause.into_inner().next().unwrap(), ns)),
+                }
+            }
+            ast::Ast::try_expr(body, catch_clause, finally_clause)
         },
         Rule::expression => {
-            construct_expression_ast(pair.into_inner().next().unwrap())
+            construct_expression_ast(pair.into_inner().next().unwrap(), ns)
         },
         Rule::comparative => {
             let mut inner_pairs = pair.into_inner();
-            let lhs = construct_expression_ast(inner_pairs.next().unwrap());
+            let lhs = construct_expression_ast(inner_pairs.next().unwrap(), ns);
             match inner_pairs.next() {
                 Some(operator) => {
-                    let rhs = construct_expression_ast(inner_pairs.next().unwrap());
+                    let rhs = construct_expression_ast(inner_pairs.next().unwrap(), ns);
                     match operator.as_str() {
                         ">=" => ast::Ast::greater_or_equal(lhs, rhs),
                         "<=" => ast::Ast::less_or_equal(lhs, rhs),
@@ -157,75 +557,112 @@ fn construct_expression_ast(pair: pest::iterators::Pair<Rule>) -> ast::Expressio
         },
         Rule::additive => {
             let mut inner_pairs = pair.into_inner();
-            let mut lhs = construct_expression_ast(inner_pairs.next().unwrap());
-            loop {
-                match inner_pairs.next() {
-                    Some(operator) => {
-                        let rhs = construct_expression_ast(inner_pairs.next().unwrap());
-                        match operator.as_str() {
-                            "+" => lhs = ast::Ast::add(lhs, rhs),
-                            "-" => lhs = ast::Ast::subtract(lhs, rhs),
-                            _ => unreachable!(),
-                        }
-                    },
-                    None => break,
+            let mut lhs = construct_expression_ast(inner_pairs.next().unwrap(), ns);
+            while let Some(operator) = inner_pairs.next() {
+                let rhs = construct_expression_ast(inner_pairs.next().unwrap(), ns);
+                match operator.as_str() {
+                    "+" => lhs = ast::Ast::add(lhs, rhs),
+                    "-" => lhs = ast::Ast::subtract(lhs, rhs),
+                    _ => unreachable!(),
                 }
             }
             lhs
         },
         Rule::multitive => {
             let mut inner_pairs = pair.into_inner();
-            let mut lhs: ast::Expression = construct_expression_ast(inner_pairs.next().unwrap());
-            loop{
-                match inner_pairs.next() {
-                    Some(operator) => {
-                        let rhs = construct_expression_ast(inner_pairs.next().unwrap());
-                        match operator.as_str() {
-                            "*" => lhs = ast::Ast::multiply(lhs, rhs),
-                            "/" => lhs = ast::Ast::divide(lhs, rhs),
-                            _ => unreachable!(),
-                        }
-                    },
-                    None => break,
+            let mut lhs: ast::Expression = construct_expression_ast(inner_pairs.next().unwrap(), ns);
+            while let Some(operator) = inner_pairs.next() {
+                let rhs = construct_expression_ast(inner_pairs.next().unwrap(), ns);
+                match operator.as_str() {
+                    "*" => lhs = ast::Ast::multiply(lhs, rhs),
+                    "/" => lhs = ast::Ast::divide(lhs, rhs),
+                    _ => unreachable!(),
                 }
             }
             lhs
         },
         Rule::primary => {
-            construct_expression_ast(pair.into_inner().next().unwrap())
+            construct_expression_ast(pair.into_inner().next().unwrap(), ns)
+        },
+        // p.a.b は p.a のフィールド b。内側の参照の位置は p から各フィールドの終わりまで
+        Rule::postfix => {
+            let start = span_of(&pair);
+            let mut inner_pairs = pair.into_inner();
+            let mut value = construct_expression_ast(inner_pairs.next().unwrap(), ns);
+            for field in inner_pairs {
+                let end = field.as_span().end();
+                value = ast::Ast::field(value, field.as_str().to_string());
+                value.set_span(ast::Span { end, ..start });
+            }
+            value
         },
         Rule::integer => {
             ast::Ast::integer(pair.as_str().parse().unwrap())
         },
-        Rule::functionCall => {
+        Rule::environmentVariable => {
             let mut inner_pairs = pair.into_inner();
-            let name = inner_pairs.next().unwrap().as_str();
-            let args = inner_pairs.map(|x| construct_expression_ast(x)).collect();
-            ast::Ast::call(name.to_string(), args)
+            let name = unquote(inner_pairs.next().unwrap().as_str());
+            let default = inner_pairs.next().map(|x| construct_expression_ast(x, ns));
+            ast::Ast::environment_variable(name, default)
         },
-        Rule::labelledParameter => {
+        Rule::assertion => {
             let mut inner_pairs = pair.into_inner();
-            let name = inner_pairs.next().unwrap().as_str().to_string();
-            let parameter = construct_expression_ast(inner_pairs.next().unwrap());
-            ast::Ast::labelled_parameter(name, parameter)
+            let left = construct_expression_ast(inner_pairs.next().unwrap(), ns);
+            let right = inner_pairs.next().map(|x| construct_expression_ast(x, ns));
+            ast::Ast::assert(left, right)
+        },
+        Rule::functionCall => {
+            let mut inner_pairs = pair.into_inner();
+            let name = ns.qualify_function(inner_pairs.next().unwrap().as_str());
+            let args = inner_pairs.map(|x| construct_expression_ast(x, ns)).collect();
+            ast::Ast::call(name, args)
         },
         Rule::labelledCall => {
             let mut inner_pairs = pair.into_inner();
-            let name = inner_pairs.next().unwrap().as_str();
+            let name = ns.qualify_function(inner_pairs.next().unwrap().as_str());
             let args = inner_pairs.map(|x| {
                 let mut y = x.into_inner();
                 let name2 = y.next().unwrap().as_str().to_string();
-                let parameter = construct_expression_ast(y.next().unwrap());
+                let parameter = construct_expression_ast(y.next().unwrap(), ns);
                 *ast::Ast::labelled_parameter(name2, parameter)
             }).collect();
-            ast::Ast::labelled_call(name.to_string(), args)
+            ast::Ast::labelled_call(name, args)
         },
-        Rule::identifier => {
-            ast::Ast::symbol(pair.as_str().to_string())
+        Rule::identifier | Rule::qualifiedIdentifier => match pair.as_str().split_once('.') {
+            // p.x は、p が import したモジュールの名前でなければレコードのフィールドになる
+            Some((base, field)) if !ns.modules.contains(base) => {
+                let span = span_of(&pair);
+                let value: ast::Expression = ast::Ast::symbol(ns.qualify_variable(base));
+                value.set_span(ast::Span { end: span.start + base.len(), ..span });
+                ast::Ast::field(value, field.to_string())
+            },
+            // 引数のないバリアントは括弧を省いても呼び出しになる
+            None if ns.variants.contains(pair.as_str()) && !ns.locals.contains(pair.as_str()) => {
+                ast::Ast::call(ns.qualify_function(pair.as_str()), LinkedList::new())
+            },
+            _ => ast::Ast::symbol(ns.qualify_variable(pair.as_str())),
         },
         Rule::println => {
             let mut inner_pairs = pair.into_inner();
-            ast::Ast::println(construct_expression_ast(inner_pairs.next().unwrap()))
+            ast::Ast::println(construct_expression_ast(inner_pairs.next().unwrap(), ns))
+        },
+        Rule::matchExpression => {
+            let mut inner_pairs = pair.into_inner();
+            let value = construct_expression_ast(inner_pairs.next().unwrap(), ns);
+            let arms = inner_pairs.map(|arm| {
+                let mut inner = arm.into_inner();
+                let pattern = construct_pattern(inner.next().unwrap(), ns);
+                let mut next = inner.next().unwrap();
+                let guard = if next.as_rule() == Rule::matchGuard {
+                    let guard = construct_expression_ast(next.into_inner().next().unwrap(), ns);
+                    next = inner.next().unwrap();
+                    Some(guard)
+                } else {
+                    None
+                };
+                ast::MatchArm { pattern, guard, body: construct_expression_ast(next, ns) }
+            }).collect();
+            ast::Ast::match_expr(value, arms)
         },
         _ => unreachable!(),
     }
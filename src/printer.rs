@@ -0,0 +1,101 @@
+use crate::ast;
+use crate::ast::{Definition, Import, Node};
+
+// AST を字下げした木として表示する
+pub fn dump_program(program: &ast::Program) -> String {
+    let mut out = String::from("Program\n");
+    for import in program.imports.iter() {
+        match import {
+            Import::Path(x) => out.push_str(&format!("  Import \"{}\"\n", x)),
+            Import::Module(x) => out.push_str(&format!("  Import {}\n", x)),
+        }
+    }
+    for toplevel in program.definitions.iter() {
+        match toplevel.definition() {
+            Definition::Function(x) => {
+                out.push_str(&format!("  Function {}\n", x.signature()));
+                dump(&x.body, 2, &mut out);
+            },
+            Definition::Global(x) => {
+                out.push_str(&format!("  Global {}\n", ast::annotated(&x.name, x.type_annotation.as_deref())));
+                dump(&x.body, 2, &mut out);
+            },
+            Definition::Test(x) => {
+                out.push_str(&format!("  Test \"{}\"\n", x.name));
+                dump(&x.body, 2, &mut out);
+            },
+            Definition::Record(x) => out.push_str(&format!("  Record {}\n", x.constructor.signature())),
+            Definition::Enum(x) => {
+                out.push_str(&format!("  Enum {}\n", x.name));
+                for variant in x.variants.iter() {
+                    out.push_str(&format!("    Variant {}\n", variant.signature()));
+                }
+            },
+        }
+    }
+    out
+}
+
+pub fn dump_expression(expression: &ast::Expression) -> String {
+    let mut out = String::new();
+    dump(expression, 0, &mut out);
+    out
+}
+
+fn dump(expression: &ast::Expression, depth: usize, out: &mut String) {
+    let node = expression.node();
+    let label = match node {
+        Node::Binary(x) => format!("Binary {}", x.operator),
+        Node::Integer(x) => format!("Integer {}", x.value),
+        Node::Assignment(x) => format!("Assignment {}", x.name),
+        Node::Identifier(x) => format!("Identifier {}", x.name),
+        Node::Block(_) => "Block".to_string(),
+        Node::While(_) => "While".to_string(),
+        Node::If(_) => "If".to_string(),
+        Node::Println(_) => "Println".to_string(),
+        Node::Call(x) => format!("Call {}", x.name),
+        Node::LabelledCall(x) => {
+            let labels: Vec<&str> = x.args.iter().map(|y| y.name.as_str()).collect();
+            format!("LabelledCall {}[{}]", x.name, labels.join(", "))
+        },
+        Node::EnvironmentVariable(x) => format!("EnvironmentVariable \"{}\"", x.name),
+        Node::Field(x) => format!("Field {}", x.field),
+        Node::FieldAssignment(x) => format!("FieldAssignment {}.{}", x.name, x.path.join(".")),
+        Node::Construct(x) => format!("Construct {}", x.name),
+        Node::Assert(x) => if x.right.is_some() { "AssertEq".to_string() } else { "Assert".to_string() },
+        // 腕はパターンを見出しにして、ガードと本体をその下に並べる
+        Node::Match(x) => {
+            out.push_str(&format!("{}Match\n", "  ".repeat(depth)));
+            dump(&x.value, depth + 1, out);
+            for arm in x.arms.iter() {
+                let guard = if arm.guard.is_some() { " if" } else { "" };
+                out.push_str(&format!("{}Arm {}{}\n", "  ".repeat(depth + 1), arm.pattern, guard));
+                for child in arm.guard.iter().chain(std::iter::once(&arm.body)) {
+                    dump(child, depth + 2, out);
+                }
+            }
+            return;
+        },
+        Node::Throw(_) => "Throw".to_string(),
+        // catch と finally はそれぞれ見出しの下に本体を置く
+        Node::Try(x) => {
+            out.push_str(&format!("{}Try\n", "  ".repeat(depth)));
+            dump(&x.body, depth + 1, out);
+            if let Some(clause) = &x.catch_clause {
+                out.push_str(&format!("{}Catch {}\n", "  ".repeat(depth + 1), clause.name));
+                dump(&clause.body, depth + 2, out);
+            }
+            if let Some(finally_clause) = &x.finally_clause {
+                out.push_str(&format!("{}Finally\n", "  ".repeat(depth + 1)));
+                dump(finally_clause, depth + 2, out);
+            }
+            return;
+        },
+    };
+    out.push_str(&"  ".repeat(depth));
+    out.push_str(&label);
+    out.push('\n');
+    for child in node.children() {
+        dump(child, depth + 1, out);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::ast;
+use crate::ast::{Environment, ExpressionTrait, FunctionDefinition, RuntimeError, Value};
+use crate::json::Json;
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+// 関数ごとの集計。再帰呼び出しの inclusive は一番外側の呼び出しだけを数える
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionProfile {
+    pub name: String,
+    pub calls: usize,
+    pub inclusive: Duration,
+    pub exclusive: Duration,
+}
+
+struct Frame {
+    name: String,
+    start: Instant,
+    children: Duration, // 呼び出した関数で使った時間
+}
+
+// chrome://tracing などで読める trace event 1 件分
+struct Event {
+    name: String,
+    start: Duration,
+    duration: Duration,
+}
+
+#[derive(Default)]
+struct State {
+    frames: Vec<Frame>,
+    functions: HashMap<String, FunctionProfile>,
+    lines: BTreeMap<usize, usize>,
+    stacks: BTreeMap<String, Duration>, // "main;fact;fact" ごとの exclusive の時間
+    events: Vec<Event>,
+}
+
+// 関数の呼び出し回数と時間、各行の実行回数を記録する
+pub struct Profiler {
+    origin: Instant,
+    state: RefCell<State>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self {
+            origin: Instant::now(),
+            state: RefCell::new(State::default()),
+        }
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // exclusive の時間が長い順に並べる
+    pub fn functions(&self) -> Vec<FunctionProfile> {
+        let mut functions: Vec<FunctionProfile> = self.state.borrow().functions.values().cloned().collect();
+        functions.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then_with(|| a.name.cmp(&b.name)));
+        functions
+    }
+    // (行, 実行回数) を行の順に並べる。取り込んだモジュールの行は含まない
+    pub fn lines(&self) -> Vec<(usize, usize)> {
+        self.state.borrow().lines.iter().map(|(k, v)| (*k, *v)).collect()
+    }
+
+    pub fn report(&self) -> String {
+        let mut out = format!("{:<20} {:>8} {:>14} {:>14}\n", "function", "calls", "inclusive", "exclusive");
+        for x in self.functions() {
+            out.push_str(&format!("{:<20} {:>8} {:>14} {:>14}\n", x.name, x.calls, milliseconds(x.inclusive), milliseconds(x.exclusive)));
+        }
+        out.push_str(&format!("\n{:>6} {:>8}\n", "line", "hits"));
+        for (line, hits) in self.lines() {
+            out.push_str(&format!("{:>6} {:>8}\n", line, hits));
+        }
+        out
+    }
+
+    // flamegraph.pl などが読む "main;fact;fact 12" の形式（マイクロ秒）
+    pub fn folded(&self) -> String {
+        self.state.borrow().stacks.iter().map(|(stack, time)| format!("{} {}\n", stack, time.as_micros())).collect()
+    }
+
+    // Chrome の trace event 形式（時間はマイクロ秒）
+    pub fn chrome_trace(&self) -> Json {
+        let events = self.state.borrow().events.iter().map(|x| Json::object(vec![
+            ("name", x.name.as_str().into()),
+            ("cat", "function".into()),
+            ("ph", "X".into()),
+            ("ts", Json::Number(x.start.as_secs_f64() * 1e6)),
+            ("dur", Json::Number(x.duration.as_secs_f64() * 1e6)),
+            ("pid", 1.into()),
+            ("tid", 1.into()),
+        ])).collect::<Vec<Json>>();
+        Json::object(vec![("traceEvents", events.into()), ("displayTimeUnit", "ms".into())])
+    }
+}
+
+impl ast::Hook for Profiler {
+    fn statement(
+        &self,
+        expression: &dyn ExpressionTrait,
+        _v: &Rc<Environment>,
+        _f: &HashMap<String, &FunctionDefinition>
+    ) -> Result<(), RuntimeError> {
+        let mut state = self.state.borrow_mut();
+        let imported = state.frames.last().is_some_and(|x| x.name.contains('.'));
+        if let Some(span) = expression.span().filter(|_| !imported) {
+            *state.lines.entry(span.line).or_insert(0) += 1;
+        }
+        Ok(())
+    }
+
+    fn enter(&self, function: &FunctionDefinition, _v: &Rc<Environment>) {
+        self.state.borrow_mut().frames.push(Frame {
+            name: function.name.clone(),
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    fn leave(&self, _function: &FunctionDefinition, _result: &Result<Value, RuntimeError>, _f: &HashMap<String, &FunctionDefinition>) {
+        let mut state = self.state.borrow_mut();
+        let frame = match state.frames.pop() {
+            Some(x) => x,
+            None => return,
+        };
+        let elapsed = frame.start.elapsed();
+        let exclusive = elapsed.saturating_sub(frame.children);
+        let recursive = state.frames.iter().any(|x| x.name == frame.name);
+        let mut stack: Vec<&str> = state.frames.iter().map(|x| x.name.as_str()).collect();
+        stack.push(&frame.name);
+        let stack = stack.join(";");
+        *state.stacks.entry(stack).or_insert(Duration::ZERO) += exclusive;
+        if let Some(parent) = state.frames.last_mut() {
+            parent.children += elapsed;
+        }
+        let profile = state.functions.entry(frame.name.clone()).or_insert_with(|| FunctionProfile {
+            name: frame.name.clone(),
+            calls: 0,
+            inclusive: Duration::ZERO,
+            exclusive: Duration::ZERO,
+        });
+        profile.calls += 1;
+        profile.exclusive += exclusive;
+        if !recursive {
+            profile.inclusive += elapsed;
+        }
+        let start = frame.start.duration_since(self.origin);
+        state.events.push(Event { name: frame.name, start, duration: elapsed });
+    }
+}
+
+fn milliseconds(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1e3)
+}
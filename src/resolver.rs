@@ -0,0 +1,243 @@
+use crate::ast;
+use crate::ast::{Definition, Node, Slot};
+
+use std::collections::{HashMap, HashSet};
+
+// 変数を (depth, index) の組に解決し、実行時の名前による探索をなくす。
+// 関数の仮引数、関数内で代入される変数と、呼び出す関数の仮引数（実引数が呼び出し元にも代入される）は
+// ローカル、それ以外はグローバルになる。ただし global で定義された変数への代入はグローバル変数を書き換える。
+// 関数の中のローカルでもグローバルでもない変数と、代入される前に読むかもしれないローカル変数は、
+// 実行時に関数と呼び出し元の環境から名前で探す
+#[derive(Default)]
+pub struct Resolver {
+    globals: HashMap<String, usize>,
+    declared: HashSet<String>, // global で定義された変数
+    parameters: HashMap<String, Vec<String>>, // 関数の仮引数
+}
+impl Resolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn resolve_program(&mut self, program: &ast::Program) {
+        for toplevel in program.definitions.iter() {
+            match toplevel.definition() {
+                Definition::Global(x) => {
+                    self.declared.insert(x.name.clone());
+                },
+                Definition::Function(x) => {
+                    self.parameters.insert(x.name.clone(), x.args.iter().cloned().collect());
+                },
+                _ => {},
+            }
+        }
+        for toplevel in program.definitions.iter() {
+            match toplevel.definition() {
+                Definition::Global(x) => {
+                    x.slot.set(Some(self.lookup(&x.name, None)));
+                    self.resolve(&x.body, None);
+                },
+                Definition::Function(x) => self.resolve_function(x),
+                Definition::Record(x) => self.resolve_function(&x.constructor),
+                Definition::Enum(x) => x.variants.iter().for_each(|y| self.resolve_function(y)),
+                // テストは main と同じくグローバル環境で実行される
+                Definition::Test(x) => self.resolve(&x.body, None),
+            }
+        }
+    }
+    pub fn resolve_expression(&mut self, expression: &ast::Expression) {
+        self.resolve(expression, None);
+    }
+    // 実行中の関数の中で評価する式を解決する。locals が None なら（main の中など）グローバル環境で評価する
+    pub fn resolve_in_scope(&mut self, expression: &ast::Expression, locals: Option<&[String]>) {
+        let scope: Option<HashMap<String, usize>> = locals.map(|x| x.iter().enumerate().map(|(i, y)| (y.clone(), i)).collect());
+        self.resolve(expression, scope.as_ref());
+    }
+    pub fn global_names(&self) -> Vec<&str> {
+        let mut names: Vec<(&str, usize)> = self.globals.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+        names.sort_by_key(|x| x.1);
+        names.into_iter().map(|x| x.0).collect()
+    }
+
+    fn resolve_function(&mut self, function: &ast::FunctionDefinition) {
+        if function.is_main() {
+            *function.global_parameters.borrow_mut() = function.args.iter().map(|x| self.lookup(x, None)).collect();
+            self.resolve(&function.body, None);
+            return;
+        }
+        let mut locals: Vec<String> = function.args.iter().cloned().collect();
+        self.collect_assigned(&function.body, &mut locals);
+        let scope: HashMap<String, usize> = locals.iter().enumerate().map(|(i, x)| (x.clone(), i)).collect();
+        self.resolve(&function.body, Some(&scope));
+        let mut assigned: HashSet<String> = function.args.iter().cloned().collect();
+        self.find_unassigned(&function.body, &mut assigned, &scope);
+        *function.locals.borrow_mut() = locals;
+    }
+
+    // 代入される変数の名前を集める
+    fn collect_assigned(&self, expression: &ast::Expression, locals: &mut Vec<String>) {
+        let node = expression.node();
+        let assigned: Vec<&String> = match &node {
+            Node::Assignment(x) => vec![&x.name],
+            Node::Match(x) => x.bindings(),
+            Node::Try(x) => x.catch_clause.iter().map(|y| &y.name).collect(),
+            Node::Call(x) => self.parameters.get(&x.name).map(|y| y.iter().collect()).unwrap_or_default(),
+            Node::LabelledCall(x) => self.parameters.get(&x.name).map(|y| y.iter().collect()).unwrap_or_default(),
+            _ => vec![],
+        };
+        for name in assigned {
+            if !locals.contains(name) && !self.declared.contains(name) {
+                locals.push(name.clone());
+            }
+        }
+        for child in node.children() {
+            self.collect_assigned(child, locals);
+        }
+    }
+
+    // 評価の順にたどり、代入される前に読むかもしれないローカル変数を名前で探すようにする。
+    // assigned はそこまでにかならず代入されている変数
+    fn find_unassigned(&mut self, expression: &ast::Expression, assigned: &mut HashSet<String>, scope: &HashMap<String, usize>) {
+        let node = expression.node();
+        match &node {
+            Node::Identifier(x) => {
+                if scope.contains_key(&x.name) && !assigned.contains(&x.name) {
+                    x.dynamic.set(true);
+                    x.slot.set(Some(Slot { depth: 1, ..self.lookup(&x.name, None) }));
+                }
+            },
+            Node::Assignment(x) => {
+                self.find_unassigned(&x.expression, assigned, scope);
+                assigned.insert(x.name.clone());
+            },
+            Node::Call(_) | Node::LabelledCall(_) => {
+                // 名前付きの実引数は仮引数の順に評価されるので、互いの代入は数えない
+                let before = assigned.clone();
+                for child in node.children() {
+                    let mut branch = before.clone();
+                    self.find_unassigned(child, &mut branch, scope);
+                    assigned.extend(branch);
+                }
+                let name = match &node {
+                    Node::Call(x) => &x.name,
+                    Node::LabelledCall(x) => &x.name,
+                    _ => unreachable!(),
+                };
+                assigned.extend(self.parameters.get(name).into_iter().flatten().cloned());
+            },
+            Node::While(x) => {
+                self.find_unassigned(&x.condition, assigned, scope);
+                self.find_unassigned(&x.body, &mut assigned.clone(), scope);
+            },
+            Node::If(x) => {
+                self.find_unassigned(&x.condition, assigned, scope);
+                let mut then_assigned = assigned.clone();
+                self.find_unassigned(&x.then_clause, &mut then_assigned, scope);
+                if let Some(clause) = &x.else_clause {
+                    self.find_unassigned(clause, assigned, scope);
+                }
+                assigned.retain(|y| then_assigned.contains(y));
+            },
+            Node::EnvironmentVariable(x) => {
+                if let Some(default) = &x.default {
+                    self.find_unassigned(default, &mut assigned.clone(), scope);
+                }
+            },
+            Node::Match(x) => {
+                self.find_unassigned(&x.value, assigned, scope);
+                let mut after: Option<HashSet<String>> = None;
+                for arm in x.arms.iter() {
+                    let mut branch = assigned.clone();
+                    let mut names = Vec::new();
+                    arm.pattern.bindings(&mut names);
+                    branch.extend(names.into_iter().cloned());
+                    if let Some(guard) = &arm.guard {
+                        self.find_unassigned(guard, &mut branch, scope);
+                    }
+                    self.find_unassigned(&arm.body, &mut branch, scope);
+                    after = Some(match after {
+                        Some(y) => y.intersection(&branch).cloned().collect(),
+                        None => branch,
+                    });
+                }
+                if let Some(after) = after {
+                    *assigned = after;
+                }
+            },
+            // catch や finally の本体は本体の途中から評価されることがある
+            Node::Try(x) => {
+                self.find_unassigned(&x.body, &mut assigned.clone(), scope);
+                if let Some(clause) = &x.catch_clause {
+                    let mut branch = assigned.clone();
+                    branch.insert(clause.name.clone());
+                    self.find_unassigned(&clause.body, &mut branch, scope);
+                }
+                if let Some(clause) = &x.finally_clause {
+                    self.find_unassigned(clause, &mut assigned.clone(), scope);
+                }
+            },
+            _ => {
+                for child in node.children() {
+                    self.find_unassigned(child, assigned, scope);
+                }
+            },
+        }
+    }
+
+    fn resolve_pattern(&mut self, pattern: &ast::Pattern, scope: Option<&HashMap<String, usize>>) {
+        match pattern {
+            ast::Pattern::Binding { name, slot } => slot.set(Some(self.lookup(name, scope))),
+            ast::Pattern::Constructor { arguments, .. } => {
+                for argument in arguments.iter() {
+                    self.resolve_pattern(argument, scope);
+                }
+            },
+            ast::Pattern::Integers(_) | ast::Pattern::Wildcard => {},
+        }
+    }
+
+    fn resolve(&mut self, expression: &ast::Expression, scope: Option<&HashMap<String, usize>>) {
+        let node = expression.node();
+        match node {
+            Node::Identifier(x) => {
+                let slot = self.lookup(&x.name, scope);
+                x.dynamic.set(slot.depth > 0 && !self.declared.contains(&x.name));
+                x.slot.set(Some(slot));
+            },
+            Node::Assignment(x) => x.slot.set(Some(self.lookup(&x.name, scope))),
+            Node::Call(x) => *x.parameters.borrow_mut() = self.bind_parameters(&x.name, scope),
+            Node::LabelledCall(x) => *x.parameters.borrow_mut() = self.bind_parameters(&x.name, scope),
+            Node::FieldAssignment(x) => x.slot.set(Some(self.lookup(&x.name, scope))),
+            Node::Try(x) => {
+                if let Some(clause) = &x.catch_clause {
+                    clause.slot.set(Some(self.lookup(&clause.name, scope)));
+                }
+            },
+            Node::Match(x) => {
+                for arm in x.arms.iter() {
+                    self.resolve_pattern(&arm.pattern, scope);
+                }
+            },
+            _ => {},
+        }
+        for child in node.children() {
+            self.resolve(child, scope);
+        }
+    }
+
+    // 呼び出す関数の仮引数と同じ名前の、呼び出し元の変数
+    fn bind_parameters(&mut self, name: &str, scope: Option<&HashMap<String, usize>>) -> Vec<Slot> {
+        let names = self.parameters.get(name).cloned().unwrap_or_default();
+        names.iter().map(|x| self.lookup(x, scope)).collect()
+    }
+
+    fn lookup(&mut self, name: &str, scope: Option<&HashMap<String, usize>>) -> Slot {
+        match scope.and_then(|x| x.get(name)) {
+            Some(index) => Slot { depth: 0, index: *index },
+            None => {
+                let next_index = self.globals.len();
+                let index = *self.globals.entry(name.to_string()).or_insert(next_index);
+                Slot { depth: if scope.is_some() { 1 } else { 0 }, index }
+            },
+        }
+    }
+}
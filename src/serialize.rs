@@ -0,0 +1,528 @@
+use crate::ast;
+use crate::ast::{Definition, Import, Node, Operator, Span};
+use crate::json::{Json, JsonError};
+
+use std::collections::LinkedList;
+use std::convert::TryFrom;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeserializeError {
+    pub message: String,
+}
+impl fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+impl From<JsonError> for DeserializeError {
+    fn from(e: JsonError) -> Self {
+        Self { message: e.to_string() }
+    }
+}
+
+// 各ノードは "type" で種類を表すオブジェクトになる。位置が分かるノードには "span" を付ける
+pub fn program_to_json(program: &ast::Program) -> Json {
+    let imports = program.imports.iter().map(|x| match x {
+        Import::Path(path) => Json::object(vec![("path", path.as_str().into())]),
+        Import::Module(name) => Json::object(vec![("module", name.as_str().into())]),
+    }).collect::<Vec<Json>>();
+    let definitions = program.definitions.iter().map(|x| match x.definition() {
+        Definition::Function(f) => with_span(vec![
+            ("type", "Function".into()),
+            ("name", f.name.as_str().into()),
+            ("args", f.args.iter().map(|y| y.as_str().into()).collect::<Vec<Json>>().into()),
+            ("argTypes", f.parameter_types.iter().map(|y| y.as_deref().into()).collect::<Vec<Json>>().into()),
+            ("returnType", f.return_type.as_deref().into()),
+            ("doc", f.doc.as_deref().into()),
+            ("body", expression_to_json(&f.body)),
+        ], f.span),
+        Definition::Global(g) => with_span(vec![
+            ("type", "Global".into()),
+            ("name", g.name.as_str().into()),
+            ("valueType", g.type_annotation.as_deref().into()),
+            ("doc", g.doc.as_deref().into()),
+            ("body", expression_to_json(&g.body)),
+        ], g.span),
+        Definition::Test(t) => with_span(vec![
+            ("type", "Test".into()),
+            ("name", t.name.as_str().into()),
+            ("body", expression_to_json(&t.body)),
+        ], t.span),
+        // コンストラクタの本体はフィールドから作り直せるので書かない
+        Definition::Record(r) => with_span(vec![
+            ("type", "Record".into()),
+            ("name", r.name().into()),
+            ("fields", r.fields().iter().map(|y| y.as_str().into()).collect::<Vec<Json>>().into()),
+            ("fieldTypes", r.constructor.parameter_types.iter().map(|y| y.as_deref().into()).collect::<Vec<Json>>().into()),
+            ("doc", r.constructor.doc.as_deref().into()),
+        ], r.constructor.span),
+        Definition::Enum(e) => with_span(vec![
+            ("type", "Enum".into()),
+            ("name", e.name.as_str().into()),
+            ("variants", e.variants.iter().map(|y| Json::object(vec![
+                ("name", y.name.as_str().into()),
+                ("fields", y.args.iter().map(|z| z.as_str().into()).collect::<Vec<Json>>().into()),
+                ("fieldTypes", y.parameter_types.iter().map(|z| z.as_deref().into()).collect::<Vec<Json>>().into()),
+            ])).collect::<Vec<Json>>().into()),
+            ("doc", e.doc.as_deref().into()),
+        ], e.span),
+    }).collect::<Vec<Json>>();
+    Json::object(vec![("imports", imports.into()), ("definitions", definitions.into())])
+}
+
+pub fn expression_to_json(expression: &ast::Expression) -> Json {
+    let members = match expression.node() {
+        Node::Binary(x) => vec![
+            ("type", "Binary".into()),
+            ("operator", x.operator.to_string().into()),
+            ("lhs", expression_to_json(&x.lhs)),
+            ("rhs", expression_to_json(&x.rhs)),
+        ],
+        Node::Integer(x) => vec![("type", "Integer".into()), ("value", x.value.into())],
+        Node::Assignment(x) => vec![
+            ("type", "Assignment".into()),
+            ("name", x.name.as_str().into()),
+            ("expression", expression_to_json(&x.expression)),
+        ],
+        Node::Identifier(x) => vec![("type", "Identifier".into()), ("name", x.name.as_str().into())],
+        Node::Block(x) => vec![("type", "Block".into()), ("elements", expressions_to_json(&x.elements))],
+        Node::While(x) => vec![
+            ("type", "While".into()),
+            ("condition", expression_to_json(&x.condition)),
+            ("body", expression_to_json(&x.body)),
+        ],
+        Node::If(x) => vec![
+            ("type", "If".into()),
+            ("condition", expression_to_json(&x.condition)),
+            ("then", expression_to_json(&x.then_clause)),
+            ("else", x.else_clause.as_ref().map(expression_to_json).into()),
+        ],
+        Node::Println(x) => vec![("type", "Println".into()), ("body", expression_to_json(&x.body))],
+        Node::Call(x) => vec![
+            ("type", "Call".into()),
+            ("name", x.name.as_str().into()),
+            ("args", expressions_to_json(&x.args)),
+        ],
+        Node::LabelledCall(x) => vec![
+            ("type", "LabelledCall".into()),
+            ("name", x.name.as_str().into()),
+            ("args", x.args.iter().map(|y| Json::object(vec![
+                ("name", y.name.as_str().into()),
+                ("parameter", expression_to_json(&y.parameter)),
+            ])).collect::<Vec<Json>>().into()),
+        ],
+        Node::EnvironmentVariable(x) => vec![
+            ("type", "EnvironmentVariable".into()),
+            ("name", x.name.as_str().into()),
+            ("default", x.default.as_ref().map(expression_to_json).into()),
+        ],
+        Node::Assert(x) => vec![
+            ("type", "Assert".into()),
+            ("left", expression_to_json(&x.left)),
+            ("right", x.right.as_ref().map(expression_to_json).into()),
+        ],
+        Node::Match(x) => vec![
+            ("type", "Match".into()),
+            ("value", expression_to_json(&x.value)),
+            ("arms", x.arms.iter().map(|y| Json::object(vec![
+                ("pattern", pattern_to_json(&y.pattern)),
+                ("guard", y.guard.as_ref().map(expression_to_json).into()),
+                ("body", expression_to_json(&y.body)),
+            ])).collect::<Vec<Json>>().into()),
+        ],
+        Node::Field(x) => vec![
+            ("type", "Field".into()),
+            ("value", expression_to_json(&x.value)),
+            ("field", x.field.as_str().into()),
+        ],
+        Node::FieldAssignment(x) => vec![
+            ("type", "FieldAssignment".into()),
+            ("name", x.name.as_str().into()),
+            ("path", x.path.iter().map(|y| y.as_str().into()).collect::<Vec<Json>>().into()),
+            ("expression", expression_to_json(&x.expression)),
+        ],
+        Node::Construct(x) => vec![
+            ("type", "Construct".into()),
+            ("name", x.name.as_str().into()),
+            ("enum", x.enumeration.as_deref().into()),
+        ],
+        Node::Throw(x) => vec![("type", "Throw".into()), ("value", expression_to_json(&x.value))],
+        Node::Try(x) => vec![
+            ("type", "Try".into()),
+            ("body", expression_to_json(&x.body)),
+            ("catchName", x.catch_clause.as_ref().map(|y| y.name.as_str()).into()),
+            ("catch", x.catch_clause.as_ref().map(|y| expression_to_json(&y.body)).into()),
+            ("finally", x.finally_clause.as_ref().map(expression_to_json).into()),
+        ],
+    };
+    with_span(members, expression.span())
+}
+
+fn pattern_to_json(pattern: &ast::Pattern) -> Json {
+    match pattern {
+        ast::Pattern::Integers(x) => Json::object(vec![
+            ("type", "Integers".into()),
+            ("values", x.iter().map(|y| (*y).into()).collect::<Vec<Json>>().into()),
+        ]),
+        ast::Pattern::Binding { name, .. } => Json::object(vec![("type", "Binding".into()), ("name", name.as_str().into())]),
+        ast::Pattern::Wildcard => Json::object(vec![("type", "Wildcard".into())]),
+        ast::Pattern::Constructor { name, arguments } => Json::object(vec![
+            ("type", "Constructor".into()),
+            ("name", name.as_str().into()),
+            ("arguments", arguments.iter().map(pattern_to_json).collect::<Vec<Json>>().into()),
+        ]),
+    }
+}
+
+fn expressions_to_json(expressions: &LinkedList<ast::Expression>) -> Json {
+    expressions.iter().map(expression_to_json).collect::<Vec<Json>>().into()
+}
+
+fn with_span(mut members: Vec<(&str, Json)>, span: Option<Span>) -> Json {
+    if let Some(span) = span {
+        members.push(("span", Json::object(vec![
+            ("start", span.start.into()),
+            ("end", span.end.into()),
+            ("line", span.line.into()),
+            ("column", span.column.into()),
+        ])));
+    }
+    Json::object(members)
+}
+
+pub fn to_json(program: &ast::Program) -> String {
+    program_to_json(program).pretty()
+}
+
+pub fn from_json(text: &str) -> Result<ast::Program, DeserializeError> {
+    program_from_json(&Json::parse(text)?)
+}
+
+pub fn program_from_json(json: &Json) -> Result<ast::Program, DeserializeError> {
+    let mut imports = LinkedList::new();
+    for x in array(json, "imports")? {
+        match (x.get("path"), x.get("module")) {
+            (Some(path), _) => imports.push_back(Import::Path(as_string(path, "path")?)),
+            (None, Some(name)) => imports.push_back(Import::Module(as_string(name, "module")?)),
+            _ => return Err(error("import must have path or module")),
+        }
+    }
+    let mut definitions: LinkedList<ast::TopLevel> = LinkedList::new();
+    for x in array(json, "definitions")? {
+        let span = span_from_json(x)?;
+        let doc = match x.get("doc") {
+            Some(Json::Null) | None => None,
+            Some(doc) => Some(as_string(doc, "doc")?),
+        };
+        match string(x, "type")?.as_str() {
+            "Function" => {
+                let args = array(x, "args")?.iter().map(|y| as_string(y, "args")).collect::<Result<_, _>>()?;
+                let mut definition = ast::Ast::define_function(string(x, "name")?, args, expression_from_json(field(x, "body")?)?);
+                definition.doc = doc;
+                // 型注釈のない古い形式の JSON も読めるよう、argTypes と returnType は省略できる
+                if x.get("argTypes").is_some() {
+                    definition.parameter_types = array(x, "argTypes")?.iter().map(|y| match y {
+                        Json::Null => Ok(None),
+                        _ => as_string(y, "argTypes").map(Some),
+                    }).collect::<Result<_, _>>()?;
+                }
+                definition.return_type = optional_string(x, "returnType")?;
+                definition.span = span;
+                definitions.push_back(definition);
+            },
+            "Global" => {
+                let mut definition = ast::GlobalVariableDefinition::new(string(x, "name")?, expression_from_json(field(x, "body")?)?);
+                definition.doc = doc;
+                definition.type_annotation = optional_string(x, "valueType")?;
+                definition.span = span;
+                definitions.push_back(Box::new(definition));
+            },
+            "Test" => {
+                let mut definition = ast::Ast::define_test(string(x, "name")?, expression_from_json(field(x, "body")?)?);
+                definition.span = span;
+                definitions.push_back(definition);
+            },
+            "Record" => {
+                let fields = array(x, "fields")?.iter().map(|y| as_string(y, "fields")).collect::<Result<_, _>>()?;
+                let mut definition = ast::Ast::define_record(string(x, "name")?, fields);
+                definition.constructor.parameter_types = array(x, "fieldTypes")?.iter().map(|y| match y {
+                    Json::Null => Ok(None),
+                    _ => as_string(y, "fieldTypes").map(Some),
+                }).collect::<Result<_, _>>()?;
+                definition.constructor.doc = doc;
+                definition.constructor.span = span;
+                definitions.push_back(definition);
+            },
+            "Enum" => {
+                let mut variants = Vec::new();
+                let mut field_types = Vec::new();
+                for y in array(x, "variants")? {
+                    let fields = array(y, "fields")?.iter().map(|z| as_string(z, "fields")).collect::<Result<_, _>>()?;
+                    variants.push((string(y, "name")?, fields));
+                    field_types.push(array(y, "fieldTypes")?.iter().map(|z| match z {
+                        Json::Null => Ok(None),
+                        _ => as_string(z, "fieldTypes").map(Some),
+                    }).collect::<Result<Vec<_>, _>>()?);
+                }
+                let mut definition = ast::Ast::define_enum(string(x, "name")?, variants);
+                for (variant, types) in definition.variants.iter_mut().zip(field_types) {
+                    variant.parameter_types = types;
+                    variant.span = span;
+                }
+                definition.doc = doc;
+                definition.span = span;
+                definitions.push_back(definition);
+            },
+            other => return Err(error(&format!("unknown definition type {}", other))),
+        }
+    }
+    Ok(ast::Program { imports, definitions })
+}
+
+pub fn expression_from_json(json: &Json) -> Result<ast::Expression, DeserializeError> {
+    let expression: ast::Expression = match string(json, "type")?.as_str() {
+        "Binary" => {
+            let symbol = string(json, "operator")?;
+            let operator = Operator::from_symbol(&symbol).ok_or_else(|| error(&format!("unknown operator {}", symbol)))?;
+            ast::Ast::binary(operator, child(json, "lhs")?, child(json, "rhs")?)
+        },
+        "Integer" => {
+            let value = field(json, "value")?.as_i64()
+                .and_then(|x| i32::try_from(x).ok())
+                .ok_or_else(|| error("value must be a 32-bit integer"))?;
+            ast::Ast::integer(value)
+        },
+        "Assignment" => ast::Ast::assignment(string(json, "name")?, child(json, "expression")?),
+        "Identifier" => ast::Ast::symbol(string(json, "name")?),
+        "Block" => ast::Ast::block(children(json, "elements")?),
+        "While" => ast::Ast::while_expr(child(json, "condition")?, child(json, "body")?),
+        "If" => ast::Ast::if_expr(child(json, "condition")?, child(json, "then")?, optional_child(json, "else")?),
+        "Println" => ast::Ast::println(child(json, "body")?),
+        "Call" => ast::Ast::call(string(json, "name")?, children(json, "args")?),
+        "LabelledCall" => {
+            let args = array(json, "args")?.iter()
+                .map(|x| Ok(*ast::Ast::labelled_parameter(string(x, "name")?, child(x, "parameter")?)))
+                .collect::<Result<_, DeserializeError>>()?;
+            ast::Ast::labelled_call(string(json, "name")?, args)
+        },
+        "EnvironmentVariable" => ast::Ast::environment_variable(string(json, "name")?, optional_child(json, "default")?),
+        "Assert" => ast::Ast::assert(child(json, "left")?, optional_child(json, "right")?),
+        "Match" => {
+            let arms = array(json, "arms")?.iter()
+                .map(|x| Ok(ast::MatchArm {
+                    pattern: pattern_from_json(field(x, "pattern")?)?,
+                    guard: optional_child(x, "guard")?,
+                    body: child(x, "body")?,
+                }))
+                .collect::<Result<_, DeserializeError>>()?;
+            ast::Ast::match_expr(child(json, "value")?, arms)
+        },
+        "Field" => ast::Ast::field(child(json, "value")?, string(json, "field")?),
+        "FieldAssignment" => {
+            let path = array(json, "path")?.iter().map(|x| as_string(x, "path")).collect::<Result<_, _>>()?;
+            ast::Ast::field_assignment(string(json, "name")?, path, child(json, "expression")?)
+        },
+        "Construct" => ast::Ast::construct(string(json, "name")?, optional_string(json, "enum")?),
+        "Throw" => ast::Ast::throw(child(json, "value")?),
+        "Try" => {
+            let catch_clause = match optional_string(json, "catchName")? {
+                Some(name) => Some((name, child(json, "catch")?)),
+                None => None,
+            };
+            ast::Ast::try_expr(child(json, "body")?, catch_clause, optional_child(json, "finally")?)
+        },
+        other => return Err(error(&format!("unknown expression type {}", other))),
+    };
+    if let Some(span) = span_from_json(json)? {
+        expression.set_span(span);
+    }
+    Ok(expression)
+}
+
+fn pattern_from_json(json: &Json) -> Result<ast::Pattern, DeserializeError> {
+    match string(json, "type")?.as_str() {
+        "Integers" => {
+            let values = array(json, "values")?.iter()
+                .map(|x| x.as_i64().and_then(|y| i32::try_from(y).ok()).ok_or_else(|| error("values must be 32-bit integers")))
+                .collect::<Result<_, _>>()?;
+            Ok(ast::Pattern::Integers(values))
+        },
+        "Binding" => Ok(ast::Pattern::binding(string(json, "name")?)),
+        "Wildcard" => Ok(ast::Pattern::Wildcard),
+        "Constructor" => Ok(ast::Pattern::Constructor {
+            name: string(json, "name")?,
+            arguments: array(json, "arguments")?.iter().map(pattern_from_json).collect::<Result<_, _>>()?,
+        }),
+        other => Err(error(&format!("unknown pattern type {}", other))),
+    }
+}
+
+fn span_from_json(json: &Json) -> Result<Option<Span>, DeserializeError> {
+    let span = match json.get("span") {
+        Some(Json::Null) | None => return Ok(None),
+        Some(x) => x,
+    };
+    let number = |key: &str| field(span, key)?.as_i64()
+        .and_then(|x| usize::try_from(x).ok())
+        .ok_or_else(|| error(&format!("span.{} must be a non-negative integer", key)));
+    Ok(Some(Span {
+        start: number("start")?,
+        end: number("end")?,
+        line: number("line")?,
+        column: number("column")?,
+    }))
+}
+
+fn error(message: &str) -> DeserializeError {
+    DeserializeError { message: message.to_string() }
+}
+
+fn field<'a>(json: &'a Json, key: &str) -> Result<&'a Json, DeserializeError> {
+    json.get(key).ok_or_else(|| error(&format!("missing field {}", key)))
+}
+
+fn as_string(json: &Json, key: &str) -> Result<String, DeserializeError> {
+    json.as_str().map(|x| x.to_string()).ok_or_else(|| error(&format!("{} must be a string", key)))
+}
+
+fn string(json: &Json, key: &str) -> Result<String, DeserializeError> {
+    as_string(field(json, key)?, key)
+}
+
+fn optional_string(json: &Json, key: &str) -> Result<Option<String>, DeserializeError> {
+    match json.get(key) {
+        Some(Json::Null) | None => Ok(None),
+        Some(x) => as_string(x, key).map(Some),
+    }
+}
+
+fn array<'a>(json: &'a Json, key: &str) -> Result<&'a Vec<Json>, DeserializeError> {
+    field(json, key)?.as_array().ok_or_else(|| error(&format!("{} must be an array", key)))
+}
+
+fn child(json: &Json, key: &str) -> Result<ast::Expression, DeserializeError> {
+    expression_from_json(field(json, key)?)
+}
+
+fn optional_child(json: &Json, key: &str) -> Result<Option<ast::Expression>, DeserializeError> {
+    match json.get(key) {
+        Some(Json::Null) | None => Ok(None),
+        Some(x) => expression_from_json(x).map(Some),
+    }
+}
+
+fn children(json: &Json, key: &str) -> Result<LinkedList<ast::Expression>, DeserializeError> {
+    array(json, key)?.iter().map(expression_from_json).collect()
+}
+
+// (define fact (n) (block (if (< n 2) 1 (* n (call fact (- n 1)))))) のような一行の S 式
+pub fn to_sexpr(program: &ast::Program) -> String {
+    let mut items: Vec<String> = vec!["program".to_string()];
+    for import in program.imports.iter() {
+        match import {
+            Import::Path(x) => items.push(format!("(import {})", Json::from(x.as_str()))),
+            Import::Module(x) => items.push(format!("(import {})", x)),
+        }
+    }
+    for toplevel in program.definitions.iter() {
+        match toplevel.definition() {
+            Definition::Function(x) => {
+                let args: Vec<&str> = x.args.iter().map(|y| y.as_str()).collect();
+                items.push(format!("(define {} ({}) {})", x.name, args.join(" "), expression_to_sexpr(&x.body)));
+            },
+            Definition::Global(x) => items.push(format!("(global {} {})", x.name, expression_to_sexpr(&x.body))),
+            Definition::Test(x) => items.push(format!("(test {} {})", Json::from(x.name.as_str()), expression_to_sexpr(&x.body))),
+            Definition::Record(x) => {
+                let fields: Vec<&str> = x.fields().iter().map(|y| y.as_str()).collect();
+                items.push(format!("(record {} ({}))", x.name(), fields.join(" ")));
+            },
+            Definition::Enum(x) => {
+                let variants: Vec<String> = x.variants.iter().map(|y| {
+                    let mut names = vec![y.name.as_str()];
+                    names.extend(y.args.iter().map(|z| z.as_str()));
+                    format!("({})", names.join(" "))
+                }).collect();
+                items.push(format!("(enum {} {})", x.name, variants.join(" ")));
+            },
+        }
+    }
+    format!("({})", items.join(" "))
+}
+
+pub fn expression_to_sexpr(expression: &ast::Expression) -> String {
+    let list = |head: &str, rest: Vec<String>| {
+        let mut items = vec![head.to_string()];
+        items.extend(rest);
+        format!("({})", items.join(" "))
+    };
+    let all = |expressions: &LinkedList<ast::Expression>| expressions.iter().map(expression_to_sexpr).collect::<Vec<String>>();
+    match expression.node() {
+        Node::Binary(x) => list(&x.operator.to_string(), vec![expression_to_sexpr(&x.lhs), expression_to_sexpr(&x.rhs)]),
+        Node::Integer(x) => x.value.to_string(),
+        Node::Assignment(x) => list("set", vec![x.name.clone(), expression_to_sexpr(&x.expression)]),
+        Node::Identifier(x) => x.name.clone(),
+        Node::Block(x) => list("block", all(&x.elements)),
+        Node::While(x) => list("while", vec![expression_to_sexpr(&x.condition), expression_to_sexpr(&x.body)]),
+        Node::If(x) => {
+            let mut rest = vec![expression_to_sexpr(&x.condition), expression_to_sexpr(&x.then_clause)];
+            rest.extend(x.else_clause.iter().map(expression_to_sexpr));
+            list("if", rest)
+        },
+        Node::Println(x) => list("println", vec![expression_to_sexpr(&x.body)]),
+        Node::Call(x) => {
+            let mut rest = vec![x.name.clone()];
+            rest.extend(all(&x.args));
+            list("call", rest)
+        },
+        Node::LabelledCall(x) => {
+            let mut rest = vec![x.name.clone()];
+            rest.extend(x.args.iter().map(|y| format!("({} {})", y.name, expression_to_sexpr(&y.parameter))));
+            list("labelled-call", rest)
+        },
+        Node::EnvironmentVariable(x) => {
+            let mut rest = vec![Json::from(x.name.as_str()).to_string()];
+            rest.extend(x.default.iter().map(expression_to_sexpr));
+            list("env", rest)
+        },
+        Node::Assert(x) => match &x.right {
+            Some(right) => list("assert-eq", vec![expression_to_sexpr(&x.left), expression_to_sexpr(right)]),
+            None => list("assert", vec![expression_to_sexpr(&x.left)]),
+        },
+        // 各腕は (パターン (when ガード) 本体)
+        Node::Match(x) => {
+            let mut rest = vec![expression_to_sexpr(&x.value)];
+            rest.extend(x.arms.iter().map(|y| {
+                let mut items = vec![pattern_to_sexpr(&y.pattern)];
+                items.extend(y.guard.iter().map(|z| format!("(when {})", expression_to_sexpr(z))));
+                items.push(expression_to_sexpr(&y.body));
+                format!("({})", items.join(" "))
+            }));
+            list("match", rest)
+        },
+        Node::Field(x) => list("field", vec![expression_to_sexpr(&x.value), x.field.clone()]),
+        Node::FieldAssignment(x) => list("set-field", vec![x.name.clone(), format!("({})", x.path.join(" ")), expression_to_sexpr(&x.expression)]),
+        Node::Construct(x) => list("construct", vec![x.name.clone()]),
+        Node::Throw(x) => list("throw", vec![expression_to_sexpr(&x.value)]),
+        // (try 本体 (catch e 本体) (finally 本体))
+        Node::Try(x) => {
+            let mut rest = vec![expression_to_sexpr(&x.body)];
+            rest.extend(x.catch_clause.iter().map(|y| list("catch", vec![y.name.clone(), expression_to_sexpr(&y.body)])));
+            rest.extend(x.finally_clause.iter().map(|y| list("finally", vec![expression_to_sexpr(y)])));
+            list("try", rest)
+        },
+    }
+}
+
+// 整数のパターンは (1 2)、バリアントのパターンは (Rect w _) のように並べる
+fn pattern_to_sexpr(pattern: &ast::Pattern) -> String {
+    match pattern {
+        ast::Pattern::Integers(values) => format!("({})", values.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(" ")),
+        ast::Pattern::Constructor { name, arguments } => {
+            let mut items = vec![name.clone()];
+            items.extend(arguments.iter().map(pattern_to_sexpr));
+            format!("({})", items.join(" "))
+        },
+        other => other.to_string(),
+    }
+}
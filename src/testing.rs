@@ -0,0 +1,65 @@
+use crate::ast;
+use crate::ast::{Definition, RuntimeError, Span};
+use crate::interpreter::Interpreter;
+
+// 1 つのテストの結果。失敗した場合は失敗した assert の位置（分からなければテストの位置）を持つ
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    pub name: String,
+    pub span: Option<Span>,
+    pub outcome: Result<(), RuntimeError>,
+}
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.outcome.is_ok()
+    }
+}
+
+pub fn tests(program: &ast::Program) -> Vec<&ast::TestDefinition> {
+    program.definitions.iter().filter_map(|x| match x.definition() {
+        Definition::Test(t) => Some(t),
+        _ => None,
+    }).collect()
+}
+
+// 名前に filter を含むテストだけを、それぞれ新しい Interpreter で実行する
+pub fn run_tests(program: &ast::Program, filter: Option<&str>) -> Vec<TestResult> {
+    tests(program).into_iter()
+        .filter(|x| filter.is_none_or(|y| x.name.contains(y)))
+        .map(|test| {
+            let outcome = Interpreter::new().run_test(program, test);
+            TestResult {
+                name: test.name.clone(),
+                span: outcome.as_ref().err().and_then(|e| e.span).or(test.span),
+                outcome: outcome.map(|_| ()),
+            }
+        })
+        .collect()
+}
+
+// cargo test に似た形式の報告。file はソースのファイル名
+pub fn report(file: &str, results: &[TestResult], filtered: usize) -> String {
+    let mut out = format!("running {} test{}\n", results.len(), if results.len() == 1 { "" } else { "s" });
+    for result in results.iter() {
+        out.push_str(&format!("test {} ... {}\n", result.name, if result.passed() { "ok" } else { "FAILED" }));
+    }
+    let failures: Vec<&TestResult> = results.iter().filter(|x| !x.passed()).collect();
+    if !failures.is_empty() {
+        out.push_str("\nfailures:\n");
+        for failure in failures.iter() {
+            let location = match failure.span {
+                Some(span) => format!("{}:{}:{}", file, span.line, span.column),
+                None => file.to_string(),
+            };
+            if let Err(e) = &failure.outcome {
+                out.push_str(&format!("\n---- {} ----\n{}: {}\n", failure.name, location, e));
+            }
+        }
+    }
+    let passed = results.len() - failures.len();
+    out.push_str(&format!(
+        "\ntest result: {}. {} passed; {} failed; {} filtered out\n",
+        if failures.is_empty() { "ok" } else { "FAILED" }, passed, failures.len(), filtered
+    ));
+    out
+}
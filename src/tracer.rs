@@ -0,0 +1,96 @@
+use crate::ast;
+use crate::ast::{Assignment, Environment, ExpressionTrait, FunctionDefinition, IfExpression, RuntimeError, Value, WhileExpression};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+
+// 評価した関数の出入り、代入、分岐、繰り返しを呼び出しの深さで字下げして書き出す
+pub struct Tracer<W> {
+    output: RefCell<W>,
+    functions: Vec<String>,        // 空でなければ、これらの関数の中だけを記録する
+    frames: RefCell<Vec<String>>, // 呼び出し中の関数の名前
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(output: W) -> Self {
+        Self::only(output, Vec::new())
+    }
+    pub fn only(output: W, functions: Vec<String>) -> Self {
+        Self {
+            output: RefCell::new(output),
+            functions,
+            frames: RefCell::new(Vec::new()),
+        }
+    }
+    pub fn into_output(self) -> W {
+        self.output.into_inner()
+    }
+
+    fn traced(&self, function: &str) -> bool {
+        self.functions.is_empty() || self.functions.iter().any(|x| x == function)
+    }
+
+    // 今いる関数が記録の対象なら書き出す
+    fn event(&self, expression: &dyn ExpressionTrait, text: String) {
+        let frames = self.frames.borrow();
+        if frames.last().is_some_and(|x| self.traced(x)) {
+            let line = expression.span().map(|x| format!("line {}: ", x.line)).unwrap_or_default();
+            self.write(frames.len(), &format!("{}{}", line, text));
+        }
+    }
+
+    fn write(&self, depth: usize, text: &str) {
+        // 出力先に書けなくても実行は続ける
+        let _ = writeln!(self.output.borrow_mut(), "{}{}", "  ".repeat(depth), text);
+    }
+}
+
+impl<W: Write> ast::Hook for Tracer<W> {
+    fn enter(&self, function: &FunctionDefinition, environment: &Rc<Environment>) {
+        let depth = self.frames.borrow().len();
+        if self.traced(&function.name) {
+            let arguments: Vec<String> = function.arguments(environment).into_iter()
+                .map(|(name, value)| format!("{} = {}", name, show(value)))
+                .collect();
+            self.write(depth, &format!("call {}({})", function.name, arguments.join(", ")));
+        }
+        self.frames.borrow_mut().push(function.name.clone());
+    }
+
+    fn leave(&self, function: &FunctionDefinition, result: &Result<Value, RuntimeError>, _f: &HashMap<String, &FunctionDefinition>) {
+        self.frames.borrow_mut().pop();
+        if self.traced(&function.name) {
+            let depth = self.frames.borrow().len();
+            match result {
+                Ok(x) => self.write(depth, &format!("return {} = {}", function.name, x)),
+                Err(e) => self.write(depth, &format!("error in {}: {}", function.name, e)),
+            }
+        }
+    }
+
+    fn assign(&self, expression: &Assignment, old: Option<Value>, new: &Value) {
+        self.event(expression, format!("{} = {} (was {})", expression.name, new, show(old)));
+    }
+
+    fn branch(&self, expression: &IfExpression, condition: i32, taken: bool) {
+        let clause = match (taken, &expression.else_clause) {
+            (true, _) => "then",
+            (false, Some(_)) => "else",
+            (false, None) => "no",
+        };
+        self.event(expression, format!("if condition {}, {} branch", condition, clause));
+    }
+
+    fn iteration(&self, expression: &WhileExpression, count: usize) {
+        self.event(expression, format!("while iteration {}", count));
+    }
+}
+
+fn show(value: Option<Value>) -> String {
+    match value {
+        Some(x) => x.to_string(),
+        None => "<unset>".to_string(),
+    }
+}
@@ -0,0 +1,675 @@
+use crate::ast;
+use crate::ast::{Definition, FunctionDefinition, Node, Operator, Span};
+use crate::checker::Diagnostic;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// Hindley–Milner による型推論。関数は呼び出しの強連結成分ごとに推論してから一般化するので、
+// 相互再帰する関数は単相に、それ以外の関数は多相に使える。グローバル変数は単相
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    Named(String), // Int 以外の型注釈の型
+    Variable(usize),
+    Function(Vec<Type>, Box<Type>),
+}
+
+// 型変数を a, b, ... と表示する。names は型変数の番号を表示する順に並べたもの
+struct Display<'a> {
+    ty: &'a Type,
+    names: &'a [usize],
+}
+impl fmt::Display for Display<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.ty {
+            Type::Int => write!(f, "Int"),
+            Type::Named(x) => write!(f, "{}", x),
+            Type::Variable(x) => match self.names.iter().position(|y| y == x) {
+                Some(i) => write!(f, "{}", variable_name(i)),
+                None => write!(f, "t{}", x),
+            },
+            Type::Function(parameters, result) => {
+                let parameters: Vec<String> = parameters.iter().map(|x| Display { ty: x, names: self.names }.to_string()).collect();
+                write!(f, "({}) -> {}", parameters.join(", "), Display { ty: result, names: self.names })
+            },
+        }
+    }
+}
+
+fn variable_name(index: usize) -> String {
+    let letter = (b'a' + (index % 26) as u8) as char;
+    if index < 26 { letter.to_string() } else { format!("{}{}", letter, index / 26) }
+}
+
+impl Type {
+    fn variables(&self, out: &mut Vec<usize>) {
+        match self {
+            Type::Int | Type::Named(_) => {},
+            Type::Variable(x) => if !out.contains(x) { out.push(*x) },
+            Type::Function(parameters, result) => {
+                for parameter in parameters.iter() {
+                    parameter.variables(out);
+                }
+                result.variables(out);
+            },
+        }
+    }
+}
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names = Vec::new();
+        self.variables(&mut names);
+        write!(f, "{}", Display { ty: self, names: &names })
+    }
+}
+
+// 型スキーム。variables の型変数は使うたびに新しい型変数に置き換える
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scheme {
+    pub variables: Vec<usize>,
+    pub ty: Type,
+}
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names = Vec::new();
+        self.ty.variables(&mut names);
+        let quantified: Vec<String> = names.iter().enumerate()
+            .filter(|(_, x)| self.variables.contains(x))
+            .map(|(i, _)| variable_name(i))
+            .collect();
+        if !quantified.is_empty() {
+            write!(f, "forall {}. ", quantified.join(" "))?;
+        }
+        write!(f, "{}", Display { ty: &self.ty, names: &names })
+    }
+}
+
+// 推論の結果。functions と globals は定義の順に並べる。expressions は位置のある式それぞれの型
+pub struct Typing {
+    pub functions: Vec<(String, Scheme)>,
+    pub globals: Vec<(String, Type)>,
+    pub expressions: Vec<(Span, Type)>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+impl Typing {
+    // 位置 (行, 列) から始まる式の型
+    pub fn type_at(&self, line: usize, column: usize) -> Option<&Type> {
+        self.expressions.iter().find(|(x, _)| x.line == line && x.column == column).map(|x| &x.1)
+    }
+}
+
+// 未定義の関数・変数や引数の数の誤りは checker が報告するので、ここでは型の不一致だけを報告する
+pub fn check_program(program: &ast::Program) -> Typing {
+    let mut functions: Vec<&FunctionDefinition> = Vec::new();
+    let mut globals: Vec<String> = Vec::new();
+    let mut records: Vec<&FunctionDefinition> = Vec::new();
+    let mut enums: Vec<&ast::EnumDefinition> = Vec::new();
+    for toplevel in program.definitions.iter() {
+        match toplevel.definition() {
+            Definition::Function(x) => {
+                // 同じ名前の関数は後の定義が使われる
+                functions.retain(|y| y.name != x.name);
+                functions.push(x);
+                if x.is_main() {
+                    globals.extend(x.args.iter().cloned());
+                    collect_assigned(&x.body, &mut globals);
+                }
+            },
+            Definition::Global(x) => globals.push(x.name.clone()),
+            Definition::Record(x) => records.push(&x.constructor),
+            Definition::Enum(x) => enums.push(x),
+            Definition::Test(_) => {},
+        }
+    }
+    let mut inference = Inference {
+        substitution: Vec::new(),
+        functions: HashMap::new(),
+        parameters: functions.iter().chain(records.iter()).copied()
+            .chain(enums.iter().flat_map(|x| x.variants.iter()))
+            .map(|x| (x.name.clone(), x.args.iter().cloned().collect()))
+            .collect(),
+        globals: HashMap::new(),
+        declared: program.definitions.iter().filter_map(|x| match x.definition() {
+            Definition::Global(y) => Some(y.name.clone()),
+            _ => None,
+        }).collect(),
+        records: HashMap::new(),
+        variants: HashMap::new(),
+        expressions: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    // catch で受け取るインタプリタのエラーは Error レコードで、kind は ErrorKind のバリアント
+    let kind = Type::Named("ErrorKind".to_string());
+    inference.records.insert("Error".to_string(), vec![("kind".to_string(), kind), ("message".to_string(), Type::Named("String".to_string()))]);
+    for kind in ast::ErrorKind::CATCHABLE.iter() {
+        inference.variants.insert(kind.name(), ("ErrorKind".to_string(), Vec::new()));
+    }
+    // レコードの型はその名前の型。注釈のないフィールドの型は使われ方から決める（単相）
+    for constructor in records {
+        let fields: Vec<(String, Type)> = constructor.args.iter().enumerate().map(|(i, x)| {
+            let ty = match constructor.parameter_types.get(i) {
+                Some(Some(annotation)) => annotated(annotation),
+                _ => inference.fresh(),
+            };
+            (x.clone(), ty)
+        }).collect();
+        let ty = Type::Function(fields.iter().map(|x| x.1.clone()).collect(), Box::new(Type::Named(constructor.name.clone())));
+        inference.functions.insert(constructor.name.clone(), Scheme { variables: Vec::new(), ty });
+        inference.records.insert(constructor.name.clone(), fields);
+    }
+    // バリアントのコンストラクタは値を受け取って列挙型の値を返す。値の型はレコードのフィールドと同じく決める
+    for enumeration in enums {
+        for variant in enumeration.variants.iter() {
+            let payload: Vec<Type> = (0..variant.args.len()).map(|i| match variant.parameter_types.get(i) {
+                Some(Some(annotation)) => annotated(annotation),
+                _ => inference.fresh(),
+            }).collect();
+            let ty = Type::Function(payload.clone(), Box::new(Type::Named(enumeration.name.clone())));
+            inference.functions.insert(variant.name.clone(), Scheme { variables: Vec::new(), ty });
+            inference.variants.insert(variant.name.clone(), (enumeration.name.clone(), payload));
+        }
+    }
+    let mut order = Vec::new();
+    for name in globals {
+        if !inference.globals.contains_key(&name) {
+            let ty = inference.fresh();
+            inference.globals.insert(name.clone(), ty);
+            order.push(name);
+        }
+    }
+
+    for component in components(&functions) {
+        // 成分の中の関数はまだ単相の型で呼び合う
+        for function in component.iter() {
+            let parameters = function.args.iter().map(|_| inference.fresh()).collect();
+            let ty = Type::Function(parameters, Box::new(inference.fresh()));
+            inference.functions.insert(function.name.clone(), Scheme { variables: Vec::new(), ty });
+        }
+        for function in component.iter() {
+            inference.function(function);
+        }
+        let environment = inference.environment_variables();
+        for function in component.iter() {
+            let ty = inference.zonk(&inference.functions[&function.name].ty);
+            let mut variables = Vec::new();
+            ty.variables(&mut variables);
+            variables.retain(|x| !environment.contains(x));
+            inference.functions.insert(function.name.clone(), Scheme { variables, ty });
+        }
+    }
+    for toplevel in program.definitions.iter() {
+        match toplevel.definition() {
+            Definition::Global(x) => {
+                let context = x.name.clone();
+                let ty = inference.infer(&x.body, &mut HashMap::new(), &context);
+                let expected = inference.globals[&x.name].clone();
+                if let Some(annotation) = &x.type_annotation {
+                    inference.unify(&annotated(annotation), &expected, x.span, &context);
+                }
+                inference.unify(&expected, &ty, x.body.span(), &context);
+            },
+            Definition::Test(x) => {
+                let context = format!("test \"{}\"", x.name);
+                let mut locals = HashMap::new();
+                let mut names = Vec::new();
+                collect_assigned(&x.body, &mut names);
+                for name in names {
+                    locals.insert(name, inference.fresh());
+                }
+                inference.infer(&x.body, &mut locals, &context);
+            },
+            Definition::Function(_) | Definition::Record(_) | Definition::Enum(_) => {},
+        }
+    }
+
+    let functions = functions.iter().map(|x| {
+        let scheme = &inference.functions[&x.name];
+        (x.name.clone(), Scheme { variables: scheme.variables.clone(), ty: inference.zonk(&scheme.ty) })
+    }).collect();
+    let globals = order.into_iter().map(|x| {
+        let ty = inference.zonk(&inference.globals[&x]);
+        (x, ty)
+    }).collect();
+    let expressions = inference.expressions.iter().map(|(span, ty)| (*span, inference.zonk(ty))).collect();
+    Typing { functions, globals, expressions, diagnostics: inference.diagnostics }
+}
+
+struct Inference {
+    substitution: Vec<Option<Type>>, // 型変数の番号から、決まった型
+    functions: HashMap<String, Scheme>,
+    parameters: HashMap<String, Vec<String>>, // 関数の仮引数の名前
+    globals: HashMap<String, Type>,
+    declared: HashSet<String>, // global で定義された変数。関数の中で代入してもローカルにならない
+    records: HashMap<String, Vec<(String, Type)>>, // レコードの名前から、フィールドの名前と型
+    variants: HashMap<String, (String, Vec<Type>)>, // バリアントの名前から、列挙型の名前と値の型
+    expressions: Vec<(Span, Type)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Inference {
+    fn fresh(&mut self) -> Type {
+        self.substitution.push(None);
+        Type::Variable(self.substitution.len() - 1)
+    }
+
+    // 決まっている型変数をすべて置き換える
+    fn zonk(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Variable(x) => match &self.substitution[*x] {
+                Some(y) => self.zonk(y),
+                None => ty.clone(),
+            },
+            Type::Function(parameters, result) => Type::Function(
+                parameters.iter().map(|x| self.zonk(x)).collect(),
+                Box::new(self.zonk(result)),
+            ),
+            Type::Int | Type::Named(_) => ty.clone(),
+        }
+    }
+
+    fn unify(&mut self, expected: &Type, actual: &Type, span: Option<Span>, context: &str) {
+        if let Err((expected, actual)) = self.unify_types(expected, actual) {
+            self.diagnostics.push(Diagnostic::error(
+                format!("type mismatch: expected {} but found {} in {}", self.zonk(&expected), self.zonk(&actual), context),
+                span,
+                context,
+            ));
+        }
+    }
+
+    fn unify_types(&mut self, expected: &Type, actual: &Type) -> Result<(), (Type, Type)> {
+        let (expected, actual) = (self.zonk(expected), self.zonk(actual));
+        match (&expected, &actual) {
+            (Type::Int, Type::Int) => Ok(()),
+            (Type::Named(x), Type::Named(y)) if x == y => Ok(()),
+            (Type::Variable(x), Type::Variable(y)) if x == y => Ok(()),
+            (Type::Variable(x), other) | (other, Type::Variable(x)) => {
+                let mut variables = Vec::new();
+                other.variables(&mut variables);
+                // 自分自身を含む無限の型は作らない
+                if variables.contains(x) {
+                    return Err((expected.clone(), actual.clone()));
+                }
+                self.substitution[*x] = Some(other.clone());
+                Ok(())
+            },
+            (Type::Function(p, r), Type::Function(q, s)) if p.len() == q.len() => {
+                for (x, y) in p.iter().zip(q.iter()) {
+                    self.unify_types(x, y).map_err(|_| (expected.clone(), actual.clone()))?;
+                }
+                self.unify_types(r, s).map_err(|_| (expected.clone(), actual.clone()))
+            },
+            _ => Err((expected.clone(), actual.clone())),
+        }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let mapping: HashMap<usize, Type> = scheme.variables.iter().map(|x| (*x, self.fresh())).collect();
+        substitute(&scheme.ty, &mapping)
+    }
+
+    // 一般化してはいけない、グローバル変数やレコードのフィールド、バリアントの値の型に残っている型変数
+    fn environment_variables(&self) -> HashSet<usize> {
+        let mut variables = Vec::new();
+        let payloads = self.variants.values().flat_map(|x| x.1.iter());
+        for ty in self.globals.values().chain(self.records.values().flatten().map(|x| &x.1)).chain(payloads) {
+            self.zonk(ty).variables(&mut variables);
+        }
+        variables.into_iter().collect()
+    }
+
+    // main の仮引数と返り値はコマンドライン引数と終了コードなので整数になる
+    fn function(&mut self, function: &FunctionDefinition) {
+        let ty = self.functions[&function.name].ty.clone();
+        let Type::Function(parameters, result) = ty else { unreachable!() };
+        let mut locals = HashMap::new();
+        if function.is_main() {
+            for (name, parameter) in function.args.iter().zip(parameters.iter()) {
+                let global = self.globals[name].clone();
+                self.unify(&Type::Int, parameter, function.span, &function.name);
+                self.unify(&global, parameter, function.span, &function.name);
+            }
+        } else {
+            for (name, parameter) in function.args.iter().zip(parameters.iter()) {
+                locals.insert(name.clone(), parameter.clone());
+            }
+            let mut names = Vec::new();
+            collect_assigned(&function.body, &mut names);
+            names.retain(|x| !function.args.contains(x) && !self.declared.contains(x));
+            for name in names {
+                let ty = self.fresh();
+                locals.insert(name, ty);
+            }
+        }
+        // 型注釈があればその型に決める。注釈のない仮引数や返り値は推論する
+        for (parameter, annotation) in parameters.iter().zip(function.parameter_types.iter()) {
+            if let Some(x) = annotation {
+                self.unify(&annotated(x), parameter, function.span, &function.name);
+            }
+        }
+        if let Some(x) = &function.return_type {
+            self.unify(&annotated(x), &result, function.span, &function.name);
+        }
+        let body = self.infer(&function.body, &mut locals, &function.name);
+        self.unify(&result, &body, function.body.span(), &function.name);
+        if function.is_main() {
+            self.unify(&Type::Int, &result, function.body.span(), &function.name);
+        }
+    }
+
+    fn variable(&mut self, name: &str, locals: &HashMap<String, Type>) -> Type {
+        match locals.get(name).or_else(|| self.globals.get(name)) {
+            Some(x) => x.clone(),
+            None => self.fresh(),
+        }
+    }
+
+    fn infer(&mut self, expression: &ast::Expression, locals: &mut HashMap<String, Type>, context: &str) -> Type {
+        let ty = self.infer_node(expression, locals, context);
+        if let Some(span) = expression.span() {
+            self.expressions.push((span, ty.clone()));
+        }
+        ty
+    }
+
+    // 整数を求める位置の式
+    fn integer(&mut self, expression: &ast::Expression, locals: &mut HashMap<String, Type>, context: &str) {
+        let ty = self.infer(expression, locals, context);
+        self.unify(&Type::Int, &ty, expression.span(), context);
+    }
+
+    // value.field の型。value の型がまだ決まっていなければ、そのフィールドを持つただ一つのレコードの型にする
+    fn field(&mut self, value: &Type, field: &str, span: Option<Span>, context: &str) -> Type {
+        let candidates: Vec<String> = match self.zonk(value) {
+            Type::Named(x) => vec![x],
+            _ => self.records.iter().filter(|(_, y)| y.iter().any(|z| z.0 == field)).map(|(x, _)| x.clone()).collect(),
+        };
+        let [record] = candidates.as_slice() else { return self.fresh() };
+        let ty = self.records.get(record).and_then(|x| x.iter().find(|y| y.0 == field)).map(|x| x.1.clone());
+        match ty {
+            Some(ty) => {
+                self.unify(&Type::Named(record.clone()), value, span, context);
+                ty
+            },
+            None => self.fresh(),
+        }
+    }
+
+    fn infer_node(&mut self, expression: &ast::Expression, locals: &mut HashMap<String, Type>, context: &str) -> Type {
+        let node = expression.node();
+        match &node {
+            Node::Integer(_) => Type::Int,
+            Node::Identifier(x) => self.variable(&x.name, locals),
+            Node::Assignment(x) => {
+                let ty = self.infer(&x.expression, locals, context);
+                let variable = self.variable(&x.name, locals);
+                self.unify(&variable, &ty, expression.span(), context);
+                ty
+            },
+            // == と != はどの型の値どうしでも比べられる
+            Node::Binary(x) if matches!(x.operator, Operator::EqualEqual | Operator::NotEqual) => {
+                let lhs = self.infer(&x.lhs, locals, context);
+                let rhs = self.infer(&x.rhs, locals, context);
+                self.unify(&lhs, &rhs, x.rhs.span(), context);
+                Type::Int
+            },
+            Node::Binary(x) => {
+                self.integer(&x.lhs, locals, context);
+                self.integer(&x.rhs, locals, context);
+                Type::Int
+            },
+            Node::Block(x) => {
+                let mut ty = Type::Int;
+                for element in x.elements.iter() {
+                    ty = self.infer(element, locals, context);
+                }
+                ty
+            },
+            Node::While(x) => {
+                self.integer(&x.condition, locals, context);
+                self.infer(&x.body, locals, context);
+                Type::Int
+            },
+            // else のない if は条件が偽なら 1 になる
+            Node::If(x) => {
+                self.integer(&x.condition, locals, context);
+                let then_type = self.infer(&x.then_clause, locals, context);
+                let else_type = match &x.else_clause {
+                    Some(else_clause) => self.infer(else_clause, locals, context),
+                    None => Type::Int,
+                };
+                self.unify(&then_type, &else_type, x.else_clause.as_ref().and_then(|y| y.span()).or(expression.span()), context);
+                then_type
+            },
+            Node::Println(x) => {
+                self.infer(&x.body, locals, context);
+                Type::Int
+            },
+            Node::Call(_) | Node::LabelledCall(_) => {
+                let (name, args): (&str, Vec<&ast::Expression>) = match &node {
+                    Node::Call(x) => (&x.name, x.args.iter().collect()),
+                    Node::LabelledCall(x) => {
+                        // 名前付きの引数は仮引数の順に並べる。足りない引数や余分な引数は checker が報告する
+                        let formals = self.parameters.get(&x.name).cloned().unwrap_or_default();
+                        let args: Vec<&ast::Expression> = formals.iter()
+                            .filter_map(|y| x.args.iter().find(|z| &z.name == y).map(|z| &z.parameter))
+                            .collect();
+                        if args.len() != formals.len() || args.len() != x.args.len() {
+                            for arg in x.args.iter() {
+                                self.infer(&arg.parameter, locals, context);
+                            }
+                            return self.fresh();
+                        }
+                        (&x.name, args)
+                    },
+                    _ => unreachable!(),
+                };
+                let types: Vec<Type> = args.iter().map(|x| self.infer(x, locals, context)).collect();
+                let scheme = match self.functions.get(name) {
+                    Some(x) => x.clone(),
+                    None => return self.fresh(),
+                };
+                match self.instantiate(&scheme) {
+                    Type::Function(parameters, result) if parameters.len() == types.len() => {
+                        for ((parameter, ty), arg) in parameters.iter().zip(types.iter()).zip(args.iter()) {
+                            self.unify(parameter, ty, arg.span().or(expression.span()), context);
+                        }
+                        *result
+                    },
+                    _ => self.fresh(),
+                }
+            },
+            Node::EnvironmentVariable(x) => {
+                if let Some(default) = &x.default {
+                    self.integer(default, locals, context);
+                }
+                Type::Int
+            },
+            Node::Assert(x) => {
+                match &x.right {
+                    Some(right) => {
+                        let left = self.infer(&x.left, locals, context);
+                        let right_type = self.infer(right, locals, context);
+                        self.unify(&left, &right_type, right.span(), context);
+                    },
+                    None => self.integer(&x.left, locals, context),
+                }
+                Type::Int
+            },
+            // 整数のパターンがあれば値は整数に、バリアントのパターンがあればその列挙型になる。各腕の本体は同じ型にそろえる
+            Node::Match(x) => {
+                let value = self.infer(&x.value, locals, context);
+                let mut ty: Option<Type> = None;
+                for arm in x.arms.iter() {
+                    self.pattern(&arm.pattern, &value, locals, expression.span(), context);
+                    if let Some(guard) = &arm.guard {
+                        self.integer(guard, locals, context);
+                    }
+                    let body = self.infer(&arm.body, locals, context);
+                    match &ty {
+                        Some(first) => {
+                            let first = first.clone();
+                            self.unify(&first, &body, arm.body.span(), context);
+                        },
+                        None => ty = Some(body),
+                    }
+                }
+                ty.unwrap_or_else(|| self.fresh())
+            },
+            Node::Field(x) => {
+                let value = self.infer(&x.value, locals, context);
+                self.field(&value, &x.field, x.value.span(), context)
+            },
+            Node::FieldAssignment(x) => {
+                let ty = self.infer(&x.expression, locals, context);
+                let mut target = self.variable(&x.name, locals);
+                for field in x.path.iter() {
+                    target = self.field(&target, field, expression.span(), context);
+                }
+                self.unify(&target, &ty, x.expression.span(), context);
+                ty
+            },
+            Node::Construct(x) => Type::Named(x.enumeration.clone().unwrap_or_else(|| x.name.clone())),
+            // throw は値を返さないので、どの型の値が求められていてもよい
+            Node::Throw(x) => {
+                self.infer(&x.value, locals, context);
+                self.fresh()
+            },
+            // catch の変数には何でも投げられうるので型を決めない。finally の値は使われない
+            Node::Try(x) => {
+                let ty = self.infer(&x.body, locals, context);
+                if let Some(clause) = &x.catch_clause {
+                    let body = self.infer(&clause.body, locals, context);
+                    self.unify(&ty, &body, clause.body.span(), context);
+                }
+                if let Some(finally_clause) = &x.finally_clause {
+                    self.infer(finally_clause, locals, context);
+                }
+                ty
+            },
+        }
+    }
+
+    fn pattern(&mut self, pattern: &ast::Pattern, value: &Type, locals: &HashMap<String, Type>, span: Option<Span>, context: &str) {
+        match pattern {
+            ast::Pattern::Integers(_) => self.unify(&Type::Int, value, span, context),
+            ast::Pattern::Binding { name, .. } => {
+                let variable = self.variable(name, locals);
+                self.unify(&variable, value, span, context);
+            },
+            ast::Pattern::Wildcard => {},
+            // 未定義のコンストラクタや値の数の誤りは checker が報告する
+            ast::Pattern::Constructor { name, arguments } => {
+                let Some((enumeration, payload)) = self.variants.get(name).cloned() else { return };
+                self.unify(&Type::Named(enumeration), value, span, context);
+                for (argument, ty) in arguments.iter().zip(payload.iter()) {
+                    self.pattern(argument, ty, locals, span, context);
+                }
+            },
+        }
+    }
+}
+
+fn annotated(name: &str) -> Type {
+    match name {
+        "Int" => Type::Int,
+        _ => Type::Named(name.to_string()),
+    }
+}
+
+fn substitute(ty: &Type, mapping: &HashMap<usize, Type>) -> Type {
+    match ty {
+        Type::Int | Type::Named(_) => ty.clone(),
+        Type::Variable(x) => mapping.get(x).cloned().unwrap_or_else(|| ty.clone()),
+        Type::Function(parameters, result) => Type::Function(
+            parameters.iter().map(|x| substitute(x, mapping)).collect(),
+            Box::new(substitute(result, mapping)),
+        ),
+    }
+}
+
+// 呼び出しの強連結成分（Tarjan の方法）。呼ばれる側の成分から順に並べる
+fn components<'a>(functions: &[&'a FunctionDefinition]) -> Vec<Vec<&'a FunctionDefinition>> {
+    let index: HashMap<&str, usize> = functions.iter().enumerate().map(|(i, x)| (x.name.as_str(), i)).collect();
+    let edges: Vec<Vec<usize>> = functions.iter().map(|x| {
+        let mut callees = Vec::new();
+        collect_calls(&x.body, &mut callees);
+        callees.iter().filter_map(|y| index.get(y.as_str()).copied()).collect()
+    }).collect();
+    let mut tarjan = Tarjan { edges: &edges, index: vec![None; functions.len()], low: vec![0; functions.len()], stack: Vec::new(), counter: 0, components: Vec::new() };
+    for i in 0..functions.len() {
+        if tarjan.index[i].is_none() {
+            tarjan.visit(i);
+        }
+    }
+    tarjan.components.into_iter().map(|x| x.into_iter().map(|y| functions[y]).collect()).collect()
+}
+
+struct Tarjan<'a> {
+    edges: &'a [Vec<usize>],
+    index: Vec<Option<usize>>,
+    low: Vec<usize>,
+    stack: Vec<usize>,
+    counter: usize,
+    components: Vec<Vec<usize>>,
+}
+impl Tarjan<'_> {
+    fn visit(&mut self, v: usize) {
+        self.index[v] = Some(self.counter);
+        self.low[v] = self.counter;
+        self.counter += 1;
+        self.stack.push(v);
+        for &w in self.edges[v].iter() {
+            match self.index[w] {
+                None => {
+                    self.visit(w);
+                    self.low[v] = self.low[v].min(self.low[w]);
+                },
+                Some(i) if self.stack.contains(&w) => self.low[v] = self.low[v].min(i),
+                Some(_) => {},
+            }
+        }
+        if Some(self.low[v]) == self.index[v] {
+            let mut component = Vec::new();
+            while let Some(w) = self.stack.pop() {
+                component.push(w);
+                if w == v {
+                    break;
+                }
+            }
+            component.reverse();
+            self.components.push(component);
+        }
+    }
+}
+
+fn collect_calls(expression: &ast::Expression, names: &mut Vec<String>) {
+    let node = expression.node();
+    match node {
+        Node::Call(x) => names.push(x.name.clone()),
+        Node::LabelledCall(x) => names.push(x.name.clone()),
+        _ => {},
+    }
+    for child in node.children() {
+        collect_calls(child, names);
+    }
+}
+
+fn collect_assigned(expression: &ast::Expression, names: &mut Vec<String>) {
+    let node = expression.node();
+    let assigned = match &node {
+        Node::Assignment(x) => vec![&x.name],
+        Node::Match(x) => x.bindings(),
+        Node::Try(x) => x.catch_clause.iter().map(|y| &y.name).collect(),
+        _ => vec![],
+    };
+    for name in assigned {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    for child in node.children() {
+        collect_assigned(child, names);
+    }
+}
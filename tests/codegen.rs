@@ -0,0 +1,490 @@
+extern crate toys_in_rust;
+
+use toys_in_rust::codegen;
+use toys_in_rust::parser;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// 実行した結果の (標準出力, 標準エラー出力, 終了コード)
+type Output = (String, String, i32);
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("toys_codegen_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn execute(command: &mut Command) -> Output {
+    let output = command.env_remove("TOYS_LIMIT").output().unwrap();
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+        output.status.code().unwrap(),
+    )
+}
+
+fn interpret(dir: &Path, source: &str, args: &[&str]) -> Output {
+    let file = dir.join("program.toys");
+    fs::write(&file, source).unwrap();
+    execute(Command::new(env!("CARGO_BIN_EXE_toys")).arg("run").arg(&file).args(args))
+}
+
+// ネイティブのプログラムにする変換。生成したソースの拡張子と、変換する関数
+struct Backend {
+    name: &'static str,
+    extension: &'static str,
+    generate: fn(&toys_in_rust::ast::Program) -> Result<String, codegen::CodegenError>,
+}
+
+const C: Backend = Backend { name: "c", extension: "c", generate: codegen::c::generate };
+const X86_64: Backend = Backend { name: "x86_64", extension: "s", generate: codegen::x86_64::generate };
+
+// 変換したソースを cc でコンパイルし、実行する。cc がないか、アセンブリを実行できない環境なら None
+fn native(dir: &Path, source: &str, args: &[&str], backend: &Backend) -> Option<Output> {
+    if backend.extension == "s" && !cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+        return None;
+    }
+    let program = parser::parse(source).unwrap();
+    let code = (backend.generate)(&program).unwrap();
+    let file = dir.join(format!("program.{}", backend.extension));
+    let binary = dir.join(format!("program_{}", backend.name));
+    fs::write(&file, code).unwrap();
+    let compiled = Command::new("cc").arg("-std=c99").arg("-o").arg(&binary).arg(&file).status();
+    match compiled {
+        Ok(status) => assert!(status.success(), "cc failed on {}", file.display()),
+        Err(_) => {
+            eprintln!("cc is not available; skipping");
+            return None;
+        },
+    }
+    Some(execute(Command::new(&binary).args(args)))
+}
+
+// node で ES モジュールを読み込み、toys run と同じように引数を main に渡して返り値を終了コードにする
+const RUNNER: &str = r#"import { main } from "./program.mjs";
+
+const args = process.argv.slice(2);
+const invalid = args.find((x) => !/^[+-]?\d+$/.test(x) || Number(x) < -2147483648 || Number(x) > 2147483647);
+if (invalid !== undefined) {
+    console.error(`error: argument ${invalid} is not an integer`);
+    process.exit(2);
+}
+try {
+    const status = main(...args.map(Number));
+    process.exitCode = status < 0 ? 1 : Math.min(status, 255);
+} catch (e) {
+    console.error(`error: ${e.message}`);
+    process.exitCode = 1;
+}
+"#;
+
+// JavaScript に変換して node で実行する。node がなければ None
+fn javascript(dir: &Path, source: &str, args: &[&str]) -> Option<Output> {
+    let program = parser::parse(source).unwrap();
+    let module = codegen::js::generate(&program, "program.toys").unwrap();
+    fs::write(dir.join("program.mjs"), &module.code).unwrap();
+    fs::write(dir.join("runner.mjs"), RUNNER).unwrap();
+    if Command::new("node").arg("--version").output().is_err() {
+        eprintln!("node is not available; skipping");
+        return None;
+    }
+    Some(execute(Command::new("node").arg(dir.join("runner.mjs")).args(args)))
+}
+
+// 標準出力と終了コードがインタプリタと同じで、エラーのメッセージも同じであることを確かめる
+fn compare(name: &str, source: &str, args: &[&str]) {
+    let dir = scratch(name);
+    let expected = interpret(&dir, source, args);
+    let mut results: Vec<(&str, Output)> = [C, X86_64].iter()
+        .filter_map(|backend| native(&dir, source, args, backend).map(|x| (backend.name, x)))
+        .collect();
+    results.extend(javascript(&dir, source, args).map(|x| ("js", x)));
+    for (backend, actual) in results {
+        let name = format!("{} ({})", name, backend);
+        assert_eq!(expected.0, actual.0, "stdout of {}", name);
+        assert_eq!(expected.2, actual.2, "exit status of {}", name);
+        let message = actual.1.trim_start_matches("error: ").trim_end();
+        assert_eq!(expected.1.is_empty(), actual.1.is_empty(), "stderr of {}: {}", name, actual.1);
+        assert!(expected.1.contains(message), "stderr of {}: {} / {}", name, expected.1, actual.1);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+fn corpus(path: &str) -> String {
+    fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join(path)).unwrap()
+}
+
+#[test]
+fn test_native_samples() {
+    compare("sample", &corpus("sample/sample.toys"), &[]);
+    compare("args", &corpus("sample/args.toys"), &["4"]);
+    compare("args_limit", &corpus("sample/args.toys"), &["5"]);
+    compare("args_missing", &corpus("sample/args.toys"), &[]);
+    compare("args_extra", &corpus("sample/sample.toys"), &["5"]);
+    compare("status_large", "define main(n) { n; }", &["256"]);
+    compare("status_negative", "define main(n) { n; }", &["-1"]);
+    compare("division_by_zero", &corpus("tests/golden/division_by_zero.toys"), &[]);
+    compare("fizzbuzz", &corpus("tests/golden/fizzbuzz.toys"), &[]);
+    compare("status", &corpus("tests/golden/status.toys"), &[]);
+}
+
+#[test]
+fn test_native_control_flow() {
+    compare("control_flow", "
+        define main() {
+            for (i in 1 to 3) {
+                println(i * total);
+            }
+            x = pick(total > 5);
+            println(x);
+            while (x > 97) x = x - 1;
+            println(pick(0));
+            println(never());
+            println(add[b = 2, a = 1]);
+            x;
+        }
+        define pick(c) {
+            if (c) { 100; } else { 200; }
+        }
+        define never() {
+            if (0) 2;
+        }
+        define sum(n) {
+            r = 0;
+            while (n > 0) {
+                r = r + n;
+                n = n - 1;
+            }
+            r;
+        }
+        define add(a, b) {
+            a * 10 + b;
+        }
+        global total = sum(4);
+    ", &[]);
+}
+
+#[test]
+fn test_native_global_assignment() {
+    compare("global_assignment", "
+        global counter = 10;
+        define inc() { counter = counter + 1; }
+        define main() { inc(); println(counter); 0; }
+    ", &[]);
+}
+
+#[test]
+fn test_native_argument_binding() {
+    // 実引数は呼び出し元の同じ名前の変数にも代入される
+    compare("argument_binding", "
+        define fib(n) { if (n < 2) n; else fib(n - 1) + fib(n - 2); }
+        define main() { println(fib(15)); 0; }
+    ", &[]);
+    compare("argument_binding_labelled", "
+        define pick(a, b) { a * 10 + b; }
+        define main() { println(pick[b = 2, a = 5] + a + b); 0; }
+    ", &[]);
+}
+
+// 呼び出し元の環境から名前で読む変数は変換できない
+#[test]
+fn test_dynamic_variables() {
+    let source = "define g() { x; } define f() { x = 7; g(); } define main() { println(f()); 0; }";
+    let dir = scratch("dynamic");
+    assert_eq!(interpret(&dir, source, &[]), ("7\n".to_string(), String::new(), 0));
+    fs::remove_dir_all(&dir).unwrap();
+    let program = parser::parse(source).unwrap();
+    let message = "variable x may be read from the caller's environment, which cannot be compiled; assign it first.";
+    for generate in [C.generate, X86_64.generate, codegen::wat::generate] {
+        let error = generate(&program).unwrap_err();
+        assert_eq!(error.message, message);
+        assert_eq!(error.span.map(|x| (x.line, x.column)), Some((1, 14)));
+    }
+    let error = codegen::js::generate(&program, "program.toys").err().unwrap();
+    assert_eq!(error.message, message);
+}
+
+#[test]
+fn test_native_comparisons() {
+    compare("comparisons", "
+        define main(a, b) {
+            println(a < b);
+            println(a <= b);
+            println(a > b);
+            println(a >= b);
+            println(a == b);
+            println(a != b);
+            println(a / b);
+            println(-7 / 2);
+            println(env(\"TOYS_LIMIT\", 3) + 1);
+            0;
+        }
+    ", &["-7", "3"]);
+}
+
+#[test]
+fn test_native_match() {
+    compare("match", "
+        define main() {
+            for (i in 0 to 12) {
+                println(classify(i));
+            }
+            y = match (sign(-5)) { -1 => 7, _ => 8 };
+            println(y + match (y) { 7 => 1, _ => 0 });
+            match (y) {
+                8 => { println(k); },
+                k => k,
+            }
+            match (y * 3) {
+                1 | 2 => 0,
+            }
+        }
+        define classify(n) {
+            r = match (n) {
+                0 => 100,
+                1 | 2 => {
+                    x = n * 10;
+                    x + 1;
+                },
+                k if k > 10 => k * 2,
+                k if k == 5 => match (k) { 5 => 55 },
+                _ => -1,
+            };
+            r;
+        }
+        define sign(n) {
+            match (n) {
+                m if m < 0 => -1,
+                0 => 0,
+                _ => 1,
+            }
+        }
+    ", &[]);
+    compare("match_guard", "
+        define main() {
+            n = 5;
+            println(match (3) { n if n > 10 => 1, _ => n });
+            println(f(3));
+            println(f(12));
+            n;
+        }
+        define f(m) {
+            n = 5;
+            r = match (m) { n if n > 10 => 1, _ => n };
+            r * 100 + n;
+        }
+    ", &[]);
+    compare("match_minimum", "define main() { match (0 - 2147483647 - 1) { 0 => 1 } }", &[]);
+}
+
+#[test]
+fn test_native_runtime_errors() {
+    compare("overflow", "define main() { println(1); x = 2147483647; x + 1; }", &[]);
+    compare("division", "define main() { d = 0; 10 / d; }", &[]);
+    compare("undefined", "define main() { if (0) y = 1; y; }", &[]);
+    compare("arity", "define main(n) { n; }", &[]);
+    compare("assert", "define main() { assert_eq(1 + 1, 3); }", &[]);
+    compare("minimum", "define main() { x = 0 - 2147483647 - 1; println(x); x / -1; }", &[]);
+    compare("invalid_argument", "define main(n) { n; }", &["x"]);
+}
+
+#[test]
+fn test_c_errors() {
+    let program = parser::parse("define main() { f(1); }").unwrap();
+    let error = codegen::c::generate(&program).unwrap_err();
+    assert_eq!(error.message, "function f not found.");
+    assert_eq!(error.span.map(|x| (x.line, x.column)), Some((1, 17)));
+
+    let program = parser::parse("define f() { 1; }").unwrap();
+    assert!(codegen::c::generate(&program).is_err());
+}
+
+// wasm の実行環境は依存に含まれないので、出力したモジュールの構造を確かめる
+fn wat(source: &str) -> String {
+    let text = codegen::wat::generate(&parser::parse(source).unwrap()).unwrap();
+    let mut depth = 0;
+    for c in text.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {},
+        }
+        assert!(depth >= 0, "{}", text);
+    }
+    assert_eq!(depth, 0, "{}", text);
+    text
+}
+
+#[test]
+fn test_wat_module() {
+    let text = wat(&corpus("sample/sample.toys"));
+    assert!(text.starts_with("(module\n"));
+    assert!(text.contains("(import \"toys\" \"println\" (func $println (param i32)))"));
+    assert!(text.contains("(import \"toys\" \"fail\" (func $fail (param i32 i32)))"));
+    assert!(text.contains("(memory (export \"memory\") 1)"));
+    assert!(text.contains("(func $f.main (result i32)"));
+    assert!(text.contains("(func $f.fact (param $a.n i32) (result i32)\n    (local $v.n i64)"));
+    assert!(text.contains("call $f.fact\n"));
+    assert!(text.contains("(func (export \"main\") (result i32)\n    call $initialize\n    call $f.main)"));
+    assert!(text.contains("undefined variable: n"));
+}
+
+#[test]
+fn test_wat_globals() {
+    let text = wat("
+        global limit = 10;
+        define main(n) {
+            count = 0;
+            while (count < limit) count = count + n;
+            println(env(\"STEP\", 1));
+            count;
+        }
+        define twice(x) {
+            y = x * 2;
+            y;
+        }
+    ");
+    for name in ["limit", "n", "count"] {
+        assert!(text.contains(&format!("(global $g.{} (mut i64) (i64.const -9223372036854775808))", name)), "{}", text);
+    }
+    assert!(text.contains("local.get $a.n\n    i64.extend_i32_s\n    global.set $g.n"), "{}", text);
+    assert!(text.contains("(local $v.x i64)\n    (local $v.y i64)"), "{}", text);
+    assert!(text.contains("(func $initialize\n"), "{}", text);
+    assert!(text.contains("i64.extend_i32_s\n    global.set $g.limit"), "{}", text);
+    assert!(text.contains("(func (export \"main\") (param i32) (result i32)\n    call $initialize\n    local.get 0\n    call $f.main)"), "{}", text);
+    assert!(text.contains("    block\n      loop\n"), "{}", text);
+    assert!(text.contains("call $env"), "{}", text);
+    assert!(text.contains("STEP"), "{}", text);
+}
+
+#[test]
+fn test_wat_match() {
+    let text = wat("define main() { f(3); } define f(n) { match (n) { 1 | 2 => 0, k if k > 5 => k, _ => 1 }; }");
+    assert!(text.contains("(local $v.k i64)\n    (local $tmp i32)\n    (local $env i64)\n    (local $m.0 i32)\n"), "{}", text);
+    assert!(text.contains("(local $m.0 i32)\n    (local $s.0 i64)\n"), "{}", text);
+    assert!(text.contains("local.get $v.k\n        local.set $s.0\n        local.get $m.0\n        i64.extend_i32_s\n        local.set $v.k\n"), "{}", text);
+    // ガードが偽なら変数を前の値に戻して次の腕へ
+    assert!(text.contains("i32.eqz\n        if\n          local.get $s.0\n          local.set $v.k\n          br 1\n        end\n"), "{}", text);
+    assert!(text.contains("      local.get $m.0\n      call $no_match\n      unreachable\n"), "{}", text);
+    assert!(text.contains("(func $no_match (param $value i32)"), "{}", text);
+    assert!(text.contains("no match arm for value "), "{}", text);
+    assert!(!wat("define main() { 0; }").contains("$no_match"));
+}
+
+#[test]
+fn test_wat_errors() {
+    let program = parser::parse("define main() { g[x = 1]; } define g(x, y) { x; }").unwrap();
+    let error = codegen::wat::generate(&program).unwrap_err();
+    assert_eq!(error.message, "argument y of function g is not given.");
+}
+
+fn javascript_module(source: &str) -> codegen::js::Module {
+    codegen::js::generate(&parser::parse(source).unwrap(), "program.toys").unwrap()
+}
+
+#[test]
+fn test_js_module() {
+    let code = javascript_module(&corpus("sample/sample.toys")).code;
+    assert!(code.contains("export function main() {\n    console.log(fact(n = 5));\n    return 0;\n}"), "{}", code);
+    assert!(code.contains("// Returns n! computed recursively.\nexport function fact(n) {"), "{}", code);
+    assert!(code.contains("return $mul(n, fact(n = $sub(n, 1)));"), "{}", code);
+    assert!(!code.contains("function $div"), "{}", code);
+
+    let code = javascript_module("
+        global limit = 10;
+        define main(n) {
+            count = 0;
+            while (count < limit) count = count + n;
+            println(power[exponent = 3, base = count]);
+            count;
+        }
+        define power(base, exponent) {
+            if (exponent == 0) 1; else base * power(base, exponent - 1);
+        }
+        define new(x) {
+            y = x + 1;
+            y;
+        }
+    ").code;
+    assert!(code.contains("let n, count, base, exponent;"), "{}", code);
+    assert!(code.contains("export let limit = 10;"), "{}", code);
+    assert!(code.contains("export function main($n = 0) {\n    n = $n;"), "{}", code);
+    assert!(code.contains("power(...[base, exponent] = [count, 3])"), "{}", code);
+    assert!(code.contains("power(...[base, exponent] = [base, $sub(exponent, 1)])"), "{}", code);
+    assert!(code.contains("export function new_(x) {\n    let y;"), "{}", code);
+
+    let code = javascript_module("define main() { if (0) y = 1; y; }").code;
+    assert!(code.contains("return $read(y, \"y\");"), "{}", code);
+}
+
+// mappings を (出力の行, 元の行, 元の列) に戻す。値はどれも 0 から数える
+fn decode_mappings(mappings: &str) -> Vec<(usize, i64, i64)> {
+    let digits = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut result = Vec::new();
+    let mut position = (0, 0);
+    for (line, segments) in mappings.split(';').enumerate() {
+        for segment in segments.split(',').filter(|x| !x.is_empty()) {
+            let mut values = Vec::new();
+            let (mut value, mut shift) = (0, 0);
+            for c in segment.chars() {
+                let digit = digits.find(c).unwrap() as i64;
+                value += (digit & 31) << shift;
+                shift += 5;
+                if digit & 32 == 0 {
+                    values.push(if value & 1 == 1 { -(value >> 1) } else { value >> 1 });
+                    value = 0;
+                    shift = 0;
+                }
+            }
+            assert_eq!(values.len(), 4, "{}", segment);
+            position = (position.0 + values[2], position.1 + values[3]);
+            result.push((line, position.0, position.1));
+        }
+    }
+    result
+}
+
+#[test]
+fn test_js_source_map() {
+    let source = corpus("sample/sample.toys");
+    let module = javascript_module(&source);
+    let map = module.source_map("program.mjs", &source);
+    assert_eq!(map.get("version").and_then(|x| x.as_i64()), Some(3));
+    assert_eq!(map.get("sources").and_then(|x| x.as_array()).map(|x| x[0].as_str()), Some(Some("program.toys")));
+    let mappings = decode_mappings(map.get("mappings").and_then(|x| x.as_str()).unwrap());
+    let lines: Vec<&str> = module.code.lines().collect();
+    assert_eq!(map.get("mappings").unwrap().as_str().unwrap().split(';').count(), lines.len());
+    // 生成した行から、その行を生成した toys の行を引く
+    let origin = |text: &str| {
+        let line = lines.iter().position(|x| x.contains(text)).unwrap();
+        mappings.iter().find(|x| x.0 == line).map(|x| x.1 + 1)
+    };
+    assert_eq!(origin("export function main"), Some(1));
+    assert_eq!(origin("console.log(fact(n = 5))"), Some(2));
+    assert_eq!(origin("return 0;"), Some(3));
+    assert_eq!(origin("export function fact"), Some(5));
+    assert_eq!(origin("if (n < 2)"), Some(7));
+    assert_eq!(origin("return $mul"), Some(10));
+    assert_eq!(origin("function $check"), None);
+    assert!(mappings.iter().all(|x| x.2 >= 0));
+}
+
+#[test]
+fn test_build_command() {
+    let file = Path::new(env!("CARGO_MANIFEST_DIR")).join("sample/sample.toys");
+    let output = Command::new(env!("CARGO_BIN_EXE_toys")).args(["build", "--target", "wat"]).arg(&file).output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().starts_with("(module"));
+    let dir = scratch("build");
+    let output = dir.join("sample.mjs");
+    let status = Command::new(env!("CARGO_BIN_EXE_toys")).args(["build", "--target", "js", "-o"]).arg(&output).arg(&file).status().unwrap();
+    assert!(status.success());
+    assert!(fs::read_to_string(&output).unwrap().ends_with("//# sourceMappingURL=sample.mjs.map\n"));
+    let map = fs::read_to_string(dir.join("sample.mjs.map")).unwrap();
+    assert!(map.contains("\"file\":\"sample.mjs\""), "{}", map);
+    fs::remove_dir_all(&dir).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_toys")).args(["build", "--target", "java"]).arg(&file).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
@@ -0,0 +1,96 @@
+extern crate toys_in_rust;
+
+use toys_in_rust::ast::ErrorKind;
+use toys_in_rust::debugger::{debug, Breakpoint};
+use toys_in_rust::parser;
+
+use std::io::Cursor;
+
+const SOURCE: &str = "global limit = 10;
+define main() {
+    x = fact(3);
+    x + 1;
+}
+define fact(n) {
+    if (n < 2) {
+        1;
+    } else {
+        r = n * fact(n - 1);
+        r;
+    }
+}
+";
+
+// コマンドを順に与えてデバッガの下で実行し、main の返り値とデバッガの出力を返す
+fn session(source: &str, breakpoints: Vec<Breakpoint>, commands: &str) -> (Result<i32, ErrorKind>, String) {
+    let program = parser::parse(source).unwrap();
+    let (result, output) = debug(&program, source, vec![], breakpoints, Cursor::new(commands.to_string()), Vec::new());
+    (result.map_err(|e| e.kind), String::from_utf8(output).unwrap())
+}
+
+// 止まった場所の行（"fact at line 8" など、ソースの抜粋が続く行）だけを取り出す
+fn stops(output: &str) -> Vec<&str> {
+    let lines: Vec<&str> = output.split("(toys) ").flat_map(|x| x.lines()).collect();
+    lines.windows(2).filter(|x| x[0].contains(" at line ") && x[1].contains(" | ")).map(|x| x[0]).collect()
+}
+
+#[test]
+fn test_step_into_over_and_out() {
+    let (result, output) = session(SOURCE, vec![], "s\ns\ns\nn\nout\nn\nn\n");
+    assert_eq!(result, Ok(7));
+    assert_eq!(stops(&output), vec![
+        "main at line 3",
+        "fact at line 7",
+        "fact at line 10",
+        "fact at line 7",
+        "fact at line 10",
+        "fact at line 11",
+        "main at line 4",
+    ]);
+    assert!(output.contains("fact returned 2\nfact at line 11\n"));
+    assert!(output.ends_with("program exited with 7\n"));
+}
+
+#[test]
+fn test_breakpoints() {
+    let (result, output) = session(SOURCE, vec![Breakpoint::Line(8)], "c\nbt\nd 8\nb 4\nbreakpoints\nc\nc\n");
+    assert_eq!(result, Ok(7));
+    assert_eq!(stops(&output), vec!["main at line 3", "fact at line 8", "main at line 4"]);
+    assert!(output.contains("#0 fact(n = 1) at line 8\n#1 fact(n = 2) at line 10\n#2 fact(n = 3) at line 10\n#3 main() at line 3\n"));
+    assert!(output.contains("deleted breakpoint at line 8\n"));
+    assert!(output.contains("breakpoint at line 4\n(toys) line 4\n"));
+
+    // 関数のブレークポイントではその関数の最初の文で止まる
+    let (_, output) = session(SOURCE, vec![], "b fact\nc\nc\n");
+    assert_eq!(stops(&output), vec!["main at line 3", "fact at line 7", "fact at line 7"]);
+}
+
+#[test]
+fn test_environment_and_print() {
+    let (result, output) = session(SOURCE, vec![Breakpoint::Line(11)], "c\nenv\np n * 100 + r\np limit = limit + 1\np fact(4)\np 1 +\nq\n");
+    assert_eq!(result, Err(ErrorKind::Interrupted));
+    // 実引数は呼び出し元の変数にも代入されるので、n は内側の呼び出しの値になっている
+    assert!(output.contains("fact: n = 1, r = 2\nnext global: limit = 10, x = <unset>, n = 3\n"));
+    assert!(output.contains("= 102\n"));
+    assert!(output.contains("= 11\n"));
+    assert!(output.contains("= 24\n"));
+    assert!(output.contains("(toys) error: "));
+    assert!(output.ends_with("program stopped: execution stopped by the debugger.\n"));
+}
+
+#[test]
+fn test_stop_on_error() {
+    let source = "define main() {\n    d = 0;\n    10 / d;\n}\n";
+    let (result, output) = session(source, vec![], "c\nenv\nc\n");
+    assert_eq!(result, Err(ErrorKind::DivisionByZero));
+    assert!(output.contains("error: division by zero: 10 / 0\nmain at line 3\n   3 |     10 / d;\n"));
+    assert!(output.contains("global: d = 0\n"));
+}
+
+#[test]
+fn test_end_of_input() {
+    // コマンドが尽きたらブレークポイントを無視して最後まで実行する
+    let (result, output) = session(SOURCE, vec![Breakpoint::Line(8)], "");
+    assert_eq!(result, Ok(7));
+    assert_eq!(stops(&output), vec!["main at line 3"]);
+}
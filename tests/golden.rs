@@ -0,0 +1,95 @@
+extern crate toys_in_rust;
+
+use toys_in_rust::golden::{report, Harness, Verdict};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn harness() -> Harness {
+    Harness::new(env!("CARGO_BIN_EXE_toys"))
+}
+
+// 期待が合わなければ差分を表示して失敗する。期待を更新するには toys golden --bless <dir> を使う
+fn check_dir(dir: &str) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(dir);
+    let results = harness().run_dir(&dir).unwrap();
+    assert!(!results.is_empty());
+    assert!(results.iter().all(|x| x.verdict == Verdict::Passed), "{}", report(&results));
+}
+
+#[test]
+fn test_golden_sample() {
+    check_dir("sample");
+}
+
+#[test]
+fn test_golden_corpus() {
+    check_dir("tests/golden");
+}
+
+// テストごとに別のディレクトリを使う
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("toys_golden_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    dir
+}
+
+#[test]
+fn test_golden_bless() {
+    let dir = scratch("bless");
+    let program = dir.join("nested").join("answer.toys");
+    fs::write(&program, "define main() { println(42); 3; }").unwrap();
+    fs::write(dir.join("nested").join("answer.err"), "stale\n").unwrap();
+
+    let results = harness().run_dir(&dir).unwrap();
+    assert_eq!(results.len(), 1);
+    match &results[0].verdict {
+        Verdict::Failed(diff) => {
+            assert!(diff.contains("stdout differs"), "{}", diff);
+            assert!(diff.contains("+ 42"), "{}", diff);
+            assert!(diff.contains("- stale"), "{}", diff);
+            assert!(diff.contains("+ [exit status: 3]"), "{}", diff);
+        },
+        x => panic!("unexpected verdict {:?}", x),
+    }
+
+    let results = harness().bless(true).run_dir(&dir).unwrap();
+    assert_eq!(results[0].verdict, Verdict::Blessed);
+    assert_eq!(fs::read_to_string(program.with_extension("out")).unwrap(), "42\n");
+    assert_eq!(fs::read_to_string(program.with_extension("err")).unwrap(), "[exit status: 3]\n");
+    assert_eq!(harness().run_dir(&dir).unwrap()[0].verdict, Verdict::Passed);
+
+    // 0 で終了するようになれば .err は消える
+    fs::write(&program, "define main() { println(42); 0; }").unwrap();
+    harness().bless(true).run_dir(&dir).unwrap();
+    assert!(!program.with_extension("err").exists());
+    assert_eq!(harness().run_dir(&dir).unwrap()[0].verdict, Verdict::Passed);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_golden_args_and_errors() {
+    let dir = scratch("args");
+    let program = dir.join("twice.toys");
+    fs::write(&program, "define main(n) {\n    println(n * 2);\n    n / 0;\n}").unwrap();
+    fs::write(dir.join("twice.args"), "21\n").unwrap();
+    let outcome = harness().run(&program).unwrap();
+    assert_eq!(outcome.stdout, "42\n");
+    assert!(outcome.stderr.starts_with("twice.toys:"), "{}", outcome.stderr);
+    assert_eq!(outcome.status, 1);
+    assert!(outcome.errors().ends_with("[exit status: 1]\n"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_golden_without_main() {
+    let dir = scratch("tests");
+    let program = dir.join("checks.toys");
+    fs::write(&program, "define twice(n) { n * 2; }\ntest \"twice\" { assert_eq(twice(2), 5); }").unwrap();
+    let outcome = harness().run(&program).unwrap();
+    assert!(outcome.stdout.contains("test twice ... FAILED"), "{}", outcome.stdout);
+    assert!(!outcome.stderr.contains("main function"), "{}", outcome.stderr);
+    assert_eq!(outcome.status, 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
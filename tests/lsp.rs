@@ -0,0 +1,208 @@
+extern crate toys_in_rust;
+
+use toys_in_rust::json::Json;
+use toys_in_rust::lsp::{read_message, serve, write_message};
+
+use std::io::Cursor;
+
+const URI: &str = "file:///tmp/toys-lsp-test/main.toys";
+
+const SOURCE: &str = "/// Multiplies two numbers.
+define mul(n, m) {
+    n * m;
+}
+global limit = 10;
+define main() {
+    mul[n = 6, m = limit];
+}
+";
+
+// メッセージを Content-Length 付きで並べた入力を serve に渡し、送り返されたメッセージと終了コードを返す
+fn session(messages: &[Json]) -> (Vec<Json>, i32) {
+    let mut input = Vec::new();
+    for message in messages {
+        write_message(&mut input, message).unwrap();
+    }
+    let mut output = Vec::new();
+    let code = serve(Cursor::new(input), &mut output).unwrap();
+    let mut reader = Cursor::new(output);
+    let mut replies = Vec::new();
+    while let Some(body) = read_message(&mut reader).unwrap() {
+        replies.push(Json::parse(&body).unwrap());
+    }
+    (replies, code)
+}
+
+fn request(id: i32, method: &str, params: Json) -> Json {
+    Json::object(vec![("jsonrpc", "2.0".into()), ("id", id.into()), ("method", method.into()), ("params", params)])
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    Json::object(vec![("jsonrpc", "2.0".into()), ("method", method.into()), ("params", params)])
+}
+
+fn open(text: &str) -> Json {
+    notification("textDocument/didOpen", Json::object(vec![("textDocument", Json::object(vec![
+        ("uri", URI.into()),
+        ("languageId", "toys".into()),
+        ("version", 1.into()),
+        ("text", text.into()),
+    ]))]))
+}
+
+fn at(line: i32, character: i32) -> Json {
+    Json::object(vec![
+        ("textDocument", Json::object(vec![("uri", URI.into())])),
+        ("position", Json::object(vec![("line", line.into()), ("character", character.into())])),
+    ])
+}
+
+fn result(replies: &[Json], id: i32) -> &Json {
+    let reply = replies.iter().find(|x| x.get("id").and_then(|y| y.as_i64()) == Some(id as i64)).unwrap();
+    reply.get("result").unwrap()
+}
+
+fn position(json: &Json) -> (i64, i64) {
+    (json.get("line").unwrap().as_i64().unwrap(), json.get("character").unwrap().as_i64().unwrap())
+}
+
+fn labels(items: &Json) -> Vec<&str> {
+    items.as_array().unwrap().iter().map(|x| x.get("label").unwrap().as_str().unwrap()).collect()
+}
+
+fn shutdown(id: i32) -> Vec<Json> {
+    vec![request(id, "shutdown", Json::Null), notification("exit", Json::Null)]
+}
+
+#[test]
+fn test_initialize_and_shutdown() {
+    let mut messages = vec![request(1, "initialize", Json::object(vec![]))];
+    messages.push(request(2, "textDocument/unknown", Json::object(vec![])));
+    messages.extend(shutdown(3));
+    let (replies, code) = session(&messages);
+    let capabilities = result(&replies, 1).get("capabilities").unwrap();
+    assert_eq!(capabilities.get("hoverProvider"), Some(&Json::Bool(true)));
+    assert_eq!(capabilities.get("textDocumentSync").unwrap().as_i64(), Some(1));
+    let error = replies[1].get("error").unwrap();
+    assert_eq!(error.get("code").unwrap().as_i64(), Some(-32601));
+    assert_eq!(result(&replies, 3), &Json::Null);
+    assert_eq!(code, 0);
+
+    // shutdown せずに exit したときは 1 で終わる
+    let (_, code) = session(&[notification("exit", Json::Null)]);
+    assert_eq!(code, 1);
+}
+
+#[test]
+fn test_diagnostics() {
+    let (replies, _) = session(&[
+        open("define main() {\n    f(1);\n    y;\n}\ndefine f(a, b) { a; }\n"),
+        notification("textDocument/didChange", Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", URI.into()), ("version", 2.into())])),
+            ("contentChanges", vec![Json::object(vec![("text", "define main() {\n    1 +;\n}\n".into())])].into()),
+        ])),
+        notification("textDocument/didChange", Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", URI.into()), ("version", 3.into())])),
+            ("contentChanges", vec![Json::object(vec![("text", SOURCE.into())])].into()),
+        ])),
+    ]);
+    let diagnostics: Vec<&Vec<Json>> = replies.iter().map(|x| {
+        assert_eq!(x.get("method").unwrap().as_str(), Some("textDocument/publishDiagnostics"));
+        x.get("params").unwrap().get("diagnostics").unwrap().as_array().unwrap()
+    }).collect();
+
+    let messages: Vec<&str> = diagnostics[0].iter().map(|x| x.get("message").unwrap().as_str().unwrap()).collect();
+    assert_eq!(messages, vec!["function f expects 2 arguments but 1 given in main", "undefined variable y in main"]);
+    let range = diagnostics[0][0].get("range").unwrap();
+    assert_eq!(position(range.get("start").unwrap()), (1, 4));
+    assert_eq!(position(range.get("end").unwrap()), (1, 8));
+
+    assert_eq!(diagnostics[1].len(), 1);
+    assert_eq!(position(diagnostics[1][0].get("range").unwrap().get("start").unwrap()), (1, 7));
+    assert!(diagnostics[1][0].get("message").unwrap().as_str().unwrap().starts_with("expected"));
+
+    assert!(diagnostics[2].is_empty());
+}
+
+#[test]
+fn test_definition_and_hover() {
+    let mut messages = vec![
+        open(SOURCE),
+        request(1, "textDocument/definition", at(6, 5)),
+        request(2, "textDocument/definition", at(6, 20)),
+        request(3, "textDocument/hover", at(6, 4)),
+        request(4, "textDocument/definition", at(6, 12)),
+    ];
+    messages.extend(shutdown(5));
+    let (replies, _) = session(&messages);
+
+    let mul = result(&replies, 1);
+    assert_eq!(mul.get("uri").unwrap().as_str(), Some(URI));
+    assert_eq!(position(mul.get("range").unwrap().get("start").unwrap()), (1, 7));
+    assert_eq!(position(mul.get("range").unwrap().get("end").unwrap()), (1, 10));
+    let limit = result(&replies, 2);
+    assert_eq!(position(limit.get("range").unwrap().get("start").unwrap()), (4, 7));
+
+    let hover = result(&replies, 3).get("contents").unwrap().get("value").unwrap().as_str().unwrap();
+    assert_eq!(hover, "```toys\ndefine mul(n, m)\n```\n\nMultiplies two numbers.");
+    assert_eq!(result(&replies, 4), &Json::Null);
+}
+
+#[test]
+fn test_document_symbols() {
+    let mut messages = vec![
+        open(SOURCE),
+        request(1, "textDocument/documentSymbol", Json::object(vec![("textDocument", Json::object(vec![("uri", URI.into())]))])),
+    ];
+    messages.extend(shutdown(2));
+    let (replies, _) = session(&messages);
+    let symbols = result(&replies, 1).as_array().unwrap();
+    let names: Vec<(&str, i64)> = symbols.iter()
+        .map(|x| (x.get("name").unwrap().as_str().unwrap(), x.get("kind").unwrap().as_i64().unwrap()))
+        .collect();
+    assert_eq!(names, vec![("mul", 12), ("limit", 13), ("main", 12)]);
+    assert_eq!(symbols[0].get("detail").unwrap().as_str(), Some("define mul(n, m)"));
+    assert_eq!(position(symbols[0].get("range").unwrap().get("start").unwrap()), (0, 0));
+    assert_eq!(position(symbols[0].get("range").unwrap().get("end").unwrap()), (3, 1));
+}
+
+#[test]
+fn test_completion() {
+    // 入力途中で構文エラーがあっても、最後に解析できたときの定義で補完する
+    let editing = SOURCE.replace("mul[n = 6, m = limit];", "mul[n = 6, ");
+    let mut messages = vec![
+        open(SOURCE),
+        request(1, "textDocument/completion", at(6, 4)),
+        notification("textDocument/didChange", Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", URI.into()), ("version", 2.into())])),
+            ("contentChanges", vec![Json::object(vec![("text", editing.as_str().into())])].into()),
+        ])),
+        request(2, "textDocument/completion", at(6, 15)),
+    ];
+    messages.extend(shutdown(3));
+    let (replies, _) = session(&messages);
+
+    let items = labels(result(&replies, 1));
+    assert_eq!(&items[..3], &["mul", "limit", "main"]);
+    assert!(items.contains(&"define") && items.contains(&"println"));
+    assert!(["test", "assert", "assert_eq"].iter().all(|x| items.contains(x)));
+
+    let labelled = result(&replies, 2);
+    assert_eq!(labels(labelled), vec!["n", "m"]);
+    assert_eq!(labelled.as_array().unwrap()[1].get("insertText").unwrap().as_str(), Some("m = "));
+}
+
+#[test]
+fn test_hover_type_annotations() {
+    let source = "define twice(n: Int, label): Int {\n    n * 2;\n}\nglobal limit: Int = 10;\ndefine main() {\n    twice(limit, 0);\n}\n";
+    let mut messages = vec![
+        open(source),
+        request(1, "textDocument/hover", at(5, 5)),
+        request(2, "textDocument/hover", at(5, 12)),
+    ];
+    messages.extend(shutdown(3));
+    let (replies, _) = session(&messages);
+    let hover = |id| result(&replies, id).get("contents").unwrap().get("value").unwrap().as_str().unwrap().to_string();
+    assert_eq!(hover(1), "```toys\ndefine twice(n: Int, label): Int\n```");
+    assert_eq!(hover(2), "```toys\nglobal limit: Int\n```");
+}
@@ -4,6 +4,26 @@ use toys_in_rust::parser::*;
 use toys_in_rust::interpreter::Interpreter;
 use toys_in_rust::execute_program;
 use toys_in_rust::execute_lines;
+use toys_in_rust::{execute_program_with, run_lines, run_program, run_program_with_args, Error, Options};
+use toys_in_rust::optimizer::{optimize, optimize_expression};
+use toys_in_rust::ast::{Definition, ErrorKind, Node, Slot, Span};
+use toys_in_rust::serialize::{from_json, to_json, to_sexpr};
+use toys_in_rust::resolver::Resolver;
+use toys_in_rust::ast::{Comment, CommentKind};
+use toys_in_rust::module::{Linker, MemoryLoader, ModuleError};
+use toys_in_rust::checker::{check_program, Severity};
+use toys_in_rust::formatter::format;
+use toys_in_rust::tracer::Tracer;
+use toys_in_rust::profiler::Profiler;
+use toys_in_rust::coverage::{BranchKind, Coverage};
+use toys_in_rust::testing::{report, run_tests};
+use toys_in_rust::codegen;
+use toys_in_rust::typeck;
+use toys_in_rust::printer::dump_program;
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::rc::Rc;
 
 use std::collections::LinkedList;
 use rstest::rstest;
@@ -18,12 +38,1079 @@ mod tests {
             println(1+2+3+4+5);
             1+2+3+4+5;
         "#.to_string();
-        let parsed = parse_lines(&program);
+        let parsed = parse_lines(&program).unwrap();
         let i = Interpreter::new();
-        let results: LinkedList<i32> = parsed.iter().map(|x| -> i32 { i.interpret(x)} ).collect();
-        let result = results.into_iter().last();
+        let results: LinkedList<i32> = parsed.iter().map(|x| -> i32 { i.interpret(x).unwrap() }).collect();
+        let result = results.into_iter().next_back();
         assert_eq!(15, result.unwrap());
     }
+    #[test]
+    fn test_comments_as_trivia() {
+        let program = r#"
+            /// Computes n!.
+            /// Recursive version.
+            define fact(n) {
+                // base case
+                if (n < 2) 1; else n * fact(n - 1); /* a /* b */ */
+            }
+        "#.to_string();
+        let comments: Vec<Comment> = parse_comments(&program).unwrap().into_iter().collect();
+        assert_eq!(comments.iter().map(|x| x.kind).collect::<Vec<CommentKind>>(),
+            vec![CommentKind::Doc, CommentKind::Doc, CommentKind::Line, CommentKind::Block]);
+        assert_eq!(comments[2].text, "// base case");
+        assert_eq!((comments[2].line, comments[2].column), (5, 17));
+        assert_eq!(comments[3].text, "/* a /* b */ */");
+
+        let parsed = parse(&program).unwrap();
+        let fact = parsed.definitions.front().unwrap();
+        assert_eq!(fact.doc(), Some("Computes n!.\nRecursive version."));
+    }
+    #[rstest]
+    #[case::in_body("define main() {\n    /// note\n    1;\n}\n")]
+    #[case::after_statement("define main() {\n    println(1); ///\n    1;\n}\n")]
+    #[case::after_last_definition("define main() {\n    1;\n}\n\n/// trailing\n")]
+    #[case::before_test("define main() {\n    1;\n}\n\n/// about the test\ntest \"one\" {\n    assert_eq(main(), 1);\n}\n")]
+    fn test_stray_doc_comments(#[case] program: &str) {
+        let parsed = parse(program).unwrap();
+        assert_eq!(parsed.definitions.front().unwrap().doc(), None);
+        assert_eq!(Interpreter::new().call_main(&parsed).unwrap(), 1);
+
+        let comments: Vec<Comment> = parse_comments(program).unwrap().into_iter().collect();
+        assert_eq!(comments.iter().map(|x| x.kind).collect::<Vec<CommentKind>>(), vec![CommentKind::Line]);
+        assert_eq!(format(program).unwrap(), program);
+    }
+    fn execute_modules(files: &[(&str, &str)], entry: &str) -> Result<i32, ModuleError> {
+        let mut loader = MemoryLoader::new();
+        for (path, contents) in files {
+            loader.add_file(path, contents);
+        }
+        let mut linker = Linker::new(loader);
+        linker.add_search_path("lib");
+        let program = linker.link_file(Path::new(entry))?;
+        Ok(Interpreter::new().call_main(&program).unwrap())
+    }
+    #[test]
+    fn test_import() {
+        let main = r#"
+            import "math/fact.toys";
+            import counter;
+            define main() {
+                fact.fact(5) + fact.offset + counter.twice(fact.offset);
+            }
+        "#;
+        let fact = r#"
+            import "../lib/counter.toys";
+            global offset = 3;
+            define fact(n) {
+                if (n < 2) 1; else n * fact(n - 1);
+            }
+        "#;
+        let counter = r#"
+            global offset = 100;
+            define twice(offset) {
+                offset * 2;
+            }
+        "#;
+        let files = [("src/main.toys", main), ("src/math/fact.toys", fact), ("lib/counter.toys", counter)];
+        assert_eq!(execute_modules(&files, "src/main.toys"), Ok(129));
+    }
+    #[test]
+    fn test_import_errors() {
+        let a = r#"import b; define main() { 0; }"#;
+        let b = r#"import "./a.toys"; define f() { 0; }"#;
+        assert_eq!(
+            execute_modules(&[("a.toys", a), ("b.toys", b)], "a.toys"),
+            Err(ModuleError::Cycle(vec![PathBuf::from("a.toys"), PathBuf::from("b.toys"), PathBuf::from("a.toys")]))
+        );
+        assert_eq!(
+            execute_modules(&[("a.toys", a)], "a.toys"),
+            Err(ModuleError::NotFound("b".to_string()))
+        );
+    }
+    #[test]
+    fn test_constant_folding() {
+        let lines = parse_lines(r#"
+            1+2+3+4+5;
+            (2 * 3 < 7) == 1;
+            if (1 > 2) { 10; } else { 20; }
+            while (0) { x = 1; }
+            { a = 1; { b = 2; { 3; } } }
+            x = 1 / 0;
+        "#).unwrap();
+        let optimized: Vec<_> = lines.iter().map(optimize_expression).collect();
+        let constant = |e: &toys_in_rust::ast::Expression| match e.node() {
+            Node::Integer(x) => Some(x.value),
+            _ => None,
+        };
+        assert_eq!(constant(&optimized[0]), Some(15));
+        assert_eq!(constant(&optimized[1]), Some(1));
+        assert_eq!(constant(&optimized[2]), Some(20));
+        assert_eq!(constant(&optimized[3]), Some(1));
+        match optimized[4].node() {
+            Node::Block(x) => assert_eq!(x.elements.len(), 3),
+            _ => panic!("block expected"),
+        }
+        match optimized[5].node() {
+            Node::Assignment(x) => assert!(matches!(x.expression.node(), Node::Binary(_))),
+            _ => panic!("assignment expected"),
+        }
+    }
+    #[test]
+    fn test_for_in_desugaring_is_flattened() {
+        let program = optimize(&parse(r#"
+            define main() {
+                for (i in 1 to 2+3) {
+                    x = i;
+                }
+            }
+        "#).unwrap());
+        let main = match program.definitions.front().unwrap().definition() {
+            Definition::Function(x) => x,
+            _ => unreachable!(),
+        };
+        let elements = match main.body.node() {
+            Node::Block(x) => &x.elements,
+            _ => panic!("block expected"),
+        };
+        let loop_body = match elements.back().unwrap().node() {
+            Node::While(x) => {
+                match x.condition.node() {
+                    Node::Binary(c) => assert!(matches!(c.rhs.node(), Node::Integer(_))),
+                    _ => panic!("comparison expected"),
+                }
+                &x.body
+            },
+            _ => panic!("while expected"),
+        };
+        match loop_body.node() {
+            Node::Block(x) => assert!(x.elements.iter().all(|e| matches!(e.node(), Node::Assignment(_)))),
+            _ => panic!("block expected"),
+        }
+    }
+    #[test]
+    fn test_resolve_slots() {
+        let program = parse(r#"
+            global g = 1;
+            define f(a, b) {
+                c = a + b;
+                c + g;
+            }
+        "#).unwrap();
+        Resolver::new().resolve_program(&program);
+        let f = match program.definitions.back().unwrap().definition() {
+            Definition::Function(x) => x,
+            _ => unreachable!(),
+        };
+        assert_eq!(*f.locals.borrow(), vec!["a", "b", "c"]);
+        let elements: Vec<_> = match f.body.node() {
+            Node::Block(x) => x.elements.iter().collect(),
+            _ => panic!("block expected"),
+        };
+        match elements[0].node() {
+            Node::Assignment(x) => assert_eq!(x.slot.get(), Some(Slot { depth: 0, index: 2 })),
+            _ => panic!("assignment expected"),
+        }
+        match elements[1].node() {
+            Node::Binary(x) => {
+                match x.rhs.node() {
+                    Node::Identifier(y) => assert_eq!(y.slot.get(), Some(Slot { depth: 1, index: 0 })),
+                    _ => panic!("identifier expected"),
+                }
+            },
+            _ => panic!("binary expression expected"),
+        }
+    }
+    #[test]
+    fn test_global_assignment_in_function() {
+        // global で定義された変数への代入は、関数の中でもグローバル変数を書き換える
+        let source = r#"
+            global counter = 10;
+            define inc() { counter = counter + 1; }
+            define main() { inc(); inc(); counter; }
+        "#;
+        assert_eq!(execute_program(source), 12);
+        assert_eq!(execute_program_with(source, &Options { optimize: false, ..Options::default() }), 12);
+        let program = parse(source).unwrap();
+        Resolver::new().resolve_program(&program);
+        match program.definitions.iter().nth(1).unwrap().definition() {
+            Definition::Function(x) => assert!(x.locals.borrow().is_empty()),
+            _ => unreachable!(),
+        }
+        // ローカルでもグローバルでもない変数は呼び出し元の変数を探す
+        assert_eq!(execute_program(r#"
+            define g() { y = 3; f(); }
+            define f() { y; }
+            define main() { y = 1; g() * 10 + f(); }
+        "#), 31);
+        let program = parse("global g = 1; define bump() { g = g + 1; } test \"a\" { assert_eq(bump(), 2); }").unwrap();
+        assert!(run_tests(&program, None)[0].passed());
+    }
+    #[test]
+    fn test_arguments_bound_in_caller() {
+        for options in [Options::default(), Options { optimize: false, ..Options::default() }] {
+            // 実引数は呼び出し元の環境にも仮引数の名前で代入されるので、後の呼び出しの n は前の呼び出しの実引数になる
+            assert_eq!(execute_program_with(r#"
+                define fib(n) {
+                    if (n < 2) n; else fib(n - 1) + fib(n - 2);
+                }
+                define main() { fib(15); }
+            "#, &options), 41);
+            // main はグローバル環境で実行されるので、グローバル変数に代入される
+            assert_eq!(execute_program_with(r#"
+                global n = 100;
+                define f(n) { n * 2; }
+                define main() { f(1) * 10 + n; }
+            "#, &options), 21);
+            // 代入される前のローカル変数は呼び出し元の変数を読む
+            assert_eq!(execute_program_with(r#"
+                define bump() { w = w + 1; w; }
+                define main() { w = 41; bump() * 100 + w; }
+            "#, &options), 4241);
+            assert_eq!(execute_program_with(r#"
+                define pick(c) { if (c) v = 2; v; }
+                define caller() { v = 5; pick(0) * 10 + pick(1); }
+                define main() { caller(); }
+            "#, &options), 52);
+        }
+    }
+    #[test]
+    #[should_panic(expected = "function f expects 2 arguments but 1 given.")]
+    fn test_arity_mismatch() {
+        execute_program(r#"
+            define f(a, b) { a + b; }
+            define main() { f(1); }
+        "#);
+    }
+    #[test]
+    fn test_parse_errors() {
+        let e = parse("define main() {\n    1 +;\n}").err().unwrap();
+        assert_eq!((e.line, e.column), (2, 8));
+        assert!(e.message.starts_with("expected"));
+        // 末尾の余計な文字も誤りとして扱う
+        assert!(parse("define main() { 1; } }").is_err());
+        assert!(parse_lines("1 + 2; )").is_err());
+        // フィールドの参照の規則の名前はメッセージに出さない
+        let e = parse("define main() {\n    x = 1 2;\n}").err().unwrap();
+        assert_eq!(e.message, "expected additive_verb, multitive_verb, comparative_verb");
+        // キーワードは変数や関数の名前にできない
+        for keyword in ["match", "try", "catch", "finally", "throw", "for", "in", "to", "record", "struct", "enum"] {
+            assert!(parse_lines(&format!("{} = 1;", keyword)).is_err(), "{}", keyword);
+            assert!(parse(&format!("define {}() {{ 0; }}", keyword)).is_err(), "{}", keyword);
+        }
+        assert_eq!(execute_lines("matches = 1; tries = 2; format = 3; matches + tries + format;"), 6);
+    }
+    #[test]
+    fn test_shebang() {
+        let program = "#!/usr/bin/env toys\ndefine main() {\n    3;\n}\n".to_string();
+        assert_eq!(execute_program(&program), 3);
+        let tokens: Vec<Token> = tokenize(&program).unwrap().into_iter().collect();
+        assert_eq!(tokens[0].kind, "shebang");
+        assert_eq!((tokens[1].kind.as_str(), tokens[1].text.as_str()), ("keyword", "define"));
+        assert_eq!((tokens[1].line, tokens[1].column), (2, 1));
+        assert_eq!(format(&program).unwrap(), program);
+    }
+    #[test]
+    fn test_format() {
+        let program = r#"
+// header
+global   x=1; // one
+global y = 2;
+/// Adds.
+define add(a,b){a+b;}
+define main( ) {   // entry
+    if(x<2){println(add(x, (y*3)));}else println(0);
+
+
+    for (i in 1 to 3) { add[a=1,b=2]; } /* trailing */
+}
+"#;
+        let expected = r#"// header
+global x = 1; // one
+global y = 2;
+
+/// Adds.
+define add(a, b) {
+    a + b;
+}
+
+define main() {
+    // entry
+    if (x < 2) {
+        println(add(x, (y * 3)));
+    } else println(0);
+
+    for (i in 1 to 3) {
+        add[a = 1, b = 2];
+    } /* trailing */
+}
+"#;
+        let formatted = format(program).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted).unwrap(), formatted);
+        assert_eq!(execute_program(&formatted), execute_program(program));
+    }
+    #[test]
+    fn test_check() {
+        let program = parse(r#"
+            global g = 1;
+            define f(a, b) {
+                c = a + b;
+                c + g + h;
+            }
+            define main() {
+                f(1);
+                f[a = 1, c = 2];
+                undefined(3);
+                h = 2;
+            }
+        "#).unwrap();
+        let messages: Vec<String> = check_program(&program).into_iter().map(|x| {
+            assert_eq!(x.severity, Severity::Error);
+            x.message
+        }).collect();
+        assert_eq!(messages, vec![
+            "function f expects 2 arguments but 1 given in main",
+            "argument b of function f is not given in main",
+            "function f has no parameter c in main",
+            "undefined function undefined in main",
+        ]);
+        let library = parse("define f() { x; }").unwrap();
+        let diagnostics = check_program(&library);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[1].message, "undefined variable x in f");
+    }
+    #[test]
+    fn test_type_inference() {
+        let program = parse(r#"
+            global limit = 10;
+            define main(n) {
+                count = 0;
+                while (count < limit) count = count + n;
+                first(id(count), 0);
+            }
+            define id(x) { x; }
+            define first(a, b) { a; }
+            define even(n) { if (n == 0) 1; else odd(n - 1); }
+            define odd(n) { if (n == 0) 0; else even(n - 1); }
+            define ignore(x) { while (0) 0; }
+            define twice(x) { id(id(x)); }
+            test "id" { assert_eq(id(1), first[b = 2, a = 1]); }
+        "#).unwrap();
+        let typing = typeck::check_program(&program);
+        assert!(typing.diagnostics.is_empty());
+        let functions: Vec<String> = typing.functions.iter().map(|(name, scheme)| format!("{} : {}", name, scheme)).collect();
+        assert_eq!(functions, vec![
+            "main : (Int) -> Int",
+            "id : forall a. (a) -> a",
+            "first : forall a b. (a, b) -> a",
+            "even : (Int) -> Int",
+            "odd : (Int) -> Int",
+            "ignore : forall a. (a) -> Int",
+            "twice : forall a. (a) -> a",
+        ]);
+        let globals: Vec<String> = typing.globals.iter().map(|(name, ty)| format!("{} : {}", name, ty)).collect();
+        assert_eq!(globals, vec!["limit : Int", "n : Int", "count : Int"]);
+        assert_eq!(typing.type_at(6, 23), Some(&typeck::Type::Int));
+
+        // 呼び出しの型変数は呼び出しごとに新しくなる
+        let program = parse("define main() { pair(1, 2); } define pair(a, b) { k(a, b); } define k(x, y) { x; }").unwrap();
+        let typing = typeck::check_program(&program);
+        assert_eq!(typing.functions[1].1.to_string(), "forall a b. (a, b) -> a");
+    }
+    #[test]
+    fn test_type_annotations() {
+        let source = r#"
+            global limit: Int = 10;
+            define main(n: Int): Int {
+                twice(n) + limit;
+            }
+            define twice(x: Int): Int { x * 2; }
+            define name(s: String, t) { s; }
+        "#;
+        let program = parse(source).unwrap();
+        let function = program.definitions.iter().find_map(|x| match x.definition() {
+            Definition::Function(f) if f.name == "name" => Some(f),
+            _ => None,
+        }).unwrap();
+        assert_eq!(function.parameter_types, vec![Some("String".to_string()), None]);
+        assert_eq!(function.return_type, None);
+        assert_eq!(function.signature(), "name(s: String, t)");
+        assert_eq!(run_program_with_args(source, &Options::default(), vec![4]).unwrap(), 18);
+
+        // 実行時には呼び出しの境界で確かめる
+        let error = |source: &str| match run_program(source, &Options::default()) {
+            Err(Error::Runtime(e)) => (e.kind, e.message.clone()),
+            _ => panic!("type mismatch expected"),
+        };
+        assert_eq!(
+            error("define main() { name(1, 2); } define name(s: String, t) { s; }"),
+            (ErrorKind::TypeMismatch, "type mismatch: argument s of function name must be String but Int given.".to_string())
+        );
+        assert_eq!(
+            error("define main() { f(); } define f(): Bool { 1; }"),
+            (ErrorKind::TypeMismatch, "type mismatch: return value of function f must be Bool but Int given.".to_string())
+        );
+        assert_eq!(
+            error("global flag: Bool = 1; define main() { flag; }"),
+            (ErrorKind::TypeMismatch, "type mismatch: global flag must be Bool but Int given.".to_string())
+        );
+
+        // 型検査では注釈が型になり、注釈のない部分は推論する
+        let program = parse("define main() { name(1, 2); } define name(s: String, t) { s; }").unwrap();
+        let messages: Vec<String> = typeck::check_program(&program).diagnostics.into_iter().map(|x| x.message).collect();
+        assert_eq!(messages, vec![
+            "type mismatch: expected String but found Int in main",
+            "type mismatch: expected Int but found String in main",
+        ]);
+        let typing = typeck::check_program(&parse(source).unwrap());
+        assert_eq!(typing.functions[2].1.to_string(), "forall a. (String, a) -> String");
+
+        let formatted = format("global limit :Int=10;\ndefine f(n:Int,m) :Int { n; }\n").unwrap();
+        assert_eq!(formatted, "global limit: Int = 10;\n\ndefine f(n: Int, m): Int {\n    n;\n}\n");
+        let program = parse(&formatted).unwrap();
+        assert!(dump_program(&program).contains("  Global limit: Int\n"));
+        assert!(dump_program(&program).contains("  Function f(n: Int, m): Int\n"));
+        let restored = from_json(&to_json(&program)).unwrap();
+        assert_eq!(dump_program(&restored), dump_program(&program));
+    }
+    #[test]
+    fn test_match() {
+        let source = r#"
+            define classify(n) {
+                match (n) {
+                    0 => 100,
+                    1 | 2 => { x = n * 10; x + 1; },
+                    k if k > 10 => k * 2,
+                    _ => -1,
+                }
+            }
+            define main(n) {
+                classify(n);
+            }
+        "#;
+        for (n, expected) in [(0, 100), (2, 21), (11, 22), (5, -1), (-3, -1)] {
+            assert_eq!(run_program_with_args(source, &Options::default(), vec![n]).unwrap(), expected);
+        }
+        // ガードは束縛した値で評価し、偽なら変数は match の前の値のまま
+        assert_eq!(execute_lines("n = 5; match (3) { n if n > 10 => 1, _ => n };"), 5);
+        assert_eq!(execute_lines("n = 5; match (12) { n if n > 10 => n, _ => 0 } * 100 + n;"), 1212);
+        assert_eq!(execute_program("
+            enum Pair { Both(first, second) }
+            define main() { n = 5; match (Both(1, 2)) { Both(n, m) if m > 2 => 0, _ => n }; }
+        "), 5);
+        assert_eq!(execute_lines("y = match (2) { 1 | 2 => 5, _ => 6 } * 2; y;"), 10);
+        match run_lines("match (3) { 1 => 1, n if n < 0 => 2, }") {
+            Err(Error::Runtime(e)) => {
+                assert_eq!(e.kind, ErrorKind::NoMatch);
+                assert_eq!(e.message, "no match arm for value 3");
+                assert_eq!(e.span.map(|x| (x.line, x.column)), Some((1, 1)));
+            },
+            _ => panic!("no match expected"),
+        }
+
+        // 網羅していない match と到達しない腕は警告になる
+        let program = parse(r#"
+            define main() {
+                match (1) { 1 => 1, 2 | 1 => 2, n if n > 0 => 3 };
+                match (2) { _ => 0, 3 => 1 };
+            }
+        "#).unwrap();
+        let diagnostics: Vec<(Severity, String)> = check_program(&program).into_iter().map(|x| (x.severity, x.message)).collect();
+        assert_eq!(diagnostics, vec![
+            (Severity::Warning, "match in main is not exhaustive; add a _ arm".to_string()),
+            (Severity::Warning, "unreachable match arm 3 in main".to_string()),
+        ]);
+        let program = parse("define main() { match (1) { 1 => 1, 1 => 2, _ => 3 }; }").unwrap();
+        let messages: Vec<String> = check_program(&program).into_iter().map(|x| x.message).collect();
+        assert_eq!(messages, vec!["unreachable match arm 1 in main"]);
+
+        // 束縛の変数は関数のローカル変数になり、型は値の型になる
+        let typing = typeck::check_program(&parse(source).unwrap());
+        assert!(typing.diagnostics.is_empty());
+        assert_eq!(typing.functions[0].1.to_string(), "(Int) -> Int");
+        let program = parse("define f(s: String) { match (s) { 1 => 0, _ => 1 } } define main() { 0; }").unwrap();
+        let messages: Vec<String> = typeck::check_program(&program).diagnostics.into_iter().map(|x| x.message).collect();
+        assert_eq!(messages, vec!["type mismatch: expected Int but found String in f"]);
+
+        let program = parse(source).unwrap();
+        assert!(dump_program(&program).contains("      Match\n        Identifier n\n        Arm 0\n          Integer 100\n        Arm 1 | 2\n"));
+        assert!(dump_program(&program).contains("        Arm k if\n          Binary >\n"));
+        assert!(to_sexpr(&program).contains("(match n ((0) 100) ((1 2) (block (set x (* n 10)) (+ x 1))) (k (when (> k 10)) (* k 2)) (_ -1))"));
+        let restored = from_json(&to_json(&program)).unwrap();
+        assert_eq!(dump_program(&restored), dump_program(&program));
+        assert!(dump_program(&optimize(&program)).contains("    Match\n      Identifier n\n      Arm 0\n"));
+
+        let formatted = format("define f(n) { r = match(n) {0=>1, k if k>1 => {k;} ,_=>2}; r + match (n) {_ => 0}; }\n").unwrap();
+        assert_eq!(formatted, concat!(
+            "define f(n) {\n",
+            "    r = match (n) {\n",
+            "        0 => 1,\n",
+            "        k if k > 1 => {\n",
+            "            k;\n",
+            "        },\n",
+            "        _ => 2,\n",
+            "    };\n",
+            "    r + match (n) { _ => 0 };\n",
+            "}\n",
+        ));
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+    #[test]
+    fn test_records() {
+        let source = r#"
+            /// 平面上の点
+            record Point(x: Int, y)
+            struct Line(start, end);
+            define moved(p, dx) {
+                p.x = p.x + dx;
+                p;
+            }
+            define main(n) {
+                p = Point[x = n, y = 2];
+                q = moved(p, 10);
+                line = Line(p, q);
+                line.end.y = 5;
+                assert(p == Point(n, 2));
+                assert(p != q);
+                q.x * 100 + line.end.y * 10 + line.start.y;
+            }
+        "#;
+        assert_eq!(run_program_with_args(source, &Options::default(), vec![1]).unwrap(), 1152);
+        assert_eq!(run_program_with_args(source, &Options { optimize: false, ..Options::default() }, vec![1]).unwrap(), 1152);
+        // 型の違う値は等しくない
+        assert_eq!(run_program("record P(x) define main() { (P(1) == 1) * 10 + (P(1) != P(2)); }", &Options::default()).unwrap(), 1);
+
+        // 値は Point[x = 1, y = 2] の形で表示される
+        let record = toys_in_rust::ast::Record {
+            name: "Point".to_string(),
+            fields: vec![("x".to_string(), 1.into()), ("y".to_string(), (-2).into())],
+        };
+        assert_eq!(toys_in_rust::ast::Value::Record(Rc::new(record)).to_string(), "Point[x = 1, y = -2]");
+
+        let error = |source: &str| match run_program(source, &Options::default()) {
+            Err(Error::Runtime(e)) => (e.kind, e.message.clone()),
+            _ => panic!("runtime error expected"),
+        };
+        assert_eq!(
+            error("record P(x) define main() { p = P(1); p.y; }"),
+            (ErrorKind::UndefinedField, "record P has no field y.".to_string())
+        );
+        assert_eq!(
+            error("record P(x) define main() { p = 1; p.x = 2; }"),
+            (ErrorKind::TypeMismatch, "type mismatch: value of .x must be a record but Int given.".to_string())
+        );
+        assert_eq!(
+            error("record P(x: Int) define main() { P(P(1)); 0; }"),
+            (ErrorKind::TypeMismatch, "type mismatch: argument x of function P must be Int but P given.".to_string())
+        );
+        assert_eq!(
+            error("record P(x) define main() { P[y = 1]; }"),
+            (ErrorKind::MissingArgument, "argument x of function P is not given.".to_string())
+        );
+        assert_eq!(
+            error("record P(x) define main() { P(1); }"),
+            (ErrorKind::TypeMismatch, "type mismatch: return value of function main must be Int but P given.".to_string())
+        );
+        assert_eq!(
+            error("record P(x) define main() { P(1) + 1; }"),
+            (ErrorKind::TypeMismatch, "type mismatch: operand of + must be Int but P given.".to_string())
+        );
+
+        // import したモジュールの名前の後の . はフィールドではない
+        let mut loader = MemoryLoader::new();
+        loader.add_file("geo.toys", "record Point(x, y) global origin = Point(0, 0);");
+        let program = Linker::new(loader).link_source("import geo; define main() { p = geo.origin; p.x = 3; p.x + geo.Point[x = 1, y = 2].y; }", None).unwrap();
+        assert_eq!(Interpreter::new().call_main(&program), Ok(5));
+
+        let program = parse(source).unwrap();
+        let messages = |source: &str| -> Vec<String> { check_program(&parse(source).unwrap()).into_iter().map(|x| x.message).collect() };
+        assert!(check_program(&program).is_empty());
+        assert_eq!(messages("record P(x) define main() { p = P(1); p.y; q.x = 1; }"), vec![
+            "no record has field y in main",
+            "undefined variable q in main",
+        ]);
+        assert_eq!(messages("record P(x) define P(y) { y; } define main() { 0; }"), vec!["function P is defined more than once"]);
+
+        // フィールドの型は注釈か使われ方から決まる。フィールドを持つレコードが一つなら値の型も決まる
+        let typing = typeck::check_program(&program);
+        assert!(typing.diagnostics.is_empty());
+        let functions: Vec<String> = typing.functions.iter().map(|(name, scheme)| format!("{} : {}", name, scheme)).collect();
+        assert_eq!(functions, vec!["moved : (Point, Int) -> Point", "main : (Int) -> Int"]);
+        let program = parse("record P(x: Int) define main() { p = P(1); p.x = P(2); 0; }").unwrap();
+        let messages: Vec<String> = typeck::check_program(&program).diagnostics.into_iter().map(|x| x.message).collect();
+        assert_eq!(messages, vec!["type mismatch: expected Int but found P in main"]);
+
+        let program = parse(source).unwrap();
+        assert!(dump_program(&program).contains("  Record Point(x: Int, y)\n  Record Line(start, end)\n"));
+        assert!(dump_program(&program).contains("      FieldAssignment p.x\n        Binary +\n          Field x\n            Identifier p\n"));
+        assert!(to_sexpr(&program).contains("(record Point (x y)) (record Line (start end))"));
+        assert!(to_sexpr(&program).contains("(set-field line (end y) 5)"));
+        let restored = from_json(&to_json(&program)).unwrap();
+        assert_eq!(dump_program(&restored), dump_program(&program));
+        assert!(matches!(codegen::c::generate(&program), Err(e) if e.message == "records are not supported; only Int values can be compiled."));
+
+        let formatted = format("struct Point(x:Int,y);\ndefine main() { p = Point[x=1,y=2]; p . x = p.x+1; p.x; }\n").unwrap();
+        assert_eq!(formatted, concat!(
+            "record Point(x: Int, y)\n",
+            "\n",
+            "define main() {\n",
+            "    p = Point[x = 1, y = 2];\n",
+            "    p.x = p.x + 1;\n",
+            "    p.x;\n",
+            "}\n",
+        ));
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+    #[test]
+    fn test_enums() {
+        let source = r#"
+            /// 図形
+            enum Shape { Circle(r: Int), Rect(w, h), Empty }
+            enum Pair { Both(first, second) }
+            define area(s: Shape) {
+                match (s) {
+                    Circle(r) => 3 * r * r,
+                    Rect(w, h) if w == h => w * w,
+                    Rect(w, h) => w * h,
+                    Empty => 0,
+                }
+            }
+            define main(n) {
+                shapes = Both(Circle(n), Empty);
+                inner = match (shapes) { Both(Circle(r), Empty) => Circle(r + 1), _ => Empty };
+                assert(inner == Circle(n + 1));
+                assert(inner != Rect(n + 1, 0));
+                area(inner) * 100 + area(Rect(2, 3)) * 10 + area(Empty);
+            }
+        "#;
+        assert_eq!(run_program_with_args(source, &Options::default(), vec![1]).unwrap(), 1260);
+        assert_eq!(run_program_with_args(source, &Options { optimize: false, ..Options::default() }, vec![1]).unwrap(), 1260);
+
+        // 値は Rect(2, 3) の形で、値のないバリアントは名前だけで表示される
+        let variant = |name: &str, values: Vec<toys_in_rust::ast::Value>| toys_in_rust::ast::Value::Variant(Rc::new(toys_in_rust::ast::Variant {
+            enumeration: "Shape".to_string(),
+            name: name.to_string(),
+            values,
+        }));
+        assert_eq!(variant("Rect", vec![2.into(), (-3).into()]).to_string(), "Rect(2, -3)");
+        assert_eq!(variant("Empty", vec![]).to_string(), "Empty");
+
+        let error = |source: &str| match run_program(source, &Options::default()) {
+            Err(Error::Runtime(e)) => (e.kind, e.message.clone()),
+            _ => panic!("runtime error expected"),
+        };
+        assert_eq!(
+            error("enum E { A, B } define f(e: E) { 0; } define main() { f(1); }"),
+            (ErrorKind::TypeMismatch, "type mismatch: argument e of function f must be E but Int given.".to_string())
+        );
+        assert_eq!(
+            error("enum E { A, B } define main() { A + 1; }"),
+            (ErrorKind::TypeMismatch, "type mismatch: operand of + must be Int but E given.".to_string())
+        );
+
+        // import したモジュールのバリアントは モジュール名.名前 で呼び、パターンにも書ける
+        let mut loader = MemoryLoader::new();
+        loader.add_file("opt.toys", "enum Option { Some(v), None } define get(o, d) { match (o) { Some(v) => v, None => d }; } define none() { None; }");
+        let program = Linker::new(loader).link_source(
+            "import opt; define main() { match (opt.Some(opt.get(opt.none(), 3))) { opt.Some(x) => x, opt.None => 0 }; }",
+            None,
+        ).unwrap();
+        assert_eq!(Interpreter::new().call_main(&program), Ok(3));
+
+        let program = parse(source).unwrap();
+        let messages = |source: &str| -> Vec<String> { check_program(&parse(source).unwrap()).into_iter().map(|x| x.message).collect() };
+        assert!(check_program(&program).is_empty());
+        assert_eq!(messages("enum E { A(x), B } define main() { match (A(1)) { A(_) => 1, C => 2, A(x, y) => 3 }; }"), vec![
+            "undefined constructor C in main",
+            "constructor A expects 1 fields but 2 given in main",
+            "unreachable match arm A(x, y) in main",
+            "match in main is not exhaustive; missing B",
+        ]);
+        assert_eq!(messages("enum E { A, B } define main() { match (A) { A => 1, B => 2, _ => 3 }; }"), vec!["unreachable match arm _ in main"]);
+        assert_eq!(messages("enum E { A } enum F { A } define main() { 0; }"), vec!["function A is defined more than once"]);
+
+        // バリアントの値の型は注釈か使われ方から決まり、パターンで値の型が列挙型に決まる
+        let typing = typeck::check_program(&program);
+        assert!(typing.diagnostics.is_empty());
+        let functions: Vec<String> = typing.functions.iter().map(|(name, scheme)| format!("{} : {}", name, scheme)).collect();
+        assert_eq!(functions, vec!["area : (Shape) -> Int", "main : (Int) -> Int"]);
+        let program = parse("enum E { A(x), B } define main() { match (A(1)) { B => 0, A(y) => y, 2 => 1 }; }").unwrap();
+        let messages: Vec<String> = typeck::check_program(&program).diagnostics.into_iter().map(|x| x.message).collect();
+        assert_eq!(messages, vec!["type mismatch: expected Int but found E in main"]);
+
+        let program = parse(source).unwrap();
+        assert!(dump_program(&program).contains("  Enum Shape\n    Variant Circle(r: Int)\n    Variant Rect(w, h)\n    Variant Empty()\n"));
+        assert!(to_sexpr(&program).contains("(enum Shape (Circle r) (Rect w h) (Empty))"));
+        assert!(to_sexpr(&program).contains("((Both (Circle r) (Empty)) (call Circle (+ r 1)))"));
+        let restored = from_json(&to_json(&program)).unwrap();
+        assert_eq!(dump_program(&restored), dump_program(&program));
+        assert_eq!(Interpreter::new().call_main_with_args(&restored, vec![1]), Ok(1260));
+        assert!(matches!(codegen::c::generate(&program), Err(e) if e.message == "enums are not supported; only Int values can be compiled."));
+
+        let formatted = format("enum Shape{Circle(r:Int),Rect(w,h),Empty(),}\ndefine main() { match (Empty) { Rect(w,_)=>w, _=>0 }; }\n").unwrap();
+        assert_eq!(formatted, concat!(
+            "enum Shape { Circle(r: Int), Rect(w, h), Empty }\n",
+            "\n",
+            "define main() {\n",
+            "    match (Empty) {\n",
+            "        Rect(w, _) => w,\n",
+            "        _ => 0,\n",
+            "    };\n",
+            "}\n",
+        ));
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+    #[test]
+    fn test_exceptions() {
+        let source = r#"
+            global log = 0;
+            define divide(dividend, divisor) {
+                dividend / divisor;
+            }
+            define checked(value) {
+                if (value < 0) throw Negative(value);
+                value;
+            }
+            define kind(e) {
+                match (e.kind) {
+                    DivisionByZero => 1,
+                    UndefinedVariable => 2,
+                    ArityMismatch => 3,
+                    _ => 4,
+                }
+            }
+            define main(n) {
+                a = try { divide(n, 0); } catch (e) { kind(e); };
+                b = try { missing; } catch (e) { kind(e); };
+                c = try { divide(n); } catch (e) { kind(e); };
+                d = try { checked(0 - n); } catch (failure) { match (failure) { Negative(x) => x, _ => 0 }; } finally { log = log + 1; };
+                f = try { try { throw n; } finally { log = log + 10; }; } catch (thrown) { thrown * 2; };
+                g = try { n; } catch (e) { 0; } finally { log = log + 100; };
+                a * 100000 + b * 10000 + c * 1000 + d * 100 + f * 10 + g + log * 1000000;
+            }
+            enum Failure { Negative(x), Unknown }
+        "#;
+        assert_eq!(run_program_with_args(source, &Options::default(), vec![1]).unwrap(), 111122921);
+        assert_eq!(run_program_with_args(source, &Options { optimize: false, ..Options::default() }, vec![1]).unwrap(), 111122921);
+
+        // インタプリタのエラーは kind と message を持つ Error レコードとして受け取る
+        let caught = |body: &str| run_program(&format!("define main() {{ e = try {{ {} }} catch (e) {{ e; }}; println(e); 0; }}", body), &Options::default());
+        assert_eq!(caught("1 / 0;").unwrap(), 0);
+        let error = toys_in_rust::ast::RuntimeError::new(ErrorKind::DivisionByZero, "division by zero: 1 / 0".to_string());
+        assert_eq!(error.to_value().to_string(), "Error[kind = DivisionByZero, message = division by zero: 1 / 0]");
+        assert_eq!(toys_in_rust::ast::RuntimeError::thrown(3.into()).to_value(), 3.into());
+
+        // catch されなかった値は Thrown のエラーになり、通り抜けた関数を内側から順に記録する
+        let program = parse("define f(x) {\n    throw x + 1;\n}\ndefine main() {\n    f(41);\n}\n").unwrap();
+        let e = Interpreter::new().call_main(&program).unwrap_err();
+        assert_eq!((e.kind, e.message.as_str(), e.value.clone()), (ErrorKind::Thrown, "uncaught exception: 42", Some(42.into())));
+        let trace: Vec<(&str, Option<(usize, usize)>)> = e.stack_trace().into_iter().map(|(x, y)| (x, y.map(|z| (z.line, z.column)))).collect();
+        assert_eq!(trace, vec![("f", Some((2, 5))), ("main", Some((5, 5)))]);
+        // 本体に入る前の引数の誤りは呼び出した位置で起きる
+        let program = parse("define f(x) { x; }\ndefine main() {\n    0 + f();\n}\n").unwrap();
+        let e = Interpreter::new().call_main(&program).unwrap_err();
+        assert_eq!((e.kind, e.span.map(|x| (x.line, x.column))), (ErrorKind::ArityMismatch, Some((3, 9))));
+        assert_eq!(e.stack_trace(), vec![("main", e.span)]);
+        // finally は catch されないエラーでも評価され、finally で起きたエラーが優先される
+        match run_program("global g = 0; define main() { try { 1 / 0; } finally { g = 1; throw g; }; }", &Options::default()) {
+            Err(Error::Runtime(e)) => assert_eq!(e.message, "uncaught exception: 1"),
+            _ => panic!("runtime error expected"),
+        }
+
+        let program = parse(source).unwrap();
+        let messages = |source: &str| -> Vec<String> { check_program(&parse(source).unwrap()).into_iter().map(|x| x.message).collect() };
+        assert_eq!(messages(source), vec![
+            "undefined variable missing in main",
+            "function divide expects 2 arguments but 1 given in main",
+        ]);
+        assert_eq!(messages("define main() { try { 0; } catch (e) { match (e.kind) { Overflow => 1, Missing => 2, _ => e.code }; }; }"), vec![
+            "undefined constructor Missing in main",
+            "no record has field code in main",
+        ]);
+
+        // catch の変数の型は決めない。catch の本体は try の本体と同じ型になる
+        let typing = typeck::check_program(&program);
+        assert!(typing.diagnostics.is_empty());
+        let functions: Vec<String> = typing.functions.iter().map(|(name, scheme)| format!("{} : {}", name, scheme)).collect();
+        assert_eq!(functions, vec!["divide : (Int, Int) -> Int", "checked : (Int) -> Int", "kind : (Error) -> Int", "main : (Int) -> Int"]);
+        let program = parse("enum E { A } define main() { try { 0; } catch (e) { A; }; }").unwrap();
+        let messages: Vec<String> = typeck::check_program(&program).diagnostics.into_iter().map(|x| x.message).collect();
+        assert_eq!(messages, vec!["type mismatch: expected Int but found E in main"]);
+
+        let program = parse("define main() { try { throw 1; } catch (e) { e; } finally { 2; } }").unwrap();
+        assert!(dump_program(&program).contains("      Try\n        Block\n          Throw\n            Integer 1\n        Catch e\n          Block\n            Identifier e\n        Finally\n"));
+        assert!(to_sexpr(&program).contains("(try (block (throw 1)) (catch e (block e)) (finally (block 2)))"));
+        let restored = from_json(&to_json(&program)).unwrap();
+        assert_eq!(dump_program(&restored), dump_program(&program));
+        assert_eq!(Interpreter::new().call_main(&restored), Ok(1));
+        assert!(matches!(codegen::c::generate(&program), Err(e) if e.message == "exceptions are not supported; try and throw cannot be compiled."));
+
+        let formatted = format("define main() { x = try{throw 1;}catch(e){e;}; try { x; } finally { println(x); } }\n").unwrap();
+        assert_eq!(formatted, concat!(
+            "define main() {\n",
+            "    x = try {\n",
+            "        throw 1;\n",
+            "    } catch (e) {\n",
+            "        e;\n",
+            "    };\n",
+            "    try {\n",
+            "        x;\n",
+            "    } finally {\n",
+            "        println(x);\n",
+            "    }\n",
+            "}\n",
+        ));
+        assert_eq!(format(&formatted).unwrap(), formatted);
+        // throw で始まる名前の変数は throw 文ではない
+        assert_eq!(run_program("define main() { throwing = 3; throwing; }", &Options::default()).unwrap(), 3);
+    }
+    #[test]
+    fn test_main_args() {
+        let program = r#"
+            define main(a, b) {
+                c = a * b;
+                c + f(1);
+            }
+            define f(a) { a; }
+        "#;
+        assert_eq!(run_program_with_args(program, &Options::default(), vec![6, 7]).unwrap(), 43);
+        // 足りない引数は 0 になり、余った引数は捨てられる
+        assert_eq!(run_program(program, &Options::default()).unwrap(), 1);
+        assert_eq!(run_program_with_args(program, &Options::default(), vec![6]).unwrap(), 1);
+        assert_eq!(run_program_with_args(program, &Options::default(), vec![6, 7, 8]).unwrap(), 43);
+        assert_eq!(run_program_with_args("define main() { 5; }", &Options::default(), vec![1, 2]).unwrap(), 5);
+    }
+    #[test]
+    fn test_environment_variable() {
+        std::env::set_var("TOYS_TEST_ENV_SET", " 42 ");
+        std::env::set_var("TOYS_TEST_ENV_INVALID", "abc");
+        std::env::remove_var("TOYS_TEST_ENV_UNSET");
+        assert_eq!(execute_lines(r#"env("TOYS_TEST_ENV_SET") + 1;"#), 43);
+        assert_eq!(execute_lines(r#"env("TOYS_TEST_ENV_SET", 1 / 0);"#), 42);
+        assert_eq!(execute_lines(r#"env("TOYS_TEST_ENV_UNSET", 2 * 3);"#), 6);
+        for line in [r#"env("TOYS_TEST_ENV_UNSET");"#, r#"env("TOYS_TEST_ENV_INVALID", 0);"#] {
+            match run_lines(line) {
+                Err(Error::Runtime(e)) => assert_eq!(e.kind, ErrorKind::InvalidEnvironmentVariable),
+                _ => panic!("invalid environment variable expected"),
+            }
+        }
+    }
+    #[test]
+    fn test_spans() {
+        let program = parse("define main() {\n    x = 1 + 2;\n    println(x);\n}").unwrap();
+        let main = match program.definitions.front().unwrap().definition() {
+            Definition::Function(x) => x,
+            _ => unreachable!(),
+        };
+        assert_eq!(main.span.map(|x| (x.line, x.column)), Some((1, 1)));
+        let elements: Vec<_> = match main.body.node() {
+            Node::Block(x) => x.elements.iter().collect(),
+            _ => panic!("block expected"),
+        };
+        assert_eq!(elements[0].span(), Some(Span { start: 20, end: 30, line: 2, column: 5 }));
+        match elements[0].node() {
+            Node::Assignment(x) => assert_eq!(x.expression.span().map(|y| (y.line, y.column)), Some((2, 9))),
+            _ => panic!("assignment expected"),
+        }
+        assert_eq!(elements[1].span().map(|x| (x.line, x.column)), Some((3, 5)));
+        // 畳み込んだ定数は元の式の位置を引き継ぐ
+        match optimize_expression(elements[0]).node() {
+            Node::Assignment(x) => assert_eq!(x.expression.span().map(|y| (y.line, y.column)), Some((2, 9))),
+            _ => panic!("assignment expected"),
+        }
+    }
+    #[test]
+    fn test_serialize() {
+        let source = r#"
+            global g = 2;
+            /// Multiplies.
+            define mul(n, m) { n * m; }
+            define main() {
+                for (i in 1 to 3) { g = g + mul[n = i, m = 2]; }
+                if (g != 14) println(env("TOYS_TEST_UNSET", 0 - 1)); else g;
+            }
+        "#;
+        let program = parse(source).unwrap();
+        assert_eq!(
+            to_sexpr(&program),
+            "(program (global g 2) (define mul (n m) (block (* n m))) (define main () (block \
+             (block (set i 1) (while (<= i 3) (block (block (set g (+ g (labelled-call mul (n i) (m 2))))) (set i (+ i 1))))) \
+             (if (!= g 14) (println (env \"TOYS_TEST_UNSET\" (- 0 1))) g))))"
+        );
+        let json = to_json(&program);
+        let restored = from_json(&json).unwrap();
+        assert_eq!(to_json(&restored), json);
+        assert_eq!(to_sexpr(&restored), to_sexpr(&program));
+        assert_eq!(restored.definitions.iter().nth(1).unwrap().doc(), Some("Multiplies."));
+        assert_eq!(Interpreter::new().call_main(&restored), Ok(14));
+
+        assert!(from_json("{\"imports\": [], \"definitions\": [{\"type\": \"Function\"}]}").is_err());
+        assert_eq!(
+            from_json("{\"imports\": [], \"definitions\": [{\"type\": \"Global\", \"name\": \"x\", \"body\": {\"type\": \"Binary\", \"operator\": \"%\"}}]}").err().unwrap().message,
+            "unknown operator %"
+        );
+    }
+    #[test]
+    fn test_trace() {
+        let source = "define main() {\n    i = 0;\n    while (i < 2) {\n        i = i + 1;\n    }\n    fact(2);\n}\n\
+                      define fact(n) {\n    if (n < 2) {\n        1;\n    } else {\n        n * fact(n - 1);\n    }\n}";
+        let program = parse(source).unwrap();
+        let trace = |functions: Vec<String>| {
+            let tracer = Rc::new(Tracer::only(Vec::new(), functions));
+            let mut i = Interpreter::new();
+            i.set_hook(tracer.clone());
+            assert_eq!(i.call_main(&program), Ok(2));
+            drop(i);
+            String::from_utf8(Rc::try_unwrap(tracer).ok().unwrap().into_output()).unwrap()
+        };
+        assert_eq!(trace(vec![]), "\
+call main()
+  line 2: i = 0 (was <unset>)
+  line 3: while iteration 1
+  line 4: i = 1 (was 0)
+  line 3: while iteration 2
+  line 4: i = 2 (was 1)
+  call fact(n = 2)
+    line 9: if condition 0, else branch
+    call fact(n = 1)
+      line 9: if condition 1, then branch
+    return fact = 1
+  return fact = 2
+return main = 2
+");
+        // 関数を指定するとその中だけを記録する。字下げは呼び出しの深さのまま
+        assert_eq!(trace(vec!["fact".to_string()]).lines().next(), Some("  call fact(n = 2)"));
+        assert!(!trace(vec!["fact".to_string()]).contains("while"));
+    }
+    #[test]
+    fn test_profile() {
+        let source = "define main() {\n    i = 0;\n    while (i < 3) {\n        i = i + 1;\n    }\n    fact(4);\n}\n\
+                      define fact(n) {\n    if (n < 2) {\n        1;\n    } else {\n        n * fact(n - 1);\n    }\n}";
+        let program = parse(source).unwrap();
+        let profiler = Rc::new(Profiler::new());
+        let mut i = Interpreter::new();
+        i.set_hook(profiler.clone());
+        assert_eq!(i.call_main(&program), Ok(24));
+
+        let mut calls: Vec<(String, usize)> = profiler.functions().into_iter().map(|x| (x.name, x.calls)).collect();
+        calls.sort();
+        assert_eq!(calls, vec![("fact".to_string(), 4), ("main".to_string(), 1)]);
+        for x in profiler.functions() {
+            assert!(x.exclusive <= x.inclusive);
+        }
+        assert_eq!(profiler.lines(), vec![(2, 1), (3, 1), (4, 3), (6, 1), (9, 4), (10, 1), (12, 3)]);
+        assert!(profiler.report().starts_with("function"));
+
+        let folded = profiler.folded();
+        let stacks: Vec<&str> = folded.lines().map(|x| x.rsplit_once(' ').unwrap().0).collect();
+        assert_eq!(stacks, vec!["main", "main;fact", "main;fact;fact", "main;fact;fact;fact", "main;fact;fact;fact;fact"]);
+        let trace = profiler.chrome_trace();
+        let events = trace.get("traceEvents").unwrap().as_array().unwrap();
+        assert_eq!(events.len(), 5);
+        assert_eq!(events[4].get("name").unwrap().as_str(), Some("main"));
+        assert_eq!(events[4].get("ph").unwrap().as_str(), Some("X"));
+    }
+    #[test]
+    fn test_profile_command_does_not_optimize() {
+        let file = std::env::temp_dir().join(format!("toys_profile_{}.toys", std::process::id()));
+        std::fs::write(&file, "define main() {\n    i = 0;\n    if (i < 1) {\n        i = 5;\n    }\n    i;\n}\n").unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_toys")).args(["run", "--profile"]).arg(&file).output().unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(output.status.code(), Some(5));
+        // 本体が1文の if も行として数えられる
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let lines: Vec<Vec<&str>> = stderr.lines().skip_while(|x| !x.trim_start().starts_with("line"))
+            .skip(1).map(|x| x.split_whitespace().collect()).collect();
+        assert_eq!(lines, vec![vec!["2", "1"], vec!["3", "1"], vec!["4", "1"], vec!["6", "1"]]);
+    }
+    #[test]
+    fn test_runtime_error_in_module() {
+        let dir = std::env::temp_dir().join(format!("toys_module_error_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("calc.toys"), "// 割り算\ndefine ratio(a, b) {\n    a / b;\n}\n").unwrap();
+        std::fs::write(dir.join("main.toys"), "import calc;\ndefine main() {\n    calc.ratio(1, 0);\n}\n").unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_toys")).arg("run").arg(dir.join("main.toys")).output().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        // エラーの位置はモジュールのファイルで示す
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let (module, main) = (dir.join("calc.toys"), dir.join("main.toys"));
+        assert_eq!(stderr, format!(
+            "{}:3:5: error: division by zero: 1 / 0\n    at calc.ratio ({}:3:5)\n    at main ({}:3:5)\n",
+            module.display(), module.display(), main.display(),
+        ));
+    }
+    #[test]
+    fn test_coverage() {
+        let source = "define main() {\n    i = 0;\n    while (i < 2) {\n        i = i + 1;\n    }\n    while (i < 0) {\n        i = 100;\n    }\n\
+                      if (i == 2) {\n        sign(3);\n    }\n}\n\
+                      define sign(n) {\n    if (n < 0) {\n        0 - 1;\n    } else {\n        1;\n    }\n}\n\
+                      define unused() {\n    1;\n}";
+        let program = parse(source).unwrap();
+        let coverage = Rc::new(Coverage::new(&program));
+        let mut i = Interpreter::new();
+        i.set_hook(coverage.clone());
+        assert_eq!(i.call_main(&program), Ok(1));
+
+        assert_eq!(coverage.lines(), vec![(2, 1), (3, 1), (4, 2), (6, 1), (7, 0), (9, 1), (10, 1), (14, 1), (15, 0), (17, 1), (21, 0)]);
+        let branches: Vec<(BranchKind, usize, [usize; 2])> = coverage.branches().into_iter().map(|x| (x.kind, x.line, x.counts)).collect();
+        assert_eq!(branches, vec![
+            (BranchKind::While, 3, [1, 0]),
+            (BranchKind::While, 6, [0, 1]),
+            (BranchKind::If, 9, [1, 0]),
+            (BranchKind::If, 14, [0, 1]),
+        ]);
+        assert!(coverage.summary().starts_with("lines:     8/11 (72.7%)\nbranches:  4/8 (50.0%)\nfunctions: 2/3 (66.7%)\n"));
+        assert!(coverage.summary().contains("uncovered lines: 7, 15, 21\n"));
+
+        let lcov = coverage.lcov("sample/coverage.toys");
+        assert!(lcov.starts_with("TN:\nSF:sample/coverage.toys\nFN:1,main\nFN:13,sign\nFN:20,unused\n"));
+        assert!(lcov.contains("FNDA:0,unused\nFNF:3\nFNH:2\n"));
+        assert!(lcov.contains("BRDA:14,3,0,0\nBRDA:14,3,1,1\nBRF:8\nBRH:4\n"));
+        assert!(lcov.contains("DA:7,0\n"));
+        assert!(lcov.ends_with("LF:11\nLH:8\nend_of_record\n"));
+    }
+    #[test]
+    fn test_test_blocks() {
+        let source = r#"
+global base = 10;
+define add(a, b) { a + b; }
+test "add works" {
+    assert_eq(add(1, 2), 3);
+    base = 0;
+    assert(add(base, 1) == 1);
+}
+test "globals are fresh in each test" {
+    assert_eq(base, 10);
+}
+test "add is broken" {
+    assert_eq(add(2, 2), 5);
+}
+define main() { assert(0); }
+"#;
+        let program = parse(source).unwrap();
+        let results = run_tests(&program, None);
+        let outcomes: Vec<(&str, bool)> = results.iter().map(|x| (x.name.as_str(), x.passed())).collect();
+        assert_eq!(outcomes, vec![("add works", true), ("globals are fresh in each test", true), ("add is broken", false)]);
+        assert_eq!(results[2].span.map(|x| (x.line, x.column)), Some((13, 5)));
+        assert_eq!(results[2].outcome.as_ref().unwrap_err().kind, ErrorKind::AssertionFailed);
+        let text = report("add.toys", &results, 0);
+        assert!(text.contains("---- add is broken ----\nadd.toys:13:5: assertion failed: left == right\n  left: 4\n right: 5\n"));
+        assert!(text.ends_with("test result: FAILED. 2 passed; 1 failed; 0 filtered out\n"));
+
+        let filtered = run_tests(&program, Some("works"));
+        assert_eq!(filtered.len(), 1);
+        assert!(report("add.toys", &filtered, 2).ends_with("test result: ok. 1 passed; 0 failed; 2 filtered out\n"));
+
+        // main からはテストは実行されず、assert は通常の実行でも使える
+        match run_program(source, &Options::default()) {
+            Err(Error::Runtime(e)) => assert_eq!((e.kind, e.span.map(|x| x.line)), (ErrorKind::AssertionFailed, Some(15))),
+            _ => panic!("assertion failure expected"),
+        }
+        assert!(check_program(&program).is_empty());
+        assert_eq!(to_sexpr(&from_json(&to_json(&program)).unwrap()), to_sexpr(&program));
+        assert_eq!(format(source.trim_start()).unwrap(), format(&format(source).unwrap()).unwrap());
+    }
     #[rstest(name, input, expected,
         case("test_while_lines", r#"
             i = 0;
@@ -65,7 +1152,7 @@ mod tests {
         ::trace
     )]
     fn test_lines(name: String, input: String, expected: i32) {
-        assert_eq!(execute_lines(&input), expected);
+        assert_eq!(execute_lines(&input), expected, "{}", name);
     }
     #[rstest(name, input, expected,
         case("test_add", r#"
@@ -146,9 +1233,61 @@ mod tests {
                 mul[n = 6, m = 21];
             }
         "#, 126),
+        case("test_recursive_locals", r#"
+            define fib(n) {
+                if (n < 2) {
+                    r = n;
+                } else {
+                    a = fib(n - 1);
+                    b = fib(n - 2);
+                    r = a + b;
+                }
+                r;
+            }
+            define main() {
+                r = 100;
+                fib(10) + r;
+            }
+        "#, 106),
+        case("test_comments", r#"
+            // line comment
+            /* block /* nested */ comment */
+            /// Multiplies two numbers.
+            define mul(n, m) {
+                n * m; // trailing comment
+            }
+            //// not a doc comment
+            define main() {
+                /* inline */ mul(6, /* 7 */ 21);
+            }
+        "#, 126),
         ::trace
     )]
     fn test_program(name: String, input: String, expected: i32) {
         assert_eq!(execute_program(&input), expected);
+        assert_eq!(execute_program_with(&input, &Options { optimize: false, ..Options::default() }), expected);
+        // 終了コードは 255 より大きい値が 255、負の値が 1 になる
+        if let Some(status) = native_status(&name, &input) {
+            assert_eq!(status, if expected < 0 { 1 } else { expected.min(255) });
+        }
+    }
+
+    // x86-64 のアセンブリに変換し、cc でリンクして実行したときの終了コード。実行できない環境では None
+    fn native_status(name: &str, input: &str) -> Option<i32> {
+        if !cfg!(all(target_arch = "x86_64", target_os = "linux")) {
+            return None;
+        }
+        let dir = std::env::temp_dir().join(format!("toys_native_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let assembly = dir.join("program.s");
+        let binary = dir.join("program");
+        std::fs::write(&assembly, codegen::x86_64::generate(&parse(input).unwrap()).unwrap()).unwrap();
+        let status = match Command::new("cc").arg("-o").arg(&binary).arg(&assembly).status() {
+            Ok(x) if x.success() => Command::new(&binary).output().unwrap().status.code(),
+            Ok(_) => panic!("cc failed on {}", assembly.display()),
+            Err(_) => None,
+        };
+        std::fs::remove_dir_all(&dir).unwrap();
+        status
     }
 }